@@ -1,3 +1,6 @@
+// Userspace counterpart of `p1c0_kernel::arch::relocation`. Drivers relocate themselves before
+// anything else runs, so they cannot depend on the kernel crate and keep their own copy.
+
 #[repr(C)]
 pub struct RelaEntry {
     offset: usize,
@@ -10,9 +10,10 @@ use core::sync::atomic::{AtomicBool, Ordering};
 
 use p1c0_kernel::{
     arch::get_exception_level,
-    boot_args::get_boot_args,
+    boot_args::{self, get_boot_args},
     drivers::display::Display,
     prelude::*,
+    selftest,
     syscall::Syscall,
     thread::{self, print_thread_info},
 };
@@ -21,15 +22,37 @@ use p1c0_kernel::{
 use p1c0_kernel::drivers::{gpio::GpioBank, hid::HidDev, spi::Spi};
 
 use aarch64_cpu::registers::DAIF;
-use embedded_graphics::pixelcolor::Rgb888;
+use embedded_graphics::{pixelcolor::Rgb888, prelude::*};
 use tinybmp::Bmp;
 use tock_registers::interfaces::Writeable;
 
 const ATE_LOGO_DATA: &[u8] = include_bytes!("../ate_logo.bmp");
 
-fn kernel_entry() {
+/// Draws the logo centered on the display
+fn draw_logo() {
     let logo = Bmp::<Rgb888>::from_slice(ATE_LOGO_DATA).unwrap();
-    Display::init(&logo);
+    let logo_size = logo.size();
+
+    let mut pixels = vec![Rgb888::BLACK; (logo_size.width * logo_size.height) as usize];
+    for Pixel(point, color) in logo.pixels() {
+        let index = point.y as u32 * logo_size.width + point.x as u32;
+        pixels[index as usize] = color;
+    }
+
+    if let Some((width, height)) = Display::size() {
+        let x = (width as i32 - logo_size.width as i32) / 2;
+        let y = (height as i32 - logo_size.height as i32) / 2;
+        Display::blit(x, y, &pixels, logo_size.width, logo_size.height);
+    }
+}
+
+fn kernel_entry() {
+    match Display::init() {
+        Ok(()) => draw_logo(),
+        Err(error) => {
+            log_error!("Unable to initialize the display: {:?}", error);
+        }
+    }
 
     log_debug!("p1c0 running on Apple M1 Pro");
     log_debug!("Exception level: {:?}", get_exception_level());
@@ -37,6 +60,12 @@ fn kernel_entry() {
     let boot_args = get_boot_args();
     print_boot_args(boot_args);
 
+    if boot_args::get_flag(selftest::CMDLINE_FLAG) {
+        if let Err(check) = selftest::run() {
+            log_error!("Self-test failed, first failing check: {}", check);
+        }
+    }
+
     #[cfg(feature = "emulator")]
     print_semihosting_caps();
 
@@ -122,7 +151,8 @@ fn finish() -> ! {
 
 #[panic_handler]
 fn panic_handler(panic_info: &core::panic::PanicInfo) -> ! {
-    // Mask interrupts.
+    // Mask all exceptions, not only interrupts like a critical section does. The panic handler
+    // does not return, so they are never unmasked again.
     DAIF.write(DAIF::D::Masked + DAIF::A::Masked + DAIF::I::Masked + DAIF::F::Masked);
 
     static ALREADY_PANICKED: AtomicBool = AtomicBool::new(false);
@@ -0,0 +1,97 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(test_fwk::runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use p1c0 as _; // needed to link libentry (and _start)
+
+use p1c0_kernel::{
+    adt::{get_adt, AdtNode},
+    drivers::{self, interfaces::char_device::CharDevice, Dev, Device, DeviceRef, Driver},
+    error,
+    prelude::*,
+    sync::spinlock::RwSpinLock,
+};
+
+#[panic_handler]
+fn panic_handler(panic_info: &core::panic::PanicInfo) -> ! {
+    test_fwk::panic_handler(panic_info)
+}
+
+#[no_mangle]
+pub extern "C" fn kernel_main() {
+    test_main();
+}
+
+/// Behaves like /dev/zero
+struct MockCharDevice;
+
+impl Device for MockCharDevice {
+    fn as_char_device(&self) -> Option<&dyn CharDevice> {
+        Some(self)
+    }
+}
+
+impl CharDevice for MockCharDevice {
+    fn read(&self, buffer: &mut [u8]) -> Result<usize, Box<dyn error::Error>> {
+        buffer.fill(0);
+        Ok(buffer.len())
+    }
+
+    fn write(&self, data: &[u8]) -> Result<usize, Box<dyn error::Error>> {
+        Ok(data.len())
+    }
+}
+
+#[test_case]
+fn test_query_registered_device() {
+    const PATH: &str = "/test/mock-char-device";
+    let device = Arc::new(RwSpinLock::new(Dev::Generic(Box::new(MockCharDevice))));
+    drivers::register_device(PATH, device);
+
+    let device = drivers::get_device(PATH).expect("Device is not registered");
+    let device = device.lock_read();
+    assert!(device.as_block_device().is_none());
+
+    let char_device = device
+        .as_char_device()
+        .expect("Device is not a char device");
+    let mut buffer = [0xAAu8; 8];
+    assert_eq!(char_device.read(&mut buffer).unwrap(), 8);
+    assert_eq!(buffer, [0; 8]);
+    assert_eq!(char_device.write(&buffer[..4]).unwrap(), 4);
+
+    assert!(drivers::get_device("/test/missing").is_none());
+}
+
+struct MockDriver;
+
+impl Driver for MockDriver {
+    fn probe(&self, _dev_path: &[AdtNode]) -> drivers::Result<DeviceRef> {
+        Ok(Arc::new(RwSpinLock::new(Dev::Generic(Box::new(
+            MockCharDevice,
+        )))))
+    }
+}
+
+#[test_case]
+fn test_probe_registers_device() {
+    // There is no driver for i2c, so the mock driver can take over the device
+    const PATH: &str = "/arm-io/i2c0";
+    let adt = get_adt().unwrap();
+    let dev_path: Vec<AdtNode> = adt.path_iter(PATH).collect();
+    let compatible = dev_path
+        .last()
+        .unwrap()
+        .get_compatible_list()
+        .unwrap()
+        .next()
+        .unwrap();
+    drivers::register_driver(compatible, Box::new(MockDriver)).unwrap();
+
+    assert!(drivers::probe_device(&dev_path).is_ok());
+
+    let device = drivers::get_device(PATH).expect("Device is not registered");
+    assert!(device.lock_read().as_char_device().is_some());
+}
@@ -0,0 +1,62 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(test_fwk::runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use p1c0 as _; // needed to link libentry (and _start)
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use test_fwk::{Fixture, WithFixture};
+
+#[panic_handler]
+fn panic_handler(panic_info: &core::panic::PanicInfo) -> ! {
+    test_fwk::panic_handler(panic_info)
+}
+
+#[no_mangle]
+pub extern "C" fn kernel_main() {
+    test_main();
+}
+
+static NUM_ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+/// Every test starts without allocations, no matter what the previous tests did.
+struct ResetAllocations;
+
+impl Fixture for ResetAllocations {
+    fn setup(&self) {
+        NUM_ALLOCATIONS.store(0, Ordering::Relaxed);
+    }
+}
+
+fn allocate() {
+    NUM_ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+}
+
+fn allocations_start_at_zero() {
+    assert_eq!(NUM_ALLOCATIONS.load(Ordering::Relaxed), 0);
+    allocate();
+    allocate();
+    assert_eq!(NUM_ALLOCATIONS.load(Ordering::Relaxed), 2);
+}
+
+#[test_case]
+fn test_leaks_allocations() {
+    allocate();
+}
+
+#[test_case]
+static TEST_ALLOCATIONS: WithFixture<ResetAllocations, fn()> = WithFixture::new(
+    "test_allocations",
+    ResetAllocations,
+    allocations_start_at_zero,
+);
+
+#[test_case]
+static TEST_ALLOCATIONS_AGAIN: WithFixture<ResetAllocations, fn()> = WithFixture::new(
+    "test_allocations_again",
+    ResetAllocations,
+    allocations_start_at_zero,
+);
@@ -0,0 +1,77 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(test_fwk::runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use p1c0 as _; // needed to link libentry (and _start)
+
+use p1c0_kernel::{
+    arch::mmu::PAGE_SIZE,
+    memory::{address::PhysicalAddress, MemoryManager},
+};
+
+#[panic_handler]
+fn panic_handler(panic_info: &core::panic::PanicInfo) -> ! {
+    test_fwk::panic_handler(panic_info)
+}
+
+#[no_mangle]
+pub extern "C" fn kernel_main() {
+    test_main();
+}
+
+// Nothing is accessed through the mappings, so any physical address works
+const TEST_PA: usize = 0x6_0000_0000;
+
+#[test_case]
+fn test_io_mapping_is_removed_on_drop() {
+    let pa = PhysicalAddress::try_from_ptr(TEST_PA as *const _).unwrap();
+    let mapping = MemoryManager::instance()
+        .map_io("test-io", pa, PAGE_SIZE)
+        .unwrap();
+    let va = mapping.va();
+
+    let range = MemoryManager::instance()
+        .find_range_containing(va)
+        .expect("The IO range is mapped");
+    assert_eq!(range.name, "test-io");
+    assert_eq!(
+        MemoryManager::instance()
+            .translate_kernel_address(va)
+            .unwrap(),
+        pa
+    );
+
+    drop(mapping);
+    assert!(MemoryManager::instance()
+        .find_range_containing(va)
+        .is_none());
+    assert!(MemoryManager::instance()
+        .translate_kernel_address(va)
+        .is_err());
+}
+
+#[test_case]
+fn test_shared_io_mapping_outlives_first_guard() {
+    let pa = PhysicalAddress::try_from_ptr(TEST_PA as *const _).unwrap();
+    let first = MemoryManager::instance()
+        .map_io("test-io-first", pa, PAGE_SIZE)
+        .unwrap();
+    let second = MemoryManager::instance()
+        .map_io("test-io-second", pa, PAGE_SIZE)
+        .unwrap();
+    assert_eq!(first.va(), second.va());
+
+    let va = first.va();
+    drop(first);
+    let range = MemoryManager::instance()
+        .find_range_containing(va)
+        .expect("The IO range is still in use");
+    assert_eq!(range.name, "test-io-first");
+
+    drop(second);
+    assert!(MemoryManager::instance()
+        .find_range_containing(va)
+        .is_none());
+}
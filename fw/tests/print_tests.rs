@@ -0,0 +1,32 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(test_fwk::runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use p1c0 as _; // needed to link libentry (and _start)
+
+use p1c0_kernel::{
+    drivers::{self, semihosting},
+    prelude::*,
+    print,
+};
+
+#[panic_handler]
+fn panic_handler(panic_info: &core::panic::PanicInfo) -> ! {
+    test_fwk::panic_handler(panic_info)
+}
+
+#[no_mangle]
+pub extern "C" fn kernel_main() {
+    test_main();
+}
+
+#[test_case]
+fn test_semihosting_printer_is_registered() {
+    let device =
+        drivers::get_device(semihosting::DEVICE_PATH).expect("Semihosting printer not registered");
+    assert!(print::printers()
+        .iter()
+        .any(|printer| Arc::ptr_eq(printer, &device)));
+}
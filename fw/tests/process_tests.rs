@@ -8,6 +8,7 @@ use p1c0 as _; // needed to link libentry (and _start)
 
 use p1c0_kernel::{
     filesystem::{OpenMode, VirtualFileSystem},
+    memory::{address::Address, Permissions},
     prelude::*,
     process,
     syscall::Syscall,
@@ -69,3 +70,102 @@ fn test_process_crash() {
     let pid = builder.start().unwrap();
     assert_eq!(Syscall::wait_pid(pid.get_raw()), 0xdeadc0de);
 }
+
+#[test_case]
+fn test_mmap_process() {
+    let mut file = VirtualFileSystem::open("/bin/mmap", OpenMode::Read).unwrap();
+    let mut elf_data = vec![];
+    elf_data.resize(file.size, 0);
+
+    VirtualFileSystem::read(&mut file, &mut elf_data[..]).unwrap();
+    VirtualFileSystem::close(file);
+
+    let builder = process::Builder::new_from_elf_data("/bin/mmap", elf_data, 0).unwrap();
+    let pid = builder.start().unwrap();
+    assert_eq!(Syscall::wait_pid(pid.get_raw()), 0);
+}
+
+#[test_case]
+fn test_shared_memory_processes() {
+    let mut file = VirtualFileSystem::open("/bin/shm", OpenMode::Read).unwrap();
+    let mut elf_data = vec![];
+    elf_data.resize(file.size, 0);
+
+    VirtualFileSystem::read(&mut file, &mut elf_data[..]).unwrap();
+    VirtualFileSystem::close(file);
+
+    // The writer creates the region and fills it, the reader maps it and checks the contents
+    let mut writer = process::Builder::new_from_elf_data("/bin/shm", elf_data.clone(), 0).unwrap();
+    writer.push_argument("writer");
+    let writer_pid = writer.start().unwrap();
+
+    let mut reader = process::Builder::new_from_elf_data("/bin/shm", elf_data, 0).unwrap();
+    reader.push_argument("reader");
+    let reader_pid = reader.start().unwrap();
+
+    assert_eq!(Syscall::wait_pid(reader_pid.get_raw()), 0);
+    assert_eq!(Syscall::wait_pid(writer_pid.get_raw()), 0);
+}
+
+#[test_case]
+fn test_relocated_pie_process() {
+    // Load the executable away from its link address, so that its pointers are only right if the
+    // loader relocated them
+    const ASLR_BASE: usize = 0x1000_0000;
+
+    let mut file = VirtualFileSystem::open("/bin/pie", OpenMode::Read).unwrap();
+    let mut elf_data = vec![];
+    elf_data.resize(file.size, 0);
+
+    VirtualFileSystem::read(&mut file, &mut elf_data[..]).unwrap();
+    VirtualFileSystem::close(file);
+
+    let builder = process::Builder::new_from_elf_data("/bin/pie", elf_data, ASLR_BASE).unwrap();
+    let pid = builder.start().unwrap();
+    assert_eq!(Syscall::wait_pid(pid.get_raw()), 0);
+}
+
+#[test_case]
+fn test_mprotect_process() {
+    // The process writes code to an anonymous mapping, makes it executable and runs it
+    let mut file = VirtualFileSystem::open("/bin/jit", OpenMode::Read).unwrap();
+    let mut elf_data = vec![];
+    elf_data.resize(file.size, 0);
+
+    VirtualFileSystem::read(&mut file, &mut elf_data[..]).unwrap();
+    VirtualFileSystem::close(file);
+
+    let builder = process::Builder::new_from_elf_data("/bin/jit", elf_data, 0).unwrap();
+    let pid = builder.start().unwrap();
+    assert_eq!(Syscall::wait_pid(pid.get_raw()), 0);
+}
+
+#[test_case]
+fn test_process_memory_map() {
+    let mut file = VirtualFileSystem::open("/bin/true", OpenMode::Read).unwrap();
+    let mut elf_data = vec![];
+    elf_data.resize(file.size, 0);
+
+    VirtualFileSystem::read(&mut file, &mut elf_data[..]).unwrap();
+    VirtualFileSystem::close(file);
+
+    let builder = process::Builder::new_from_elf_data("/bin/true", elf_data, 0).unwrap();
+    let pid = builder.start().unwrap();
+
+    // The address space is only released once the process is reaped by `wait_pid`
+    let sections = process::memory_map(&pid).unwrap();
+    let has_section = |name: &str| sections.iter().any(|section| section.name == name);
+    assert!(has_section(".stack"));
+    assert!(has_section(".args"));
+    assert!(sections
+        .iter()
+        .any(|section| section.permissions.unprivileged == Permissions::RX));
+    assert!(sections
+        .windows(2)
+        .all(|pair| pair[0].va.as_usize() < pair[1].va.as_usize()));
+
+    let map = process::format_memory_map(&sections);
+    assert_eq!(map.lines().count(), sections.len());
+
+    assert_eq!(Syscall::wait_pid(pid.get_raw()), 0);
+}
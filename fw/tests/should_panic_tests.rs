@@ -0,0 +1,50 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(test_fwk::runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use p1c0 as _; // needed to link libentry (and _start)
+
+use p1c0_kernel::{arch::mmu::PAGE_SIZE, memory::address::PhysicalAddress};
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use test_fwk::ShouldPanic;
+
+#[panic_handler]
+fn panic_handler(panic_info: &core::panic::PanicInfo) -> ! {
+    test_fwk::panic_handler(panic_info)
+}
+
+#[no_mangle]
+pub extern "C" fn kernel_main() {
+    test_main();
+}
+
+static TESTS_RUN: AtomicUsize = AtomicUsize::new(0);
+
+#[test_case]
+static TEST_UNALIGNED_ADDRESS: ShouldPanic<fn()> =
+    ShouldPanic::new("test_unaligned_address", unaligned_address);
+
+fn unaligned_address() {
+    // The test expected to panic runs last, after all other tests. Asserting would panic and
+    // make it pass.
+    if TESTS_RUN.load(Ordering::Relaxed) != 2 {
+        test_fwk::finish_with_status(test_fwk::Status::Fail);
+    }
+
+    PhysicalAddress::try_from_ptr((0x8_0000_0000 + PAGE_SIZE / 2) as *const _).unwrap();
+}
+
+#[test_case]
+fn test_aligned_address() {
+    PhysicalAddress::try_from_ptr((0x8_0000_0000 + PAGE_SIZE) as *const _).unwrap();
+    TESTS_RUN.fetch_add(1, Ordering::Relaxed);
+}
+
+#[test_case]
+fn test_runs_before_should_panic() {
+    TESTS_RUN.fetch_add(1, Ordering::Relaxed);
+}
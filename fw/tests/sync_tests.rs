@@ -6,7 +6,18 @@
 
 use p1c0 as _; // needed to link libentry (and _start)
 
-use p1c0_kernel::sync::spinlock::{RwSpinLock, SpinLock};
+use core::{
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+use p1c0_kernel::{
+    drivers::{generic_timer::get_timer, interfaces::timer::Timer},
+    prelude::*,
+    sync::spinlock::{self, RwSpinLock, SpinLock},
+    syscall::Syscall,
+    thread,
+};
 
 #[panic_handler]
 fn panic_handler(panic_info: &core::panic::PanicInfo) -> ! {
@@ -15,7 +26,11 @@ fn panic_handler(panic_info: &core::panic::PanicInfo) -> ! {
 
 #[no_mangle]
 pub extern "C" fn kernel_main() {
-    test_main();
+    thread::Builder::new().name("Test").spawn(|| {
+        test_main();
+    });
+
+    thread::initialize();
 }
 
 #[test_case]
@@ -69,3 +84,114 @@ fn test_rwspinlock_access_inner_without_locking() {
     };
     assert!(did_run);
 }
+
+#[test_case]
+fn test_writer_preferring_rwspinlock_blocks_new_readers() {
+    static LOCK: RwSpinLock<u32> = RwSpinLock::new_writer_preferring(0);
+
+    let rlock = LOCK.lock_read();
+    let writer = thread::spawn(|| {
+        *LOCK.lock_write() += 1;
+    });
+
+    // Let the writer run until it starts waiting for the lock. From then on new readers are
+    // rejected, even though the lock is only held for reading.
+    loop {
+        Syscall::yield_exec();
+        if LOCK.try_lock_read().is_err() {
+            break;
+        }
+    }
+    drop(rlock);
+
+    writer.join();
+    assert_eq!(*LOCK.lock_read(), 1);
+}
+
+#[test_case]
+fn test_writer_preferring_rwspinlock_stress() {
+    static LOCK: RwSpinLock<u32> = RwSpinLock::new_writer_preferring(0);
+    static STOP_READERS: AtomicBool = AtomicBool::new(false);
+    const NUM_READERS: usize = 4;
+
+    // Readers yield while holding the lock, so there is always at least one reader unless new
+    // readers are kept out
+    let readers: Vec<_> = (0..NUM_READERS)
+        .map(|_| {
+            thread::spawn(|| {
+                while !STOP_READERS.load(Ordering::Relaxed) {
+                    let rlock = LOCK.lock_read();
+                    Syscall::yield_exec();
+                    drop(rlock);
+                    Syscall::yield_exec();
+                }
+            })
+        })
+        .collect();
+
+    let writer = thread::spawn(|| {
+        *LOCK.lock_write() += 1;
+    });
+
+    writer.join();
+    assert_eq!(*LOCK.lock_read(), 1);
+
+    STOP_READERS.store(true, Ordering::Relaxed);
+    readers.into_iter().for_each(|reader| reader.join());
+}
+
+fn now() -> Duration {
+    let timer = get_timer();
+    timer.resolution().ticks_to_duration(timer.ticks())
+}
+
+fn elapsed_since(start: Duration) -> Duration {
+    now() - start
+}
+
+#[test_case]
+fn test_spinlock_lock_timeout() {
+    const TIMEOUT: Duration = Duration::from_millis(10);
+    let spinlock = SpinLock::new(0);
+
+    let _lock = spinlock.lock_timeout(TIMEOUT).unwrap();
+    assert!(matches!(
+        spinlock.try_lock(),
+        Err(spinlock::Error::WouldBlock)
+    ));
+
+    let start = now();
+    assert!(matches!(
+        spinlock.lock_timeout(TIMEOUT),
+        Err(spinlock::Error::TimedOut)
+    ));
+    let elapsed = elapsed_since(start);
+    assert!(elapsed >= TIMEOUT);
+    assert!(elapsed < 10 * TIMEOUT);
+}
+
+#[test_case]
+fn test_rwspinlock_lock_timeout() {
+    const TIMEOUT: Duration = Duration::from_millis(10);
+    let rwspinlock = RwSpinLock::new_writer_preferring(0);
+
+    let rlock = rwspinlock.lock_read_timeout(TIMEOUT).unwrap();
+    let start = now();
+    assert!(matches!(
+        rwspinlock.lock_write_timeout(TIMEOUT),
+        Err(spinlock::Error::TimedOut)
+    ));
+    let elapsed = elapsed_since(start);
+    assert!(elapsed >= TIMEOUT);
+    assert!(elapsed < 10 * TIMEOUT);
+
+    // A writer that gave up does not keep readers out
+    drop(rwspinlock.try_lock_read().unwrap());
+    drop(rlock);
+
+    let _wlock = rwspinlock.lock_write_timeout(TIMEOUT).unwrap();
+    assert!(matches!(
+        rwspinlock.lock_read_timeout(TIMEOUT),
+        Err(spinlock::Error::TimedOut)
+    ));
+}
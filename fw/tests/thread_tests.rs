@@ -9,8 +9,10 @@ use p1c0 as _; // needed to link libentry (and _start)
 use core::time::Duration;
 
 use p1c0_kernel::{
+    channel,
     drivers::{generic_timer::get_timer, interfaces::timer::Timer},
     sync::spinlock::SpinLock,
+    syscall::Syscall,
     thread,
 };
 
@@ -123,3 +125,146 @@ fn test_join_thread() {
     t2.join();
     assert_eq!(*NUM_THREADS.lock(), 2);
 }
+
+#[test_case]
+fn test_channel_ping_pong() {
+    let (mut ping_tx, mut ping_rx) = (0u64, 0u64);
+    let (mut pong_tx, mut pong_rx) = (0u64, 0u64);
+    assert_eq!(Syscall::channel_create(&mut ping_tx, &mut ping_rx), 0);
+    assert_eq!(Syscall::channel_create(&mut pong_tx, &mut pong_rx), 0);
+
+    const NUM_ROUNDS: usize = 8;
+    let ponger = thread::spawn(move || {
+        let mut buffer = [0u8; 8];
+        for _ in 0..NUM_ROUNDS {
+            let length = Syscall::channel_recv(ping_rx, buffer.as_mut_ptr(), buffer.len());
+            assert_eq!(&buffer[..length as usize], b"ping");
+            assert_eq!(Syscall::channel_send(pong_tx, b"pong".as_ptr(), 4), 0);
+        }
+        assert_eq!(Syscall::channel_close(pong_tx), 0);
+    });
+
+    let mut buffer = [0u8; 8];
+    for _ in 0..NUM_ROUNDS {
+        assert_eq!(Syscall::channel_send(ping_tx, b"ping".as_ptr(), 4), 0);
+        let length = Syscall::channel_recv(pong_rx, buffer.as_mut_ptr(), buffer.len());
+        assert_eq!(&buffer[..length as usize], b"pong");
+    }
+
+    // Once the sender is gone the receiver is no longer blocked
+    assert_eq!(
+        Syscall::channel_recv(pong_rx, buffer.as_mut_ptr(), buffer.len()),
+        channel::ERROR_CLOSED
+    );
+    ponger.join();
+
+    assert_eq!(Syscall::channel_close(pong_rx), 0);
+    assert_eq!(Syscall::channel_close(ping_tx), 0);
+    assert_eq!(Syscall::channel_close(ping_rx), 0);
+    assert_eq!(
+        Syscall::channel_send(ping_tx, b"ping".as_ptr(), 4),
+        channel::ERROR_INVALID_ENDPOINT
+    );
+}
+
+#[test_case]
+fn test_thread_listing() {
+    let sleeper = thread::Builder::new().name("Sleeper").spawn(|| {
+        Syscall::sleep_us(100_000);
+    });
+    let yielder = thread::Builder::new().name("Yielder").spawn(|| {
+        Syscall::yield_exec();
+    });
+
+    // Let the sleeper go to sleep
+    Syscall::yield_exec();
+    Syscall::yield_exec();
+
+    let threads = thread::list();
+    let current = threads
+        .iter()
+        .find(|thread| thread.name() == Some("Test"))
+        .unwrap();
+    assert_eq!(current.state, thread::ThreadState::Running);
+    assert!(current.process.is_none());
+
+    let sleeping = threads
+        .iter()
+        .find(|thread| thread.tid == sleeper.tid())
+        .unwrap();
+    assert_eq!(sleeping.name(), Some("Sleeper"));
+    assert_eq!(sleeping.state, thread::ThreadState::Sleeping);
+
+    sleeper.join();
+    yielder.join();
+    assert!(!thread::list()
+        .iter()
+        .any(|thread| matches!(thread.name(), Some("Sleeper") | Some("Yielder"))));
+}
+
+#[test_case]
+fn test_find_thread_by_name() {
+    let sleeper = thread::Builder::new().name("Finder").spawn(|| {
+        Syscall::sleep_us(10_000);
+    });
+
+    let found = thread::find_by_name("Finder").unwrap();
+    assert_eq!(found.tid(), sleeper.tid());
+    assert!(thread::find_by_name("Test").is_some());
+    assert!(thread::find_by_name("Missing").is_none());
+
+    sleeper.join();
+    assert!(thread::find_by_name("Finder").is_none());
+}
+
+#[test_case]
+fn test_unique_thread_names() {
+    let first = thread::Builder::new()
+        .name("Unique")
+        .unique_name()
+        .try_spawn(|| {
+            Syscall::sleep_us(10_000);
+        })
+        .unwrap();
+
+    let duplicate = thread::Builder::new()
+        .name("Unique")
+        .unique_name()
+        .try_spawn(|| {});
+    assert!(matches!(duplicate, Err(thread::Error::NameAlreadyInUse(name)) if name == "Unique"));
+
+    // Without the option duplicates are allowed
+    let second = thread::Builder::new().name("Unique").spawn(|| {});
+
+    first.join();
+    second.join();
+}
+
+static RUN_ORDER: SpinLock<[u32; 2]> = SpinLock::new([0; 2]);
+static RUN_COUNT: SpinLock<usize> = SpinLock::new(0);
+
+fn record_run(id: u32) {
+    let mut count = RUN_COUNT.lock();
+    RUN_ORDER.lock()[*count] = id;
+    *count += 1;
+}
+
+#[test_case]
+fn test_high_priority_thread_runs_first() {
+    *RUN_COUNT.lock() = 0;
+
+    // Spawned first, so it would run first with plain round robin
+    let low = thread::Builder::new()
+        .name("Low")
+        .priority(thread::MIN_PRIORITY)
+        .spawn(|| record_run(1));
+    let high = thread::Builder::new()
+        .name("High")
+        .priority(thread::MAX_PRIORITY)
+        .spawn(|| record_run(2));
+
+    high.join();
+    low.join();
+    assert_eq!(*RUN_COUNT.lock(), 2);
+    assert_eq!(*RUN_ORDER.lock(), [2, 1]);
+}
@@ -1,8 +1,27 @@
-use crate::memory::address::{Address, PhysicalAddress};
+use crate::{
+    collections::flat_map::FlatMap,
+    memory::address::{Address, PhysicalAddress},
+    sync::spinlock::SpinLock,
+};
 
-use core::{mem, ops::FnMut, slice, str};
+use core::{fmt, iter, mem, ops::FnMut, slice, str};
 
-use heapless::Vec;
+use heapless::{String, Vec};
+
+/// Longest path or name kept in errors. Longer ones are truncated.
+const MAX_ERROR_NAME_LENGTH: usize = 64;
+
+type ErrorName = String<MAX_ERROR_NAME_LENGTH>;
+
+fn error_name(name: &str) -> ErrorName {
+    let mut truncated = ErrorName::new();
+    for c in name.chars() {
+        if truncated.push(c).is_err() {
+            break;
+        }
+    }
+    truncated
+}
 
 #[derive(Debug, Clone)]
 pub enum Error {
@@ -13,6 +32,31 @@ pub enum Error {
     InvalidPropertyType,
     InvalidRangeDataSize,
     InvalidRegDataSize,
+    InvalidArrayDataSize,
+    /// The size of the property does not match the size of the value read with an exact accessor
+    InvalidValueSize {
+        expected: usize,
+        actual: usize,
+    },
+    /// A node required by the kernel is not present. Holds the path of the node
+    MissingNode(ErrorName),
+    /// A node does not have a property required by the kernel
+    MissingProperty {
+        node: ErrorName,
+        property: ErrorName,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::MissingNode(path) => write!(f, "ADT node `{}` not found", path),
+            Error::MissingProperty { node, property } => {
+                write!(f, "ADT node `{}` has no property `{}`", node, property)
+            }
+            error => write!(f, "ADT error: {:?}", error),
+        }
+    }
 }
 
 /// ADT Memory layout
@@ -115,9 +159,41 @@ impl AdtNode {
             .find(|property| property.get_name() == name)
     }
 
+    /// Like `find_property`, but a missing property is an `Error::MissingProperty` describing it.
+    pub fn require_property(&self, name: &str) -> Result<AdtProperty, Error> {
+        self.find_property(name)
+            .ok_or_else(|| Error::MissingProperty {
+                node: error_name(self.get_name()),
+                property: error_name(name),
+            })
+    }
+
+    /// Returns the property with the given name, panicking with a descriptive message if it is
+    /// missing.
+    pub fn expect_property(&self, name: &str) -> AdtProperty {
+        self.require_property(name)
+            .unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    /// Returns the handle other nodes use to reference this one, if it has any.
+    pub fn phandle(&self) -> Option<u32> {
+        self.find_property("AAPL,phandle")
+            .and_then(|prop| prop.u32_value().ok())
+    }
+
+    /// Depth-first search of the node with the given phandle in this node and its descendants.
+    fn find_by_phandle(&self, phandle: u32) -> Option<AdtNode> {
+        if self.phandle() == Some(phandle) {
+            return Some(self.clone());
+        }
+
+        self.child_iter()
+            .find_map(|child| child.find_by_phandle(phandle))
+    }
+
     pub fn get_address_cells(&self) -> Option<u32> {
         self.find_property("#address-cells").and_then(|prop| {
-            prop.u32_value()
+            prop.u32_value_exact()
                 .map(|val| {
                     debug_assert!(val <= 2 && val > 0);
                     val
@@ -128,7 +204,7 @@ impl AdtNode {
 
     pub fn get_size_cells(&self) -> Option<u32> {
         self.find_property("#size-cells").and_then(|prop| {
-            prop.u32_value()
+            prop.u32_value_exact()
                 .map(|val| {
                     debug_assert!(val <= 2);
                     val
@@ -187,10 +263,19 @@ impl AdtNode {
             })
             .is_some()
     }
+
+    /// Returns the first string in the compatible list of the node that is also in `accepted`.
+    /// The compatible list is ordered from most to least specific, so this is the best match.
+    pub fn is_compatible_any(&self, accepted: &[&str]) -> Option<&'static str> {
+        self.get_compatible_list()?
+            .find(|compatible| accepted.contains(compatible))
+    }
 }
 
 macro_rules! define_value_method {
-    ($func_name: ident, $type: ty) => {
+    ($func_name: ident, $exact_func_name: ident, $type: ty) => {
+        /// Returns the little endian value at the beginning of the property. Any data after the
+        /// value is ignored.
         pub fn $func_name(&self) -> Result<$type, Error> {
             const SIZE: usize = mem::size_of::<$type>();
             if self.get_size() < SIZE {
@@ -201,6 +286,40 @@ macro_rules! define_value_method {
             let bytes: [u8; SIZE] = data.try_into().expect("There are exactly SIZE elements");
             Ok(<$type>::from_le_bytes(bytes))
         }
+
+        /// Returns the little endian value in the property. Fails unless the property has exactly
+        /// the size of the value, which catches properties read with the wrong type.
+        pub fn $exact_func_name(&self) -> Result<$type, Error> {
+            const SIZE: usize = mem::size_of::<$type>();
+            let actual = self.get_size();
+            if actual != SIZE {
+                return Err($crate::adt::Error::InvalidValueSize {
+                    expected: SIZE,
+                    actual,
+                });
+            }
+
+            self.$func_name()
+        }
+    };
+}
+
+macro_rules! define_array_iter_method {
+    ($func_name: ident, $type: ty) => {
+        /// Returns an iterator over the little endian values in the property. Fails if the size of
+        /// the property is not a multiple of the size of the values.
+        pub fn $func_name(&self) -> Result<impl Iterator<Item = $type>, Error> {
+            const SIZE: usize = mem::size_of::<$type>();
+            let data = self.get_data();
+            if data.len() % SIZE != 0 {
+                return Err($crate::adt::Error::InvalidArrayDataSize);
+            }
+
+            Ok(data.chunks_exact(SIZE).map(|chunk| {
+                let bytes: [u8; SIZE] = chunk.try_into().expect("There are exactly SIZE elements");
+                <$type>::from_le_bytes(bytes)
+            }))
+        }
     };
 }
 
@@ -271,6 +390,14 @@ impl AdtProperty {
         }
     }
 
+    /// Like `str_list_value`, but also yields the index of each string in the list. It stops at
+    /// the first empty string too, so indices are always contiguous.
+    pub fn str_list_value_indexed(
+        &self,
+    ) -> iter::Enumerate<StrListIter<impl FnMut(&'_ u8) -> bool>> {
+        self.str_list_value().enumerate()
+    }
+
     pub fn function_value(&self) -> Result<Function<'static>, Error> {
         const U32_SIZE: usize = mem::size_of::<u32>();
 
@@ -309,17 +436,20 @@ impl AdtProperty {
         })
     }
 
-    define_value_method!(u8_value, u8);
-    define_value_method!(u16_value, u16);
-    define_value_method!(u32_value, u32);
-    define_value_method!(u64_value, u64);
-    define_value_method!(usize_value, usize);
+    define_value_method!(u8_value, u8_value_exact, u8);
+    define_value_method!(u16_value, u16_value_exact, u16);
+    define_value_method!(u32_value, u32_value_exact, u32);
+    define_value_method!(u64_value, u64_value_exact, u64);
+    define_value_method!(usize_value, usize_value_exact, usize);
+
+    define_value_method!(i8_value, i8_value_exact, i8);
+    define_value_method!(i16_value, i16_value_exact, i16);
+    define_value_method!(i32_value, i32_value_exact, i32);
+    define_value_method!(i64_value, i64_value_exact, i64);
+    define_value_method!(isize_value, isize_value_exact, isize);
 
-    define_value_method!(i8_value, i8);
-    define_value_method!(i16_value, i16);
-    define_value_method!(i32_value, i32);
-    define_value_method!(i64_value, i64);
-    define_value_method!(isize_value, isize);
+    define_array_iter_method!(u32_array_iter, u32);
+    define_array_iter_method!(u64_array_iter, u64);
 
     /// Returns a slice with the data contained in value.
     pub fn get_data(&self) -> &'static [u8] {
@@ -356,6 +486,34 @@ impl Adt {
         Some(node)
     }
 
+    /// Like `find_node`, but a missing node is an `Error::MissingNode` with its path, which can be
+    /// logged before giving up on boot.
+    pub fn require_node(&self, path: &str) -> Result<AdtNode, Error> {
+        self.find_node(path)
+            .ok_or_else(|| Error::MissingNode(error_name(path)))
+    }
+
+    /// Returns the node at the given path, panicking with a descriptive message if it is missing.
+    pub fn expect_node(&self, path: &str) -> AdtNode {
+        self.require_node(path)
+            .unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    /// Returns the kernel command line in the `bootargs` property of `/chosen`, if it is present
+    /// and not empty.
+    pub fn bootargs(&self) -> Option<&'static str> {
+        self.find_node("/chosen")?
+            .find_property("bootargs")?
+            .str_value()
+            .ok()
+            .filter(|bootargs| !bootargs.is_empty())
+    }
+
+    /// Finds the node with the given `AAPL,phandle` property. The whole tree is searched every time.
+    pub fn find_by_phandle(&self, phandle: u32) -> Option<AdtNode> {
+        self.head.find_by_phandle(phandle)
+    }
+
     pub fn path_iter<'a>(&self, path: &'a str) -> PathIter<'a> {
         PathIter {
             node: self.head.clone(),
@@ -363,10 +521,45 @@ impl Adt {
         }
     }
 
+    /// Returns the physical address and size of a register range of the device at `path`. After
+    /// relocation, resolved addresses are kept in a global cache.
     pub fn get_device_addr(
         &self,
         path: &str,
         reg_index: usize,
+    ) -> Option<(PhysicalAddress, usize)> {
+        // The cache needs the heap, which is only available after relocation. If the cache is in use
+        // by someone else the address is resolved without it.
+        if crate::init::is_kernel_relocated() {
+            if let Ok(mut cache) = DEVICE_ADDR_CACHE.try_lock() {
+                let cache = cache.get_or_insert_with(DeviceAddrCache::new);
+                return self.get_device_addr_cached(cache, path, reg_index);
+            }
+        }
+
+        self.resolve_device_addr(path, reg_index)
+    }
+
+    /// Like `get_device_addr`, looking up and storing the result in the given cache.
+    pub fn get_device_addr_cached(
+        &self,
+        cache: &mut DeviceAddrCache,
+        path: &str,
+        reg_index: usize,
+    ) -> Option<(PhysicalAddress, usize)> {
+        if let Some(device_addr) = cache.lookup(path, reg_index) {
+            return Some(device_addr);
+        }
+
+        let device_addr = self.resolve_device_addr(path, reg_index)?;
+        cache.insert(path, reg_index, device_addr);
+        Some(device_addr)
+    }
+
+    fn resolve_device_addr(
+        &self,
+        path: &str,
+        reg_index: usize,
     ) -> Option<(PhysicalAddress, usize)> {
         let nodes: Vec<AdtNode, 8> = self.path_iter(path).collect();
         self.get_device_addr_from_nodes(&nodes, reg_index)
@@ -409,6 +602,56 @@ impl Adt {
     }
 }
 
+/// Device addresses resolved from the ADT, keyed by device path and register index. The ADT never
+/// changes, so cached addresses are always valid.
+pub struct DeviceAddrCache {
+    /// Keyed by path alone so that lookups can borrow it. Each device holds the addresses of its
+    /// resolved register indices.
+    entries: FlatMap<alloc::string::String, alloc::vec::Vec<(usize, (PhysicalAddress, usize))>>,
+}
+
+impl DeviceAddrCache {
+    pub fn new() -> Self {
+        Self {
+            entries: FlatMap::new(),
+        }
+    }
+
+    fn lookup(&self, path: &str, reg_index: usize) -> Option<(PhysicalAddress, usize)> {
+        self.entries
+            .lookup(path)?
+            .iter()
+            .find(|(index, _)| *index == reg_index)
+            .map(|(_, device_addr)| *device_addr)
+    }
+
+    fn insert(&mut self, path: &str, reg_index: usize, device_addr: (PhysicalAddress, usize)) {
+        if let Some(regs) = self.entries.lookup_mut(path) {
+            regs.push((reg_index, device_addr));
+        } else {
+            self.entries
+                .insert(path.into(), alloc::vec![(reg_index, device_addr)]);
+        }
+    }
+
+    /// Number of cached register ranges
+    pub fn len(&self) -> usize {
+        self.entries.iter().map(|(_, regs)| regs.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl Default for DeviceAddrCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+static DEVICE_ADDR_CACHE: SpinLock<Option<DeviceAddrCache>> = SpinLock::new(None);
+
 #[derive(Debug, Clone)]
 pub struct NodeIter {
     num_nodes: u32,
@@ -456,19 +699,7 @@ impl Iterator for PropertyIter {
 }
 
 pub fn get_adt() -> Result<Adt, Error> {
-    let boot_args = crate::boot_args::get_boot_args();
-    unsafe {
-        // After the MMU is initialized, the adt has been remapped as RO to the ADT_VIRT_BASE
-        let addr = if !crate::arch::mmu::is_initialized() {
-            boot_args
-                .device_tree
-                .offset(-(boot_args.virt_base as isize))
-                .add(boot_args.phys_base)
-        } else {
-            crate::memory::map::ADT_VIRTUAL_BASE.as_ptr()
-        };
-        Adt::new(addr)
-    }
+    unsafe { Adt::new(crate::boot_args::device_tree_slice().as_ptr()) }
 }
 
 pub struct StrListIter<P>
@@ -828,3 +1059,377 @@ pub struct Function<'a> {
     pub name: heapless::String<4>,
     pub args: &'a [u32],
 }
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+
+    use std::{vec, vec::Vec};
+
+    /// Node used to build ADT data for tests
+    pub(crate) struct TestNode {
+        properties: Vec<(&'static str, Vec<u8>)>,
+        children: Vec<TestNode>,
+    }
+
+    impl TestNode {
+        pub(crate) fn new(name: &str) -> Self {
+            let mut name_data = name.as_bytes().to_vec();
+            name_data.push(0);
+            Self {
+                properties: vec![("name", name_data)],
+                children: vec![],
+            }
+        }
+
+        pub(crate) fn property(mut self, name: &'static str, data: Vec<u8>) -> Self {
+            self.properties.push((name, data));
+            self
+        }
+
+        pub(crate) fn child(mut self, child: TestNode) -> Self {
+            self.children.push(child);
+            self
+        }
+
+        fn serialize(&self, data: &mut Vec<u8>) {
+            data.extend_from_slice(&(self.properties.len() as u32).to_le_bytes());
+            data.extend_from_slice(&(self.children.len() as u32).to_le_bytes());
+
+            for (name, value) in &self.properties {
+                let mut name_data = [0u8; 32];
+                name_data[..name.len()].copy_from_slice(name.as_bytes());
+                data.extend_from_slice(&name_data);
+                data.extend_from_slice(&(value.len() as u32).to_le_bytes());
+                data.extend_from_slice(value);
+                while data.len() % mem::size_of::<u32>() != 0 {
+                    data.push(0);
+                }
+            }
+
+            for child in &self.children {
+                child.serialize(data);
+            }
+        }
+
+        /// Returns the ADT data with this node as root. The data is leaked, since ADT data is
+        /// 'static.
+        pub(crate) fn data(&self) -> &'static [u32] {
+            let mut bytes = vec![];
+            self.serialize(&mut bytes);
+
+            // Copy into a u32 buffer to get the alignment the ADT requires
+            let mut words = vec![0u32; bytes.len() / mem::size_of::<u32>()];
+            for (word, chunk) in words.iter_mut().zip(bytes.chunks_exact(4)) {
+                *word = u32::from_ne_bytes(chunk.try_into().unwrap());
+            }
+            words.leak()
+        }
+
+        /// Returns an ADT with this node as root.
+        pub(crate) fn build(&self) -> Adt {
+            unsafe { Adt::new(self.data().as_ptr() as *const u8).unwrap() }
+        }
+    }
+
+    fn le_bytes<const N: usize>(values: impl IntoIterator<Item = [u8; N]>) -> Vec<u8> {
+        values.into_iter().flatten().collect()
+    }
+
+    #[test]
+    fn array_properties() {
+        let adt = TestNode::new("device")
+            .property(
+                "clock-ids",
+                le_bytes([1u32, 0x1234_5678, 0xffff_ffff].map(u32::to_le_bytes)),
+            )
+            .property(
+                "ranges64",
+                le_bytes([0x1_0000_0000u64, 2].map(u64::to_le_bytes)),
+            )
+            .property("odd", vec![1, 2, 3, 4, 5, 6])
+            .property("empty", vec![])
+            .build();
+        let node = adt.find_node("/").unwrap();
+
+        let clock_ids = node.find_property("clock-ids").unwrap();
+        assert_eq!(
+            clock_ids.u32_array_iter().unwrap().collect::<Vec<_>>(),
+            vec![1, 0x1234_5678, 0xffff_ffff]
+        );
+        assert!(matches!(
+            clock_ids.u64_array_iter(),
+            Err(Error::InvalidArrayDataSize)
+        ));
+
+        let ranges = node.find_property("ranges64").unwrap();
+        assert_eq!(
+            ranges.u64_array_iter().unwrap().collect::<Vec<_>>(),
+            vec![0x1_0000_0000, 2]
+        );
+        assert_eq!(ranges.u32_array_iter().unwrap().count(), 4);
+
+        let odd = node.find_property("odd").unwrap();
+        assert!(matches!(
+            odd.u32_array_iter(),
+            Err(Error::InvalidArrayDataSize)
+        ));
+
+        let empty = node.find_property("empty").unwrap();
+        assert_eq!(empty.u32_array_iter().unwrap().count(), 0);
+    }
+
+    #[test]
+    fn indexed_string_list() {
+        let adt = TestNode::new("device")
+            .property("compatible", b"uart-1,samsung\0uart,s5l\0serial\0".to_vec())
+            .property("clock-names", b"core\0\0ignored\0".to_vec())
+            .build();
+        let node = adt.find_node("/").unwrap();
+
+        let compatible = node.find_property("compatible").unwrap();
+        assert_eq!(
+            compatible.str_list_value_indexed().collect::<Vec<_>>(),
+            vec![(0, "uart-1,samsung"), (1, "uart,s5l"), (2, "serial")]
+        );
+        assert_eq!(
+            compatible
+                .str_list_value_indexed()
+                .find(|(_, name)| *name == "serial")
+                .map(|(index, _)| index),
+            Some(2)
+        );
+
+        // Same as `str_list_value`, the list ends at the first empty string
+        let clock_names = node.find_property("clock-names").unwrap();
+        assert_eq!(
+            clock_names.str_list_value_indexed().collect::<Vec<_>>(),
+            vec![(0, "core")]
+        );
+        assert_eq!(clock_names.str_list_value().count(), 1);
+    }
+
+    #[test]
+    fn compatible_with_any() {
+        let adt = TestNode::new("device-tree")
+            .child(
+                TestNode::new("spi0")
+                    .property("compatible", b"spi-2,spimc\0spi-1,spimc\0".to_vec()),
+            )
+            .child(TestNode::new("chosen"))
+            .build();
+        let spi = adt.find_node("/spi0").unwrap();
+
+        assert_eq!(
+            spi.is_compatible_any(&["spi-1,spimc", "spi-2,spimc"]),
+            Some("spi-2,spimc")
+        );
+        assert_eq!(
+            spi.is_compatible_any(&["gpio,t6000", "spi-1,spimc"]),
+            Some("spi-1,spimc")
+        );
+        assert_eq!(spi.is_compatible_any(&["gpio,t6000", "spi"]), None);
+        assert_eq!(spi.is_compatible_any(&[]), None);
+
+        // Nodes without a compatible property never match
+        let chosen = adt.find_node("/chosen").unwrap();
+        assert_eq!(chosen.is_compatible_any(&["spi-1,spimc"]), None);
+    }
+
+    #[test]
+    fn exact_size_values() {
+        let adt = TestNode::new("device")
+            .property("#address-cells", 2u32.to_le_bytes().to_vec())
+            .property("#size-cells", 2u64.to_le_bytes().to_vec())
+            .property("dram-base", 0x8_0000_0000u64.to_le_bytes().to_vec())
+            .property("short", vec![1, 2])
+            .build();
+        let node = adt.find_node("/").unwrap();
+
+        let address_cells = node.find_property("#address-cells").unwrap();
+        assert_eq!(address_cells.u32_value_exact().unwrap(), 2);
+        assert_eq!(address_cells.i32_value_exact().unwrap(), 2);
+        assert!(matches!(
+            address_cells.u64_value_exact(),
+            Err(Error::InvalidValueSize {
+                expected: 8,
+                actual: 4
+            })
+        ));
+        assert_eq!(node.get_address_cells(), Some(2));
+
+        // A cell count stored as a u64 is only accepted by the lenient accessor
+        let size_cells = node.find_property("#size-cells").unwrap();
+        assert_eq!(size_cells.u32_value().unwrap(), 2);
+        assert!(matches!(
+            size_cells.u32_value_exact(),
+            Err(Error::InvalidValueSize {
+                expected: 4,
+                actual: 8
+            })
+        ));
+        assert_eq!(node.get_size_cells(), None);
+
+        let dram_base = node.find_property("dram-base").unwrap();
+        assert_eq!(dram_base.u64_value_exact().unwrap(), 0x8_0000_0000);
+        assert_eq!(dram_base.u8_value().unwrap(), 0);
+        assert!(dram_base.u8_value_exact().is_err());
+
+        // Too short for either accessor
+        let short = node.find_property("short").unwrap();
+        assert!(matches!(short.u32_value(), Err(Error::InvalidPropertyType)));
+        assert!(matches!(
+            short.u32_value_exact(),
+            Err(Error::InvalidValueSize {
+                expected: 4,
+                actual: 2
+            })
+        ));
+        assert_eq!(short.u16_value_exact().unwrap(), 0x0201);
+    }
+
+    #[test]
+    fn phandle_references() {
+        let phandle = |value: u32| value.to_le_bytes().to_vec();
+
+        let adt = TestNode::new("device-tree")
+            .property("AAPL,phandle", phandle(1))
+            .child(
+                TestNode::new("arm-io")
+                    .child(
+                        TestNode::new("pmgr")
+                            .property("AAPL,phandle", phandle(10))
+                            .property("consumer", phandle(11)),
+                    )
+                    .child(
+                        TestNode::new("spi0")
+                            .property("AAPL,phandle", phandle(11))
+                            .property("power-parent", phandle(10)),
+                    ),
+            )
+            .child(TestNode::new("chosen"))
+            .build();
+
+        let pmgr = adt.find_node("/arm-io/pmgr").unwrap();
+        assert_eq!(pmgr.phandle(), Some(10));
+        assert_eq!(adt.find_node("/chosen").unwrap().phandle(), None);
+
+        // Both nodes can be reached following the reference in the other one
+        let spi_phandle = pmgr.find_property("consumer").unwrap().u32_value().unwrap();
+        let spi = adt.find_by_phandle(spi_phandle).unwrap();
+        assert_eq!(spi.get_name(), "spi0");
+
+        let pmgr_phandle = spi
+            .find_property("power-parent")
+            .unwrap()
+            .u32_value()
+            .unwrap();
+        assert_eq!(
+            adt.find_by_phandle(pmgr_phandle).unwrap().get_name(),
+            "pmgr"
+        );
+
+        assert_eq!(adt.find_by_phandle(1).unwrap().get_name(), "device-tree");
+        assert!(adt.find_by_phandle(12).is_none());
+    }
+
+    #[test]
+    fn chosen_bootargs() {
+        let adt = TestNode::new("device-tree")
+            .child(TestNode::new("chosen").property("bootargs", b"quiet debug=1\0\0".to_vec()))
+            .build();
+        assert_eq!(adt.bootargs(), Some("quiet debug=1"));
+
+        let adt = TestNode::new("device-tree")
+            .child(TestNode::new("chosen").property("bootargs", b"\0".to_vec()))
+            .build();
+        assert_eq!(adt.bootargs(), None);
+
+        let adt = TestNode::new("device-tree")
+            .child(TestNode::new("chosen"))
+            .build();
+        assert_eq!(adt.bootargs(), None);
+
+        let adt = TestNode::new("device-tree").build();
+        assert_eq!(adt.bootargs(), None);
+    }
+
+    #[test]
+    fn required_nodes_and_properties() {
+        use std::string::ToString;
+
+        let adt = TestNode::new("device-tree")
+            .child(TestNode::new("chosen").property("dram-base", vec![0; 8]))
+            .build();
+
+        let chosen = adt.require_node("/chosen").unwrap();
+        assert_eq!(chosen.get_name(), "chosen");
+        assert!(chosen.require_property("dram-base").is_ok());
+        assert_eq!(chosen.expect_property("dram-base").get_size(), 8);
+
+        let error = adt.require_node("/arm-io/uart0").unwrap_err();
+        assert!(matches!(&error, Error::MissingNode(path) if path == "/arm-io/uart0"));
+        assert_eq!(error.to_string(), "ADT node `/arm-io/uart0` not found");
+
+        let error = chosen.require_property("dram-size").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "ADT node `chosen` has no property `dram-size`"
+        );
+
+        // Long paths are truncated instead of failing
+        let path = "/a".repeat(100);
+        match adt.require_node(&path).unwrap_err() {
+            Error::MissingNode(truncated) => assert_eq!(truncated, &path[..MAX_ERROR_NAME_LENGTH]),
+            error => panic!("Unexpected error {:?}", error),
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "ADT node `/arm-io` not found")]
+    fn expect_missing_node() {
+        let adt = TestNode::new("device-tree").build();
+        adt.expect_node("/arm-io");
+    }
+
+    #[test]
+    fn cached_device_addresses() {
+        let u64_cells = |values: &[u64]| le_bytes(values.iter().map(|value| value.to_le_bytes()));
+
+        let adt = TestNode::new("device-tree")
+            .child(
+                TestNode::new("arm-io")
+                    .property("ranges", u64_cells(&[0x0, 0x2_0000_0000, 0x1_0000_0000]))
+                    .child(
+                        TestNode::new("uart0")
+                            .property("reg", u64_cells(&[0x3520_0000, 0x4000, 0x3521_0000, 0x100])),
+                    ),
+            )
+            .build();
+
+        let mut cache = DeviceAddrCache::new();
+        for _ in 0..2 {
+            for reg_index in 0..3 {
+                let uncached = adt.resolve_device_addr("/arm-io/uart0", reg_index);
+                let cached = adt.get_device_addr_cached(&mut cache, "/arm-io/uart0", reg_index);
+                assert_eq!(cached, uncached);
+            }
+        }
+
+        assert_eq!(
+            adt.get_device_addr_cached(&mut cache, "/arm-io/uart0", 1),
+            Some((
+                PhysicalAddress::from_unaligned_ptr(0x2_3521_0000 as *const _),
+                0x100
+            ))
+        );
+        // Missing registers are not cached
+        assert_eq!(cache.len(), 2);
+        // Registers of the same device share an entry
+        assert_eq!(cache.entries.len(), 1);
+        assert!(adt
+            .get_device_addr_cached(&mut cache, "/arm-io/spi0", 0)
+            .is_none());
+        assert_eq!(cache.len(), 2);
+    }
+}
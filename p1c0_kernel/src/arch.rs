@@ -1,13 +1,23 @@
 pub mod cache;
+pub mod cpu;
 pub mod exceptions;
 pub mod mmu;
+pub mod pan;
+pub mod relocation;
+
+pub use pan::with_user_access;
 
 use crate::memory::address::VirtualAddress;
 
-use aarch64_cpu::registers::{CurrentEL, SPSel};
-use tock_registers::interfaces::Readable;
+use core::fmt;
+
+use aarch64_cpu::registers::{CurrentEL, SPSel, CNTHCTL_EL2, CNTVOFF_EL2, HCR_EL2, SPSR_EL2};
+use tock_registers::{
+    interfaces::{Readable, Writeable},
+    registers::InMemoryRegister,
+};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ExceptionLevel {
     Application,
     OS,
@@ -26,6 +36,59 @@ pub fn get_exception_level() -> ExceptionLevel {
     }
 }
 
+impl ExceptionLevel {
+    pub fn number(self) -> u8 {
+        match self {
+            ExceptionLevel::Application => 0,
+            ExceptionLevel::OS => 1,
+            ExceptionLevel::Hypervisor => 2,
+            ExceptionLevel::SecureMonitor => 3,
+        }
+    }
+}
+
+impl fmt::Display for ExceptionLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "EL{} ({:?})", self.number(), self)
+    }
+}
+
+/// The kernel is running at a different exception level than the code expects.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExceptionLevelMismatch {
+    pub expected: ExceptionLevel,
+    pub current: ExceptionLevel,
+}
+
+impl fmt::Display for ExceptionLevelMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "expected to run at {}, but running at {}",
+            self.expected, self.current
+        )
+    }
+}
+
+pub fn check_exception_level(
+    expected: ExceptionLevel,
+    current: ExceptionLevel,
+) -> Result<(), ExceptionLevelMismatch> {
+    if expected != current {
+        return Err(ExceptionLevelMismatch { expected, current });
+    }
+    Ok(())
+}
+
+/// Halts the kernel if it is not running at the `expected` exception level. Meant for the points of
+/// the boot process that configure registers of a specific level, where running at another level
+/// leads to failures that are much harder to track down. `stage` names the caller in the message.
+pub fn require_exception_level(expected: ExceptionLevel, stage: &str) {
+    if let Err(mismatch) = check_exception_level(expected, get_exception_level()) {
+        panic!("{}: {}", stage, mismatch);
+    }
+}
+
 #[inline(always)]
 pub fn read_frame_pointer() -> VirtualAddress {
     let fp: usize;
@@ -63,3 +126,125 @@ impl StackType {
         }
     }
 }
+
+/// Values of the EL2 registers that configure EL1 before dropping to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct El1TransitionConfig {
+    pub hcr_el2: u64,
+    pub spsr_el2: u64,
+    pub cnthctl_el2: u64,
+    pub cntvoff_el2: u64,
+}
+
+impl El1TransitionConfig {
+    /// Builds the configuration. Any trap or routing that the firmware left enabled in `HCR_EL2`
+    /// is cleared, only `RW` is set.
+    pub fn new() -> Self {
+        let hcr = InMemoryRegister::<u64, HCR_EL2::Register>::new(0);
+        hcr.write(HCR_EL2::RW::EL1IsAarch64);
+
+        // Exceptions stay masked, they are unmasked by the kernel once it is ready for them
+        let spsr = InMemoryRegister::<u64, SPSR_EL2::Register>::new(0);
+        spsr.write(
+            SPSR_EL2::D::Masked
+                + SPSR_EL2::A::Masked
+                + SPSR_EL2::I::Masked
+                + SPSR_EL2::F::Masked
+                + SPSR_EL2::M::EL1h,
+        );
+
+        // Do not trap timer accesses to EL2.
+        let cnthctl = InMemoryRegister::<u64, CNTHCTL_EL2::Register>::new(0);
+        cnthctl.write(CNTHCTL_EL2::EL1PCTEN::SET + CNTHCTL_EL2::EL1PCEN::SET);
+
+        Self {
+            hcr_el2: hcr.get(),
+            spsr_el2: spsr.get(),
+            cnthctl_el2: cnthctl.get(),
+            cntvoff_el2: 0,
+        }
+    }
+}
+
+impl Default for El1TransitionConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Drops from EL2 to EL1 and returns to the caller, now running at EL1h on the same stack. It does
+/// nothing if the kernel already runs at EL1.
+///
+/// The register state established is the one of `El1TransitionConfig`:
+///   * `HCR_EL2`: EL1 is AArch64, nothing else is trapped to EL2.
+///   * `SPSR_EL2`: EL1h with debug, SError, IRQ and FIQ masked.
+///   * `CNTHCTL_EL2`: EL1 can access the physical counter and timer.
+///   * `CNTVOFF_EL2`: 0, so the virtual and physical counters match.
+///
+/// The EL1 translation registers are left untouched. `arch::mmu` only ever programs the EL1
+/// registers, so EL1 uses the translation tables built there no matter whether they were set up
+/// before or after the transition.
+pub fn drop_to_el1() {
+    match get_exception_level() {
+        ExceptionLevel::OS => {}
+        ExceptionLevel::Hypervisor => {
+            let config = El1TransitionConfig::new();
+            HCR_EL2.set(config.hcr_el2);
+            SPSR_EL2.set(config.spsr_el2);
+            CNTHCTL_EL2.set(config.cnthctl_el2);
+            CNTVOFF_EL2.set(config.cntvoff_el2);
+
+            #[cfg(target_arch = "aarch64")]
+            unsafe {
+                core::arch::asm!(
+                    "adr {tmp}, 2f",
+                    "msr ELR_EL2, {tmp}",
+                    "mov {tmp}, sp",
+                    "msr SP_EL1, {tmp}",
+                    "eret",
+                    "2:",
+                    tmp = out(reg) _,
+                );
+            }
+        }
+        level => panic!("Cannot drop to EL1 from {:?}", level),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn el1_transition_config() {
+        let config = El1TransitionConfig::new();
+        // Only RW (bit 31) is set
+        assert_eq!(config.hcr_el2, 1 << 31);
+        // DAIF masked and M = EL1h
+        assert_eq!(config.spsr_el2, 0x3c5);
+        assert_eq!(config.cnthctl_el2, 0b11);
+        assert_eq!(config.cntvoff_el2, 0);
+    }
+
+    #[test]
+    fn exception_level_check() {
+        assert_eq!(
+            check_exception_level(ExceptionLevel::OS, ExceptionLevel::OS),
+            Ok(())
+        );
+
+        let mismatch =
+            check_exception_level(ExceptionLevel::OS, ExceptionLevel::Hypervisor).unwrap_err();
+        assert_eq!(
+            mismatch,
+            ExceptionLevelMismatch {
+                expected: ExceptionLevel::OS,
+                current: ExceptionLevel::Hypervisor,
+            }
+        );
+        assert_eq!(
+            mismatch.to_string(),
+            "expected to run at EL1 (OS), but running at EL2 (Hypervisor)"
+        );
+    }
+}
@@ -1,43 +1,142 @@
+//! Data cache maintenance by virtual address range.
+//!
+//! Devices doing DMA do not snoop the CPU caches. Buffers read by a device must be cleaned before
+//! handing them over, and buffers written by a device must be invalidated before the CPU reads
+//! them. Code written as data must also reach the instruction cache before it runs. On the host
+//! these operations do nothing.
+
 use crate::memory::address::{Address, VirtualAddress};
 
-use aarch64_cpu::asm::barrier::{dmb, SY};
+#[cfg(all(not(test), target_arch = "aarch64"))]
+use aarch64_cpu::asm::barrier;
+
+/// Returns the size in bytes of the smallest data cache line, given the value of `CTR_EL0`.
+fn dcache_line_size_from_ctr(ctr_el0: u64) -> usize {
+    // DminLine is the log2 of the number of 4-byte words in the line
+    const DMIN_LINE_OFFSET: u64 = 16;
+    const DMIN_LINE_MASK: u64 = 0xF;
+    4 << ((ctr_el0 >> DMIN_LINE_OFFSET) & DMIN_LINE_MASK)
+}
 
-const CACHE_LINE_SIZE: usize = 64;
+#[cfg(all(not(test), target_arch = "aarch64"))]
+fn read_ctr_el0() -> u64 {
+    let ctr_el0: u64;
+    unsafe { core::arch::asm!("mrs {}, ctr_el0", out(reg) ctr_el0) };
+    ctr_el0
+}
 
-pub fn invalidate_va_range(mut va: VirtualAddress, size_bytes: usize) {
-    let mut num_lines = (size_bytes + CACHE_LINE_SIZE - 1) / CACHE_LINE_SIZE;
-    let aligned_va = va.floor_to_alignment(CACHE_LINE_SIZE);
-    if va != aligned_va {
-        num_lines += 1;
+#[cfg(not(all(not(test), target_arch = "aarch64")))]
+fn read_ctr_el0() -> u64 {
+    // 64 byte lines, like the M1
+    4 << 16
+}
+
+pub fn dcache_line_size() -> usize {
+    dcache_line_size_from_ctr(read_ctr_el0())
+}
+
+/// Returns the address of every cache line that overlaps the range.
+fn cache_lines(
+    va: VirtualAddress,
+    size_bytes: usize,
+    line_size: usize,
+) -> impl Iterator<Item = usize> {
+    let start = va.as_usize() & !(line_size - 1);
+    let end = va.as_usize() + size_bytes;
+    (start..end).step_by(line_size)
+}
+
+fn dcache_range_op(va: VirtualAddress, size_bytes: usize, op: impl Fn(usize)) {
+    for line in cache_lines(va, size_bytes, dcache_line_size()) {
+        op(line);
     }
 
-    for i in 0..num_lines {
+    // Ensures the maintenance operations complete before any subsequent memory access
+    #[cfg(all(not(test), target_arch = "aarch64"))]
+    barrier::dsb(barrier::SY);
+}
+
+/// Writes dirty lines in the range back to memory, so that devices observe the latest data.
+pub fn clean_dcache_range(va: VirtualAddress, size_bytes: usize) {
+    dcache_range_op(va, size_bytes, |_line| {
+        #[cfg(all(not(test), target_arch = "aarch64"))]
         unsafe {
-            let _va = va.offset(i * CACHE_LINE_SIZE).as_usize();
-            #[cfg(target_arch = "aarch64")]
-            core::arch::asm!("dc ivac, {}", in(reg) _va);
-        }
-    }
+            core::arch::asm!("dc cvac, {}", in(reg) _line)
+        };
+    });
+}
 
-    // Add barrier operation to ensure the data cache clean completes before the next instructions
-    dmb(SY);
+/// Discards the lines in the range, so that the next CPU access reads the data written by a
+/// device. Dirty data in the lines is lost, including data outside of the range that shares its
+/// first or last line.
+pub fn invalidate_dcache_range(va: VirtualAddress, size_bytes: usize) {
+    dcache_range_op(va, size_bytes, |_line| {
+        #[cfg(all(not(test), target_arch = "aarch64"))]
+        unsafe {
+            core::arch::asm!("dc ivac, {}", in(reg) _line)
+        };
+    });
 }
 
-pub fn clean_va_range(mut va: VirtualAddress, size_bytes: usize) {
-    let mut num_lines = (size_bytes + CACHE_LINE_SIZE - 1) / CACHE_LINE_SIZE;
-    let aligned_va = va.floor_to_alignment(CACHE_LINE_SIZE);
-    if va != aligned_va {
-        num_lines += 1;
-    }
+/// Writes dirty lines in the range back to memory and then discards them.
+pub fn clean_invalidate_dcache_range(va: VirtualAddress, size_bytes: usize) {
+    dcache_range_op(va, size_bytes, |_line| {
+        #[cfg(all(not(test), target_arch = "aarch64"))]
+        unsafe {
+            core::arch::asm!("dc civac, {}", in(reg) _line)
+        };
+    });
+}
 
-    for i in 0..num_lines {
+/// Makes instructions written to the range visible to instruction fetches. Needed before executing
+/// code that was written as data, like the code generated by a JIT.
+pub fn sync_icache_range(va: VirtualAddress, size_bytes: usize) {
+    dcache_range_op(va, size_bytes, |_line| {
+        #[cfg(all(not(test), target_arch = "aarch64"))]
         unsafe {
-            let _va = va.offset(i * CACHE_LINE_SIZE).as_usize();
-            #[cfg(target_arch = "aarch64")]
-            core::arch::asm!("dc cvau, {}", in(reg) _va);
-        }
+            core::arch::asm!("dc cvau, {}", in(reg) _line)
+        };
+    });
+
+    // Invalidating the whole instruction cache avoids decoding its line size as well
+    #[cfg(all(not(test), target_arch = "aarch64"))]
+    unsafe {
+        core::arch::asm!("ic ialluis");
+        barrier::dsb(barrier::ISH);
+        barrier::isb(barrier::SY);
+    };
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::{vec, vec::Vec};
+
+    #[test]
+    fn line_size_decode() {
+        // CTR_EL0 as reported by the M1
+        assert_eq!(dcache_line_size_from_ctr(0x8444_c004), 64);
+        assert_eq!(dcache_line_size_from_ctr(0x0003_0000), 32);
+        assert_eq!(dcache_line_size_from_ctr(0), 4);
+        assert_eq!(dcache_line_size(), 64);
     }
 
-    // Add barrier operation to ensure the data cache clean completes before the next instructions
-    dmb(SY);
+    #[test]
+    fn lines_in_range() {
+        let lines = |va: usize, size_bytes| -> Vec<usize> {
+            cache_lines(
+                VirtualAddress::new_unaligned(va as *const _),
+                size_bytes,
+                64,
+            )
+            .collect()
+        };
+
+        assert_eq!(lines(0x1000, 128), vec![0x1000, 0x1040]);
+        assert_eq!(lines(0x1010, 64), vec![0x1000, 0x1040]);
+        assert_eq!(lines(0x103F, 2), vec![0x1000, 0x1040]);
+        assert_eq!(lines(0x1040, 1), vec![0x1040]);
+        assert!(lines(0x1040, 0).is_empty());
+    }
 }
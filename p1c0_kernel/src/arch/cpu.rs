@@ -0,0 +1,185 @@
+use crate::registers::{
+    ID_AA64ISAR1_EL1, ID_AA64MMFR0_EL1, ID_AA64MMFR1_EL1, ID_AA64MMFR2_EL1, ID_AA64PFR0_EL1,
+};
+
+use tock_registers::{interfaces::Readable, LocalRegisterCopy};
+
+static mut CPU_FEATURES: Option<CpuFeatures> = None;
+
+/// Raw values of the ID registers used to decode the CPU features.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct IdRegisters {
+    pub pfr0: u64,
+    pub isar1: u64,
+    pub mmfr0: u64,
+    pub mmfr1: u64,
+    pub mmfr2: u64,
+}
+
+impl IdRegisters {
+    /// Reads the ID registers of the current CPU. On the host all registers read as 0.
+    pub fn read() -> Self {
+        Self {
+            pfr0: ID_AA64PFR0_EL1.get(),
+            isar1: ID_AA64ISAR1_EL1.get(),
+            mmfr0: ID_AA64MMFR0_EL1.get(),
+            mmfr1: ID_AA64MMFR1_EL1.get(),
+            mmfr2: ID_AA64MMFR2_EL1.get(),
+        }
+    }
+}
+
+/// Architectural capabilities of the CPU that the kernel cares about.
+///
+/// Note that the contiguous bit of translation descriptors is part of ARMv8.0, so there is no
+/// feature for it. Whether the hint can be used only depends on the translation granule.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CpuFeatures {
+    /// Number of physical address bits supported by the implementation.
+    pub pa_bits: u8,
+    pub granule_4k: bool,
+    pub granule_16k: bool,
+    pub granule_64k: bool,
+    /// Hardware update of the access flag.
+    pub hw_access_flag: bool,
+    /// Hardware update of the dirty state (DBM).
+    pub hw_dirty_state: bool,
+    pub vhe: bool,
+    pub pan: bool,
+    pub uao: bool,
+    pub common_not_private: bool,
+    pub pointer_auth: bool,
+    pub el2: bool,
+    pub el3: bool,
+}
+
+impl CpuFeatures {
+    pub fn decode(regs: &IdRegisters) -> Self {
+        let pfr0 = LocalRegisterCopy::<u64, ID_AA64PFR0_EL1::Register>::new(regs.pfr0);
+        let isar1 = LocalRegisterCopy::<u64, ID_AA64ISAR1_EL1::Register>::new(regs.isar1);
+        let mmfr0 = LocalRegisterCopy::<u64, ID_AA64MMFR0_EL1::Register>::new(regs.mmfr0);
+        let mmfr1 = LocalRegisterCopy::<u64, ID_AA64MMFR1_EL1::Register>::new(regs.mmfr1);
+        let mmfr2 = LocalRegisterCopy::<u64, ID_AA64MMFR2_EL1::Register>::new(regs.mmfr2);
+
+        let pa_bits = match mmfr0.read(ID_AA64MMFR0_EL1::PARANGE) {
+            0b0000 => 32,
+            0b0001 => 36,
+            0b0010 => 40,
+            0b0011 => 42,
+            0b0100 => 44,
+            0b0101 => 48,
+            _ => 52,
+        };
+
+        // Note that the encodings of TGran4 and TGran64 differ from TGran16. For the former 0b0000
+        // means supported and 0b1111 not supported. For the latter 0b0000 means not supported.
+        let hafdbs = mmfr1.read(ID_AA64MMFR1_EL1::HAFDBS);
+        Self {
+            pa_bits,
+            granule_4k: mmfr0.read(ID_AA64MMFR0_EL1::TGRAN4) != 0b1111,
+            granule_16k: mmfr0.read(ID_AA64MMFR0_EL1::TGRAN16) != 0b0000,
+            granule_64k: mmfr0.read(ID_AA64MMFR0_EL1::TGRAN64) != 0b1111,
+            hw_access_flag: hafdbs >= 0b0001,
+            hw_dirty_state: hafdbs >= 0b0010,
+            vhe: mmfr1.read(ID_AA64MMFR1_EL1::VH) != 0,
+            pan: mmfr1.read(ID_AA64MMFR1_EL1::PAN) != 0,
+            uao: mmfr2.read(ID_AA64MMFR2_EL1::UAO) != 0,
+            common_not_private: mmfr2.read(ID_AA64MMFR2_EL1::CNP) != 0,
+            pointer_auth: isar1.read(ID_AA64ISAR1_EL1::APA) != 0
+                || isar1.read(ID_AA64ISAR1_EL1::API) != 0
+                || isar1.read(ID_AA64ISAR1_EL1::GPA) != 0
+                || isar1.read(ID_AA64ISAR1_EL1::GPI) != 0,
+            el2: pfr0.read(ID_AA64PFR0_EL1::EL2) != 0,
+            el3: pfr0.read(ID_AA64PFR0_EL1::EL3) != 0,
+        }
+    }
+}
+
+/// Reads the CPU features and caches them for later use through `features()`.
+///
+/// # Safety
+///   Must be called during boot in a single-threaded context, before anyone calls `features()`.
+pub unsafe fn init() {
+    CPU_FEATURES = Some(CpuFeatures::decode(&IdRegisters::read()));
+}
+
+pub fn features() -> CpuFeatures {
+    // This is only written once during boot, afterwards it is read-only.
+    unsafe { CPU_FEATURES }.expect("CPU features have not been initialized")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Synthetic values describing a CPU similar to the M1: 42-bit PA, 16KB granule, VHE, PAN, UAO
+    // and EL2, but no EL3 or pointer authentication.
+    const M1_REGS: IdRegisters = IdRegisters {
+        pfr0: 0x0000_0000_0000_0111,
+        isar1: 0x0000_0000_0000_0001,
+        mmfr0: 0x0000_0000_1f10_0003,
+        mmfr1: 0x0000_0000_0020_0100,
+        mmfr2: 0x0000_0000_0000_0011,
+    };
+
+    #[test]
+    fn decode_zeroed_registers() {
+        let features = CpuFeatures::decode(&IdRegisters::default());
+        assert_eq!(features.pa_bits, 32);
+        assert!(features.granule_4k);
+        assert!(!features.granule_16k);
+        assert!(features.granule_64k);
+        assert!(!features.hw_access_flag);
+        assert!(!features.hw_dirty_state);
+        assert!(!features.vhe);
+        assert!(!features.pan);
+        assert!(!features.uao);
+        assert!(!features.common_not_private);
+        assert!(!features.pointer_auth);
+        assert!(!features.el2);
+        assert!(!features.el3);
+    }
+
+    #[test]
+    fn decode_m1_registers() {
+        let features = CpuFeatures::decode(&M1_REGS);
+        assert_eq!(features.pa_bits, 42);
+        assert!(features.granule_4k);
+        assert!(features.granule_16k);
+        assert!(!features.granule_64k);
+        assert!(!features.hw_access_flag);
+        assert!(!features.hw_dirty_state);
+        assert!(features.vhe);
+        assert!(features.pan);
+        assert!(features.uao);
+        assert!(features.common_not_private);
+        assert!(!features.pointer_auth);
+        assert!(features.el2);
+        assert!(!features.el3);
+    }
+
+    #[test]
+    fn decode_hardware_access_and_dirty_flags() {
+        let mut regs = IdRegisters {
+            mmfr1: 0x1,
+            ..IdRegisters::default()
+        };
+        let features = CpuFeatures::decode(&regs);
+        assert!(features.hw_access_flag);
+        assert!(!features.hw_dirty_state);
+
+        regs.mmfr1 = 0x2;
+        let features = CpuFeatures::decode(&regs);
+        assert!(features.hw_access_flag);
+        assert!(features.hw_dirty_state);
+    }
+
+    #[test]
+    fn decode_pointer_auth() {
+        let regs = IdRegisters {
+            isar1: 0x1 << 8,
+            ..IdRegisters::default()
+        };
+        assert!(CpuFeatures::decode(&regs).pointer_auth);
+    }
+}
@@ -1,5 +1,5 @@
 use crate::{
-    arch::StackType,
+    arch::{with_user_access, ExceptionLevel, StackType},
     backtrace,
     drivers::{generic_timer, interfaces::interrupt_controller, interfaces::timer::Timer},
     memory::address::VirtualAddress,
@@ -39,6 +39,13 @@ impl SpsrEL1 {
         self.0.set(value);
     }
 
+    fn returns_to_el0(&self) -> bool {
+        matches!(
+            self.0.read_as_enum(SPSR_EL1::M),
+            Some(SPSR_EL1::M::Value::EL0t)
+        )
+    }
+
     fn stack_type(&self) -> StackType {
         match self.0.read_as_enum(SPSR_EL1::M).unwrap() {
             SPSR_EL1::M::Value::EL1t | SPSR_EL1::M::Value::EL0t => StackType::ProcessStack,
@@ -99,10 +106,20 @@ fn default_exception_handler(exc: &ExceptionContext) {
     );
 }
 
+/// Signals are only delivered on the way back to userspace, after any context switch.
+fn deliver_pending_signals(e: &mut ExceptionContext) {
+    if e.spsr_el1.returns_to_el0() {
+        process::deliver_pending_signals(e);
+    }
+}
+
 fn handle_fiq(e: &mut ExceptionContext) {
     let timer = generic_timer::get_timer();
 
     if timer.is_irq_active() {
+        #[cfg(feature = "benchmark")]
+        crate::benchmark::on_timer_irq();
+
         timer.handle_irq();
 
         // Run scheduler and maybe do context switch
@@ -122,6 +139,7 @@ fn handle_fiq(e: &mut ExceptionContext) {
         // PMCR0 is trapped by the HV, so this causes m1n1 HV to check again and synchronously
         // disable the Virtual FIQ.
         crate::registers::SYS_IMPL_APL_PMCR0.get();
+        deliver_pending_signals(e);
         return;
     }
 
@@ -136,11 +154,55 @@ enum ExceptionOrigin {
     LowerAarch64EL,
 }
 
+/// Decoded fault status code of instruction and data aborts. Holds the translation level at which
+/// the fault happened or the raw status code for faults we don't handle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FaultStatus {
+    Translation(u8),
+    AccessFlag(u8),
+    Permission(u8),
+    Other(u8),
+}
+
+impl FaultStatus {
+    fn from_iss(iss: u32) -> Self {
+        let fsc = (iss & 0x3f) as u8;
+        let level = fsc & 0b11;
+        match fsc >> 2 {
+            0b0001 => FaultStatus::Translation(level),
+            0b0010 => FaultStatus::AccessFlag(level),
+            0b0011 => FaultStatus::Permission(level),
+            _ => FaultStatus::Other(fsc),
+        }
+    }
+}
+
+/// Tries to resolve an abort coming from EL0. Returns true if the fault was handled and the
+/// faulting instruction can be retried.
+fn handle_lower_el_abort(e: &ExceptionContext) -> bool {
+    const WNR: u32 = 1 << 6;
+
+    let va = VirtualAddress::new_unaligned(FAR_EL1.get() as *const _);
+    let iss = e.esr_el1.instruction_specific_syndrome();
+    let is_write = matches!(
+        e.exception_class(),
+        Some(ESR_EL1::EC::Value::DataAbortLowerEL)
+    ) && (iss & WNR) != 0;
+
+    match FaultStatus::from_iss(iss) {
+        FaultStatus::AccessFlag(_) => process::handle_access_flag_fault(va),
+        FaultStatus::Permission(_) if is_write => process::handle_write_fault(va),
+        _ => false,
+    }
+}
+
 unsafe fn handle_synchronous(e: &mut ExceptionContext, origin: ExceptionOrigin) {
     match e.esr_el1.exception_class() {
         Some(ESR_EL1::EC::Value::SVC64) => {
             syscall_handler(e.esr_el1.instruction_specific_syndrome(), e);
         }
+        Some(ESR_EL1::EC::Value::DataAbortLowerEL | ESR_EL1::EC::Value::InstrAbortLowerEL)
+            if handle_lower_el_abort(e) => {}
         _ => {
             match origin {
                 ExceptionOrigin::SameELStackFromEL0 => {
@@ -169,6 +231,8 @@ unsafe fn handle_synchronous(e: &mut ExceptionContext, origin: ExceptionOrigin)
             }
         }
     }
+
+    deliver_pending_signals(e);
 }
 
 #[no_mangle]
@@ -414,7 +478,8 @@ impl fmt::Display for ExceptionContext {
                         validator.clone(),
                         Some(symbolicator),
                     );
-                    write!(f, "{}", backtracer).unwrap();
+                    // The frames of a process live in user memory
+                    with_user_access(|| write!(f, "{}", backtracer)).unwrap();
                 });
             } else if let Some(symbolicator) = backtrace::ksyms::symbolicator() {
                 let backtracer = backtrace::backtracer(
@@ -439,55 +504,241 @@ impl fmt::Display for ExceptionContext {
     }
 }
 
+/// The exception frame as it is stored on the stack by the EL2 exception vectors.
+#[repr(C)]
+#[derive(Default)]
+pub struct El2ExceptionFrame {
+    pub gpr: [u64; 31],
+    pub elr_el2: u64,
+    pub spsr_el2: u64,
+    pub esr_el2: u64,
+    pub far_el2: u64,
+}
+
+/// Returns the name of the exception vector with the given index in the vector table.
+fn vector_name(index: usize) -> &'static str {
+    match index {
+        0 => "current_el0_synchronous",
+        1 => "current_el0_irq",
+        2 => "current_el0_fiq",
+        3 => "current_el0_serror",
+        4 => "current_elx_synchronous",
+        5 => "current_elx_irq",
+        6 => "current_elx_fiq",
+        7 => "current_elx_serror",
+        8 => "lower_el_aarch64_synchronous",
+        9 => "lower_el_aarch64_irq",
+        10 => "lower_el_aarch64_fiq",
+        11 => "lower_el_aarch64_serror",
+        12 => "lower_el_aarch32_synchronous",
+        13 => "lower_el_aarch32_irq",
+        14 => "lower_el_aarch32_fiq",
+        15 => "lower_el_aarch32_serror",
+        _ => "unknown",
+    }
+}
+
+fn put_str(putc: &mut impl FnMut(u8), s: &str) {
+    s.bytes().for_each(putc);
+}
+
+fn put_hex(putc: &mut impl FnMut(u8), value: u64) {
+    put_str(putc, "0x");
+    for shift in (0..16).rev().map(|digit| digit * 4) {
+        let nibble = ((value >> shift) & 0xF) as u8;
+        putc(if nibble < 10 {
+            b'0' + nibble
+        } else {
+            b'A' + nibble - 10
+        });
+    }
+}
+
+fn put_dec(putc: &mut impl FnMut(u8), value: usize) {
+    if value >= 10 {
+        put_dec(putc, value / 10);
+    }
+    putc(b'0' + (value % 10) as u8);
+}
+
+/// Writes the EL2 exception frame one character at a time. `core::fmt` is not used on purpose:
+/// the output must not depend on anything but the stack, since EL2 exceptions may be taken before
+/// the kernel is relocated or any logger is available.
+#[cfg_attr(
+    any(test, not(all(target_os = "none", target_arch = "aarch64"))),
+    allow(dead_code)
+)]
+fn dump_el2_frame(frame: &El2ExceptionFrame, vector: usize, mut putc: impl FnMut(u8)) {
+    put_str(&mut putc, "\n==== EL2 exception: ");
+    put_str(&mut putc, vector_name(vector));
+    put_str(&mut putc, " ====\n");
+
+    let registers = [
+        ("ELR_EL2: ", frame.elr_el2),
+        ("ESR_EL2: ", frame.esr_el2),
+        ("FAR_EL2: ", frame.far_el2),
+        ("SPSR_EL2: ", frame.spsr_el2),
+    ];
+    for (name, value) in registers {
+        put_str(&mut putc, name);
+        put_hex(&mut putc, value);
+        putc(b'\n');
+    }
+
+    for (i, reg) in frame.gpr.iter().enumerate() {
+        putc(b'x');
+        put_dec(&mut putc, i);
+        put_str(&mut putc, ": ");
+        put_hex(&mut putc, *reg);
+        putc(b'\n');
+    }
+}
+
+#[cfg(all(target_os = "none", target_arch = "aarch64", not(test)))]
 extern "C" {
-    pub static __exception_vector_start: u8;
-    pub static __el2_exception_vector_start: u8;
+    // Provided by the startup code. They access the hardware through physical addresses and do not
+    // use any global state.
+    fn _uart_putc(c: u32);
+    fn _wdt_reboot() -> !;
 }
 
-/// Init exception handling by setting the exception vector base address register.
-pub fn handling_init() {
-    #[cfg(target_os = "none")]
-    let vectors = unsafe { &__exception_vector_start as *const _ };
-
-    #[cfg(not(target_os = "none"))]
-    let vectors = 0;
-
-    VBAR_EL1.set(vectors as u64);
-    // Force VBAR update to complete before next instruction.
-    barrier::isb(barrier::SY);
-
-    if matches!(
-        CurrentEL.read_as_enum(CurrentEL::EL),
-        Some(CurrentEL::EL::Value::EL2)
-    ) {
-        HCR_EL2.write(
-            HCR_EL2::RW::EL1IsAarch64, // These settings would make EL2 work just like an OS and also trap any exceptions
-                                       // from EL1 to EL2. EL1 cannot be used with them.
-                                       //
-                                       // + HCR_EL2::API::NoTrapPointerAuthInstToEl2
-                                       // + HCR_EL2::APK::NoTrapPointerAuthKeyRegsToEl2
-                                       // + HCR_EL2::TEA::RouteSyncExtAborts
-                                       // + HCR_EL2::E2H::EnableOsAtEl2
-                                       // + HCR_EL2::TGE::TrapGeneralExceptions
-                                       // + HCR_EL2::AMO::SET
-                                       // + HCR_EL2::IMO::SET
-                                       // + HCR_EL2::FMO::SET,
+/// Called by the EL2 exception vectors. Prints the exception frame and reboots, since EL2 is only
+/// used during early boot and there is nothing to recover.
+#[cfg(all(target_os = "none", target_arch = "aarch64", not(test)))]
+#[no_mangle]
+unsafe extern "C" fn el2_exception_handler(frame: &El2ExceptionFrame, vector: usize) -> ! {
+    dump_el2_frame(frame, vector, |c| _uart_putc(c as u32));
+    _wdt_reboot()
+}
+
+/// Returns the address of the EL2 exception vectors relative to the PC. This is where the vectors
+/// actually are even if the kernel runs from an address other than the one it was linked at.
+#[cfg(all(target_os = "none", target_arch = "aarch64"))]
+fn el2_vector_base() -> u64 {
+    let base: u64;
+    unsafe {
+        core::arch::asm!(
+            "adrp {0}, __el2_exception_vector_start",
+            "add {0}, {0}, :lo12:__el2_exception_vector_start",
+            out(reg) base
         );
+    }
+    base
+}
 
-        // Force HCR update to complete before next instruction.
-        barrier::isb(barrier::SY);
+extern "C" {
+    pub static __exception_vector_start: u8;
+}
+
+/// `HCR_EL2` value programmed by `handling_init` at EL2.
+fn el2_hcr() -> u64 {
+    let hcr = InMemoryRegister::<u64, HCR_EL2::Register>::new(0);
+    hcr.write(
+        HCR_EL2::RW::EL1IsAarch64, // These settings would make EL2 work just like an OS and also trap any exceptions
+                                   // from EL1 to EL2. EL1 cannot be used with them.
+                                   //
+                                   // + HCR_EL2::API::NoTrapPointerAuthInstToEl2
+                                   // + HCR_EL2::APK::NoTrapPointerAuthKeyRegsToEl2
+                                   // + HCR_EL2::TEA::RouteSyncExtAborts
+                                   // + HCR_EL2::E2H::EnableOsAtEl2
+                                   // + HCR_EL2::TGE::TrapGeneralExceptions
+                                   // + HCR_EL2::AMO::SET
+                                   // + HCR_EL2::IMO::SET
+                                   // + HCR_EL2::FMO::SET,
+    );
+    hcr.get()
+}
 
-        #[cfg(target_os = "none")]
-        let vectors = unsafe { &__el2_exception_vector_start as *const _ };
+/// Returns true if `VBAR_EL1` accesses reach the EL1 register. At EL2 with `HCR_EL2.E2H` set they
+/// are redirected to `VBAR_EL2`.
+fn vbar_el1_is_el1(level: ExceptionLevel, hcr_el2: u64) -> bool {
+    let hcr = InMemoryRegister::<u64, HCR_EL2::Register>::new(hcr_el2);
+    !(matches!(level, ExceptionLevel::Hypervisor) && hcr.is_set(HCR_EL2::E2H))
+}
 
-        #[cfg(not(target_os = "none"))]
-        let vectors = 0;
+/// The exception vector tables of the kernel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum VectorTable {
+    /// Handles exceptions taken to EL1, where the kernel runs
+    El1,
+    /// Handles exceptions taken to EL2 during early boot by dumping the frame and rebooting
+    El2,
+}
 
-        VBAR_EL2.set(vectors as u64);
+impl VectorTable {
+    /// Tables to install when running at the given exception level. At EL2 the EL1 table is
+    /// installed as well, since the kernel transitions to EL1 afterwards.
+    fn tables_for(level: ExceptionLevel) -> &'static [VectorTable] {
+        match level {
+            ExceptionLevel::OS => &[VectorTable::El1],
+            ExceptionLevel::Hypervisor => &[VectorTable::El1, VectorTable::El2],
+            ExceptionLevel::Application | ExceptionLevel::SecureMonitor => &[],
+        }
+    }
 
-        // Force VBAR update to complete before next instruction.
+    #[cfg(all(target_os = "none", target_arch = "aarch64"))]
+    fn base(&self) -> u64 {
+        match self {
+            VectorTable::El1 => unsafe { &__exception_vector_start as *const u8 as u64 },
+            // The EL2 MMU is off, so the table must be addressed with the address the kernel runs
+            // from
+            VectorTable::El2 => el2_vector_base(),
+        }
+    }
+
+    #[cfg(not(all(target_os = "none", target_arch = "aarch64")))]
+    fn base(&self) -> u64 {
+        0
+    }
+
+    /// Programs the VBAR of the exception level of the table and checks that it holds the address
+    /// of the table afterwards. `hcr_el2` is the value of `HCR_EL2` when running at EL2.
+    fn install(&self, level: ExceptionLevel, hcr_el2: u64) {
+        let base = self.base();
+        let vbar = match self {
+            VectorTable::El1 => {
+                assert!(
+                    vbar_el1_is_el1(level, hcr_el2),
+                    "VBAR_EL1 is redirected to VBAR_EL2"
+                );
+                VBAR_EL1.set(base);
+                // Force VBAR update to complete before next instruction.
+                barrier::isb(barrier::SY);
+                VBAR_EL1.get()
+            }
+            VectorTable::El2 => {
+                VBAR_EL2.set(base);
+                // Force VBAR update to complete before next instruction.
+                barrier::isb(barrier::SY);
+                VBAR_EL2.get()
+            }
+        };
+
+        if vbar != base {
+            panic!(
+                "VBAR of the {:?} vector table is {:#x} instead of {:#x}",
+                self, vbar, base
+            );
+        }
+    }
+}
+
+/// Init exception handling by setting the exception vector base address registers of the current
+/// exception level.
+pub fn handling_init() {
+    let level = crate::arch::get_exception_level();
+    let hcr_el2 = el2_hcr();
+
+    if matches!(level, ExceptionLevel::Hypervisor) {
+        HCR_EL2.set(hcr_el2);
+
+        // Force HCR update to complete before next instruction.
         barrier::isb(barrier::SY);
     }
+
+    for table in VectorTable::tables_for(level) {
+        table.install(level, hcr_el2);
+    }
 }
 
 /// This simulates a return from an exception with the given context. It can be used whenever
@@ -526,3 +777,77 @@ pub fn return_from_exception(_cx: ExceptionContext) -> ! {
     }
     unreachable!();
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn decode_fault_status() {
+        // Access flag fault, level 3 with WnR set
+        assert_eq!(FaultStatus::from_iss(0x4b), FaultStatus::AccessFlag(3));
+        assert_eq!(FaultStatus::from_iss(0x07), FaultStatus::Translation(3));
+        assert_eq!(FaultStatus::from_iss(0x0d), FaultStatus::Permission(1));
+        // Alignment fault
+        assert_eq!(FaultStatus::from_iss(0x21), FaultStatus::Other(0x21));
+    }
+
+    #[test]
+    fn vector_tables_per_exception_level() {
+        assert_eq!(
+            VectorTable::tables_for(ExceptionLevel::OS),
+            &[VectorTable::El1]
+        );
+        assert_eq!(
+            VectorTable::tables_for(ExceptionLevel::Hypervisor),
+            &[VectorTable::El1, VectorTable::El2]
+        );
+        assert!(VectorTable::tables_for(ExceptionLevel::Application).is_empty());
+    }
+
+    #[test]
+    fn el1_vector_base_register() {
+        const E2H: u64 = 1 << 34;
+
+        assert!(vbar_el1_is_el1(ExceptionLevel::OS, 0));
+        assert!(vbar_el1_is_el1(ExceptionLevel::OS, E2H));
+        assert!(!vbar_el1_is_el1(ExceptionLevel::Hypervisor, E2H));
+
+        // The EL1 table can be installed at EL2 with the HCR_EL2 value of `handling_init`
+        assert_eq!(el2_hcr() & E2H, 0);
+        assert!(vbar_el1_is_el1(ExceptionLevel::Hypervisor, el2_hcr()));
+    }
+
+    #[test]
+    fn dump_el2_exception_frame() {
+        let mut frame = El2ExceptionFrame {
+            elr_el2: 0x8_0380_4abc,
+            esr_el2: 0x9600_0045,
+            far_el2: 0xdead_beef,
+            spsr_el2: 0x3c9,
+            ..Default::default()
+        };
+        frame.gpr[0] = 1;
+        frame.gpr[30] = 0xffff_fe00_0000_1234;
+
+        let mut output = vec![];
+        dump_el2_frame(&frame, 4, |c| output.push(c));
+        let output = String::from_utf8(output).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+
+        assert_eq!(lines[0], "");
+        assert_eq!(lines[1], "==== EL2 exception: current_elx_synchronous ====");
+        assert_eq!(lines[2], "ELR_EL2: 0x0000000803804ABC");
+        assert_eq!(lines[3], "ESR_EL2: 0x0000000096000045");
+        assert_eq!(lines[4], "FAR_EL2: 0x00000000DEADBEEF");
+        assert_eq!(lines[5], "SPSR_EL2: 0x00000000000003C9");
+        assert_eq!(lines[6], "x0: 0x0000000000000001");
+        assert_eq!(lines[16], "x10: 0x0000000000000000");
+        assert_eq!(lines[36], "x30: 0xFFFFFE0000001234");
+        assert_eq!(lines.len(), 37);
+
+        let mut output = vec![];
+        dump_el2_frame(&frame, 16, |c| output.push(c));
+        assert!(output.starts_with(b"\n==== EL2 exception: unknown ===="));
+    }
+}
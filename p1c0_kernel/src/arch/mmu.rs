@@ -1,11 +1,13 @@
 mod early_alloc;
 
 use crate::{
+    arch::cpu,
     memory::{
         address::{Address, LogicalAddress, PhysicalAddress, VirtualAddress},
         Attributes, GlobalPermissions, Permissions,
     },
     prelude::*,
+    sync::once::Once,
 };
 use early_alloc::{AllocRef, EarlyAllocator};
 
@@ -25,16 +27,19 @@ pub const PAGE_SIZE: usize = 1 << PAGE_BITS;
 const PXN: u64 = 1 << 53;
 const UXN: u64 = 1 << 54;
 
-const EARLY_ALLOCATOR_SIZE: usize = 128 * 1024;
+/// Memory for the translation tables created before the global allocator is available. Every
+/// table takes a page, so this bounds the number of tables of the early mappings.
+pub const EARLY_ALLOCATOR_SIZE: usize = 128 * 1024;
 static EARLY_ALLOCATOR: EarlyAllocator<EARLY_ALLOCATOR_SIZE> = EarlyAllocator::new();
 
-static mut MMU_INITIALIZED: bool = false;
+static MMU_INITIALIZED: Once<()> = Once::new();
 
 #[derive(Debug, Clone)]
 pub enum Error {
     OverlapsExistingMapping(VirtualAddress, TranslationLevel),
     UnalignedAddress,
     InvalidPermissions,
+    UnmappedAddress(VirtualAddress),
 }
 
 const MAIR_ATTR_OFFSET: usize = 2;
@@ -86,6 +91,11 @@ fn permission_bits(permissions: GlobalPermissions) -> Result<u64, Error> {
     Ok(permission_ap_bits(permissions)? | permission_nx_bits(permissions)?)
 }
 
+/// Checks that the permissions can be described by a descriptor, without mapping anything.
+pub fn validate_permissions(permissions: GlobalPermissions) -> Result<(), Error> {
+    permission_bits(permissions).map(|_| ())
+}
+
 fn permissions_from_mapping(mapping: u64) -> GlobalPermissions {
     let pxn = (mapping & PXN) != 0;
     let uxn = (mapping & UXN) != 0;
@@ -167,10 +177,15 @@ impl DescriptorEntry {
     fn new_table_desc() -> Self {
         let early = !is_initialized();
         let table_addr = if early {
-            Box::leak(Box::new_in(
-                LevelTable::new(),
-                AllocRef::new(&EARLY_ALLOCATOR),
-            ))
+            let table = Box::try_new_in(LevelTable::new(), AllocRef::new(&EARLY_ALLOCATOR))
+                .unwrap_or_else(|_| {
+                    panic!(
+                        "early allocator exhausted, increase EARLY_ALLOCATOR_SIZE ({} bytes, {} used)",
+                        EARLY_ALLOCATOR_SIZE,
+                        early_allocator_high_water_mark()
+                    )
+                });
+            Box::leak(table)
         } else {
             // This gives a logical memory address, we need to translate it to its physical
             // address for the table
@@ -215,7 +230,7 @@ impl DescriptorEntry {
         ))
     }
 
-    fn get_table(&mut self) -> Option<&mut LevelTable> {
+    fn table_ptr(&self) -> Option<*mut LevelTable> {
         match self.ty() {
             DescriptorType::Table => {
                 let table_ptr = (self.0 & VA_MASK) as *mut LevelTable;
@@ -228,15 +243,23 @@ impl DescriptorEntry {
                         .try_into_logical()
                         .map(|kla| kla.as_ptr() as *mut LevelTable)
                         .expect("table ptr is not a logical address");
-                    Some(unsafe { &mut *table_ptr })
+                    Some(table_ptr)
                 } else {
-                    Some(unsafe { &mut *table_ptr })
+                    Some(table_ptr)
                 }
             }
             _ => None,
         }
     }
 
+    fn get_table(&mut self) -> Option<&mut LevelTable> {
+        self.table_ptr().map(|table_ptr| unsafe { &mut *table_ptr })
+    }
+
+    fn table(&self) -> Option<&LevelTable> {
+        self.table_ptr().map(|table_ptr| unsafe { &*table_ptr })
+    }
+
     fn is_early_table(&self) -> bool {
         (self.ty() == DescriptorType::Table) && (self.0 & Self::EARLY_BIT) != 0
     }
@@ -275,6 +298,24 @@ impl DescriptorEntry {
             _ => None,
         }
     }
+
+    fn set_permissions(&mut self, permissions: GlobalPermissions) -> Result<(), Error> {
+        const PERMISSION_MASK: u64 = (0b11 << 6) | PXN | UXN;
+        self.0 = (self.0 & !PERMISSION_MASK) | permission_bits(permissions)?;
+        Ok(())
+    }
+
+    fn is_accessed(&self) -> bool {
+        (self.0 & Self::ACCESS_FLAG) != 0
+    }
+
+    fn set_accessed(&mut self, accessed: bool) {
+        if accessed {
+            self.0 |= Self::ACCESS_FLAG;
+        } else {
+            self.0 &= !Self::ACCESS_FLAG;
+        }
+    }
 }
 
 impl Drop for DescriptorEntry {
@@ -296,6 +337,77 @@ impl Drop for DescriptorEntry {
 
 const INVALID_DESCRIPTOR: DescriptorEntry = DescriptorEntry::new_invalid();
 
+/// Information about the leaf descriptor that translates a virtual address.
+#[derive(Clone, Copy, Debug)]
+pub struct Mapping {
+    /// Physical address the queried virtual address translates to.
+    pub pa: PhysicalAddress,
+    pub attributes: Attributes,
+    pub permissions: GlobalPermissions,
+    /// Whether the access flag is set. Accessing a mapping with the flag clear raises an access
+    /// flag fault.
+    pub accessed: bool,
+    pub level: TranslationLevel,
+}
+
+/// Half of the virtual address space translated by a table. The high half is translated by
+/// TTBR1_EL1 and the low half by TTBR0_EL1.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum VaSpace {
+    Low,
+    High,
+}
+
+impl VaSpace {
+    /// Bits of the virtual address above the ones used to index the tables.
+    fn base(&self) -> usize {
+        match *self {
+            VaSpace::Low => 0,
+            VaSpace::High => 0xFFFF_0000_0000_0000,
+        }
+    }
+}
+
+/// A range of virtual addresses
+#[derive(Clone, Copy, Debug)]
+pub struct VaRange {
+    pub va: VirtualAddress,
+    pub size: usize,
+}
+
+impl VaRange {
+    fn overlaps(&self, va: usize, size: usize) -> bool {
+        let start = self.va.as_usize();
+        va < start.saturating_add(self.size) && start < va.saturating_add(size)
+    }
+}
+
+/// Contiguous virtual addresses translated to contiguous physical addresses, with the same
+/// attributes and permissions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MappedRange {
+    pub va: VirtualAddress,
+    pub pa: PhysicalAddress,
+    pub size: usize,
+    pub attributes: Attributes,
+    pub permissions: GlobalPermissions,
+}
+
+impl MappedRange {
+    /// Extends this range with the next leaf mapping if both can be described by a single range.
+    fn try_extend(&mut self, next: &MappedRange) -> bool {
+        let contiguous = self.va.as_usize() + self.size == next.va.as_usize()
+            && self.pa.as_usize() + self.size == next.pa.as_usize();
+        if contiguous && self.attributes == next.attributes && self.permissions == next.permissions
+        {
+            self.size += next.size;
+            true
+        } else {
+            false
+        }
+    }
+}
+
 /// Translation granule is hardcoded to 16KB
 /// size of L2 memory region is 32MB
 /// size of L1 memory region is 64GB
@@ -435,24 +547,7 @@ impl LevelTable {
                 } else {
                     if matches!(entry_type, DescriptorType::Block) {
                         // Turn it into a table, then go in and remove whatever is left
-                        let attrs = descriptor_entry.attrs().unwrap();
-                        let permissions = descriptor_entry.permissions().unwrap();
-                        let pa = descriptor_entry.pa().unwrap();
-
-                        // Now it is a table!
-                        *descriptor_entry = DescriptorEntry::new_table_desc();
-
-                        descriptor_entry
-                            .get_table()
-                            .expect("Is a table")
-                            .map_region_internal(
-                                va,
-                                pa,
-                                entry_size,
-                                attrs,
-                                permissions,
-                                level.next(),
-                            )?;
+                        Self::split_block(descriptor_entry, va, level)?;
                     }
 
                     // Now unmap what's left
@@ -472,6 +567,249 @@ impl LevelTable {
         Ok(())
     }
 
+    /// Replaces a block descriptor with a table of the next level that maps the same memory with
+    /// the same attributes, permissions and access flag.
+    fn split_block(
+        descriptor_entry: &mut DescriptorEntry,
+        va: VirtualAddress,
+        level: TranslationLevel,
+    ) -> Result<(), Error> {
+        let entry_size = level.entry_size();
+        let attrs = descriptor_entry.attrs().unwrap();
+        let permissions = descriptor_entry.permissions().unwrap();
+        let pa = descriptor_entry.pa().unwrap();
+        let accessed = descriptor_entry.is_accessed();
+        let mut block_va = va;
+        let block_va = block_va.floor_to_alignment(entry_size);
+
+        // Now it is a table!
+        *descriptor_entry = DescriptorEntry::new_table_desc();
+
+        let table = descriptor_entry.get_table().expect("Is a table");
+        table.map_region_internal(block_va, pa, entry_size, attrs, permissions, level.next())?;
+
+        if !accessed {
+            table.modify_region_internal(block_va, entry_size, level.next(), &mut |entry| {
+                entry.set_accessed(false);
+                Ok(())
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Calls `f` for every leaf descriptor in the given region. Blocks that are only partially
+    /// covered by the region are split first. All the region must be mapped.
+    fn modify_region_internal(
+        &mut self,
+        mut va: VirtualAddress,
+        mut size: usize,
+        level: TranslationLevel,
+        f: &mut impl FnMut(&mut DescriptorEntry) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        // Size needs to be aligned to page size
+        if (size % PAGE_SIZE) != 0 {
+            size = size + PAGE_SIZE - (size % PAGE_SIZE);
+        }
+
+        let entry_size = level.entry_size();
+
+        let mut remaining_size = size;
+        while remaining_size != 0 {
+            let index = level.table_index_for_addr(va);
+            let aligned = level.is_address_aligned(va);
+            let descriptor_entry = &mut self.table[index];
+
+            let chunk_size = if !aligned {
+                let next_level = level.next();
+                let rem_entry_size =
+                    entry_size - next_level.table_index_for_addr(va) * next_level.entry_size();
+                core::cmp::min(rem_entry_size, remaining_size)
+            } else {
+                core::cmp::min(entry_size, remaining_size)
+            };
+
+            match descriptor_entry.ty() {
+                DescriptorType::Invalid => {
+                    return Err(Error::UnmappedAddress(va));
+                }
+                DescriptorType::Page => {
+                    f(descriptor_entry)?;
+                }
+                DescriptorType::Block if aligned && (chunk_size == entry_size) => {
+                    f(descriptor_entry)?;
+                }
+                DescriptorType::Block | DescriptorType::Table => {
+                    if matches!(descriptor_entry.ty(), DescriptorType::Block) {
+                        Self::split_block(descriptor_entry, va, level)?;
+                    }
+
+                    descriptor_entry
+                        .get_table()
+                        .expect("Is a table")
+                        .modify_region_internal(va, chunk_size, level.next(), f)?;
+                }
+            }
+
+            unsafe {
+                va = va.offset(chunk_size);
+            }
+
+            remaining_size = remaining_size.saturating_sub(chunk_size);
+        }
+        Ok(())
+    }
+
+    /// Changes the permissions of an already mapped region. The caller is responsible for
+    /// invalidating any stale TLB entries.
+    pub fn protect(
+        &mut self,
+        va: VirtualAddress,
+        size: usize,
+        permissions: GlobalPermissions,
+    ) -> Result<(), Error> {
+        log_debug!(
+            "Changing permissions at {:?}, size 0x{:x} to {:?}",
+            va,
+            size,
+            permissions
+        );
+
+        // Validate the permissions before touching any descriptor
+        permission_bits(permissions)?;
+
+        self.modify_region_internal(va, size, TranslationLevel::Level0, &mut |entry| {
+            entry.set_permissions(permissions)
+        })
+    }
+
+    /// Sets or clears the access flag of an already mapped region. Accessing memory with a clear
+    /// access flag raises an access flag fault, which can be used for demand paging and to track
+    /// the working set. The caller is responsible for invalidating any stale TLB entries.
+    pub fn set_access_flag(
+        &mut self,
+        va: VirtualAddress,
+        size: usize,
+        accessed: bool,
+    ) -> Result<(), Error> {
+        self.modify_region_internal(va, size, TranslationLevel::Level0, &mut |entry| {
+            entry.set_accessed(accessed);
+            Ok(())
+        })
+    }
+
+    fn find_leaf(
+        &mut self,
+        va: VirtualAddress,
+    ) -> Option<(&mut DescriptorEntry, TranslationLevel)> {
+        let mut table = self;
+        let mut level = TranslationLevel::Level0;
+        loop {
+            let index = level.table_index_for_addr(va);
+            let descriptor_entry = &mut table.table[index];
+            match descriptor_entry.ty() {
+                DescriptorType::Invalid => return None,
+                DescriptorType::Page | DescriptorType::Block => {
+                    return Some((descriptor_entry, level))
+                }
+                DescriptorType::Table => {
+                    table = descriptor_entry.get_table()?;
+                    level = level.next();
+                }
+            }
+        }
+    }
+
+    /// Calls `f` for each leaf descriptor in the tables, in increasing virtual address order.
+    fn for_each_leaf<F>(&self, level: TranslationLevel, table_va: usize, f: &mut F)
+    where
+        F: FnMut(usize, TranslationLevel, &DescriptorEntry),
+    {
+        for (index, descriptor_entry) in self.table.iter().enumerate() {
+            let va = table_va + index * level.entry_size();
+            match descriptor_entry.ty() {
+                DescriptorType::Invalid => {}
+                DescriptorType::Page | DescriptorType::Block => f(va, level, descriptor_entry),
+                DescriptorType::Table => {
+                    if let Some(table) = descriptor_entry.table() {
+                        table.for_each_leaf(level.next(), va, f);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Walks the translation tables and returns all mappings, merging contiguous leaf mappings
+    /// with the same attributes and permissions. Only leaf mappings overlapping `filter` are
+    /// included if it is given.
+    pub fn mapped_ranges(&self, space: VaSpace, filter: Option<VaRange>) -> Vec<MappedRange> {
+        let mut ranges: Vec<MappedRange> = vec![];
+        self.for_each_leaf(
+            TranslationLevel::Level0,
+            space.base(),
+            &mut |va, level, descriptor_entry| {
+                let size = level.entry_size();
+                if filter.map_or(false, |filter| !filter.overlaps(va, size)) {
+                    return;
+                }
+
+                let range = match (
+                    descriptor_entry.pa(),
+                    descriptor_entry.attrs(),
+                    descriptor_entry.permissions(),
+                ) {
+                    (Some(pa), Some(attributes), Some(permissions)) => MappedRange {
+                        va: VirtualAddress::new_unaligned(va as *const _),
+                        pa,
+                        size,
+                        attributes,
+                        permissions,
+                    },
+                    _ => return,
+                };
+                match ranges.last_mut() {
+                    Some(last) if last.try_extend(&range) => {}
+                    _ => ranges.push(range),
+                }
+            },
+        );
+        ranges
+    }
+
+    /// Logs the mappings of the tables, as given by `mapped_ranges`. Output is limited to
+    /// `MAX_DUMPED_RANGES` ranges.
+    pub fn dump(&self, space: VaSpace, filter: Option<VaRange>) {
+        const MAX_DUMPED_RANGES: usize = 256;
+
+        let ranges = self.mapped_ranges(space, filter);
+        log_info!("{:?} translation table, {} ranges", space, ranges.len());
+        for range in ranges.iter().take(MAX_DUMPED_RANGES) {
+            log_info!(
+                "  {:?} -> {:?}, size 0x{:x}, {:?}, {:?}",
+                range.va,
+                range.pa,
+                range.size,
+                range.attributes,
+                range.permissions
+            );
+        }
+        if ranges.len() > MAX_DUMPED_RANGES {
+            log_info!("  ... {} more ranges", ranges.len() - MAX_DUMPED_RANGES);
+        }
+    }
+
+    /// Walks the translation tables and returns the mapping for the given virtual address, if any.
+    pub fn query(&mut self, va: VirtualAddress) -> Option<Mapping> {
+        let (descriptor_entry, level) = self.find_leaf(va)?;
+        let offset = va.as_usize() % level.entry_size();
+        Some(Mapping {
+            pa: unsafe { descriptor_entry.pa()?.offset(offset) },
+            attributes: descriptor_entry.attrs()?,
+            permissions: descriptor_entry.permissions()?,
+            accessed: descriptor_entry.is_accessed(),
+            level,
+        })
+    }
+
     pub fn map_region(
         &mut self,
         va: VirtualAddress,
@@ -617,15 +955,23 @@ pub fn flush_tlb_page(addr: VirtualAddress) {
 }
 
 pub fn initialize(high_table: &LevelTable, low_table: &LevelTable) {
-    if unsafe { MMU_INITIALIZED } {
+    if is_initialized() {
         panic!("MMU Already initialized!");
     }
 
+    // Translation granule is hardcoded to 16KB, so we cannot continue without it.
+    assert!(
+        cpu::features().granule_16k,
+        "The CPU does not support a 16KB translation granule"
+    );
+
     MAIR_EL1.write(
         MAIR_EL1::Attr0_Normal_Outer::WriteBack_NonTransient_ReadWriteAlloc
             + MAIR_EL1::Attr0_Normal_Inner::WriteBack_NonTransient_ReadWriteAlloc
             + MAIR_EL1::Attr1_Device::nonGathering_nonReordering_noEarlyWriteAck
-            + MAIR_EL1::Attr2_Device::nonGathering_nonReordering_EarlyWriteAck,
+            + MAIR_EL1::Attr2_Device::nonGathering_nonReordering_EarlyWriteAck
+            + MAIR_EL1::Attr3_Normal_Outer::NonCacheable
+            + MAIR_EL1::Attr3_Normal_Inner::NonCacheable,
     );
 
     TCR_EL1.write(
@@ -662,12 +1008,24 @@ pub fn initialize(high_table: &LevelTable, low_table: &LevelTable) {
         log_error!("Error enabling MMU");
     }
 
-    // It is safe to set it here, because we are in a single-threaded context
-    unsafe { MMU_INITIALIZED = true };
+    MMU_INITIALIZED.get_or_init(|| ());
 }
 
 pub fn is_initialized() -> bool {
-    unsafe { MMU_INITIALIZED }
+    MMU_INITIALIZED.is_completed()
+}
+
+/// Returns the number of bytes of the early allocator used to build the translation tables before
+/// the MMU was initialized. It never exceeds `EARLY_ALLOCATOR_SIZE`.
+pub fn early_allocator_high_water_mark() -> usize {
+    EARLY_ALLOCATOR.high_water_mark()
+}
+
+/// Makes translation tables use the global allocator in host tests, where the early allocator
+/// assumptions don't hold.
+#[cfg(test)]
+pub(crate) fn initialize_for_test() {
+    MMU_INITIALIZED.get_or_init(|| ());
 }
 
 #[cfg(test)]
@@ -679,7 +1037,7 @@ mod test {
         // Let's trick the test to use the global allocator instead of the early allocator. On
         // tests our assumptions don't hold for the global allocator, so we need to make sure to
         // use an adequate allocator.
-        unsafe { MMU_INITIALIZED = true };
+        initialize_for_test();
 
         let mut table = LevelTable::new();
 
@@ -735,7 +1093,7 @@ mod test {
         // Let's trick the test to use the global allocator instead of the early allocator. On
         // tests our assumptions don't hold for the global allocator, so we need to make sure to
         // use an adequate allocator.
-        unsafe { MMU_INITIALIZED = true };
+        initialize_for_test();
 
         let mut table = LevelTable::new();
 
@@ -781,7 +1139,7 @@ mod test {
         // Let's trick the test to use the global allocator instead of the early allocator. On
         // tests our assumptions don't hold for the global allocator, so we need to make sure to
         // use an adequate allocator.
-        unsafe { MMU_INITIALIZED = true };
+        initialize_for_test();
 
         let mut table = LevelTable::new();
 
@@ -845,7 +1203,7 @@ mod test {
         // Let's trick the test to use the global allocator instead of the early allocator. On
         // tests our assumptions don't hold for the global allocator, so we need to make sure to
         // use an adequate allocator.
-        unsafe { MMU_INITIALIZED = true };
+        initialize_for_test();
 
         let mut table = LevelTable::new();
 
@@ -914,7 +1272,7 @@ mod test {
         // Let's trick the test to use the global allocator instead of the early allocator. On
         // tests our assumptions don't hold for the global allocator, so we need to make sure to
         // use an adequate allocator.
-        unsafe { MMU_INITIALIZED = true };
+        initialize_for_test();
 
         let mut table = LevelTable::new();
 
@@ -973,7 +1331,7 @@ mod test {
         // Let's trick the test to use the global allocator instead of the early allocator. On
         // tests our assumptions don't hold for the global allocator, so we need to make sure to
         // use an adequate allocator.
-        unsafe { MMU_INITIALIZED = true };
+        initialize_for_test();
 
         let mut table = LevelTable::new();
 
@@ -1011,4 +1369,345 @@ mod test {
             assert!(matches!(desc.ty(), DescriptorType::Invalid));
         }
     }
+
+    #[test]
+    fn query_mapping() {
+        // Let's trick the test to use the global allocator instead of the early allocator. On
+        // tests our assumptions don't hold for the global allocator, so we need to make sure to
+        // use an adequate allocator.
+        initialize_for_test();
+
+        let mut table = LevelTable::new();
+
+        let from = VirtualAddress::try_from_ptr(0x012345678000 as *const u8).unwrap();
+        let to = PhysicalAddress::try_from_ptr(0x012345670000 as *const u8).unwrap();
+        let size = 1 << 14;
+        table
+            .map_region(
+                from,
+                to,
+                size,
+                Attributes::Normal,
+                GlobalPermissions::new_only_privileged(Permissions::RW),
+            )
+            .expect("Adding region was successful");
+
+        let mapping = table
+            .query(unsafe { from.offset(0x123) })
+            .expect("Address is mapped");
+        assert_eq!(mapping.pa, unsafe { to.offset(0x123) });
+        assert_eq!(mapping.level, TranslationLevel::Level3);
+        assert!(mapping.accessed);
+        assert!(matches!(mapping.attributes, Attributes::Normal));
+        assert!(matches!(
+            mapping.permissions,
+            GlobalPermissions {
+                privileged: Permissions::RW,
+                unprivileged: Permissions::None
+            }
+        ));
+
+        assert!(table.query(unsafe { from.offset(size) }).is_none());
+    }
+
+    #[test]
+    fn attributes_round_trip() {
+        for attributes in [
+            Attributes::Normal,
+            Attributes::DevicenGnRnE,
+            Attributes::DevicenGnRE,
+            Attributes::NormalNonCacheable,
+        ] {
+            let mapping = mair_index_from_attrs(attributes);
+            assert_eq!(attributes_from_mapping(mapping).unwrap(), attributes);
+            assert_eq!(Attributes::try_from(attributes as u64), Ok(attributes));
+        }
+
+        // The device attributes match the MAIR_EL1 setup done in `initialize`
+        assert_eq!(
+            mair_index_from_attrs(Attributes::DevicenGnRnE),
+            1 << MAIR_ATTR_OFFSET
+        );
+        assert_eq!(
+            mair_index_from_attrs(Attributes::DevicenGnRE),
+            2 << MAIR_ATTR_OFFSET
+        );
+        assert_eq!(Attributes::try_from(4), Err(()));
+    }
+
+    #[test]
+    fn normal_non_cacheable_descriptor() {
+        let pa = PhysicalAddress::try_from_ptr(0x80000000 as *const u8).unwrap();
+        let permissions = GlobalPermissions::new_only_privileged(Permissions::RW);
+
+        let page = DescriptorEntry::new_page_desc(pa, Attributes::NormalNonCacheable, permissions)
+            .unwrap();
+        assert_eq!((page.0 >> MAIR_ATTR_OFFSET) & 0x7, 3);
+        assert_eq!(page.attrs(), Some(Attributes::NormalNonCacheable));
+        assert_eq!(page.pa(), Some(pa));
+
+        let block =
+            DescriptorEntry::new_block_desc(pa, Attributes::NormalNonCacheable, permissions)
+                .unwrap();
+        assert_eq!(block.attrs(), Some(Attributes::NormalNonCacheable));
+    }
+
+    #[test]
+    fn coalesced_mapped_ranges() {
+        // Let's trick the test to use the global allocator instead of the early allocator. On
+        // tests our assumptions don't hold for the global allocator, so we need to make sure to
+        // use an adequate allocator.
+        initialize_for_test();
+
+        let mut table = LevelTable::new();
+        let rw = GlobalPermissions::new_only_privileged(Permissions::RW);
+        let ro = GlobalPermissions::new_only_privileged(Permissions::RO);
+        let va = |addr: usize| VirtualAddress::try_from_ptr(addr as *const u8).unwrap();
+        let pa = |addr: usize| PhysicalAddress::try_from_ptr(addr as *const u8).unwrap();
+
+        // Two separate calls mapping contiguous memory are merged
+        table
+            .map_region(
+                va(0x10000000),
+                pa(0x80000000),
+                PAGE_SIZE * 2,
+                Attributes::Normal,
+                rw,
+            )
+            .unwrap();
+        table
+            .map_region(
+                va(0x10008000),
+                pa(0x80008000),
+                PAGE_SIZE,
+                Attributes::Normal,
+                rw,
+            )
+            .unwrap();
+        // Contiguous, but with other permissions
+        table
+            .map_region(
+                va(0x1000c000),
+                pa(0x8000c000),
+                PAGE_SIZE,
+                Attributes::Normal,
+                ro,
+            )
+            .unwrap();
+        // Contiguous virtual addresses, but not physical ones
+        table
+            .map_region(
+                va(0x10010000),
+                pa(0x90000000),
+                PAGE_SIZE,
+                Attributes::Normal,
+                ro,
+            )
+            .unwrap();
+        // A level 2 block
+        table
+            .map_region(
+                va(0x40000000),
+                pa(0x200000000),
+                1 << 25,
+                Attributes::Normal,
+                rw,
+            )
+            .unwrap();
+        // Contiguous device memory is not merged with normal memory
+        table
+            .map_region(
+                va(0x42000000),
+                pa(0x202000000),
+                PAGE_SIZE,
+                Attributes::DevicenGnRnE,
+                rw,
+            )
+            .unwrap();
+
+        let range = |va_addr, pa_addr, size, attributes, permissions| MappedRange {
+            va: va(va_addr),
+            pa: pa(pa_addr),
+            size,
+            attributes,
+            permissions,
+        };
+        let expected = [
+            range(
+                0x10000000,
+                0x80000000,
+                PAGE_SIZE * 3,
+                Attributes::Normal,
+                rw,
+            ),
+            range(0x1000c000, 0x8000c000, PAGE_SIZE, Attributes::Normal, ro),
+            range(0x10010000, 0x90000000, PAGE_SIZE, Attributes::Normal, ro),
+            range(0x40000000, 0x200000000, 1 << 25, Attributes::Normal, rw),
+            range(
+                0x42000000,
+                0x202000000,
+                PAGE_SIZE,
+                Attributes::DevicenGnRnE,
+                rw,
+            ),
+        ];
+        assert_eq!(table.mapped_ranges(VaSpace::Low, None), expected);
+
+        let filter = VaRange {
+            va: va(0x1000c000),
+            size: PAGE_SIZE * 2,
+        };
+        assert_eq!(
+            table.mapped_ranges(VaSpace::Low, Some(filter)),
+            &expected[1..3]
+        );
+
+        // The same tables used for the high half of the address space
+        let high_ranges = table.mapped_ranges(VaSpace::High, None);
+        assert_eq!(high_ranges.len(), expected.len());
+        assert_eq!(high_ranges[0].va.as_usize(), 0xFFFF_0000_1000_0000);
+        assert!(high_ranges[0].va.is_high_address());
+    }
+
+    #[test]
+    fn toggle_access_flag() {
+        // Let's trick the test to use the global allocator instead of the early allocator. On
+        // tests our assumptions don't hold for the global allocator, so we need to make sure to
+        // use an adequate allocator.
+        initialize_for_test();
+
+        let mut table = LevelTable::new();
+
+        let from = VirtualAddress::try_from_ptr(0x012345678000 as *const u8).unwrap();
+        let to = PhysicalAddress::try_from_ptr(0x012345678000 as *const u8).unwrap();
+        let size = 4 << 14;
+        table
+            .map_region(
+                from,
+                to,
+                size,
+                Attributes::Normal,
+                GlobalPermissions::new_for_process(Permissions::RW),
+            )
+            .expect("Adding region was successful");
+
+        table
+            .set_access_flag(from, size, false)
+            .expect("Region is mapped");
+        for page in 0..4 {
+            let mapping = table
+                .query(unsafe { from.offset(page << 14) })
+                .expect("Address is mapped");
+            assert!(!mapping.accessed);
+        }
+
+        // Now simulate the fault handler setting the flag on the second page
+        let faulting_page = unsafe { from.offset(1 << 14) };
+        table
+            .set_access_flag(faulting_page, 1 << 14, true)
+            .expect("Region is mapped");
+        for page in 0..4 {
+            let mapping = table
+                .query(unsafe { from.offset(page << 14) })
+                .expect("Address is mapped");
+            assert_eq!(mapping.accessed, page == 1);
+        }
+
+        // Changing permissions keeps the access flag untouched
+        table
+            .protect(
+                from,
+                size,
+                GlobalPermissions::new_for_process(Permissions::RO),
+            )
+            .expect("Region is mapped");
+        for page in 0..4 {
+            let mapping = table
+                .query(unsafe { from.offset(page << 14) })
+                .expect("Address is mapped");
+            assert_eq!(mapping.accessed, page == 1);
+            assert!(matches!(
+                mapping.permissions,
+                GlobalPermissions {
+                    privileged: Permissions::RO,
+                    unprivileged: Permissions::RO
+                }
+            ));
+        }
+    }
+
+    #[test]
+    fn protect_part_of_block() {
+        // Let's trick the test to use the global allocator instead of the early allocator. On
+        // tests our assumptions don't hold for the global allocator, so we need to make sure to
+        // use an adequate allocator.
+        initialize_for_test();
+
+        let mut table = LevelTable::new();
+
+        let block_size = 1 << 25;
+        let page_size = 1 << 14;
+
+        let from = VirtualAddress::try_from_ptr(0x12344000000 as *const u8).unwrap();
+        let to = PhysicalAddress::try_from_ptr(0x12344000000 as *const u8).unwrap();
+        table
+            .map_region(
+                from,
+                to,
+                block_size,
+                Attributes::Normal,
+                GlobalPermissions::new_only_privileged(Permissions::RWX),
+            )
+            .expect("Adding region was successful");
+        table
+            .set_access_flag(from, block_size, false)
+            .expect("Region is mapped");
+
+        let protected_va = unsafe { from.offset(page_size * 3) };
+        table
+            .protect(
+                protected_va,
+                page_size,
+                GlobalPermissions::new_only_privileged(Permissions::RO),
+            )
+            .expect("Region is mapped");
+
+        // The block must have been split in pages
+        for page in 0..(block_size / page_size) {
+            let va = unsafe { from.offset(page * page_size) };
+            let mapping = table.query(va).expect("Address is mapped");
+            assert_eq!(mapping.level, TranslationLevel::Level3);
+            assert_eq!(mapping.pa, unsafe { to.offset(page * page_size) });
+            assert!(!mapping.accessed);
+            if page == 3 {
+                assert!(matches!(mapping.permissions.privileged, Permissions::RO));
+            } else {
+                assert!(matches!(mapping.permissions.privileged, Permissions::RWX));
+            }
+        }
+    }
+
+    #[test]
+    fn protect_unmapped_region() {
+        // Let's trick the test to use the global allocator instead of the early allocator. On
+        // tests our assumptions don't hold for the global allocator, so we need to make sure to
+        // use an adequate allocator.
+        initialize_for_test();
+
+        let mut table = LevelTable::new();
+
+        let va = VirtualAddress::try_from_ptr(0x012345678000 as *const u8).unwrap();
+        assert!(matches!(
+            table.protect(
+                va,
+                1 << 14,
+                GlobalPermissions::new_only_privileged(Permissions::RO)
+            ),
+            Err(Error::UnmappedAddress(_))
+        ));
+        assert!(matches!(
+            table.set_access_flag(va, 1 << 14, true),
+            Err(Error::UnmappedAddress(_))
+        ));
+    }
 }
@@ -35,6 +35,12 @@ impl<const SIZE: usize> EarlyAllocator<SIZE> {
             offset: RefCell::new(0),
         }
     }
+
+    /// Returns the maximum number of bytes that were in use at any time, including alignment
+    /// padding. Memory is never freed, so this is all memory given out so far.
+    pub fn high_water_mark(&self) -> usize {
+        *self.offset.borrow()
+    }
 }
 
 /// SAFETY:
@@ -172,6 +178,37 @@ mod test {
         assert_eq!(test.get_offset(), 24);
     }
 
+    #[test]
+    fn high_water_mark() {
+        let test = EarlyAllocatorTest::new();
+        assert_eq!(test.allocator.high_water_mark(), 0);
+
+        let ptr = unsafe { test.allocator.alloc(Layout::new::<u8>()) };
+        assert_eq!(test.allocator.high_water_mark(), 1);
+
+        // Padding to align the allocation counts as well
+        unsafe { test.allocator.alloc(Layout::new::<u64>()) };
+        assert_eq!(test.allocator.high_water_mark(), 16);
+
+        // Memory is leaked on deallocation, so the mark does not go down
+        unsafe { test.allocator.dealloc(ptr, Layout::new::<u8>()) };
+        assert_eq!(test.allocator.high_water_mark(), 16);
+
+        // Failed allocations do not move it either
+        let ptr = unsafe {
+            test.allocator
+                .alloc(Layout::from_size_align(1024, 1).unwrap())
+        };
+        assert!(ptr.is_null());
+        assert_eq!(test.allocator.high_water_mark(), 16);
+
+        unsafe {
+            test.allocator
+                .alloc(Layout::from_size_align(1008, 1).unwrap())
+        };
+        assert_eq!(test.allocator.high_water_mark(), 1024);
+    }
+
     #[test]
     fn allocator_ref() {
         let test = EarlyAllocatorTest::new();
@@ -0,0 +1,192 @@
+//! Privileged Access Never (PAN).
+//!
+//! While PAN is set, the kernel faults on any load or store to memory accessible by userspace, so
+//! that user pointers cannot be dereferenced by accident. The kernel clears it for intentional
+//! accesses to user memory with `with_user_access`.
+
+use crate::{arch::cpu, prelude::*, registers::PAN};
+
+use aarch64_cpu::registers::SCTLR_EL1;
+use tock_registers::interfaces::{Readable, Writeable};
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// Set Privileged Access Never on exceptions taken to EL1. PAN is only set on exception entry if
+/// this bit is clear.
+const SCTLR_EL1_SPAN: u64 = 1 << 23;
+
+/// Value of PAN restored to `PSTATE` by an exception return.
+const SPSR_EL1_PAN: u64 = 1 << 22;
+
+static PAN_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Access to the PAN bit of `PSTATE`.
+trait PanState {
+    fn is_set(&self) -> bool;
+    fn set(&self, value: bool);
+}
+
+struct PanRegister;
+
+impl PanState for PanRegister {
+    fn is_set(&self) -> bool {
+        PAN.is_set(PAN::PAN)
+    }
+
+    fn set(&self, value: bool) {
+        if value {
+            PAN.write(PAN::PAN::SET);
+        } else {
+            PAN.write(PAN::PAN::CLEAR);
+        }
+    }
+}
+
+/// Enables PAN if the CPU supports it. From then on it is also set on every exception taken to EL1.
+///
+/// # Safety
+///   Must be called during boot in a single-threaded context, after `cpu::init`.
+pub unsafe fn init() {
+    if !cpu::features().pan {
+        log_warning!("The CPU does not support PAN, user memory is always accessible");
+        return;
+    }
+
+    SCTLR_EL1.set(SCTLR_EL1.get() & !SCTLR_EL1_SPAN);
+    PanRegister.set(true);
+    PAN_ENABLED.store(true, Ordering::Relaxed);
+    log_info!("PAN enabled");
+}
+
+pub fn is_enabled() -> bool {
+    PAN_ENABLED.load(Ordering::Relaxed)
+}
+
+fn spsr_with_pan(spsr: u64, pan: bool) -> u64 {
+    if pan {
+        spsr | SPSR_EL1_PAN
+    } else {
+        spsr & !SPSR_EL1_PAN
+    }
+}
+
+/// Sets PAN in the saved program status of a kernel thread if it is enabled, so that the thread
+/// runs with it after the exception return that starts it.
+pub fn kernel_thread_spsr(spsr: u64) -> u64 {
+    spsr_with_pan(spsr, is_enabled())
+}
+
+fn with_pan_cleared<T>(state: &impl PanState, f: impl FnOnce() -> T) -> T {
+    // PAN may already be clear if the caller is nested in another `with_user_access`
+    let was_set = state.is_set();
+    if was_set {
+        state.set(false);
+    }
+
+    let result = f();
+
+    if was_set {
+        state.set(true);
+    }
+    result
+}
+
+/// Runs `f` with access to user memory, restoring the previous state of PAN afterwards. Keep `f`
+/// as short as possible, ideally just the copy from or to user memory.
+pub fn with_user_access<T>(f: impl FnOnce() -> T) -> T {
+    if !is_enabled() {
+        return f();
+    }
+    with_pan_cleared(&PanRegister, f)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::{cell::RefCell, vec, vec::Vec};
+
+    /// Records the writes to the PAN bit
+    struct MockPan {
+        value: RefCell<bool>,
+        writes: RefCell<Vec<bool>>,
+    }
+
+    impl MockPan {
+        fn new(value: bool) -> Self {
+            Self {
+                value: RefCell::new(value),
+                writes: RefCell::new(vec![]),
+            }
+        }
+    }
+
+    impl PanState for MockPan {
+        fn is_set(&self) -> bool {
+            *self.value.borrow()
+        }
+
+        fn set(&self, value: bool) {
+            *self.value.borrow_mut() = value;
+            self.writes.borrow_mut().push(value);
+        }
+    }
+
+    #[test]
+    fn user_access_restores_pan() {
+        let pan = MockPan::new(true);
+        let result = with_pan_cleared(&pan, || {
+            assert!(!pan.is_set());
+            42
+        });
+
+        assert_eq!(result, 42);
+        assert!(pan.is_set());
+        assert_eq!(*pan.writes.borrow(), vec![false, true]);
+    }
+
+    #[test]
+    fn nested_user_access() {
+        let pan = MockPan::new(true);
+        with_pan_cleared(&pan, || {
+            with_pan_cleared(&pan, || assert!(!pan.is_set()));
+            // The inner call must not set PAN again before the outer one is done
+            assert!(!pan.is_set());
+        });
+
+        assert!(pan.is_set());
+        assert_eq!(*pan.writes.borrow(), vec![false, true]);
+    }
+
+    #[test]
+    fn user_access_with_pan_clear() {
+        let pan = MockPan::new(false);
+        with_pan_cleared(&pan, || assert!(!pan.is_set()));
+
+        assert!(!pan.is_set());
+        assert!(pan.writes.borrow().is_empty());
+    }
+
+    #[test]
+    fn pan_in_saved_program_status() {
+        const EL1T: u64 = 0b0100;
+
+        assert_eq!(spsr_with_pan(EL1T, true), EL1T | SPSR_EL1_PAN);
+        assert_eq!(
+            spsr_with_pan(EL1T | SPSR_EL1_PAN, true),
+            EL1T | SPSR_EL1_PAN
+        );
+        assert_eq!(spsr_with_pan(EL1T | SPSR_EL1_PAN, false), EL1T);
+        assert_eq!(spsr_with_pan(EL1T, false), EL1T);
+
+        // PAN is never enabled on the host
+        assert_eq!(kernel_thread_spsr(EL1T | SPSR_EL1_PAN), EL1T);
+    }
+
+    #[test]
+    fn user_access_without_pan() {
+        // PAN is never enabled on the host
+        assert!(!is_enabled());
+        assert_eq!(with_user_access(|| 7), 7);
+    }
+}
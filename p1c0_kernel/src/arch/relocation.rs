@@ -0,0 +1,153 @@
+//! Dynamic relocations of position independent images.
+//!
+//! Both the kernel, when it relocates itself to the high kernel addresses, and the loader of
+//! position independent executables use this. Only `R_AARCH64_RELATIVE` relocations are
+//! supported, since they are the only ones a static PIE needs.
+
+/// An entry of a `.rela.dyn` section, as defined by the ELF spec
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RelaEntry {
+    pub offset: usize,
+    pub ty: usize,
+    pub addend: usize,
+}
+
+pub const R_AARCH64_RELATIVE: usize = 1027;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    UnsupportedType { offset: usize, ty: usize },
+}
+
+impl RelaEntry {
+    /// Returns the value that has to be written at `offset` for an image loaded at `base`.
+    pub fn value(&self, base: usize) -> Result<usize, Error> {
+        match self.ty {
+            R_AARCH64_RELATIVE => Ok(base.wrapping_add(self.addend)),
+            ty => Err(Error::UnsupportedType {
+                offset: self.offset,
+                ty,
+            }),
+        }
+    }
+}
+
+/// Checks that all relocations are supported, so that nothing is written if any of them is not.
+fn validate(entries: &[RelaEntry]) -> Result<(), Error> {
+    entries
+        .iter()
+        .try_for_each(|entry| entry.value(0).map(|_| ()))
+}
+
+/// Applies the relocations of an image loaded at `base`.
+///
+/// # Safety
+///   `rela_start` must point to `rela_len_bytes` bytes of relocation entries and the image must
+///   be writable at `base`.
+pub unsafe fn apply_rela(
+    base: usize,
+    rela_start: *const RelaEntry,
+    rela_len_bytes: usize,
+) -> Result<(), Error> {
+    apply_rela_at(base, base, rela_start, rela_len_bytes)
+}
+
+/// Like `apply_rela`, but the image is written through the address `image`, while the relocated
+/// values point to `base`. Used when the image is relocated to an address other than the one it is
+/// currently accessible at.
+///
+/// # Safety
+///   `rela_start` must point to `rela_len_bytes` bytes of relocation entries and the image must
+///   be writable at `image`.
+pub unsafe fn apply_rela_at(
+    image: usize,
+    base: usize,
+    rela_start: *const RelaEntry,
+    rela_len_bytes: usize,
+) -> Result<(), Error> {
+    let rela_len = rela_len_bytes / core::mem::size_of::<RelaEntry>();
+    let entries = core::slice::from_raw_parts(rela_start, rela_len);
+    validate(entries)?;
+
+    for entry in entries {
+        let ptr = image.wrapping_add(entry.offset) as *mut usize;
+        ptr.write_unaligned(entry.value(base)?);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use core::mem;
+
+    const WORD: usize = mem::size_of::<usize>();
+
+    fn relative(offset: usize, addend: usize) -> RelaEntry {
+        RelaEntry {
+            offset,
+            ty: R_AARCH64_RELATIVE,
+            addend,
+        }
+    }
+
+    fn rela_size(entries: &[RelaEntry]) -> usize {
+        mem::size_of_val(entries)
+    }
+
+    #[test]
+    fn applies_relative_relocations() {
+        let mut image = [0usize; 4];
+        let base = image.as_mut_ptr() as usize;
+        let entries = [relative(0, 0x10), relative(2 * WORD, 3 * WORD)];
+
+        unsafe { apply_rela(base, entries.as_ptr(), rela_size(&entries)).unwrap() };
+        assert_eq!(image, [base + 0x10, 0, base + 3 * WORD, 0]);
+    }
+
+    #[test]
+    fn relocates_to_another_base() {
+        let mut image = [0usize; 2];
+        let entries = [relative(WORD, 0x100)];
+        let new_base = 0xffff_fe00_0000_0000;
+
+        unsafe {
+            apply_rela_at(
+                image.as_mut_ptr() as usize,
+                new_base,
+                entries.as_ptr(),
+                rela_size(&entries),
+            )
+            .unwrap()
+        };
+        assert_eq!(image, [0, new_base + 0x100]);
+    }
+
+    #[test]
+    fn unsupported_relocations_are_not_applied() {
+        const R_AARCH64_ABS64: usize = 257;
+
+        let mut image = [0usize; 2];
+        let base = image.as_mut_ptr() as usize;
+        let entries = [
+            relative(0, 0x10),
+            RelaEntry {
+                offset: WORD,
+                ty: R_AARCH64_ABS64,
+                addend: 0,
+            },
+        ];
+
+        let result = unsafe { apply_rela(base, entries.as_ptr(), rela_size(&entries)) };
+        assert_eq!(
+            result,
+            Err(Error::UnsupportedType {
+                offset: WORD,
+                ty: R_AARCH64_ABS64
+            })
+        );
+        assert_eq!(image, [0, 0]);
+    }
+}
@@ -12,8 +12,45 @@ struct Frame {
     lr: *const u8,
 }
 
+/// Maximum number of frames walked in a backtrace, so that a corrupted stack cannot make the
+/// backtracer loop forever.
+pub const MAX_FRAMES: usize = 64;
+
 pub trait Symbolicator {
     fn symbolicate(&self, addr: VirtualAddress) -> Option<(String, usize)>;
+
+    /// Returns all the functions that the address belongs to, starting with the innermost inlined
+    /// function and ending with the function that contains it in the binary. Symbolicators without
+    /// inlining information return only the latter.
+    fn symbolicate_all(&self, addr: VirtualAddress) -> Vec<(String, usize)> {
+        self.symbolicate(addr).into_iter().collect()
+    }
+}
+
+fn write_frame(
+    f: &mut Formatter<'_>,
+    level: isize,
+    addr: VirtualAddress,
+    symbols: &[(String, usize)],
+) -> core::fmt::Result {
+    match symbols.split_first() {
+        Some(((symbol_name, symbol_offset), inlined_into)) => {
+            writeln!(
+                f,
+                "\t[{}] = {} - {} (+0x{:x})",
+                level, addr, symbol_name, symbol_offset
+            )?;
+            for (symbol_name, symbol_offset) in inlined_into {
+                writeln!(
+                    f,
+                    "\t\tinlined into {} (+0x{:x})",
+                    symbol_name, symbol_offset
+                )?;
+            }
+            Ok(())
+        }
+        None => writeln!(f, "\t[{}] = {}", level, addr),
+    }
 }
 
 pub struct Backtracer<V: Validator, S: Symbolicator> {
@@ -25,11 +62,11 @@ pub struct Backtracer<V: Validator, S: Symbolicator> {
 
 impl<V: Validator + Clone, S: Symbolicator + Clone> Backtracer<V, S> {
     fn stack_frame_iter(&self) -> StackFrameIter<V, S> {
-        StackFrameIter {
-            frame_ptr: self.frame_ptr,
-            validator: self.validator.clone(),
-            symbolicator: self.symbolicator.clone(),
-        }
+        StackFrameIter::new(
+            self.frame_ptr,
+            self.validator.clone(),
+            self.symbolicator.clone(),
+        )
     }
 }
 
@@ -38,35 +75,71 @@ pub struct StackFrameIter<V: Validator, S: Symbolicator> {
     frame_ptr: VirtualAddress,
     validator: V,
     symbolicator: Option<S>,
+    num_frames: usize,
+    finished: bool,
+}
+
+impl<V: Validator, S: Symbolicator> StackFrameIter<V, S> {
+    fn new(frame_ptr: VirtualAddress, validator: V, symbolicator: Option<S>) -> Self {
+        Self {
+            frame_ptr,
+            validator,
+            symbolicator,
+            num_frames: 0,
+            finished: false,
+        }
+    }
+
+    /// Checks that the whole frame record is within the stack before it is dereferenced.
+    fn is_frame_valid(&self, frame_ptr: VirtualAddress) -> bool {
+        let start = frame_ptr.as_usize();
+        if start % core::mem::align_of::<Frame>() != 0 {
+            return false;
+        }
+
+        let end = match start.checked_add(core::mem::size_of::<Frame>() - 1) {
+            Some(end) => VirtualAddress::new_unaligned(end as *const _),
+            None => return false,
+        };
+        self.validator.is_valid(frame_ptr) && self.validator.is_valid(end)
+    }
 }
 
 impl<V: Validator, S: Symbolicator> Iterator for StackFrameIter<V, S> {
-    type Item = (VirtualAddress, Option<(String, usize)>);
+    type Item = (VirtualAddress, Vec<(String, usize)>);
 
     fn next(&mut self) -> Option<Self::Item> {
-        if !self.validator.is_valid(self.frame_ptr) {
+        if self.finished || self.num_frames >= MAX_FRAMES || !self.is_frame_valid(self.frame_ptr) {
             return None;
         }
+        self.num_frames += 1;
 
         let frame_ptr = self.frame_ptr.as_ptr() as *const Frame;
 
         // # Safety: This should be safe because it is within the validated range
         let item = VirtualAddress::new_unaligned(unsafe { (*frame_ptr).lr });
+        let next_frame_ptr =
+            VirtualAddress::new_unaligned(unsafe { (*frame_ptr).next } as *const _);
 
-        self.frame_ptr = VirtualAddress::new_unaligned(unsafe { (*frame_ptr).next } as *const _);
+        // The stack grows downwards, so the frame of the caller is always at a higher address. If
+        // it isn't the chain is corrupted (or cyclic) and this is the last frame we can trust.
+        if next_frame_ptr.as_usize() <= self.frame_ptr.as_usize() {
+            self.finished = true;
+        }
+        self.frame_ptr = next_frame_ptr;
 
         // We hit the end on nullptr
         if item.as_ptr().is_null() {
             return None;
         }
 
-        let symbol = if let Some(symbolicator) = &self.symbolicator {
-            symbolicator.symbolicate(item)
+        let symbols = if let Some(symbolicator) = &self.symbolicator {
+            symbolicator.symbolicate_all(item)
         } else {
-            None
+            vec![]
         };
 
-        Some((item, symbol))
+        Some((item, symbols))
     }
 }
 
@@ -76,31 +149,15 @@ impl<V: Validator + Clone, S: Symbolicator + Clone> core::fmt::Display for Backt
 
         writeln!(f, "Stack trace:")?;
 
-        if let Some((symbol_name, symbol_offset)) = self
+        let symbols = self
             .symbolicator
             .as_ref()
-            .and_then(|symbolicator| symbolicator.symbolicate(self.link_register))
-        {
-            writeln!(
-                f,
-                "\t[0] = {} - {} (+0x{:x})",
-                self.link_register, symbol_name, symbol_offset
-            )?;
-        } else {
-            writeln!(f, "\t[0] = {}", self.link_register)?;
-        }
+            .map(|symbolicator| symbolicator.symbolicate_all(self.link_register))
+            .unwrap_or_default();
+        write_frame(f, 0, self.link_register, &symbols)?;
 
-        for (level, (frame, symbol)) in iter.enumerate() {
-            let level = -(level as isize + 1);
-            if let Some((symbol_name, symbol_offset)) = symbol {
-                writeln!(
-                    f,
-                    "\t[{}] = {} - {} (+0x{:x})",
-                    level, frame, symbol_name, symbol_offset
-                )?;
-            } else {
-                writeln!(f, "\t[{}] = {}", level, frame)?;
-            }
+        for (level, (frame, symbols)) in iter.enumerate() {
+            write_frame(f, -(level as isize + 1), frame, &symbols)?;
         }
         Ok(())
     }
@@ -144,10 +201,20 @@ pub mod ksyms {
         pub const NUM_SYMBOLS_OFFSET: usize = 0x08;
         pub const SYMBOL_TABLE_OFFSET_OFFSET: usize = 0x0C;
         pub const STRING_TABLE_OFFSET_OFFSET: usize = 0x10;
+        pub const FLAGS_OFFSET: usize = 0x14;
+        pub const NUM_INLINE_ENTRIES_OFFSET: usize = 0x18;
+        pub const INLINE_TABLE_OFFSET_OFFSET: usize = 0x1C;
+
+        pub const SIZE: usize = 0x20;
 
-        pub const SIZE: usize = 0x14;
+        /// Names are stored mangled and need to be demangled by the kernel
+        pub const FLAG_MANGLED_NAMES: u32 = 1 << 0;
     }
 
+    /// Entries of both the symbol table and the inline table share this layout. Symbol table
+    /// entries describe functions in the binary. Inline table entries describe the range of
+    /// addresses where a function was inlined into its caller. Inlined ranges may be nested and are
+    /// sorted by address.
     mod entry {
         pub const ENTRY_NAME_OFFSET_OFFSET: usize = 0x00;
         pub const ENTRY_NAME_LENGTH_OFFSET: usize = 0x04;
@@ -183,35 +250,18 @@ pub mod ksyms {
     pub struct KSyms {
         base_address: VirtualAddress,
         symbol_table_data: &'static [u8],
+        inline_table_data: &'static [u8],
         string_table_data: &'static [u8],
+        mangled_names: bool,
     }
 
-    pub(crate) fn parse(data: &'static [u8]) -> Result<usize, ()> {
-        if data[header::MAGIC_OFFSET..header::MAGIC_OFFSET + core::mem::size_of_val(&header::MAGIC)]
-            != header::MAGIC
-        {
-            return Err(());
-        }
-
-        let header = &data[..header::SIZE];
-
-        let filesize = read_u32!(header, header::FILESIZE_OFFSET) as usize;
-        let data = &data[..filesize];
-
-        let symbol_table_offset = read_u32!(header, header::SYMBOL_TABLE_OFFSET_OFFSET) as usize;
-        let num_symbols = read_u32!(header, header::NUM_SYMBOLS_OFFSET) as usize;
-        let string_table_offset = read_u32!(header, header::STRING_TABLE_OFFSET_OFFSET) as usize;
-
-        let symbol_table_data =
-            &data[symbol_table_offset..symbol_table_offset + num_symbols * entry::SIZE];
-
-        let string_table_data = &data[string_table_offset..];
+    /// Returns the demangled name of a symbol. Non-Rust symbols are returned unchanged.
+    fn demangled_name(name: &str) -> String {
+        alloc::format!("{:#}", rustc_demangle::demangle(name))
+    }
 
-        let ksyms = KSyms {
-            base_address: init::get_base(),
-            symbol_table_data,
-            string_table_data,
-        };
+    pub(crate) fn parse(data: &'static [u8]) -> Result<usize, ()> {
+        let (ksyms, filesize) = KSyms::from_data(data, init::get_base())?;
 
         let prev_syms = KSYMS.lock_write().replace(ksyms);
         assert!(prev_syms.is_none(), "KSyms are duplicated in payload!");
@@ -226,6 +276,52 @@ pub mod ksyms {
     }
 
     impl KSyms {
+        /// Parses a symbol file generated by the stripper. Returns the symbols and the size of the
+        /// symbol file.
+        fn from_data(
+            data: &'static [u8],
+            base_address: VirtualAddress,
+        ) -> Result<(Self, usize), ()> {
+            if data[header::MAGIC_OFFSET
+                ..header::MAGIC_OFFSET + core::mem::size_of_val(&header::MAGIC)]
+                != header::MAGIC
+            {
+                return Err(());
+            }
+
+            let header = &data[..header::SIZE];
+
+            let filesize = read_u32!(header, header::FILESIZE_OFFSET) as usize;
+            let data = &data[..filesize];
+
+            let symbol_table_offset =
+                read_u32!(header, header::SYMBOL_TABLE_OFFSET_OFFSET) as usize;
+            let num_symbols = read_u32!(header, header::NUM_SYMBOLS_OFFSET) as usize;
+            let string_table_offset =
+                read_u32!(header, header::STRING_TABLE_OFFSET_OFFSET) as usize;
+            let flags = read_u32!(header, header::FLAGS_OFFSET);
+            let num_inline_entries = read_u32!(header, header::NUM_INLINE_ENTRIES_OFFSET) as usize;
+            let inline_table_offset =
+                read_u32!(header, header::INLINE_TABLE_OFFSET_OFFSET) as usize;
+
+            let symbol_table_data =
+                &data[symbol_table_offset..symbol_table_offset + num_symbols * entry::SIZE];
+            let inline_table_data =
+                &data[inline_table_offset..inline_table_offset + num_inline_entries * entry::SIZE];
+
+            let string_table_data = &data[string_table_offset..];
+
+            let ksyms = KSyms {
+                base_address,
+                symbol_table_data,
+                inline_table_data,
+                string_table_data,
+                mangled_names: (flags & header::FLAG_MANGLED_NAMES) != 0,
+            };
+
+            Ok((ksyms, filesize))
+        }
+
         fn get_name(&self, name_offset: usize, name_length: usize) -> Option<&str> {
             let data = &self.string_table_data[name_offset..name_offset + name_length];
             core::str::from_utf8(data).ok()
@@ -243,18 +339,19 @@ pub mod ksyms {
                 let name_offset = read_u32!(entry_data, entry::ENTRY_NAME_OFFSET_OFFSET) as usize;
                 let name_length = read_u32!(entry_data, entry::ENTRY_NAME_LENGTH_OFFSET) as usize;
 
-                EntryMatch::Match(
-                    self.get_name(name_offset, name_length)
-                        .map(|name| (name.to_string(), addr - symbol_start)),
-                )
+                EntryMatch::Match(self.get_name(name_offset, name_length).map(|name| {
+                    let name = if self.mangled_names {
+                        demangled_name(name)
+                    } else {
+                        name.to_string()
+                    };
+                    (name, addr - symbol_start)
+                }))
             }
         }
-    }
-
-    impl Symbolicator for KSyms {
-        fn symbolicate(&self, addr: VirtualAddress) -> Option<(String, usize)> {
-            let addr = addr.remove_base(self.base_address).as_usize();
 
+        /// Looks up the symbol containing `addr`, given as an offset from the kernel base address.
+        fn symbolicate_offset(&self, addr: usize) -> Option<(String, usize)> {
             let mut symbol_table_data = self.symbol_table_data;
             loop {
                 let num_entries = symbol_table_data.len() / entry::SIZE;
@@ -290,9 +387,147 @@ pub mod ksyms {
         }
     }
 
+    impl Symbolicator for KSyms {
+        fn symbolicate(&self, addr: VirtualAddress) -> Option<(String, usize)> {
+            let addr = addr.remove_base(self.base_address).as_usize();
+            self.symbolicate_offset(addr)
+        }
+
+        fn symbolicate_all(&self, addr: VirtualAddress) -> Vec<(String, usize)> {
+            let addr = addr.remove_base(self.base_address).as_usize();
+
+            // Nested inlined ranges are narrower than the ones containing them, so sorting by size
+            // puts the innermost function first.
+            let mut inlined: Vec<(usize, (String, usize))> = self
+                .inline_table_data
+                .chunks_exact(entry::SIZE)
+                .filter_map(|entry_data| match self.matches_entry(entry_data, addr) {
+                    EntryMatch::Match(Some(symbol)) => Some((
+                        read_u64!(entry_data, entry::ENTRY_SIZE_OFFSET) as usize,
+                        symbol,
+                    )),
+                    _ => None,
+                })
+                .collect();
+            inlined.sort_by_key(|(size, _)| *size);
+
+            inlined
+                .into_iter()
+                .map(|(_, symbol)| symbol)
+                .chain(self.symbolicate_offset(addr))
+                .collect()
+        }
+    }
+
     pub fn symbolicator() -> Option<KSyms> {
         KSYMS.lock_read().as_ref().cloned()
     }
+
+    #[cfg(test)]
+    mod test {
+        use super::*;
+
+        #[test]
+        fn demangles_symbol_names() {
+            assert_eq!(
+                demangled_name("_ZN11p1c0_kernel7process7Builder5start17h0123456789abcdefE"),
+                "p1c0_kernel::process::Builder::start"
+            );
+
+            // Names that are not mangled are left alone
+            assert_eq!(demangled_name("kernel_main"), "kernel_main");
+        }
+
+        /// Builds a symbol file with the same layout the stripper generates. Entries are given
+        /// as (name, address, size).
+        fn symbol_file(symbols: &[(&str, u64, u64)], inlined: &[(&str, u64, u64)]) -> KSyms {
+            let mut string_table = vec![];
+            let mut write_entries = |data: &mut Vec<u8>, entries: &[(&str, u64, u64)]| {
+                for (name, address, size) in entries {
+                    data.extend_from_slice(&(string_table.len() as u32).to_le_bytes());
+                    data.extend_from_slice(&(name.len() as u32).to_le_bytes());
+                    data.extend_from_slice(&address.to_le_bytes());
+                    data.extend_from_slice(&size.to_le_bytes());
+                    string_table.extend_from_slice(name.as_bytes());
+                }
+            };
+
+            let mut tables = vec![];
+            write_entries(&mut tables, symbols);
+            write_entries(&mut tables, inlined);
+
+            let symbol_table_offset = header::SIZE;
+            let inline_table_offset = symbol_table_offset + symbols.len() * entry::SIZE;
+            let string_table_offset = inline_table_offset + inlined.len() * entry::SIZE;
+            let filesize = string_table_offset + string_table.len();
+
+            let mut data = vec![];
+            data.extend_from_slice(&header::MAGIC);
+            for value in [
+                filesize,
+                symbols.len(),
+                symbol_table_offset,
+                string_table_offset,
+                0,
+                inlined.len(),
+                inline_table_offset,
+            ] {
+                data.extend_from_slice(&(value as u32).to_le_bytes());
+            }
+            data.extend_from_slice(&tables);
+            data.extend_from_slice(&string_table);
+
+            let (ksyms, size) = KSyms::from_data(
+                Box::leak(data.into_boxed_slice()),
+                VirtualAddress::new_unaligned(core::ptr::null()),
+            )
+            .unwrap();
+            assert_eq!(size, filesize);
+            ksyms
+        }
+
+        fn addr(offset: usize) -> VirtualAddress {
+            VirtualAddress::new_unaligned(offset as *const _)
+        }
+
+        #[test]
+        fn symbolicates_inlined_functions() {
+            let ksyms = symbol_file(
+                &[("kernel_main", 0x1000, 0x100), ("other", 0x1100, 0x10)],
+                &[
+                    ("outer_inlined", 0x1010, 0x40),
+                    ("inner_inlined", 0x1020, 0x8),
+                ],
+            );
+
+            assert_eq!(
+                ksyms.symbolicate_all(addr(0x1024)),
+                vec![
+                    ("inner_inlined".to_string(), 0x4),
+                    ("outer_inlined".to_string(), 0x14),
+                    ("kernel_main".to_string(), 0x24),
+                ]
+            );
+            assert_eq!(
+                ksyms.symbolicate_all(addr(0x1030)),
+                vec![
+                    ("outer_inlined".to_string(), 0x20),
+                    ("kernel_main".to_string(), 0x30),
+                ]
+            );
+
+            // symbolicate only reports the function in the symbol table
+            assert_eq!(
+                ksyms.symbolicate(addr(0x1024)),
+                Some(("kernel_main".to_string(), 0x24))
+            );
+            assert_eq!(
+                ksyms.symbolicate_all(addr(0x1104)),
+                vec![("other".to_string(), 0x4)]
+            );
+            assert!(ksyms.symbolicate_all(addr(0x2000)).is_empty());
+        }
+    }
 }
 
 #[inline(always)]
@@ -309,3 +544,104 @@ pub fn kernel_backtracer() -> Option<Backtracer<crate::thread::StackValidator, k
         None
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[derive(Clone)]
+    struct RangeValidator {
+        start: usize,
+        end: usize,
+    }
+
+    impl RangeValidator {
+        fn new(frames: &[Frame]) -> Self {
+            let start = frames.as_ptr() as usize;
+            Self {
+                start,
+                end: start + core::mem::size_of_val(frames),
+            }
+        }
+    }
+
+    impl Validator for RangeValidator {
+        fn is_valid(&self, va: VirtualAddress) -> bool {
+            (va.as_usize() >= self.start) && (va.as_usize() < self.end)
+        }
+    }
+
+    #[derive(Clone)]
+    struct NoSymbols;
+
+    impl Symbolicator for NoSymbols {
+        fn symbolicate(&self, _addr: VirtualAddress) -> Option<(String, usize)> {
+            None
+        }
+    }
+
+    fn lr(value: usize) -> *const u8 {
+        value as *const u8
+    }
+
+    /// Builds a chain of frames where each frame points to the next one in the slice, like a
+    /// regular stack would.
+    fn frame_chain(num_frames: usize) -> Vec<Frame> {
+        let mut frames: Vec<Frame> = (0..num_frames)
+            .map(|i| Frame {
+                next: core::ptr::null(),
+                lr: lr(0x1000 + i),
+            })
+            .collect();
+        for i in 0..num_frames - 1 {
+            frames[i].next = &frames[i + 1] as *const _;
+        }
+        frames
+    }
+
+    fn walk(frames: &[Frame], first: usize) -> Vec<usize> {
+        let frame_ptr = VirtualAddress::new_unaligned(&frames[first] as *const _ as *const _);
+        StackFrameIter::new(frame_ptr, RangeValidator::new(frames), None::<NoSymbols>)
+            .map(|(lr, symbol)| {
+                assert!(symbol.is_empty());
+                lr.as_usize()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn walks_frame_chain() {
+        let frames = frame_chain(4);
+        assert_eq!(walk(&frames, 0), vec![0x1000, 0x1001, 0x1002, 0x1003]);
+    }
+
+    #[test]
+    fn stops_on_cyclic_chain() {
+        let mut frames = frame_chain(4);
+        frames[3].next = &frames[1] as *const _;
+        assert_eq!(walk(&frames, 0), vec![0x1000, 0x1001, 0x1002, 0x1003]);
+
+        // A frame pointing to itself
+        frames[1].next = &frames[1] as *const _;
+        assert_eq!(walk(&frames, 0), vec![0x1000, 0x1001]);
+    }
+
+    #[test]
+    fn stops_outside_of_stack() {
+        let mut frames = frame_chain(4);
+        frames[2].next = usize::MAX as *const _;
+        assert_eq!(walk(&frames, 0), vec![0x1000, 0x1001, 0x1002]);
+
+        // Misaligned frame pointers are not dereferenced either
+        frames[1].next = (&frames[2] as *const _ as usize + 1) as *const _;
+        assert_eq!(walk(&frames, 0), vec![0x1000, 0x1001]);
+    }
+
+    #[test]
+    fn limits_number_of_frames() {
+        let frames = frame_chain(MAX_FRAMES + 8);
+        let lrs = walk(&frames, 0);
+        assert_eq!(lrs.len(), MAX_FRAMES);
+        assert_eq!(lrs[MAX_FRAMES - 1], 0x1000 + MAX_FRAMES - 1);
+    }
+}
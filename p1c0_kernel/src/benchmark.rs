@@ -0,0 +1,294 @@
+//! Microbenchmarks of the scheduler and of interrupt handling, run with the `bench` shell command.
+//!
+//! Every benchmark prints a single line with its statistics, so that results can be collected
+//! from the log:
+//!
+//! ```text
+//! bench name=context_switch samples=1000 min_ns=1250 avg_ns=1833 max_ns=20041
+//! ```
+
+use crate::{
+    drivers::{
+        generic_timer::{self, get_timer},
+        interfaces::{timer::Timer, Ticks},
+    },
+    prelude::*,
+    shell,
+    syscall::Syscall,
+    thread,
+};
+
+use core::{
+    fmt,
+    sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use p1c0_macros::initcall;
+
+const DEFAULT_SAMPLES: usize = 1000;
+
+/// Timer interrupts happen once per jiffy, so there are much fewer samples of them
+const DEFAULT_IRQ_SAMPLES: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stats {
+    pub samples: u64,
+    pub min: Duration,
+    pub avg: Duration,
+    pub max: Duration,
+}
+
+/// Output line of a benchmark
+pub struct Report<'a> {
+    pub name: &'a str,
+    pub stats: Stats,
+}
+
+impl fmt::Display for Report<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "bench name={} samples={} min_ns={} avg_ns={} max_ns={}",
+            self.name,
+            self.stats.samples,
+            self.stats.min.as_nanos(),
+            self.stats.avg.as_nanos(),
+            self.stats.max.as_nanos()
+        )
+    }
+}
+
+/// Accumulates durations measured with the timer. It does not lock, so samples can be recorded from
+/// exception handlers.
+pub struct Recorder {
+    samples: AtomicU64,
+    total: AtomicU64,
+    min: AtomicU64,
+    max: AtomicU64,
+}
+
+impl Recorder {
+    pub const fn new() -> Self {
+        Self {
+            samples: AtomicU64::new(0),
+            total: AtomicU64::new(0),
+            min: AtomicU64::new(u64::MAX),
+            max: AtomicU64::new(0),
+        }
+    }
+
+    pub fn reset(&self) {
+        self.samples.store(0, Ordering::Relaxed);
+        self.total.store(0, Ordering::Relaxed);
+        self.min.store(u64::MAX, Ordering::Relaxed);
+        self.max.store(0, Ordering::Relaxed);
+    }
+
+    /// Records the time elapsed between two readings of the timer
+    pub fn record_ticks(&self, start: Ticks, end: Ticks) {
+        let elapsed = get_timer()
+            .resolution()
+            .ticks_to_duration(end.saturating_sub(start));
+        self.record(elapsed);
+    }
+
+    pub fn record(&self, elapsed: Duration) {
+        let elapsed = elapsed.as_nanos() as u64;
+        self.total.fetch_add(elapsed, Ordering::Relaxed);
+        self.min.fetch_min(elapsed, Ordering::Relaxed);
+        self.max.fetch_max(elapsed, Ordering::Relaxed);
+        self.samples.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn num_samples(&self) -> u64 {
+        self.samples.load(Ordering::Relaxed)
+    }
+
+    /// Returns `None` if nothing was recorded
+    pub fn stats(&self) -> Option<Stats> {
+        let samples = self.num_samples();
+        if samples == 0 {
+            return None;
+        }
+
+        Some(Stats {
+            samples,
+            min: Duration::from_nanos(self.min.load(Ordering::Relaxed)),
+            avg: Duration::from_nanos(self.total.load(Ordering::Relaxed) / samples),
+            max: Duration::from_nanos(self.max.load(Ordering::Relaxed)),
+        })
+    }
+}
+
+impl Default for Recorder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+static CONTEXT_SWITCH: Recorder = Recorder::new();
+static TURN: AtomicUsize = AtomicUsize::new(0);
+
+/// Takes the turn from the other thread `iterations` times. The first thread measures how long it
+/// takes to get the turn back, which includes two context switches.
+fn ping_pong(me: usize, iterations: usize) {
+    let timer = get_timer();
+
+    for _ in 0..iterations {
+        while TURN.load(Ordering::Acquire) != me {
+            Syscall::yield_exec();
+        }
+
+        let handoff = timer.ticks();
+        TURN.store(1 - me, Ordering::Release);
+        Syscall::yield_exec();
+
+        if me == 0 {
+            while TURN.load(Ordering::Acquire) != me {
+                Syscall::yield_exec();
+            }
+            let round_trip = timer
+                .resolution()
+                .ticks_to_duration(timer.ticks().saturating_sub(handoff));
+            CONTEXT_SWITCH.record(round_trip / 2);
+        }
+    }
+}
+
+/// Measures the time it takes to switch to another thread that is ready to run.
+pub fn context_switch(iterations: usize) -> Option<Stats> {
+    CONTEXT_SWITCH.reset();
+    TURN.store(0, Ordering::Release);
+
+    let threads = [
+        thread::Builder::new()
+            .name("bench-ping")
+            .spawn(move || ping_pong(0, iterations)),
+        thread::Builder::new()
+            .name("bench-pong")
+            .spawn(move || ping_pong(1, iterations)),
+    ];
+    for thread in threads {
+        thread.join();
+    }
+
+    CONTEXT_SWITCH.stats()
+}
+
+static IRQ_LATENCY: Recorder = Recorder::new();
+static RECORD_IRQ_LATENCY: AtomicBool = AtomicBool::new(false);
+
+/// Called by the FIQ handler when the timer interrupt is active, before acknowledging it.
+#[cfg_attr(test, allow(dead_code))]
+pub(crate) fn on_timer_irq() {
+    if RECORD_IRQ_LATENCY.load(Ordering::Relaxed) {
+        let latency = get_timer()
+            .resolution()
+            .ticks_to_duration(generic_timer::ticks_since_irq_deadline());
+        IRQ_LATENCY.record(latency);
+    }
+}
+
+/// Measures the time from the timer interrupt being due until its handler runs.
+pub fn irq_latency(samples: usize) -> Option<Stats> {
+    IRQ_LATENCY.reset();
+    RECORD_IRQ_LATENCY.store(true, Ordering::Relaxed);
+    while IRQ_LATENCY.num_samples() < samples as u64 {
+        Syscall::yield_exec();
+    }
+    RECORD_IRQ_LATENCY.store(false, Ordering::Relaxed);
+
+    IRQ_LATENCY.stats()
+}
+
+fn print_report(name: &str, stats: Option<Stats>) {
+    match stats {
+        Some(stats) => println!("{}", Report { name, stats }),
+        None => println!("bench name={} samples=0", name),
+    }
+}
+
+/// `bench [samples]` runs all benchmarks.
+fn bench_command(args: &[&str]) {
+    let samples = match args.first().map(|samples| samples.parse::<usize>()) {
+        None => None,
+        Some(Ok(samples)) if samples > 0 => Some(samples),
+        Some(_) => {
+            println!("Usage: bench [samples]");
+            return;
+        }
+    };
+
+    print_report(
+        "context_switch",
+        context_switch(samples.unwrap_or(DEFAULT_SAMPLES)),
+    );
+    print_report(
+        "irq_latency",
+        irq_latency(samples.unwrap_or(DEFAULT_IRQ_SAMPLES)),
+    );
+}
+
+#[initcall(priority = 0)]
+fn benchmark_register_commands() {
+    shell::register_command("bench", bench_command).unwrap();
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn no_samples() {
+        let recorder = Recorder::new();
+        assert_eq!(recorder.stats(), None);
+
+        recorder.record(Duration::from_micros(1));
+        recorder.reset();
+        assert_eq!(recorder.stats(), None);
+    }
+
+    #[test]
+    fn statistics_from_mock_timer() {
+        let timer = get_timer();
+        let recorder = Recorder::new();
+
+        for elapsed_us in [30, 10, 20] {
+            let start = timer.ticks();
+            timer.advance(Duration::from_micros(elapsed_us));
+            recorder.record_ticks(start, timer.ticks());
+        }
+
+        let stats = recorder.stats().unwrap();
+        assert_eq!(
+            stats,
+            Stats {
+                samples: 3,
+                min: Duration::from_micros(10),
+                avg: Duration::from_micros(20),
+                max: Duration::from_micros(30),
+            }
+        );
+        assert_eq!(
+            Report {
+                name: "context_switch",
+                stats
+            }
+            .to_string(),
+            "bench name=context_switch samples=3 min_ns=10000 avg_ns=20000 max_ns=30000"
+        );
+    }
+
+    #[test]
+    fn average_rounds_down() {
+        let recorder = Recorder::new();
+        recorder.record(Duration::from_nanos(1));
+        recorder.record(Duration::from_nanos(2));
+
+        let stats = recorder.stats().unwrap();
+        assert_eq!(stats.avg, Duration::from_nanos(1));
+        assert_eq!(stats.min, Duration::from_nanos(1));
+        assert_eq!(stats.max, Duration::from_nanos(2));
+    }
+}
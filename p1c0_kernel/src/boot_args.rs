@@ -1,3 +1,7 @@
+use crate::{adt, memory::address::Address, prelude::*};
+
+use core::ops::RangeInclusive;
+
 #[repr(C)]
 #[derive(Clone, Debug)]
 pub struct BootVideoArgs {
@@ -9,6 +13,87 @@ pub struct BootVideoArgs {
     pub depth: usize,
 }
 
+/// Bit of `BootVideoArgs::depth` set for high density (retina) displays
+pub const RETINA_DEPTH_FLAG: usize = 1 << 16;
+const DEPTH_BITS_PER_PIXEL_MASK: usize = 0xFF;
+
+/// Upper bound for the size of a framebuffer. Enough for a 6K display with 4 bytes per pixel.
+pub const MAX_FRAMEBUFFER_SIZE: usize = 128 * 1024 * 1024;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum FramebufferError {
+    NullBase,
+    EmptyFramebuffer,
+    InvalidDepth(usize),
+    StrideTooSmall { stride: usize, min_stride: usize },
+    TooLarge(usize),
+}
+
+/// Framebuffer described by `BootVideoArgs`, after checking that its dimensions are consistent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Framebuffer {
+    pub base: *mut u8,
+    pub width: usize,
+    pub height: usize,
+    /// Bytes between the start of two consecutive rows
+    pub stride: usize,
+    pub bytes_per_pixel: usize,
+    pub retina: bool,
+}
+
+impl Framebuffer {
+    pub fn size_bytes(&self) -> usize {
+        self.height * self.stride
+    }
+}
+
+impl BootVideoArgs {
+    /// Returns the framebuffer described by the arguments if all of it can be written without
+    /// exceeding `MAX_FRAMEBUFFER_SIZE` bytes from its base.
+    pub fn validate(&self) -> Result<Framebuffer, FramebufferError> {
+        if self.base.is_null() {
+            return Err(FramebufferError::NullBase);
+        }
+        if self.width == 0 || self.height == 0 {
+            return Err(FramebufferError::EmptyFramebuffer);
+        }
+
+        let bits_per_pixel = self.depth & DEPTH_BITS_PER_PIXEL_MASK;
+        if bits_per_pixel == 0 {
+            return Err(FramebufferError::InvalidDepth(self.depth));
+        }
+        let bytes_per_pixel = (bits_per_pixel + 7) / 8;
+
+        let min_stride = self
+            .width
+            .checked_mul(bytes_per_pixel)
+            .ok_or(FramebufferError::TooLarge(usize::MAX))?;
+        if self.stride < min_stride {
+            return Err(FramebufferError::StrideTooSmall {
+                stride: self.stride,
+                min_stride,
+            });
+        }
+
+        let size = self
+            .height
+            .checked_mul(self.stride)
+            .ok_or(FramebufferError::TooLarge(usize::MAX))?;
+        if size > MAX_FRAMEBUFFER_SIZE {
+            return Err(FramebufferError::TooLarge(size));
+        }
+
+        Ok(Framebuffer {
+            base: self.base,
+            width: self.width,
+            height: self.height,
+            stride: self.stride,
+            bytes_per_pixel,
+            retina: (self.depth & RETINA_DEPTH_FLAG) != 0,
+        })
+    }
+}
+
 #[repr(C)]
 #[derive(Clone, Debug)]
 pub struct BootArgs {
@@ -27,6 +112,18 @@ pub struct BootArgs {
     pub mem_size_actual: u64,
 }
 
+/// Revisions of the boot arguments struct whose layout matches `BootArgs`
+pub const SUPPORTED_REVISIONS: RangeInclusive<u16> = 0..=3;
+/// Versions of the boot arguments struct whose layout matches `BootArgs`. Version 2 introduced the
+/// 608 byte command line; later versions only append fields.
+pub const SUPPORTED_VERSIONS: RangeInclusive<u16> = 2..=3;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Error {
+    UnsupportedRevision(u16),
+    UnsupportedVersion(u16),
+}
+
 static mut BOOT_ARGS: Option<BootArgs> = None;
 
 /// Assumes that set_boot_args has been called and panics if the option is None
@@ -34,6 +131,22 @@ pub fn get_boot_args() -> &'static BootArgs {
     unsafe { BOOT_ARGS.as_ref().expect("Boot args are set") }
 }
 
+/// Returns the device tree passed by the bootloader. Before the MMU is initialized it is read from
+/// its physical address, afterwards from the read-only mapping at `ADT_VIRTUAL_BASE`.
+pub fn device_tree_slice() -> &'static [u8] {
+    let boot_args = get_boot_args();
+    if !crate::arch::mmu::is_initialized() {
+        unsafe { boot_args.device_tree_at_phys_addr() }
+    } else {
+        unsafe {
+            core::slice::from_raw_parts(
+                crate::memory::map::ADT_VIRTUAL_BASE.as_ptr(),
+                boot_args.device_tree_size(),
+            )
+        }
+    }
+}
+
 /// Must be called by the init code of the processor.
 /// SAFETY
 ///   This shall only be called right after booting where no-one has already accessed the boot
@@ -41,3 +154,321 @@ pub fn get_boot_args() -> &'static BootArgs {
 pub(crate) unsafe fn set_boot_args(boot_args: &BootArgs) {
     BOOT_ARGS.replace(boot_args.clone());
 }
+
+impl BootArgs {
+    /// Checks that the layout of the struct the bootloader passed is the one of `BootArgs`.
+    /// Otherwise every field after the version may be read from the wrong offset.
+    pub fn validate(&self) -> Result<(), Error> {
+        if !SUPPORTED_REVISIONS.contains(&self.revision) {
+            return Err(Error::UnsupportedRevision(self.revision));
+        }
+        if !SUPPORTED_VERSIONS.contains(&self.version) {
+            return Err(Error::UnsupportedVersion(self.version));
+        }
+        Ok(())
+    }
+
+    /// Physical address of the device tree. The bootloader passes a pointer relative to
+    /// `virt_base`.
+    pub fn device_tree_phys_addr(&self) -> usize {
+        self.device_tree as usize - self.virt_base + self.phys_base
+    }
+
+    pub fn device_tree_size(&self) -> usize {
+        self.device_tree_size as usize
+    }
+
+    /// Returns the device tree at its physical address.
+    /// # Safety
+    ///   The physical address of the device tree must be accessible, which is only the case while
+    ///   the identity mapping is present.
+    pub unsafe fn device_tree_at_phys_addr(&self) -> &'static [u8] {
+        core::slice::from_raw_parts(
+            self.device_tree_phys_addr() as *const u8,
+            self.device_tree_size(),
+        )
+    }
+
+    /// Returns the command line in the boot arguments struct, up to the first NUL character. Prefer
+    /// `cmdline`, which also considers the ADT.
+    pub fn cmdline(&self) -> &str {
+        cmdline_from_raw(&self.cmdline)
+    }
+
+    /// Returns true if `flag` is one of the whitespace-separated arguments of the command line.
+    pub fn has_cmdline_flag(&self, flag: &str) -> bool {
+        self.cmdline().split_whitespace().any(|arg| arg == flag)
+    }
+}
+
+fn cmdline_from_raw(raw: &[u8]) -> &str {
+    let length = raw.iter().position(|c| *c == 0).unwrap_or(raw.len());
+    core::str::from_utf8(&raw[..length]).unwrap_or("")
+}
+
+/// Splits the command line in whitespace-separated arguments. Whitespace between double quotes is
+/// part of the argument and the quotes are removed, so `key="a b"` is a single argument.
+fn split_args(cmdline: &str) -> Vec<String> {
+    let mut args = vec![];
+    let mut current = String::new();
+    let mut in_arg = false;
+    let mut in_quotes = false;
+
+    for c in cmdline.chars() {
+        match c {
+            '"' => {
+                in_quotes = !in_quotes;
+                in_arg = true;
+            }
+            c if c.is_whitespace() && !in_quotes => {
+                if in_arg {
+                    args.push(core::mem::take(&mut current));
+                    in_arg = false;
+                }
+            }
+            c => {
+                current.push(c);
+                in_arg = true;
+            }
+        }
+    }
+
+    if in_arg {
+        args.push(current);
+    }
+    args
+}
+
+/// Parses `key=value` arguments. Arguments without `=` are flags and map to an empty value. If a
+/// key is repeated the last value is kept.
+fn parse(cmdline: &str) -> FlatMap<String, String> {
+    let mut args = FlatMap::new();
+    for arg in split_args(cmdline) {
+        let (key, value) = match arg.split_once('=') {
+            Some((key, value)) => (key, value),
+            None => (arg.as_str(), ""),
+        };
+        if !key.is_empty() {
+            args.insert(key.to_string(), value.to_string());
+        }
+    }
+    args
+}
+
+/// A flag is set if it is present without a value or with a value other than `0`, `false`, `no`
+/// or `off`.
+fn is_flag_set(args: &FlatMap<String, String>, name: &str) -> bool {
+    match args.lookup(name) {
+        Some(value) => !matches!(value.as_str(), "0" | "false" | "no" | "off"),
+        None => false,
+    }
+}
+
+/// The ADT is where the bootloader stores the command line on hardware, the boot arguments struct
+/// is used as a fallback.
+fn select_cmdline(
+    adt_bootargs: Option<&'static str>,
+    boot_args: &'static BootArgs,
+) -> &'static str {
+    adt_bootargs.unwrap_or_else(|| boot_args.cmdline())
+}
+
+/// Returns the kernel command line, preferably from the `bootargs` property of the ADT `/chosen`
+/// node, or from the boot arguments otherwise.
+pub fn cmdline() -> &'static str {
+    let adt_bootargs = adt::get_adt().ok().and_then(|adt| adt.bootargs());
+    select_cmdline(adt_bootargs, get_boot_args())
+}
+
+/// Parses the `key=value` arguments of the kernel command line.
+pub fn parse_cmdline() -> FlatMap<String, String> {
+    parse(cmdline())
+}
+
+/// Returns the value of a `key=value` argument of the kernel command line.
+pub fn get_value(key: &str) -> Option<String> {
+    parse_cmdline().lookup(key).cloned()
+}
+
+/// Returns true if the flag is set in the kernel command line, like `quiet` or `quiet=1`.
+pub fn get_flag(name: &str) -> bool {
+    is_flag_set(&parse_cmdline(), name)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn boot_args_with_cmdline(cmdline: &str) -> BootArgs {
+        // All fields are integers, pointers or arrays of integers, so all zeroes is a valid value
+        let mut boot_args: BootArgs = unsafe { core::mem::zeroed() };
+        boot_args.version = *SUPPORTED_VERSIONS.start();
+        boot_args.cmdline[..cmdline.len()].copy_from_slice(cmdline.as_bytes());
+        boot_args
+    }
+
+    #[test]
+    fn validates_layout() {
+        let mut boot_args = boot_args_with_cmdline("");
+        assert_eq!(boot_args.validate(), Ok(()));
+        boot_args.revision = *SUPPORTED_REVISIONS.end();
+        boot_args.version = *SUPPORTED_VERSIONS.end();
+        assert_eq!(boot_args.validate(), Ok(()));
+
+        boot_args.revision = SUPPORTED_REVISIONS.end() + 1;
+        assert_eq!(
+            boot_args.validate(),
+            Err(Error::UnsupportedRevision(SUPPORTED_REVISIONS.end() + 1))
+        );
+
+        boot_args.revision = *SUPPORTED_REVISIONS.start();
+        boot_args.version = 1;
+        assert_eq!(boot_args.validate(), Err(Error::UnsupportedVersion(1)));
+    }
+
+    #[test]
+    fn parses_cmdline() {
+        let boot_args = boot_args_with_cmdline("debug=0x14e  p1c0.selftest");
+        assert_eq!(boot_args.cmdline(), "debug=0x14e  p1c0.selftest");
+        assert!(boot_args.has_cmdline_flag("p1c0.selftest"));
+        assert!(!boot_args.has_cmdline_flag("debug"));
+        assert!(!boot_args.has_cmdline_flag("p1c0"));
+
+        assert_eq!(boot_args_with_cmdline("").cmdline(), "");
+
+        // Invalid UTF-8 is ignored
+        assert_eq!(cmdline_from_raw(&[0xff, 0xfe, 0]), "");
+    }
+
+    #[test]
+    fn cmdline_sources() {
+        let boot_args: &'static BootArgs = Box::leak(Box::new(boot_args_with_cmdline("serial=0")));
+        assert_eq!(select_cmdline(Some("quiet"), boot_args), "quiet");
+        assert_eq!(select_cmdline(None, boot_args), "serial=0");
+
+        // The whole array is used if there is no NUL terminator
+        let mut boot_args = boot_args_with_cmdline("");
+        boot_args.cmdline.fill(b'a');
+        let boot_args: &'static BootArgs = Box::leak(Box::new(boot_args));
+        assert_eq!(select_cmdline(None, boot_args).len(), 608);
+    }
+
+    #[test]
+    fn parses_key_value_pairs() {
+        let args = parse("debug=0x14e  quiet serial=\"uart0 115200\" \"path=/a b\" =x");
+        assert_eq!(args.len(), 4);
+        assert_eq!(args.lookup("debug").unwrap(), "0x14e");
+        assert_eq!(args.lookup("quiet").unwrap(), "");
+        assert_eq!(args.lookup("serial").unwrap(), "uart0 115200");
+        assert_eq!(args.lookup("path").unwrap(), "/a b");
+
+        // The value may contain `=` and the last occurrence of a key wins
+        let args = parse("root=dev=sda1 level=info level=debug");
+        assert_eq!(args.lookup("root").unwrap(), "dev=sda1");
+        assert_eq!(args.lookup("level").unwrap(), "debug");
+
+        // Unterminated quotes extend to the end of the command line
+        let args = parse("msg=\"hello world");
+        assert_eq!(args.lookup("msg").unwrap(), "hello world");
+
+        // Empty quotes are an empty argument
+        assert_eq!(split_args("a \"\" b"), vec!["a", "", "b"]);
+        assert!(parse("   ").is_empty());
+    }
+
+    #[test]
+    fn flags() {
+        let args = parse("quiet p1c0.selftest=1 p1c0.nowdt=0 verbose=off debug=yes");
+        assert!(is_flag_set(&args, "quiet"));
+        assert!(is_flag_set(&args, "p1c0.selftest"));
+        assert!(!is_flag_set(&args, "p1c0.nowdt"));
+        assert!(!is_flag_set(&args, "verbose"));
+        assert!(is_flag_set(&args, "debug"));
+        assert!(!is_flag_set(&args, "missing"));
+    }
+
+    fn video_args(width: usize, height: usize, stride: usize, depth: usize) -> BootVideoArgs {
+        BootVideoArgs {
+            base: 0x1_0000_0000 as *mut u8,
+            display: 0,
+            stride,
+            width,
+            height,
+            depth,
+        }
+    }
+
+    #[test]
+    fn valid_framebuffer() {
+        // As passed on the M1 Pro
+        let framebuffer = video_args(3024, 1964, 3024 * 4, RETINA_DEPTH_FLAG | 30)
+            .validate()
+            .unwrap();
+        assert_eq!(framebuffer.bytes_per_pixel, 4);
+        assert!(framebuffer.retina);
+        assert_eq!(framebuffer.size_bytes(), 1964 * 3024 * 4);
+
+        // Rows may be padded
+        let framebuffer = video_args(100, 10, 512, 32).validate().unwrap();
+        assert!(!framebuffer.retina);
+        assert_eq!(framebuffer.stride, 512);
+    }
+
+    #[test]
+    fn malformed_framebuffers() {
+        let mut args = video_args(100, 10, 400, 30);
+        args.base = core::ptr::null_mut();
+        assert_eq!(args.validate(), Err(FramebufferError::NullBase));
+
+        assert_eq!(
+            video_args(0, 10, 400, 30).validate(),
+            Err(FramebufferError::EmptyFramebuffer)
+        );
+        assert_eq!(
+            video_args(100, 10, 400, RETINA_DEPTH_FLAG).validate(),
+            Err(FramebufferError::InvalidDepth(RETINA_DEPTH_FLAG))
+        );
+        assert_eq!(
+            video_args(100, 10, 399, 30).validate(),
+            Err(FramebufferError::StrideTooSmall {
+                stride: 399,
+                min_stride: 400
+            })
+        );
+        assert_eq!(
+            video_args(usize::MAX / 2, 10, usize::MAX, 32).validate(),
+            Err(FramebufferError::TooLarge(usize::MAX))
+        );
+        assert_eq!(
+            video_args(100_000, 100_000, 400_000, 32).validate(),
+            Err(FramebufferError::TooLarge(40_000_000_000))
+        );
+    }
+
+    #[test]
+    fn device_tree_from_boot_args() {
+        use crate::adt::test::TestNode;
+
+        let data = TestNode::new("device-tree")
+            .child(TestNode::new("chosen").property("bootargs", b"debug=1\0".to_vec()))
+            .data();
+        let data_ptr = data.as_ptr() as usize;
+        let data_size = core::mem::size_of_val(data);
+
+        let mut boot_args = boot_args_with_cmdline("");
+        boot_args.virt_base = 0x1_0000_0000;
+        boot_args.phys_base = 0x8000_0000;
+        boot_args.device_tree = (data_ptr + 0x8000_0000) as *const u8;
+        boot_args.device_tree_size = data_size as u32;
+
+        assert_eq!(boot_args.device_tree_phys_addr(), data_ptr);
+        assert_eq!(boot_args.device_tree_size(), data_size);
+
+        let device_tree = unsafe { boot_args.device_tree_at_phys_addr() };
+        assert_eq!(device_tree.as_ptr() as usize, data_ptr);
+        assert_eq!(device_tree.len(), data_size);
+
+        let adt = unsafe { adt::Adt::new(device_tree.as_ptr()).unwrap() };
+        assert_eq!(adt.bootargs(), Some("debug=1"));
+    }
+}
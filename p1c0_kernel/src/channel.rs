@@ -0,0 +1,346 @@
+//! Bounded message channels for IPC between threads and processes.
+//!
+//! A channel has two endpoints. Messages sent through the transmit endpoint are queued in order
+//! until they are received through the receive endpoint. Endpoint IDs are derived from the channel
+//! ID, with the transmit endpoint being even and the receive endpoint being odd.
+
+use crate::{arch::mmu::PAGE_SIZE, prelude::*, sync::spinlock::SpinLock};
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Error {
+    InvalidEndpoint,
+    /// The channel already holds `Channel::CAPACITY` messages. The sender should try again later.
+    ChannelFull,
+    /// The other endpoint of the channel was closed.
+    ChannelClosed,
+    InvalidMessageSize,
+    /// The receive buffer cannot hold the next message. The message remains in the channel.
+    BufferTooSmall,
+}
+
+impl Error {
+    /// Value returned to userspace by the channel syscalls to report this error.
+    pub fn code(&self) -> u64 {
+        match self {
+            Error::InvalidEndpoint => ERROR_INVALID_ENDPOINT,
+            Error::ChannelFull => ERROR_AGAIN,
+            Error::ChannelClosed => ERROR_CLOSED,
+            Error::InvalidMessageSize => ERROR_INVALID_SIZE,
+            Error::BufferTooSmall => ERROR_BUFFER_TOO_SMALL,
+        }
+    }
+}
+
+pub const ERROR_INVALID_ENDPOINT: u64 = 0xFFFF;
+pub const ERROR_AGAIN: u64 = 0xFFFE;
+pub const ERROR_CLOSED: u64 = 0xFFFD;
+pub const ERROR_INVALID_SIZE: u64 = 0xFFFC;
+pub const ERROR_BUFFER_TOO_SMALL: u64 = 0xFFFB;
+/// Returned by the channel syscalls if the process cannot access the memory it passed.
+pub const ERROR_FAULT: u64 = 0xFFFA;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Endpoint {
+    Transmit(u64),
+    Receive(u64),
+}
+
+impl Endpoint {
+    pub fn from_raw(id: u64) -> Self {
+        if id & 1 == 0 {
+            Endpoint::Transmit(id >> 1)
+        } else {
+            Endpoint::Receive(id >> 1)
+        }
+    }
+
+    pub fn get_raw(&self) -> u64 {
+        match self {
+            Endpoint::Transmit(channel_id) => channel_id << 1,
+            Endpoint::Receive(channel_id) => (channel_id << 1) | 1,
+        }
+    }
+
+    pub fn channel_id(&self) -> u64 {
+        match self {
+            Endpoint::Transmit(channel_id) | Endpoint::Receive(channel_id) => *channel_id,
+        }
+    }
+}
+
+pub struct Channel {
+    messages: IntrusiveList<Vec<u8>>,
+    transmit_open: bool,
+    receive_open: bool,
+}
+
+impl Channel {
+    /// Maximum number of messages queued in a channel
+    pub const CAPACITY: usize = 16;
+    pub const MAX_MESSAGE_SIZE: usize = PAGE_SIZE;
+
+    pub const fn new() -> Self {
+        Self {
+            messages: IntrusiveList::new(),
+            transmit_open: true,
+            receive_open: true,
+        }
+    }
+
+    pub fn send(&mut self, data: &[u8]) -> Result<(), Error> {
+        if !self.receive_open {
+            return Err(Error::ChannelClosed);
+        }
+
+        if data.len() > Self::MAX_MESSAGE_SIZE {
+            return Err(Error::InvalidMessageSize);
+        }
+
+        if self.messages.len() >= Self::CAPACITY {
+            return Err(Error::ChannelFull);
+        }
+
+        self.messages
+            .push(OwnedMutPtr::new_from_box(Box::new(IntrusiveItem::new(
+                data.to_vec(),
+            ))));
+        Ok(())
+    }
+
+    /// Copies the next message into `buffer` and returns its length. Returns `Ok(None)` if there
+    /// are no messages yet but more may arrive later.
+    pub fn receive(&mut self, buffer: &mut [u8]) -> Result<Option<usize>, Error> {
+        let length = match self.messages.iter().next() {
+            Some(message) => message.len(),
+            None if self.transmit_open => return Ok(None),
+            None => return Err(Error::ChannelClosed),
+        };
+
+        if length > buffer.len() {
+            return Err(Error::BufferTooSmall);
+        }
+
+        let message = unsafe { self.messages.pop().unwrap().into_box() };
+        buffer[..length].copy_from_slice(&message[..]);
+        Ok(Some(length))
+    }
+
+    /// Closes one of the endpoints of the channel. Returns true if both endpoints are closed and
+    /// the channel can be destroyed.
+    fn close(&mut self, endpoint: Endpoint) -> bool {
+        match endpoint {
+            Endpoint::Transmit(_) => self.transmit_open = false,
+            Endpoint::Receive(_) => self.receive_open = false,
+        }
+        !self.transmit_open && !self.receive_open
+    }
+}
+
+impl Default for Channel {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for Channel {
+    fn drop(&mut self) {
+        // Free messages that were never received
+        core::mem::replace(&mut self.messages, IntrusiveList::new()).release(|message| {
+            let _ = unsafe { message.into_box() };
+        });
+    }
+}
+
+/// Endpoints that a process can use. Processes get the endpoints of the channels they create, so
+/// they cannot use the channels of other processes by guessing endpoint IDs.
+#[derive(Debug, Default)]
+pub struct EndpointTable {
+    endpoints: Vec<Endpoint>,
+}
+
+impl EndpointTable {
+    pub const fn new() -> Self {
+        Self { endpoints: vec![] }
+    }
+
+    pub fn insert(&mut self, endpoint: Endpoint) {
+        if !self.contains(endpoint) {
+            self.endpoints.push(endpoint);
+        }
+    }
+
+    pub fn contains(&self, endpoint: Endpoint) -> bool {
+        self.endpoints.contains(&endpoint)
+    }
+
+    /// Returns false if the endpoint is not in the table.
+    pub fn remove(&mut self, endpoint: Endpoint) -> bool {
+        match self.endpoints.iter().position(|owned| *owned == endpoint) {
+            Some(index) => {
+                self.endpoints.swap_remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Removes all endpoints from the table, so that they can be closed.
+    pub fn take_all(&mut self) -> Vec<Endpoint> {
+        core::mem::take(&mut self.endpoints)
+    }
+}
+
+static NUM_CHANNELS: AtomicU64 = AtomicU64::new(0);
+
+static CHANNELS: SpinLock<FlatMap<u64, Channel>> = SpinLock::new(FlatMap::new_no_capacity());
+
+/// Creates a new channel and returns its transmit and receive endpoints.
+pub fn create() -> (Endpoint, Endpoint) {
+    let channel_id = NUM_CHANNELS.fetch_add(1, Ordering::Relaxed);
+    CHANNELS.lock().insert(channel_id, Channel::new());
+    (
+        Endpoint::Transmit(channel_id),
+        Endpoint::Receive(channel_id),
+    )
+}
+
+pub fn send(endpoint: Endpoint, data: &[u8]) -> Result<(), Error> {
+    let channel_id = match endpoint {
+        Endpoint::Transmit(channel_id) => channel_id,
+        Endpoint::Receive(_) => return Err(Error::InvalidEndpoint),
+    };
+
+    CHANNELS
+        .lock()
+        .lookup_mut(&channel_id)
+        .ok_or(Error::InvalidEndpoint)?
+        .send(data)
+}
+
+/// Receives the next message without blocking. Returns `Ok(None)` if the channel is empty.
+pub fn try_receive(endpoint: Endpoint, buffer: &mut [u8]) -> Result<Option<usize>, Error> {
+    let channel_id = match endpoint {
+        Endpoint::Receive(channel_id) => channel_id,
+        Endpoint::Transmit(_) => return Err(Error::InvalidEndpoint),
+    };
+
+    CHANNELS
+        .lock()
+        .lookup_mut(&channel_id)
+        .ok_or(Error::InvalidEndpoint)?
+        .receive(buffer)
+}
+
+/// Closes an endpoint. The channel and any pending messages are freed once both endpoints are
+/// closed.
+pub fn close(endpoint: Endpoint) -> Result<(), Error> {
+    let channel_id = endpoint.channel_id();
+    let mut channels = CHANNELS.lock();
+    let channel = channels
+        .lookup_mut(&channel_id)
+        .ok_or(Error::InvalidEndpoint)?;
+
+    if channel.close(endpoint) {
+        drop(channels.remove(&channel_id));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn endpoint_ids() {
+        assert_eq!(Endpoint::from_raw(4), Endpoint::Transmit(2));
+        assert_eq!(Endpoint::from_raw(5), Endpoint::Receive(2));
+        assert_eq!(Endpoint::Transmit(2).get_raw(), 4);
+        assert_eq!(Endpoint::Receive(2).get_raw(), 5);
+        assert_eq!(Endpoint::Receive(2).channel_id(), 2);
+    }
+
+    #[test]
+    fn messages_are_received_in_order() {
+        let mut channel = Channel::new();
+        let mut buffer = [0u8; 8];
+        assert_eq!(channel.receive(&mut buffer), Ok(None));
+
+        channel.send(b"ping").unwrap();
+        channel.send(b"pong!").unwrap();
+
+        assert_eq!(channel.receive(&mut buffer), Ok(Some(4)));
+        assert_eq!(&buffer[..4], b"ping");
+        assert_eq!(channel.receive(&mut buffer), Ok(Some(5)));
+        assert_eq!(&buffer[..5], b"pong!");
+        assert_eq!(channel.receive(&mut buffer), Ok(None));
+    }
+
+    #[test]
+    fn full_channel() {
+        let mut channel = Channel::new();
+        for i in 0..Channel::CAPACITY {
+            channel.send(&[i as u8]).unwrap();
+        }
+        assert_eq!(channel.send(&[0xff]), Err(Error::ChannelFull));
+
+        let mut buffer = [0u8; 1];
+        assert_eq!(channel.receive(&mut buffer), Ok(Some(1)));
+        assert_eq!(buffer[0], 0);
+        channel.send(&[0xff]).unwrap();
+    }
+
+    #[test]
+    fn message_sizes() {
+        let mut channel = Channel::new();
+        let large_message = vec![0u8; Channel::MAX_MESSAGE_SIZE + 1];
+        assert_eq!(channel.send(&large_message), Err(Error::InvalidMessageSize));
+
+        // The message is kept until a large enough buffer is given
+        channel.send(b"hello").unwrap();
+        let mut buffer = [0u8; 4];
+        assert_eq!(channel.receive(&mut buffer), Err(Error::BufferTooSmall));
+        let mut buffer = [0u8; 5];
+        assert_eq!(channel.receive(&mut buffer), Ok(Some(5)));
+        assert_eq!(&buffer, b"hello");
+    }
+
+    #[test]
+    fn endpoint_table() {
+        let mut table = EndpointTable::new();
+        table.insert(Endpoint::Transmit(3));
+        table.insert(Endpoint::Receive(3));
+        table.insert(Endpoint::Receive(3));
+
+        assert!(table.contains(Endpoint::Transmit(3)));
+        assert!(table.contains(Endpoint::Receive(3)));
+        // Endpoints of other channels are not accessible
+        assert!(!table.contains(Endpoint::Transmit(4)));
+        assert!(!table.contains(Endpoint::Receive(2)));
+
+        assert!(table.remove(Endpoint::Transmit(3)));
+        assert!(!table.remove(Endpoint::Transmit(3)));
+        assert!(!table.contains(Endpoint::Transmit(3)));
+
+        assert_eq!(table.take_all(), vec![Endpoint::Receive(3)]);
+        assert!(!table.contains(Endpoint::Receive(3)));
+    }
+
+    #[test]
+    fn closed_endpoints() {
+        let mut channel = Channel::new();
+        channel.send(b"last").unwrap();
+        assert!(!channel.close(Endpoint::Transmit(0)));
+
+        // Pending messages can still be received after the sender is gone
+        let mut buffer = [0u8; 4];
+        assert_eq!(channel.receive(&mut buffer), Ok(Some(4)));
+        assert_eq!(channel.receive(&mut buffer), Err(Error::ChannelClosed));
+
+        let mut channel = Channel::new();
+        assert!(!channel.close(Endpoint::Receive(0)));
+        assert_eq!(channel.send(b"lost"), Err(Error::ChannelClosed));
+        assert!(channel.close(Endpoint::Transmit(0)));
+    }
+}
@@ -1,5 +1,7 @@
 pub mod flat_map;
+pub mod history_buffer;
 pub mod intrusive_list;
+pub mod mpsc;
 pub mod ring_buffer;
 
 use crate::prelude::*;
@@ -44,6 +46,45 @@ impl<T> OwnedMutPtr<T> {
     pub unsafe fn into_box(self) -> Box<T> {
         Box::from_raw(self.leak())
     }
+
+    /// Transforms the owned value in place, reusing its memory for the result. This keeps the
+    /// pointer valid for whatever it was valid for, e.g. `into_box`, as long as `U` has the same
+    /// layout as `T`.
+    ///
+    /// # Panics
+    /// If the size or alignment of `U` differ from the ones of `T`. If `f` panics the memory is
+    /// leaked, without running the destructor of the value.
+    pub fn map<U>(self, f: impl FnOnce(T) -> U) -> OwnedMutPtr<U> {
+        assert_eq!(
+            core::alloc::Layout::new::<T>(),
+            core::alloc::Layout::new::<U>(),
+            "OwnedMutPtr::map requires types with the same layout"
+        );
+
+        let ptr = self.leak();
+        // Safety:
+        //   * The pointer is valid and uniquely owned, as required to construct the OwnedMutPtr.
+        //   * The value is moved out before the memory is reused, and never read again as a T.
+        //   * The layout of U matches the one of T, so the memory is valid for a U.
+        unsafe {
+            let value = f(ptr.read());
+            let ptr = ptr as *mut U;
+            ptr.write(value);
+            OwnedMutPtr::new_from_raw(ptr)
+        }
+    }
+}
+
+impl<T> AsRef<T> for OwnedMutPtr<T> {
+    fn as_ref(&self) -> &T {
+        self
+    }
+}
+
+impl<T> AsMut<T> for OwnedMutPtr<T> {
+    fn as_mut(&mut self) -> &mut T {
+        self
+    }
 }
 
 impl<T> OwnedPtr<T> {
@@ -93,6 +134,12 @@ impl<T> core::ops::Deref for OwnedPtr<T> {
     }
 }
 
+impl<T> AsRef<T> for OwnedPtr<T> {
+    fn as_ref(&self) -> &T {
+        self
+    }
+}
+
 impl<T> Drop for OwnedMutPtr<T> {
     fn drop(&mut self) {
         log_warning!(
@@ -120,3 +167,46 @@ impl<T> Drop for OwnedPtr<T> {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    struct Celsius(u32);
+
+    #[derive(Debug, PartialEq)]
+    struct Kelvin(u32);
+
+    #[test]
+    fn map_reuses_the_allocation() {
+        let ptr = OwnedMutPtr::new_from_box(Box::new(Celsius(20)));
+        let address = &*ptr as *const Celsius as usize;
+
+        let ptr = ptr.map(|Celsius(degrees)| Kelvin(degrees + 273));
+        assert_eq!(*ptr, Kelvin(293));
+        assert_eq!(&*ptr as *const Kelvin as usize, address);
+
+        let boxed = unsafe { ptr.into_box() };
+        assert_eq!(*boxed, Kelvin(293));
+    }
+
+    #[test]
+    #[should_panic]
+    fn map_with_different_layouts() {
+        let ptr = OwnedMutPtr::new_from_box(Box::new(0u32));
+        let _ = ptr.map(|value| value as u64);
+    }
+
+    #[test]
+    fn borrow_inner_value() {
+        let mut ptr = OwnedMutPtr::new_from_box(Box::new(vec![1, 2]));
+        ptr.as_mut().push(3);
+        assert_eq!(ptr.as_ref(), &vec![1, 2, 3]);
+        drop(unsafe { ptr.into_box() });
+
+        let ptr = OwnedPtr::new_from_box(Box::new(5));
+        assert_eq!(*ptr.as_ref(), 5);
+        drop(unsafe { ptr.into_box() });
+    }
+}
@@ -106,6 +106,9 @@ where
     metadata_buckets: Vec<Meta>,
     buckets: Vec<MaybeUninit<(K, V)>>,
     num_elements: usize,
+    /// Buckets of removed elements. They still need to be traversed by lookups, so they count
+    /// towards the load factor until the map is rebuilt.
+    num_deleted: usize,
     capacity: usize,
     _hasher_builder: PhantomData<H>,
 }
@@ -158,6 +161,7 @@ where
             metadata_buckets: vec![],
             buckets: vec![],
             num_elements: 0,
+            num_deleted: 0,
             capacity: 0,
             _hasher_builder: hasher_builder,
         }
@@ -174,6 +178,7 @@ where
             metadata_buckets: Vec::with_capacity(capacity),
             buckets: Vec::with_capacity(capacity),
             num_elements: 0,
+            num_deleted: 0,
             capacity,
             _hasher_builder: hasher_builder,
         };
@@ -204,10 +209,21 @@ where
         hasher.finish()
     }
 
-    /// Integer between 0-100 (%) to indicate the number of used entries / capacity of the table
+    /// Integer between 0-100 (%) to indicate the number of used entries / capacity of the table.
+    /// Buckets of removed elements count as used until the map is rebuilt.
     #[must_use]
     pub fn load_factor(&self) -> usize {
-        (self.num_elements * 100) / self.capacity
+        ((self.num_elements + self.num_deleted) * 100) / self.capacity
+    }
+
+    /// Smallest power of two capacity that holds `num_elements` without exceeding the max load
+    /// factor, and not smaller than the default capacity.
+    fn capacity_for(num_elements: usize) -> usize {
+        let mut capacity = Self::DEFAULT_CAPACITY;
+        while num_elements * 100 > capacity * Self::MAX_LOAD_FACTOR {
+            capacity *= 2;
+        }
+        capacity
     }
 
     pub fn resize(&mut self, new_capacity: usize) -> Result<()> {
@@ -217,6 +233,35 @@ where
         if new_capacity == self.capacity {
             return Ok(());
         }
+        self.rebuild(new_capacity);
+        Ok(())
+    }
+
+    /// Grows the map so that `additional` more elements can be inserted without resizing it.
+    pub fn reserve(&mut self, additional: usize) {
+        let required_elements = self.num_elements + additional;
+        let required_capacity = Self::capacity_for(required_elements);
+        if required_capacity > self.capacity {
+            self.rebuild(required_capacity);
+        } else if (required_elements + self.num_deleted) * 100
+            > self.capacity * Self::MAX_LOAD_FACTOR
+        {
+            // The capacity is enough, but not with the buckets of removed elements
+            self.rebuild(self.capacity);
+        }
+    }
+
+    /// Shrinks the map to the smallest capacity that holds its elements, which also drops the
+    /// buckets of removed elements.
+    pub fn shrink_to_fit(&mut self) {
+        let capacity = Self::capacity_for(self.num_elements);
+        if capacity < self.capacity || self.num_deleted != 0 {
+            self.rebuild(capacity);
+        }
+    }
+
+    /// Moves all elements to a new table with the given capacity, which must fit all of them.
+    fn rebuild(&mut self, new_capacity: usize) {
         let mut old_map = core::mem::replace(
             self,
             Self::with_capacity_and_hasher(new_capacity, PhantomData),
@@ -237,7 +282,6 @@ where
                     ));
             }
         }
-        Ok(())
     }
 
     fn insert_without_resize(&mut self, key: K, value: V, strategy: InsertStrategy) -> Result<()> {
@@ -252,6 +296,7 @@ where
                     let index = if let Some(deleted_slot_idx) = found_deleted_slot {
                         // The key was not found, but there was a deleted slot, so we should insert
                         // there instead of using the empty slot.
+                        self.num_deleted -= 1;
                         deleted_slot_idx
                     } else {
                         index
@@ -404,6 +449,8 @@ where
         self.lookup_index(key)
             .map(|index| {
                 self.metadata_buckets[index].set_deleted();
+                self.num_elements -= 1;
+                self.num_deleted += 1;
                 let element = core::mem::replace(&mut self.buckets[index], MaybeUninit::uninit());
                 let (_k, v) = unsafe { element.assume_init() };
                 v
@@ -439,6 +486,20 @@ where
     }
 }
 
+impl<K, V, H> core::fmt::Debug for FlatMap<K, V, H>
+where
+    K: Hash + Eq + PartialEq + core::fmt::Debug,
+    V: core::fmt::Debug,
+    H: BuildHasher,
+{
+    /// Prints the elements like a map, in bucket order.
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_map()
+            .entries(self.iter().map(|(key, value)| (key, value)))
+            .finish()
+    }
+}
+
 pub struct FlatMapIter<'a, K, V, H>
 where
     K: Hash + Eq + PartialEq,
@@ -781,4 +842,81 @@ mod tests {
         map.resize(16).unwrap();
         assert_eq!(map.capacity(), 16);
     }
+
+    #[test]
+    fn test_reserve() {
+        let mut map: FlatMap<u32, u32> = FlatMap::new();
+        map.insert(0, 0);
+        map.reserve(100);
+        // 100 elements need at least 143 buckets to stay under the load factor
+        assert_eq!(map.capacity(), 256);
+
+        for i in 1..=100 {
+            map.insert_with_strategy(i, i, InsertStrategy::NoReplaceNoResize)
+                .unwrap();
+        }
+        assert_eq!(map.capacity(), 256);
+
+        // Enough capacity already
+        map.reserve(10);
+        assert_eq!(map.capacity(), 256);
+        for i in 0..=100 {
+            assert_eq!(map.lookup(&i), Some(&i));
+        }
+    }
+
+    #[test]
+    fn test_shrink_to_fit() {
+        let mut map: FlatMap<String, u32> = FlatMap::new();
+        for i in 0..100 {
+            map.insert(format!("key {}", i), i);
+        }
+        assert_eq!(map.capacity(), 512);
+
+        // Leaves tombstones for the removed keys
+        for i in 10..100 {
+            map.remove(&format!("key {}", i)).unwrap();
+        }
+        assert_eq!(map.len(), 10);
+        assert_eq!(map.load_factor(), 100 * 100 / 512);
+
+        map.shrink_to_fit();
+        assert_eq!(map.capacity(), 16);
+        assert_eq!(map.len(), 10);
+        assert_eq!(map.load_factor(), 10 * 100 / 16);
+        for i in 0..10 {
+            assert_eq!(map.lookup(&format!("key {}", i)), Some(&i));
+        }
+        for i in 10..100 {
+            assert!(map.lookup(&format!("key {}", i)).is_none());
+        }
+
+        // Removing everything shrinks to the default capacity
+        for i in 0..10 {
+            map.remove(&format!("key {}", i)).unwrap();
+        }
+        map.shrink_to_fit();
+        assert_eq!(map.capacity(), 8);
+        assert!(map.is_empty());
+        map.insert("new key".to_string(), 1);
+        assert_eq!(map.lookup("new key"), Some(&1));
+    }
+
+    #[test]
+    fn test_debug() {
+        let mut map: FlatMap<&str, u32> = FlatMap::new();
+        assert_eq!(format!("{:?}", map), "{}");
+
+        map.insert("a", 1);
+        map.insert("b", 2);
+        map.insert("removed", 3);
+        map.remove("removed").unwrap();
+
+        // Bucket order depends on the hash, so check the entries in any order
+        let output = format!("{:?}", map);
+        assert!(output.starts_with('{') && output.ends_with('}'));
+        let mut entries: Vec<&str> = output[1..output.len() - 1].split(", ").collect();
+        entries.sort_unstable();
+        assert_eq!(entries, vec!["\"a\": 1", "\"b\": 2"]);
+    }
 }
@@ -0,0 +1,103 @@
+/// Fixed-size buffer that keeps the last `SIZE` bytes written to it. Writing never fails, the oldest
+/// bytes are overwritten instead.
+#[derive(Debug)]
+pub struct HistoryBuffer<const SIZE: usize> {
+    data: [u8; SIZE],
+    /// Index where the next byte is written, which is also the oldest byte once the buffer wraps
+    write_index: usize,
+    wrapped: bool,
+}
+
+impl<const SIZE: usize> HistoryBuffer<SIZE> {
+    pub const fn new() -> Self {
+        Self {
+            data: [0; SIZE],
+            write_index: 0,
+            wrapped: false,
+        }
+    }
+
+    pub fn push(&mut self, data: u8) {
+        self.data[self.write_index] = data;
+        self.write_index += 1;
+        if self.write_index == SIZE {
+            self.write_index = 0;
+            self.wrapped = true;
+        }
+    }
+
+    pub fn extend(&mut self, data: &[u8]) {
+        for byte in data {
+            self.push(*byte);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        if self.wrapped {
+            SIZE
+        } else {
+            self.write_index
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns true if bytes have been overwritten since the buffer was created.
+    pub fn has_wrapped(&self) -> bool {
+        self.wrapped
+    }
+
+    /// Iterates over the bytes in the order they were written, from the oldest one.
+    pub fn iter(&self) -> impl Iterator<Item = u8> + '_ {
+        let (newest, oldest) = self.data.split_at(self.write_index);
+        let oldest = if self.wrapped { oldest } else { &[] };
+        oldest.iter().chain(newest.iter()).copied()
+    }
+}
+
+impl<const SIZE: usize> Default for HistoryBuffer<SIZE> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::vec::Vec;
+
+    #[test]
+    fn test_empty_buffer() {
+        let buffer: HistoryBuffer<4> = HistoryBuffer::new();
+        assert!(buffer.is_empty());
+        assert_eq!(buffer.iter().count(), 0);
+    }
+
+    #[test]
+    fn test_keeps_bytes_in_order() {
+        let mut buffer: HistoryBuffer<4> = HistoryBuffer::new();
+        buffer.extend(b"abc");
+        assert_eq!(buffer.len(), 3);
+        assert!(!buffer.has_wrapped());
+        assert_eq!(buffer.iter().collect::<Vec<_>>(), b"abc");
+    }
+
+    #[test]
+    fn test_keeps_last_bytes_on_wraparound() {
+        let mut buffer: HistoryBuffer<4> = HistoryBuffer::new();
+        buffer.extend(b"abcd");
+        assert_eq!(buffer.iter().collect::<Vec<_>>(), b"abcd");
+        assert!(buffer.has_wrapped());
+
+        buffer.extend(b"ef");
+        assert_eq!(buffer.len(), 4);
+        assert_eq!(buffer.iter().collect::<Vec<_>>(), b"cdef");
+
+        // Wrapping several times over
+        buffer.extend(b"ghijklmno");
+        assert_eq!(buffer.iter().collect::<Vec<_>>(), b"lmno");
+    }
+}
@@ -57,6 +57,50 @@ impl<T> IntrusiveList<T> {
         Some(item)
     }
 
+    /// Inserts an element before the first element with a greater key, which keeps the list sorted
+    /// if it already was. Elements with equal keys keep their insertion order.
+    pub fn insert_sorted_by_key<K, F>(&mut self, item: OwnedMutPtr<IntrusiveItem<T>>, mut key: F)
+    where
+        K: Ord,
+        F: FnMut(&T) -> K,
+    {
+        let item_key = key(&item);
+
+        let mut element = self.head;
+        while !element.is_null() && key(unsafe { &(*element).inner }) <= item_key {
+            element = unsafe { (*element).next };
+        }
+
+        if element.is_null() {
+            self.push(item);
+            return;
+        }
+
+        let new_item = item.leak();
+        unsafe {
+            let prev = (*element).prev;
+            (*new_item).prev = prev;
+            (*new_item).next = element;
+            (*element).prev = new_item;
+
+            if prev.is_null() {
+                self.head = new_item;
+            } else {
+                (*prev).next = new_item;
+            }
+        }
+        self.length += 1;
+    }
+
+    /// Returns the head of the queue without removing it
+    pub fn front(&self) -> Option<&T> {
+        if self.head.is_null() {
+            None
+        } else {
+            Some(unsafe { &(*self.head).inner })
+        }
+    }
+
     pub fn iter(&self) -> IntrusiveListIter<'_, T> {
         if self.head.is_null() {
             IntrusiveListIter {
@@ -227,6 +271,10 @@ impl<T> IntrusiveItem<T> {
             prev: core::ptr::null_mut(),
         }
     }
+
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
 }
 
 impl<T> core::ops::Deref for IntrusiveItem<T> {
@@ -595,4 +643,43 @@ mod test {
         list.push(OwnedMutPtr::new_from_box(Box::new(IntrusiveItem::new(32))));
         assert_eq!(list.len(), 1);
     }
+
+    #[test]
+    fn sorted_insertion() {
+        let mut list = IntrusiveList::<_>::new();
+        assert!(list.front().is_none());
+
+        for (key, id) in [
+            (30, 'a'),
+            (10, 'b'),
+            (20, 'c'),
+            (10, 'd'),
+            (40, 'e'),
+            (5, 'f'),
+        ] {
+            let item = OwnedMutPtr::new_from_box(Box::new(IntrusiveItem::new((key, id))));
+            list.insert_sorted_by_key(item, |(key, _)| *key);
+        }
+
+        let vector: Vec<_> = list.iter().map(|item| item.inner).collect();
+        assert_eq!(
+            vector,
+            vec![
+                (5, 'f'),
+                (10, 'b'),
+                (10, 'd'),
+                (20, 'c'),
+                (30, 'a'),
+                (40, 'e')
+            ]
+        );
+        let reversed: Vec<_> = list.iter().rev().map(|item| item.inner.1).collect();
+        assert_eq!(reversed, vec!['e', 'a', 'c', 'd', 'b', 'f']);
+        assert_eq!(list.len(), 6);
+        assert_eq!(list.front(), Some(&(5, 'f')));
+
+        list.release(|element| {
+            let _ = unsafe { element.into_box() };
+        });
+    }
 }
@@ -0,0 +1,243 @@
+//! Bounded multi-producer, single-consumer queue, for delivering events from drivers (possibly from
+//! interrupt handlers) to the thread that handles them.
+
+use super::{
+    intrusive_list::{IntrusiveItem, IntrusiveList},
+    OwnedMutPtr,
+};
+use crate::{prelude::*, sync::spinlock::SpinLock, syscall::Syscall};
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// What to do when pushing to a full queue
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FullPolicy {
+    /// The new element is returned to the producer
+    Reject,
+    /// The oldest element is dropped to make room for the new one
+    DropOldest,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Error<T> {
+    /// The queue is full, holds the element that could not be pushed
+    Full(T),
+}
+
+pub struct Mpsc<T> {
+    queue: SpinLock<IntrusiveList<T>>,
+    capacity: usize,
+    policy: FullPolicy,
+    dropped: AtomicUsize,
+}
+
+impl<T> Mpsc<T> {
+    pub const fn new(capacity: usize, policy: FullPolicy) -> Self {
+        Self {
+            queue: SpinLock::new(IntrusiveList::new()),
+            capacity,
+            policy,
+            dropped: AtomicUsize::new(0),
+        }
+    }
+
+    /// Pushes an element to the tail of the queue. Never blocks, so it can be used from interrupt
+    /// handlers.
+    pub fn try_push(&self, value: T) -> Result<(), Error<T>> {
+        self.try_push_or_merge(value, |_, _| false)
+    }
+
+    /// Like `try_push`, but when the queue is full `merge` is first given the chance to fold the
+    /// new element into the newest one. The full policy only applies if it returns false.
+    pub fn try_push_or_merge(
+        &self,
+        value: T,
+        merge: impl FnOnce(&mut T, &T) -> bool,
+    ) -> Result<(), Error<T>> {
+        let mut queue = self.queue.lock();
+        if queue.len() >= self.capacity {
+            if let Some(newest) = queue.iter_mut().next_back() {
+                if merge(newest, &value) {
+                    return Ok(());
+                }
+            }
+
+            match self.policy {
+                FullPolicy::Reject => return Err(Error::Full(value)),
+                FullPolicy::DropOldest => {
+                    if let Some(oldest) = queue.pop() {
+                        drop(unsafe { oldest.into_box() });
+                    }
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+
+        // A queue without capacity drops everything
+        if self.capacity != 0 {
+            queue.push(OwnedMutPtr::new_from_box(Box::new(IntrusiveItem::new(
+                value,
+            ))));
+        }
+        Ok(())
+    }
+
+    /// Pops the element at the head of the queue, if any.
+    pub fn pop(&self) -> Option<T> {
+        let item = self.queue.lock().pop()?;
+        Some(unsafe { item.into_box() }.into_inner())
+    }
+
+    /// Pops the element at the head of the queue, yielding the current thread while it is empty.
+    /// Must be called from a thread.
+    pub fn pop_wait(&self) -> T {
+        loop {
+            if let Some(value) = self.pop() {
+                return value;
+            }
+            Syscall::yield_exec();
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Number of elements dropped by the `DropOldest` policy.
+    pub fn dropped(&self) -> usize {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl<T> Drop for Mpsc<T> {
+    fn drop(&mut self) {
+        let mut queue = self.queue.lock();
+        while let Some(item) = queue.pop() {
+            drop(unsafe { item.into_box() });
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::{sync::Arc, thread, vec::Vec};
+
+    #[test]
+    fn test_pops_in_push_order() {
+        let queue = Mpsc::new(4, FullPolicy::Reject);
+        assert!(queue.is_empty());
+        queue.try_push(1).unwrap();
+        queue.try_push(2).unwrap();
+        assert_eq!(queue.len(), 2);
+
+        assert_eq!(queue.pop(), Some(1));
+        assert_eq!(queue.pop(), Some(2));
+        assert_eq!(queue.pop(), None);
+    }
+
+    #[test]
+    fn test_full_queue_rejects() {
+        let queue = Mpsc::new(2, FullPolicy::Reject);
+        queue.try_push(1).unwrap();
+        queue.try_push(2).unwrap();
+        assert_eq!(queue.try_push(3), Err(Error::Full(3)));
+        assert_eq!(queue.dropped(), 0);
+
+        assert_eq!(queue.pop(), Some(1));
+        queue.try_push(3).unwrap();
+        assert_eq!(queue.pop(), Some(2));
+        assert_eq!(queue.pop(), Some(3));
+    }
+
+    #[test]
+    fn test_full_queue_drops_oldest() {
+        let queue = Mpsc::new(2, FullPolicy::DropOldest);
+        for value in 1..=5 {
+            queue.try_push(value).unwrap();
+        }
+        assert_eq!(queue.dropped(), 3);
+        assert_eq!(queue.pop(), Some(4));
+        assert_eq!(queue.pop(), Some(5));
+        assert_eq!(queue.pop(), None);
+    }
+
+    #[test]
+    fn test_full_queue_merges_with_newest() {
+        let queue = Mpsc::new(2, FullPolicy::Reject);
+        let sum = |newest: &mut i32, value: &i32| {
+            *newest += value;
+            true
+        };
+
+        // Merging only happens once the queue is full
+        queue.try_push_or_merge(1, sum).unwrap();
+        queue.try_push_or_merge(2, sum).unwrap();
+        queue.try_push_or_merge(3, sum).unwrap();
+        assert_eq!(queue.len(), 2);
+
+        // The full policy applies if the element is not merged
+        assert_eq!(
+            queue.try_push_or_merge(10, |_, _| false),
+            Err(Error::Full(10))
+        );
+
+        assert_eq!(queue.pop(), Some(1));
+        assert_eq!(queue.pop(), Some(5));
+        assert_eq!(queue.pop(), None);
+    }
+
+    #[test]
+    fn test_remaining_elements_are_freed() {
+        let value = Arc::new(0);
+        let queue = Mpsc::new(2, FullPolicy::Reject);
+        queue.try_push(value.clone()).unwrap();
+        drop(queue);
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+
+    #[test]
+    fn test_multiple_producers() {
+        const PRODUCERS: usize = 4;
+        const VALUES_PER_PRODUCER: usize = 1000;
+
+        let queue = Arc::new(Mpsc::new(16, FullPolicy::Reject));
+        let producers: Vec<_> = (0..PRODUCERS)
+            .map(|producer| {
+                let queue = queue.clone();
+                thread::spawn(move || {
+                    for index in 0..VALUES_PER_PRODUCER {
+                        let mut value = (producer, index);
+                        while let Err(Error::Full(rejected)) = queue.try_push(value) {
+                            value = rejected;
+                            thread::yield_now();
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        // Values of each producer arrive in the order they were pushed
+        let mut next_index = [0; PRODUCERS];
+        for _ in 0..PRODUCERS * VALUES_PER_PRODUCER {
+            let (producer, index) = queue.pop_wait();
+            assert_eq!(index, next_index[producer]);
+            next_index[producer] += 1;
+        }
+
+        for producer in producers {
+            producer.join().unwrap();
+        }
+        assert!(queue.is_empty());
+        assert_eq!(next_index, [VALUES_PER_PRODUCER; PRODUCERS]);
+    }
+}
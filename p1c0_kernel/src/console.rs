@@ -0,0 +1,196 @@
+//! Line-oriented console reading from the serial port and the keyboard. Input is echoed to both
+//! the serial port and the display.
+
+use crate::{
+    drivers::display,
+    input::{self, Event, KeyEvent},
+    prelude::*,
+    print,
+    syscall::Syscall,
+};
+
+use heapless::String;
+
+pub const MAX_LINE_LENGTH: usize = 256;
+
+pub type Line = String<MAX_LINE_LENGTH>;
+
+const BACKSPACE: u8 = 0x08;
+const DELETE: u8 = 0x7F;
+const CTRL_C: u8 = 0x03;
+const CTRL_U: u8 = 0x15;
+
+/// Erases the last character in a terminal
+const ERASE: &str = "\x08 \x08";
+
+/// Assembles a line from input bytes. Only printable ASCII characters are kept, other control
+/// characters than the ones handled are ignored.
+pub struct LineEditor<const N: usize> {
+    line: String<N>,
+    last_was_cr: bool,
+}
+
+impl<const N: usize> LineEditor<N> {
+    pub const fn new() -> Self {
+        Self {
+            line: String::new(),
+            last_was_cr: false,
+        }
+    }
+
+    /// Processes an input byte and passes the text to echo to `echo`. Returns the line once it is
+    /// terminated. Characters beyond the capacity of the line are dropped.
+    pub fn feed(&mut self, byte: u8, mut echo: impl FnMut(&str)) -> Option<String<N>> {
+        // Terminals may send CR LF as a single line terminator
+        let last_was_cr = core::mem::replace(&mut self.last_was_cr, byte == b'\r');
+        match byte {
+            b'\n' if last_was_cr => {}
+            b'\r' | b'\n' => {
+                echo("\n");
+                return Some(core::mem::take(&mut self.line));
+            }
+            BACKSPACE | DELETE => {
+                if self.line.pop().is_some() {
+                    echo(ERASE);
+                }
+            }
+            CTRL_U => {
+                while self.line.pop().is_some() {
+                    echo(ERASE);
+                }
+            }
+            CTRL_C => {
+                echo("^C\n");
+                self.line.clear();
+            }
+            0x20..=0x7E => {
+                if self.line.push(byte as char).is_ok() {
+                    let byte = [byte];
+                    echo(core::str::from_utf8(&byte).unwrap());
+                }
+            }
+            _ => {}
+        }
+        None
+    }
+}
+
+impl<const N: usize> Default for LineEditor<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Translates a key press into the byte a serial terminal would send for it.
+fn key_to_byte(event: &KeyEvent) -> Option<u8> {
+    const BACKSPACE_SCANCODE: u8 = 0x2A;
+
+    if !event.pressed {
+        return None;
+    }
+    if event.scancode.value() == BACKSPACE_SCANCODE {
+        return Some(BACKSPACE);
+    }
+
+    let c = event.scancode.to_char()?;
+    let ctrl = event.modifiers.contains(input::Modifiers::LEFT_CTRL)
+        || event.modifiers.contains(input::Modifiers::RIGHT_CTRL);
+    match c {
+        'C' if ctrl => Some(CTRL_C),
+        'U' if ctrl => Some(CTRL_U),
+        '\n' => Some(b'\r'),
+        c if c.is_ascii_alphabetic() && !event.modifiers.shift() => {
+            Some(c.to_ascii_lowercase() as u8)
+        }
+        c => Some(c as u8),
+    }
+}
+
+fn read_byte() -> Option<u8> {
+    if let Some(byte) = print::read_u8() {
+        return Some(byte);
+    }
+
+    while let Some(event) = input::poll_event() {
+        match event {
+            Event::Key(key_event) => {
+                if let Some(byte) = key_to_byte(&key_event) {
+                    return Some(byte);
+                }
+            }
+            Event::Pointer(pointer_event) => display::Display::move_pointer(&pointer_event),
+        }
+    }
+    None
+}
+
+fn echo(text: &str) {
+    print!("{}", text);
+    display::_print(format_args!("{}", text));
+}
+
+/// Reads a line from the serial port or the keyboard, yielding while no input is available. Must
+/// be called from a thread.
+pub fn read_line() -> Line {
+    let mut editor = LineEditor::new();
+    loop {
+        match read_byte() {
+            Some(byte) => {
+                if let Some(line) = editor.feed(byte, echo) {
+                    return line;
+                }
+            }
+            None => Syscall::yield_exec(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::string::String as StdString;
+
+    fn feed_all<const N: usize>(
+        editor: &mut LineEditor<N>,
+        input: &[u8],
+    ) -> (Vec<StdString>, StdString) {
+        let mut echoed = StdString::new();
+        let mut lines = vec![];
+        for byte in input {
+            if let Some(line) = editor.feed(*byte, |text| echoed.push_str(text)) {
+                lines.push(StdString::from(line.as_str()));
+            }
+        }
+        (lines, echoed)
+    }
+
+    #[test]
+    fn line_with_backspaces() {
+        let mut editor = LineEditor::<32>::new();
+        let (lines, echoed) = feed_all(&mut editor, b"helo\x08\x08llo\r\n");
+        assert_eq!(lines, vec!["hello"]);
+        assert_eq!(echoed, "helo\x08 \x08\x08 \x08llo\n");
+
+        // Backspace on an empty line echoes nothing, DEL behaves like backspace
+        let (lines, echoed) = feed_all(&mut editor, b"\x08ab\x7f\n");
+        assert_eq!(lines, vec!["a"]);
+        assert_eq!(echoed, "ab\x08 \x08\n");
+    }
+
+    #[test]
+    fn control_characters() {
+        let mut editor = LineEditor::<32>::new();
+        let (lines, echoed) = feed_all(&mut editor, b"rm -rf\x03ls\x1b\x15pwd\r\r");
+        assert_eq!(lines, vec!["pwd", ""]);
+        assert_eq!(echoed, "rm -rf^C\nls\x08 \x08\x08 \x08pwd\n\n");
+    }
+
+    #[test]
+    fn line_length_is_bounded() {
+        let mut editor = LineEditor::<4>::new();
+        let (lines, echoed) = feed_all(&mut editor, b"abcdef\x08g\n");
+        assert_eq!(lines, vec!["abcg"]);
+        assert_eq!(echoed, "abcd\x08 \x08g\n");
+    }
+}
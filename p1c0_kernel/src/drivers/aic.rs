@@ -112,7 +112,9 @@ impl Aic {
             .get_device_addr_from_nodes(dev_path, 0)
             .ok_or_else(|| Box::new(Error::InvalidAdtNode) as Box<dyn error::Error>)?;
 
-        let va = MemoryManager::instance().map_io("aic", aic_pa, size)?;
+        let va = MemoryManager::instance()
+            .map_io("aic", aic_pa, size)?
+            .leak();
 
         let global_regs = unsafe { &mut *(va.as_mut_ptr() as *mut AicGlobalRegs) };
         let irq_regs = unsafe { &mut *(va.offset(IRQ_CONFIG_OFFSET).as_mut_ptr() as *mut AicRegs) };
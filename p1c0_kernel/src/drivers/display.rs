@@ -1,11 +1,13 @@
 use crate::{
-    boot_args::get_boot_args,
-    font::FIRA_CODE_30,
+    boot_args::{get_boot_args, FramebufferError},
+    font::{self, Scaled, FIRA_CODE_30},
+    input::PointerEvent,
     memory::{
         self,
         address::{Address, PhysicalAddress},
         Attributes, Permissions,
     },
+    prelude::*,
     sync::spinlock::SpinLock,
 };
 
@@ -13,19 +15,25 @@ use core::fmt::{self, Write};
 
 use embedded_graphics::{
     draw_target::DrawTarget,
-    image::Image,
     mono_font::{ascii::FONT_7X14, MonoFont, MonoTextStyle},
     pixelcolor::Rgb888,
     prelude::*,
-    primitives::Rectangle,
     text::{Baseline, Text},
 };
 
-const RETINA_DEPTH_FLAG: usize = 1 << 16;
+/// Pixels are 32 bit values, with 10 bits per color component
+const BYTES_PER_PIXEL: usize = 4;
 
 const ROW_MARGIN: u32 = 10;
 const COL_MARGIN: u32 = 10;
 
+#[derive(Debug)]
+pub enum Error {
+    InvalidFramebuffer(FramebufferError),
+    UnsupportedPixelSize(usize),
+    MappingFailed(memory::Error),
+}
+
 static DISPLAY: LockedDisplay = LockedDisplay::new();
 
 pub struct Display {
@@ -36,9 +44,15 @@ pub struct Display {
 
     // Console members
     font: &'static MonoFont<'static>,
+    /// Factor the font is scaled by, as last read from `font::scale`
+    scale: u32,
     current_row: u32,
     current_col: u32,
     max_rows: u32,
+
+    /// Framebuffer contents under the overlay, if one is drawn
+    overlay: Option<SavedRegion>,
+    pointer: PointerTracker,
 }
 
 struct LockedDisplay(SpinLock<Option<Display>>);
@@ -67,6 +81,240 @@ impl core::ops::DerefMut for LockedDisplay {
     }
 }
 
+/// Converts a color to the pixel format of the framebuffer
+fn encode_color(color: Rgb888) -> u32 {
+    (color.r() as u32) << 22 | (color.g() as u32) << 12 | (color.b() as u32) << 2
+}
+
+/// Inverse of `encode_color`
+fn decode_color(pixel: u32) -> Rgb888 {
+    Rgb888::new((pixel >> 22) as u8, (pixel >> 12) as u8, (pixel >> 2) as u8)
+}
+
+/// Mixes two colors, `alpha` being the opacity of `top` from 0 (transparent) to 255 (opaque).
+fn blend(top: Rgb888, bottom: Rgb888, alpha: u8) -> Rgb888 {
+    let mix = |top: u8, bottom: u8| {
+        ((top as u32 * alpha as u32 + bottom as u32 * (255 - alpha as u32) + 127) / 255) as u8
+    };
+    Rgb888::new(
+        mix(top.r(), bottom.r()),
+        mix(top.g(), bottom.g()),
+        mix(top.b(), bottom.b()),
+    )
+}
+
+/// Image drawn over the framebuffer contents. Pixels that are `None` are transparent.
+pub struct Sprite<'a> {
+    pub pixels: &'a [Option<Rgb888>],
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Copy of a rectangle of the framebuffer, used to restore it after drawing over it
+struct SavedRegion {
+    columns: core::ops::Range<u32>,
+    rows: core::ops::Range<u32>,
+    pixels: Vec<u32>,
+}
+
+/// Pixel operations over framebuffer memory. Everything that falls outside of the framebuffer is
+/// clipped.
+struct Canvas<'a> {
+    pixels: &'a mut [u32],
+    width: u32,
+    height: u32,
+    /// Pixels between the start of two consecutive rows
+    stride: u32,
+}
+
+impl<'a> Canvas<'a> {
+    /// Returns the intersection of the rectangle with the framebuffer, as the ranges of columns and
+    /// rows it covers.
+    fn clip(
+        &self,
+        x: i32,
+        y: i32,
+        width: u32,
+        height: u32,
+    ) -> (core::ops::Range<u32>, core::ops::Range<u32>) {
+        let clip_axis = |start: i32, length: u32, limit: u32| {
+            let end = (start as i64 + length as i64).clamp(0, limit as i64) as u32;
+            let start = (start as i64).clamp(0, limit as i64) as u32;
+            start..end
+        };
+        (
+            clip_axis(x, width, self.width),
+            clip_axis(y, height, self.height),
+        )
+    }
+
+    /// Clips an image with `width` pixels per row at `(x, y)`. Returns the visible columns and, for
+    /// each visible row, the framebuffer row with the index of its first visible pixel in the image.
+    /// Returns `None` if no part of the image is visible.
+    fn clip_image(
+        &self,
+        x: i32,
+        y: i32,
+        width: u32,
+        height: u32,
+    ) -> Option<(core::ops::Range<u32>, impl Iterator<Item = (u32, usize)>)> {
+        let (columns, rows) = self.clip(x, y, width, height);
+        if columns.is_empty() || rows.is_empty() {
+            return None;
+        }
+
+        // Offset of the visible part within the image, non-zero if it is clipped at the top or left
+        let first_column = (columns.start as i64 - x as i64) as usize;
+        let rows = rows.map(move |row| {
+            let image_row = (row as i64 - y as i64) as usize;
+            (row, image_row * width as usize + first_column)
+        });
+        Some((columns, rows))
+    }
+
+    fn row_mut(&mut self, row: u32, columns: core::ops::Range<u32>) -> &mut [u32] {
+        let row_start = (row * self.stride) as usize;
+        &mut self.pixels[row_start + columns.start as usize..row_start + columns.end as usize]
+    }
+
+    fn set_pixel(&mut self, x: i32, y: i32, color: Rgb888) {
+        self.fill_rect(x, y, 1, 1, color);
+    }
+
+    fn fill_rect(&mut self, x: i32, y: i32, width: u32, height: u32, color: Rgb888) {
+        let (columns, rows) = self.clip(x, y, width, height);
+        let color = encode_color(color);
+        for row in rows {
+            self.row_mut(row, columns.clone()).fill(color);
+        }
+    }
+
+    fn save(&mut self, x: i32, y: i32, width: u32, height: u32) -> SavedRegion {
+        let (columns, rows) = self.clip(x, y, width, height);
+        let mut pixels = Vec::with_capacity(columns.len() * rows.len());
+        for row in rows.clone() {
+            pixels.extend_from_slice(self.row_mut(row, columns.clone()));
+        }
+        SavedRegion {
+            columns,
+            rows,
+            pixels,
+        }
+    }
+
+    fn restore(&mut self, region: &SavedRegion) {
+        if region.columns.is_empty() {
+            return;
+        }
+
+        let saved_rows = region.pixels.chunks_exact(region.columns.len());
+        for (row, saved) in region.rows.clone().zip(saved_rows) {
+            self.row_mut(row, region.columns.clone())
+                .copy_from_slice(saved);
+        }
+    }
+
+    /// Blends a sprite over the current contents, with its top left corner at `(x, y)`.
+    fn blend_sprite(&mut self, x: i32, y: i32, sprite: &Sprite<'_>, alpha: u8) {
+        assert!(sprite.pixels.len() >= (sprite.width * sprite.height) as usize);
+
+        let (columns, rows) = match self.clip_image(x, y, sprite.width, sprite.height) {
+            Some(visible) => visible,
+            None => return,
+        };
+        for (row, sprite_start) in rows {
+            let source = &sprite.pixels[sprite_start..sprite_start + columns.len()];
+            for (pixel, color) in self.row_mut(row, columns.clone()).iter_mut().zip(source) {
+                if let Some(color) = color {
+                    *pixel = encode_color(blend(*color, decode_color(*pixel), alpha));
+                }
+            }
+        }
+    }
+
+    /// Copies an image with `width` pixels per row to the given position.
+    fn blit(&mut self, x: i32, y: i32, pixels: &[Rgb888], width: u32, height: u32) {
+        assert!(pixels.len() >= (width * height) as usize);
+
+        let (columns, rows) = match self.clip_image(x, y, width, height) {
+            Some(visible) => visible,
+            None => return,
+        };
+        for (row, image_start) in rows {
+            let source = &pixels[image_start..image_start + columns.len()];
+            for (pixel, color) in self.row_mut(row, columns.clone()).iter_mut().zip(source) {
+                *pixel = encode_color(*color);
+            }
+        }
+    }
+}
+
+/// Rows of the pointer sprite, the most significant bit being the leftmost pixel
+const POINTER_SHAPE: [u8; 8] = [
+    0b1000_0000,
+    0b1100_0000,
+    0b1110_0000,
+    0b1111_0000,
+    0b1111_1000,
+    0b1111_1100,
+    0b1110_0000,
+    0b1010_0000,
+];
+const POINTER_ALPHA: u8 = 0xC0;
+/// Trackpad units per pixel of pointer motion
+const TOUCH_UNITS_PER_PIXEL: i32 = 4;
+
+fn pointer_sprite_pixels() -> Vec<Option<Rgb888>> {
+    POINTER_SHAPE
+        .iter()
+        .flat_map(|row| (0..8).map(move |bit| (row & (0x80 >> bit)) != 0))
+        .map(|set| set.then_some(Rgb888::WHITE))
+        .collect()
+}
+
+/// Pointer position, driven by pointer events. Touches move the pointer by the motion of their
+/// first contact, like a trackpad does.
+struct PointerTracker {
+    x: i32,
+    y: i32,
+    last_contact: Option<(u32, i32, i32)>,
+}
+
+impl PointerTracker {
+    const fn new() -> Self {
+        Self {
+            x: 0,
+            y: 0,
+            last_contact: None,
+        }
+    }
+
+    /// Returns the new position, which is kept within `width` and `height`
+    fn update(&mut self, event: &PointerEvent, width: u32, height: u32) -> (i32, i32) {
+        let (dx, dy) = match event {
+            PointerEvent::Relative { dx, dy, .. } => (*dx, *dy),
+            PointerEvent::Touch { contacts, .. } => {
+                let contact = contacts
+                    .first()
+                    .map(|contact| (contact.id, contact.x, contact.y));
+                let delta = match (self.last_contact, contact) {
+                    (Some((last_id, last_x, last_y)), Some((id, x, y))) if last_id == id => (
+                        (x - last_x) / TOUCH_UNITS_PER_PIXEL,
+                        (y - last_y) / TOUCH_UNITS_PER_PIXEL,
+                    ),
+                    _ => (0, 0),
+                };
+                self.last_contact = contact;
+                delta
+            }
+        };
+
+        self.x = self.x.saturating_add(dx).clamp(0, width as i32 - 1);
+        self.y = self.y.saturating_add(dy).clamp(0, height as i32 - 1);
+        (self.x, self.y)
+    }
+}
+
 extern "C" {
     fn _memcpy128_aligned(dst: *mut u32, src: *const u32, num_bytes: usize);
 }
@@ -80,14 +328,15 @@ impl Display {
 
         // SAFETY:
         //   This is safe because the memory range passed to our kernel already does not contain
-        //   the pages reserved for the framebuffer, but they are still regular ram (And should be
-        //   mapped as such to give nice read/write performance).
+        //   the pages reserved for the framebuffer, but they are still regular ram. They are not
+        //   cached, so that writes reach the display without cache maintenance, but can still be
+        //   combined for large sequential writes.
         unsafe {
             memory::MemoryManager::instance().map_logical_reserved(
                 "framebuffer",
                 la,
                 size,
-                Attributes::Normal,
+                Attributes::NormalNonCacheable,
                 Permissions::RW,
             )?
         };
@@ -95,43 +344,135 @@ impl Display {
         Ok(la.as_ptr() as *mut u32)
     }
 
-    /// Initializes the display HW with the given logo to work as a console.
-    pub fn init<T: ImageDrawable<Color = Rgb888>>(logo: &T) {
-        let video_args = &get_boot_args().boot_video;
-        let retina = (video_args.depth & RETINA_DEPTH_FLAG) != 0;
-        let font = if retina { &FIRA_CODE_30 } else { &FONT_7X14 };
-        let max_rows = (video_args.height as u32 - ROW_MARGIN * 2) / font.character_size.height;
+    /// Initializes the display HW to work as a console and clears it. The framebuffer described in
+    /// the boot arguments is validated first, and left untouched if it is not valid.
+    pub fn init() -> Result<(), Error> {
+        let framebuffer = get_boot_args()
+            .boot_video
+            .validate()
+            .map_err(Error::InvalidFramebuffer)?;
+        if framebuffer.bytes_per_pixel != BYTES_PER_PIXEL {
+            return Err(Error::UnsupportedPixelSize(framebuffer.bytes_per_pixel));
+        }
 
-        let size = video_args.height * video_args.stride;
-        let video_base = Self::map_fb(video_args.base as *mut u32, size).unwrap();
+        let font = if framebuffer.retina {
+            &FIRA_CODE_30
+        } else {
+            &FONT_7X14
+        };
+        let video_base = Self::map_fb(framebuffer.base as *mut u32, framebuffer.size_bytes())
+            .map_err(Error::MappingFailed)?;
 
         let mut display = Self {
             hwbase: video_base,
-            width: video_args.width as u32,
-            height: video_args.height as u32,
-            stride: video_args.stride as u32 / 4,
+            width: framebuffer.width as u32,
+            height: framebuffer.height as u32,
+            stride: (framebuffer.stride / BYTES_PER_PIXEL) as u32,
             font,
+            scale: font::scale(),
             current_row: 0,
             current_col: 0,
-            max_rows,
+            max_rows: 0,
+            overlay: None,
+            pointer: PointerTracker::new(),
         };
 
-        let rect = Rectangle::new(Point::new(0, 0), Size::new(display.width, display.height));
-        display.fill_solid(&rect, Rgb888::BLACK).unwrap();
-        display.draw_logo(logo);
+        display.max_rows = display.rows_for_scale();
+        display
+            .canvas()
+            .fill_rect(0, 0, display.width, display.height, Rgb888::BLACK);
 
         DISPLAY.lock().replace(display);
+        Ok(())
     }
 
-    fn draw_logo<T: ImageDrawable<Color = Rgb888>>(&mut self, logo: &T) {
-        let logo_size = logo.bounding_box().size;
+    fn canvas(&mut self) -> Canvas<'_> {
+        let pixels = unsafe {
+            &mut *core::ptr::slice_from_raw_parts_mut(
+                self.hwbase,
+                (self.stride * self.height) as usize,
+            )
+        };
+        Canvas {
+            pixels,
+            width: self.width,
+            height: self.height,
+            stride: self.stride,
+        }
+    }
+
+    fn with_display(f: impl FnOnce(&mut Display)) {
+        if let Some(display) = DISPLAY.lock().as_mut() {
+            f(display);
+        }
+    }
 
-        let x_pos = (self.width - logo_size.width) / 2;
-        let y_pos = (self.height - logo_size.height) / 2;
+    /// Returns the width and height of the display, if it is initialized.
+    pub fn size() -> Option<(u32, u32)> {
+        DISPLAY
+            .lock()
+            .as_ref()
+            .map(|display| (display.width, display.height))
+    }
+
+    /// Copies an image to the display, with its top left corner at `(x, y)`. `pixels` holds the
+    /// image row by row, with `width` pixels per row. Parts outside of the display are clipped.
+    pub fn blit(x: i32, y: i32, pixels: &[Rgb888], width: u32, height: u32) {
+        Self::with_display(|display| display.canvas().blit(x, y, pixels, width, height));
+    }
 
-        Image::new(logo, Point::new(x_pos as i32, y_pos as i32))
-            .draw(self)
-            .ok();
+    /// Fills a rectangle with a solid color. Parts outside of the display are clipped.
+    pub fn draw_rect(x: i32, y: i32, width: u32, height: u32, color: Rgb888) {
+        Self::with_display(|display| display.canvas().fill_rect(x, y, width, height, color));
+    }
+
+    /// Blends a sprite over the display contents, with its top left corner at `(x, y)`. `alpha` is
+    /// the opacity of the sprite. Only one overlay is drawn at a time, a previous one is undrawn
+    /// first. Text written to the console while an overlay is drawn may be erased by `undraw`.
+    pub fn draw_overlay(x: i32, y: i32, sprite: &Sprite<'_>, alpha: u8) {
+        Self::with_display(|display| display.draw_overlay_locked(x, y, sprite, alpha));
+    }
+
+    /// Removes the overlay, restoring the pixels it was drawn over.
+    pub fn undraw() {
+        Self::with_display(|display| display.undraw_locked());
+    }
+
+    /// Moves the pointer overlay according to a pointer event.
+    pub fn move_pointer(event: &PointerEvent) {
+        Self::with_display(|display| {
+            let (x, y) = display.pointer.update(event, display.width, display.height);
+            let pixels = pointer_sprite_pixels();
+            let sprite = Sprite {
+                pixels: &pixels,
+                width: 8,
+                height: POINTER_SHAPE.len() as u32,
+            };
+            display.draw_overlay_locked(x, y, &sprite, POINTER_ALPHA);
+        });
+    }
+
+    fn draw_overlay_locked(&mut self, x: i32, y: i32, sprite: &Sprite<'_>, alpha: u8) {
+        self.undraw_locked();
+
+        let mut canvas = self.canvas();
+        let saved = canvas.save(x, y, sprite.width, sprite.height);
+        canvas.blend_sprite(x, y, sprite, alpha);
+        self.overlay = Some(saved);
+    }
+
+    fn undraw_locked(&mut self) {
+        if let Some(saved) = self.overlay.take() {
+            self.canvas().restore(&saved);
+        }
+    }
+
+    /// Fills the whole display with a solid color.
+    pub fn clear(color: Rgb888) {
+        Self::with_display(|display| {
+            let (width, height) = (display.width, display.height);
+            display.canvas().fill_rect(0, 0, width, height, color);
+        });
     }
 
     fn scroll_up(&mut self) {
@@ -141,7 +482,7 @@ impl Display {
                 (self.width * self.height) as usize,
             )
         };
-        let offset = (self.width * self.font.character_size.height) as usize;
+        let offset = (self.width * self.cell_size().height) as usize;
         let count = (self.height * self.width) as usize - offset;
         let source = &hw[offset] as *const u32;
         let destination = hw.as_mut_ptr();
@@ -167,25 +508,9 @@ impl DrawTarget for Display {
     where
         I: IntoIterator<Item = Pixel<Self::Color>>,
     {
-        for Pixel(coord, color) in pixels.into_iter() {
-            let Point { x, y } = coord;
-
-            // Ignore whatever falls outside of the display
-            if x >= self.width as i32 || x < 0 || y >= self.height as i32 || y < 0 {
-                continue;
-            }
-
-            // Calculate the index in the framebuffer.
-            let pix_offset = (x + y * self.stride as i32) as usize;
-            let color =
-                (color.r() as u32) << 22 | (color.g() as u32) << 12 | (color.b() as u32) << 2;
-            let hw = unsafe {
-                &mut *core::ptr::slice_from_raw_parts_mut(
-                    self.hwbase,
-                    (self.width * self.height) as usize,
-                )
-            };
-            hw[pix_offset] = color;
+        let mut canvas = self.canvas();
+        for Pixel(Point { x, y }, color) in pixels.into_iter() {
+            canvas.set_pixel(x, y, color);
         }
 
         Ok(())
@@ -198,22 +523,84 @@ impl OriginDimensions for Display {
     }
 }
 
-impl Write for Display {
-    fn write_str(&mut self, s: &str) -> Result<(), fmt::Error> {
-        let splits = s.split_inclusive('\n');
-
-        let style = MonoTextStyle::new(self.font, Rgb888::WHITE);
-        for sub in splits {
-            let x_pos = COL_MARGIN + self.current_col * self.font.character_size.width;
-            let y_pos = ROW_MARGIN + self.current_row * self.font.character_size.height;
-            Text::with_baseline(
-                sub,
-                Point::new(x_pos as i32, y_pos as i32),
-                style,
-                Baseline::Top,
-            )
-            .draw(self)
+impl Display {
+    /// Size of a character on the display, with the font scaled.
+    fn cell_size(&self) -> Size {
+        self.font.character_size * self.scale
+    }
+
+    fn rows_for_scale(&self) -> u32 {
+        (self.height.saturating_sub(ROW_MARGIN * 2) / self.cell_size().height).max(1)
+    }
+
+    /// Picks up changes of `font::scale`. The console starts over from a clear screen, since text
+    /// with the old scale does not line up with the new rows.
+    fn update_scale(&mut self) {
+        let scale = font::scale();
+        if scale == self.scale {
+            return;
+        }
+
+        self.scale = scale;
+        self.max_rows = self.rows_for_scale();
+        self.current_row = 0;
+        self.current_col = 0;
+        let (width, height) = (self.width, self.height);
+        self.canvas().fill_rect(0, 0, width, height, Rgb888::BLACK);
+    }
+
+    /// Draws characters that all have a glyph in the font at the cursor, which is moved past them.
+    fn draw_run(&mut self, run: &str, color: Rgb888) {
+        if run.is_empty() {
+            return;
+        }
+
+        let position = self.cursor_position();
+        let scale = self.scale;
+        let style = MonoTextStyle::new(self.font, color);
+        Text::with_baseline(run, position, style, Baseline::Top)
+            .draw(&mut Scaled::new(self, position, scale))
             .expect("draw is infallible");
+        self.current_col += run.chars().count() as u32;
+    }
+
+    /// Draws an inverted `?` at the cursor, standing for `font::REPLACEMENT_CHARACTER`.
+    fn draw_replacement(&mut self) {
+        let position = self.cursor_position();
+        let size = self.cell_size();
+        self.canvas().fill_rect(
+            position.x,
+            position.y,
+            size.width,
+            size.height,
+            Rgb888::WHITE,
+        );
+        self.draw_run("?", Rgb888::BLACK);
+    }
+
+    fn cursor_position(&self) -> Point {
+        let cell_size = self.cell_size();
+        let x_pos = COL_MARGIN + self.current_col * cell_size.width;
+        let y_pos = ROW_MARGIN + self.current_row * cell_size.height;
+        Point::new(x_pos as i32, y_pos as i32)
+    }
+
+    fn write_text(&mut self, s: &str) {
+        self.update_scale();
+
+        for sub in s.split_inclusive('\n') {
+            let line = sub.strip_suffix('\n').unwrap_or(sub);
+
+            // Characters without a glyph split the line into runs that are drawn at once
+            let mut run_start = 0;
+            for (index, c) in line.char_indices() {
+                if !font::has_glyph(self.font, c) {
+                    self.draw_run(&line[run_start..index], Rgb888::WHITE);
+                    self.draw_replacement();
+                    run_start = index + c.len_utf8();
+                }
+            }
+            self.draw_run(&line[run_start..], Rgb888::WHITE);
 
             if sub.ends_with('\n') {
                 self.current_row += 1;
@@ -222,10 +609,41 @@ impl Write for Display {
                     self.scroll_up();
                     self.current_row = self.max_rows - 1;
                 }
-            } else {
-                self.current_col += sub.len() as u32;
             }
         }
+    }
+
+    /// Moves the cursor one character back and clears the character under it.
+    fn backspace(&mut self) {
+        if self.current_col == 0 {
+            return;
+        }
+        self.current_col -= 1;
+
+        let size = self.cell_size();
+        let x_pos = COL_MARGIN + self.current_col * size.width;
+        let y_pos = ROW_MARGIN + self.current_row * size.height;
+        self.canvas().fill_rect(
+            x_pos as i32,
+            y_pos as i32,
+            size.width,
+            size.height,
+            Rgb888::BLACK,
+        );
+    }
+}
+
+impl Write for Display {
+    fn write_str(&mut self, s: &str) -> Result<(), fmt::Error> {
+        // Backspace characters erase the previous character, as in a terminal
+        let mut parts = s.split('\x08');
+        if let Some(text) = parts.next() {
+            self.write_text(text);
+        }
+        for text in parts {
+            self.backspace();
+            self.write_text(text);
+        }
 
         Ok(())
     }
@@ -241,3 +659,232 @@ pub fn _print(args: fmt::Arguments) {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::{vec, vec::Vec};
+
+    const WIDTH: u32 = 4;
+    const HEIGHT: u32 = 3;
+    /// Rows are padded with two pixels, which must never be written
+    const STRIDE: u32 = 6;
+    const PADDING: u32 = 0xdead_beef;
+
+    fn framebuffer() -> Vec<u32> {
+        let mut pixels = vec![0; (STRIDE * HEIGHT) as usize];
+        for row in pixels.chunks_mut(STRIDE as usize) {
+            row[WIDTH as usize..].fill(PADDING);
+        }
+        pixels
+    }
+
+    fn canvas(pixels: &mut [u32]) -> Canvas<'_> {
+        Canvas {
+            pixels,
+            width: WIDTH,
+            height: HEIGHT,
+            stride: STRIDE,
+        }
+    }
+
+    /// Returns the visible rows of the framebuffer, checking the padding is untouched
+    fn rows(pixels: &[u32]) -> Vec<Vec<u32>> {
+        pixels
+            .chunks(STRIDE as usize)
+            .map(|row| {
+                assert!(row[WIDTH as usize..].iter().all(|pixel| *pixel == PADDING));
+                row[..WIDTH as usize].to_vec()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn color_encoding() {
+        assert_eq!(encode_color(Rgb888::BLACK), 0);
+        assert_eq!(encode_color(Rgb888::WHITE), 0x3fc_ff3fc);
+        assert_eq!(
+            encode_color(Rgb888::new(1, 2, 3)),
+            1 << 22 | 2 << 12 | 3 << 2
+        );
+    }
+
+    #[test]
+    fn fill_and_clear() {
+        let mut pixels = framebuffer();
+        let red = encode_color(Rgb888::RED);
+
+        canvas(&mut pixels).fill_rect(1, 1, 2, 5, Rgb888::RED);
+        assert_eq!(
+            rows(&pixels),
+            vec![vec![0, 0, 0, 0], vec![0, red, red, 0], vec![0, red, red, 0]]
+        );
+
+        // Fully outside of the framebuffer
+        canvas(&mut pixels).fill_rect(-3, 0, 3, 3, Rgb888::WHITE);
+        canvas(&mut pixels).fill_rect(0, 3, 3, 3, Rgb888::WHITE);
+        canvas(&mut pixels).set_pixel(4, 0, Rgb888::WHITE);
+
+        canvas(&mut pixels).fill_rect(-100, -100, 1000, 1000, Rgb888::BLACK);
+        assert_eq!(
+            rows(&pixels),
+            vec![vec![0; WIDTH as usize]; HEIGHT as usize]
+        );
+    }
+
+    #[test]
+    fn blit_with_clipping() {
+        let image: Vec<Rgb888> = (1..=9).map(|value| Rgb888::new(0, 0, value)).collect();
+        let p = |value: u8| encode_color(Rgb888::new(0, 0, value));
+
+        let mut pixels = framebuffer();
+        canvas(&mut pixels).blit(0, 0, &image[..6], 3, 2);
+        assert_eq!(
+            rows(&pixels),
+            vec![
+                vec![p(1), p(2), p(3), 0],
+                vec![p(4), p(5), p(6), 0],
+                vec![0; 4]
+            ]
+        );
+
+        // Clipped at the right and bottom edges
+        let mut pixels = framebuffer();
+        canvas(&mut pixels).blit(2, 1, &image, 3, 3);
+        assert_eq!(
+            rows(&pixels),
+            vec![vec![0; 4], vec![0, 0, p(1), p(2)], vec![0, 0, p(4), p(5)]]
+        );
+
+        // Clipped at the left and top edges
+        let mut pixels = framebuffer();
+        canvas(&mut pixels).blit(-1, -2, &image, 3, 3);
+        assert_eq!(
+            rows(&pixels),
+            vec![vec![p(8), p(9), 0, 0], vec![0; 4], vec![0; 4]]
+        );
+
+        // Fully outside, also when some of its rows would be visible
+        for (x, y) in [
+            (-3, 5),
+            (-3, 0),
+            (WIDTH as i32, 0),
+            (i32::MIN, -1),
+            (i32::MAX, 1),
+        ] {
+            let mut pixels = framebuffer();
+            canvas(&mut pixels).blit(x, y, &image, 3, 3);
+            assert_eq!(rows(&pixels), vec![vec![0; 4]; 3]);
+        }
+    }
+
+    #[test]
+    fn overlay_blending() {
+        let background = Rgb888::new(0, 100, 200);
+        assert_eq!(decode_color(encode_color(background)), background);
+        assert_eq!(blend(Rgb888::WHITE, background, 0), background);
+        assert_eq!(blend(Rgb888::WHITE, background, 255), Rgb888::WHITE);
+        assert_eq!(
+            blend(Rgb888::WHITE, background, 128),
+            Rgb888::new(128, 178, 228)
+        );
+
+        let mut pixels = framebuffer();
+        canvas(&mut pixels).fill_rect(0, 0, WIDTH, HEIGHT, background);
+        let before = pixels.clone();
+
+        // 2x2 sprite with a transparent pixel, partially outside of the framebuffer
+        let sprite_pixels = [
+            Some(Rgb888::WHITE),
+            None,
+            Some(Rgb888::BLACK),
+            Some(Rgb888::RED),
+        ];
+        let sprite = Sprite {
+            pixels: &sprite_pixels,
+            width: 2,
+            height: 2,
+        };
+        let mut overlay_canvas = canvas(&mut pixels);
+        let saved = overlay_canvas.save(3, 1, 2, 2);
+        overlay_canvas.blend_sprite(3, 1, &sprite, 128);
+
+        let bg = encode_color(background);
+        let white = encode_color(Rgb888::new(128, 178, 228));
+        let black = encode_color(Rgb888::new(0, 50, 100));
+        assert_eq!(
+            rows(&pixels),
+            vec![
+                vec![bg; 4],
+                vec![bg, bg, bg, white],
+                vec![bg, bg, bg, black]
+            ]
+        );
+
+        canvas(&mut pixels).restore(&saved);
+        assert_eq!(pixels, before);
+
+        // Sprites fully outside of the framebuffer are not drawn
+        for (x, y) in [
+            (-2, 1),
+            (WIDTH as i32, 1),
+            (i32::MAX, 0),
+            (0, HEIGHT as i32),
+        ] {
+            let mut overlay_canvas = canvas(&mut pixels);
+            let saved = overlay_canvas.save(x, y, 2, 2);
+            overlay_canvas.blend_sprite(x, y, &sprite, 128);
+            assert_eq!(pixels, before);
+
+            canvas(&mut pixels).restore(&saved);
+            assert_eq!(pixels, before);
+        }
+    }
+
+    #[test]
+    fn image_clipping() {
+        let mut pixels = framebuffer();
+        let canvas = canvas(&mut pixels);
+        let visible = |x, y, width, height| {
+            canvas
+                .clip_image(x, y, width, height)
+                .map(|(columns, rows)| (columns, rows.collect::<Vec<_>>()))
+        };
+
+        assert_eq!(visible(0, 0, 3, 2), Some((0..3, vec![(0, 0), (1, 3)])));
+        // The offsets skip the clipped pixels at the left and top
+        assert_eq!(visible(-1, -2, 3, 3), Some((0..2, vec![(0, 7)])));
+        assert_eq!(visible(2, 1, 3, 3), Some((2..4, vec![(1, 0), (2, 3)])));
+
+        // Rows are visible but columns are not, or the other way around
+        assert_eq!(visible(-3, 0, 3, 3), None);
+        assert_eq!(visible(WIDTH as i32, 0, 3, 3), None);
+        assert_eq!(visible(0, -3, 3, 3), None);
+        assert_eq!(visible(0, HEIGHT as i32, 3, 3), None);
+        assert_eq!(visible(1, 1, 0, 1), None);
+    }
+
+    #[test]
+    fn pointer_tracking() {
+        let mut pointer = PointerTracker::new();
+        let relative = |dx, dy| PointerEvent::Relative {
+            dx,
+            dy,
+            wheel: 0,
+            buttons: 0,
+        };
+        assert_eq!(pointer.update(&relative(5, 3), 100, 50), (5, 3));
+        assert_eq!(pointer.update(&relative(-10, 100), 100, 50), (0, 49));
+
+        let touch = |id, x, y| PointerEvent::Touch {
+            contacts: vec![crate::drivers::hid::report::Contact { id, x, y }],
+            buttons: 0,
+        };
+        // The first touch only sets the reference point
+        assert_eq!(pointer.update(&touch(1, 1000, 1000), 100, 50), (0, 49));
+        assert_eq!(pointer.update(&touch(1, 1040, 960), 100, 50), (10, 39));
+        // A different finger starts over
+        assert_eq!(pointer.update(&touch(2, 0, 0), 100, 50), (10, 39));
+    }
+}
@@ -4,7 +4,7 @@ use core::sync::atomic::{AtomicU32, Ordering};
 
 use aarch64_cpu::{
     asm::barrier,
-    registers::{CNTFRQ_EL0, CNTVCT_EL0, CNTV_CTL_EL0, CNTV_TVAL_EL0},
+    registers::{CNTFRQ_EL0, CNTVCT_EL0, CNTV_CTL_EL0, CNTV_CVAL_EL0, CNTV_TVAL_EL0},
 };
 use tock_registers::interfaces::{Readable, Writeable};
 
@@ -13,6 +13,7 @@ pub struct GenericTimer {
 }
 
 impl GenericTimer {
+    #[cfg_attr(test, allow(dead_code))]
     const fn new() -> Self {
         Self {
             ticks_per_cycle: AtomicU32::new(0),
@@ -54,10 +55,53 @@ impl interfaces::timer::Timer for GenericTimer {
     }
 }
 
+/// Returns the ticks elapsed since the timer interrupt was due. Only meaningful while the
+/// interrupt is active, since it is computed from the compare value of the timer.
+pub fn ticks_since_irq_deadline() -> interfaces::Ticks {
+    barrier::isb(barrier::SY);
+    interfaces::Ticks::new(CNTVCT_EL0.get().saturating_sub(CNTV_CVAL_EL0.get()))
+}
+
 // TODO(javier-varez): As with everything else, this should be moved towards a more
 // generic interface where we instantiate everything from the ADT.
+#[cfg(not(test))]
 static GENERIC_TIMER: GenericTimer = GenericTimer::new();
 
+#[cfg(not(test))]
 pub fn get_timer() -> &'static GenericTimer {
     &GENERIC_TIMER
 }
+
+/// Host unit tests get a mock timer instead. Every test thread has its own instance, so tests can
+/// move time forward without affecting each other.
+#[cfg(test)]
+pub fn get_timer() -> &'static interfaces::timer::MockTimer {
+    use interfaces::timer::MockTimer;
+
+    std::thread_local! {
+        static MOCK_TIMER: &'static MockTimer =
+            Box::leak(Box::new(MockTimer::new(MockTimer::DEFAULT_RESOLUTION_HZ)));
+    }
+    MOCK_TIMER.with(|timer| *timer)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use core::time::Duration;
+    use interfaces::timer::Timer;
+
+    #[test]
+    fn uses_mock_timer_in_tests() {
+        let timer = get_timer();
+        let start = timer.ticks();
+        timer.advance(Duration::from_millis(10));
+
+        let resolution = timer.resolution();
+        assert_eq!(
+            resolution.ticks_to_duration(timer.ticks()) - resolution.ticks_to_duration(start),
+            Duration::from_millis(10)
+        );
+    }
+}
@@ -172,7 +172,9 @@ impl GpioBank {
             .get_device_addr(gpio_bank, 0)
             .ok_or(Error::MissingAdtProperty("reg"))?;
 
-        let va = MemoryManager::instance().map_io(gpio_bank, pa, size)?;
+        let va = MemoryManager::instance()
+            .map_io(gpio_bank, pa, size)?
+            .leak();
 
         if let Some(num_pins) = node
             .find_property("#gpio-pins")
@@ -1,4 +1,6 @@
+pub mod descriptor;
 pub mod keyboard;
+pub mod report;
 
 use crate::{
     adt,
@@ -8,9 +10,11 @@ use crate::{
         interfaces::timer::Timer,
         spi::{self, Spi},
     },
+    input,
     prelude::*,
 };
-use keyboard::{Keyboard, KeyboardReport};
+use descriptor::ReportDescriptor;
+use report::{Event, ReportDecoder};
 
 use core::{mem::MaybeUninit, time::Duration};
 
@@ -27,12 +31,101 @@ pub enum Error {
     NodeNotCompatible,
     ProbeFailed,
     InvalidAdt(adt::Error),
+    InvalidReportDescriptor(descriptor::Error),
     IOError(IoError),
 }
 
 const KBD_DEVICE_ID: u8 = 1;
 const TRACKPAD_DEVICE_ID: u8 = 2;
 
+/// Boot keyboard layout with report ID 1, followed by a byte of Apple-specific data
+#[rustfmt::skip]
+pub const KEYBOARD_REPORT_DESCRIPTOR: &[u8] = &[
+    0x05, 0x01,       // Usage Page (Generic Desktop)
+    0x09, 0x06,       // Usage (Keyboard)
+    0xA1, 0x01,       // Collection (Application)
+    0x85, 0x01,       //   Report ID (1)
+    0x05, 0x07,       //   Usage Page (Keyboard)
+    0x19, 0xE0,       //   Usage Minimum (Left Control)
+    0x29, 0xE7,       //   Usage Maximum (Right GUI)
+    0x15, 0x00,       //   Logical Minimum (0)
+    0x25, 0x01,       //   Logical Maximum (1)
+    0x75, 0x01,       //   Report Size (1)
+    0x95, 0x08,       //   Report Count (8)
+    0x81, 0x02,       //   Input (Data, Variable, Absolute)
+    0x75, 0x08,       //   Report Size (8)
+    0x95, 0x01,       //   Report Count (1)
+    0x81, 0x01,       //   Input (Constant)
+    0x19, 0x00,       //   Usage Minimum (0)
+    0x29, 0xFF,       //   Usage Maximum (255)
+    0x26, 0xFF, 0x00, //   Logical Maximum (255)
+    0x95, 0x06,       //   Report Count (6)
+    0x81, 0x00,       //   Input (Data, Array)
+    0xC0,             // End Collection
+];
+
+/// Header of the Apple multi-touch trackpad report, up to the first finger. The layout is the one of
+/// `struct touchpad_protocol` in the Linux applespi driver (drivers/input/keyboard/applespi.c):
+/// `clicked` is the button and `number_of_fingers` the contact count.
+#[rustfmt::skip]
+const TRACKPAD_HEADER_DESCRIPTOR: &[u8] = &[
+    0x05, 0x0D,       // Usage Page (Digitizer)
+    0x09, 0x05,       // Usage (Touch Pad)
+    0xA1, 0x01,       // Collection (Application)
+    0x75, 0x08,       //   Report Size (8)
+    0x95, 0x01,       //   Report Count (1)
+    0x81, 0x01,       //   Input (Constant)
+    0x05, 0x09,       //   Usage Page (Button)
+    0x09, 0x01,       //   Usage (Button 1)
+    0x15, 0x00,       //   Logical Minimum (0)
+    0x25, 0x01,       //   Logical Maximum (1)
+    0x81, 0x02,       //   Input (Data, Variable, Absolute)
+    0x95, 0x1C,       //   Report Count (28)
+    0x81, 0x01,       //   Input (Constant)
+    0x05, 0x0D,       //   Usage Page (Digitizer)
+    0x09, 0x54,       //   Usage (Contact Count)
+    0x25, 0x7F,       //   Logical Maximum (127)
+    0x95, 0x01,       //   Report Count (1)
+    0x81, 0x02,       //   Input (Data, Variable, Absolute)
+    0x95, 0x11,       //   Report Count (17)
+    0x81, 0x01,       //   Input (Constant)
+];
+
+/// A finger of the Apple multi-touch trackpad report, repeated for every finger. Follows
+/// `struct tp_finger` of the applespi driver, keeping only `abs_x` and `abs_y`.
+#[rustfmt::skip]
+const TRACKPAD_FINGER_DESCRIPTOR: &[u8] = &[
+    0x05, 0x0D,       // Usage Page (Digitizer)
+    0x09, 0x22,       // Usage (Finger)
+    0xA1, 0x02,       // Collection (Logical)
+    0x75, 0x10,       //   Report Size (16)
+    0x95, 0x01,       //   Report Count (1)
+    0x81, 0x01,       //   Input (Constant)
+    0x05, 0x01,       //   Usage Page (Generic Desktop)
+    0x09, 0x30,       //   Usage (X)
+    0x09, 0x31,       //   Usage (Y)
+    0x16, 0x00, 0x80, //   Logical Minimum (-32768)
+    0x26, 0xFF, 0x7F, //   Logical Maximum (32767)
+    0x95, 0x02,       //   Report Count (2)
+    0x81, 0x02,       //   Input (Data, Variable, Absolute)
+    0x95, 0x0C,       //   Report Count (12)
+    0x81, 0x01,       //   Input (Constant)
+    0xC0,             // End Collection
+];
+
+const TRACKPAD_MAX_FINGERS: usize = 5;
+
+/// Returns the descriptor of the Apple multi-touch trackpad report.
+pub fn trackpad_report_descriptor() -> Vec<u8> {
+    let mut descriptor = TRACKPAD_HEADER_DESCRIPTOR.to_vec();
+    for _ in 0..TRACKPAD_MAX_FINGERS {
+        descriptor.extend_from_slice(TRACKPAD_FINGER_DESCRIPTOR);
+    }
+    // End of the application collection
+    descriptor.push(0xC0);
+    descriptor
+}
+
 #[repr(C)]
 #[derive(Debug)]
 struct HidTransferPacket {
@@ -45,6 +138,8 @@ struct HidTransferPacket {
     crc16: u16,
 }
 
+/// Header of the messages carried by the packets, `struct message` of the applespi driver. `byte0`
+/// and `byte1` are the message type, 0x0110 for the keyboard and 0x0210 for the trackpad.
 #[repr(C)]
 #[derive(Debug)]
 struct HidMsgHeader {
@@ -66,7 +161,8 @@ pub struct HidDev<'a> {
     spidev: Spi,
     enable_pin: gpio::Pin<'a, gpio::mode::Output>,
     irq_pin: gpio::Pin<'a, gpio::mode::Input>,
-    keyboard_dev: Keyboard,
+    keyboard_decoder: ReportDecoder,
+    trackpad_decoder: ReportDecoder,
 }
 
 impl<'a> HidDev<'a> {
@@ -115,14 +211,36 @@ impl<'a> HidDev<'a> {
         spidev.set_clock_to_cs_delay(Duration::from_micros(45));
         spidev.set_clock_rate(Duration::from_nanos(125)); // 1 / 8 MHz
 
+        let keyboard_descriptor = ReportDescriptor::parse(KEYBOARD_REPORT_DESCRIPTOR)
+            .map_err(Error::InvalidReportDescriptor)?;
+        let trackpad_descriptor = ReportDescriptor::parse(&trackpad_report_descriptor())
+            .map_err(Error::InvalidReportDescriptor)?;
+
         Ok(Self {
             spidev,
             enable_pin,
             irq_pin,
-            keyboard_dev: Keyboard::new(),
+            keyboard_decoder: ReportDecoder::new(keyboard_descriptor),
+            trackpad_decoder: ReportDecoder::new(trackpad_descriptor),
         })
     }
 
+    /// Replaces the layout used to decode keyboard reports.
+    pub fn set_keyboard_descriptor(&mut self, descriptor: &[u8]) -> Result<(), Error> {
+        let descriptor =
+            ReportDescriptor::parse(descriptor).map_err(Error::InvalidReportDescriptor)?;
+        self.keyboard_decoder = ReportDecoder::new(descriptor);
+        Ok(())
+    }
+
+    /// Replaces the layout used to decode trackpad reports.
+    pub fn set_trackpad_descriptor(&mut self, descriptor: &[u8]) -> Result<(), Error> {
+        let descriptor =
+            ReportDescriptor::parse(descriptor).map_err(Error::InvalidReportDescriptor)?;
+        self.trackpad_decoder = ReportDecoder::new(descriptor);
+        Ok(())
+    }
+
     pub fn has_events(&mut self) -> bool {
         matches!(self.irq_pin.get_pin_state(), PinState::Low)
     }
@@ -162,40 +280,95 @@ impl<'a> HidDev<'a> {
         Ok(unsafe { hid_packet.assume_init() })
     }
 
-    fn parse_keyboard_packet(&mut self, packet: HidTransferPacket) {
-        if packet.length as usize >= core::mem::size_of::<HidMsgHeader>() {
-            let header: HidMsgHeader = unsafe { core::mem::transmute_copy(&packet.data) };
-            if header.len >= 9
-                && header.byte0 == 0x10
-                && header.byte1 == 0x01
-                && header.byte2 == 0x00
-            {
-                let off = core::mem::size_of::<HidMsgHeader>();
-                let data = &packet.data[off..off + header.len as usize];
-
-                let report = KeyboardReport::new(data);
-                self.keyboard_dev.handle_report(report);
+    /// Returns the report carried by the packet, if any.
+    fn packet_report(packet: &HidTransferPacket) -> Option<&[u8]> {
+        let payload = packet.data.get(..packet.length as usize)?;
+        let off = core::mem::size_of::<HidMsgHeader>();
+        if payload.len() < off {
+            return None;
+        }
+
+        let header: HidMsgHeader = unsafe { core::mem::transmute_copy(&packet.data) };
+        if header.byte0 != 0x10 || header.byte1 != packet.device || header.byte2 != 0x00 {
+            return None;
+        }
+
+        // Messages longer than the payload of the packet are rejected
+        payload.get(off..off + header.len as usize)
+    }
+
+    fn dispatch_events(events: Vec<Event>) {
+        for event in events {
+            if let Event::Key(key_event) = &event {
+                if let (true, Some(c)) = (key_event.pressed, key_event.scancode.to_char()) {
+                    log_info!("User pressed key: {}", c);
+                }
             }
-        } else {
-            log_error!("Short keyboard packet");
+            input::push_event(event);
         }
     }
 
     pub fn process(&mut self) {
         if self.has_events() {
             let packet = self.receive_packet().unwrap();
-            match packet.device {
-                KBD_DEVICE_ID => {
-                    self.parse_keyboard_packet(packet);
-                }
-                TRACKPAD_DEVICE_ID => {
-                    // Ignore trackpad packets for now
-                    // log_info!("Trackpad packet, {:?}", packet);
-                }
+            let decoder = match packet.device {
+                KBD_DEVICE_ID => &mut self.keyboard_decoder,
+                TRACKPAD_DEVICE_ID => &mut self.trackpad_decoder,
                 _ => {
                     log_warning!("Unknown packet, {:?}", packet);
+                    return;
+                }
+            };
+
+            match Self::packet_report(&packet) {
+                Some(report) => Self::dispatch_events(decoder.decode(report)),
+                None => {
+                    log_error!("Invalid packet from device {}", packet.device);
                 }
             }
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn packet(device: u8, length: u16, message: &[u8]) -> HidTransferPacket {
+        let mut data = [0; 246];
+        data[..message.len()].copy_from_slice(message);
+        HidTransferPacket {
+            flags: 0x20,
+            device,
+            offset: 0,
+            remaining: 0,
+            length,
+            data,
+            crc16: 0,
+        }
+    }
+
+    #[test]
+    fn packet_reports() {
+        // Keyboard message with a 10 byte report
+        let mut message = vec![0x10, 0x01, 0x00, 0x05, 0x0a, 0x00, 0x0a, 0x00];
+        message.extend_from_slice(&[1, 0x02, 0, 0x04, 0, 0, 0, 0, 0, 0]);
+
+        let keyboard = packet(KBD_DEVICE_ID, message.len() as u16, &message);
+        assert_eq!(HidDev::packet_report(&keyboard), Some(&message[8..]));
+
+        // The message type must match the device
+        let trackpad = packet(TRACKPAD_DEVICE_ID, message.len() as u16, &message);
+        assert_eq!(HidDev::packet_report(&trackpad), None);
+
+        // Truncated packets are rejected
+        let truncated = packet(KBD_DEVICE_ID, message.len() as u16 - 1, &message);
+        assert_eq!(HidDev::packet_report(&truncated), None);
+        let truncated = packet(KBD_DEVICE_ID, 4, &message);
+        assert_eq!(HidDev::packet_report(&truncated), None);
+
+        // As are packets declaring more data than they can hold
+        let oversized = packet(KBD_DEVICE_ID, 300, &message);
+        assert_eq!(HidDev::packet_report(&oversized), None);
+    }
+}
@@ -0,0 +1,451 @@
+//! Parser for HID report descriptors.
+//!
+//! Only input reports are described, since they are the only ones the driver decodes. Long items,
+//! physical ranges, units and designators are accepted but ignored.
+
+use crate::prelude::*;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Error {
+    UnexpectedEnd,
+    UnbalancedCollection,
+    EmptyGlobalStack,
+    InvalidReportSize(u32),
+}
+
+pub mod usage_page {
+    pub const GENERIC_DESKTOP: u16 = 0x01;
+    pub const KEYBOARD: u16 = 0x07;
+    pub const BUTTON: u16 = 0x09;
+    pub const DIGITIZER: u16 = 0x0D;
+}
+
+pub mod usage {
+    pub const X: u16 = 0x30;
+    pub const Y: u16 = 0x31;
+    pub const WHEEL: u16 = 0x38;
+
+    pub const FINGER: u16 = 0x22;
+    pub const TIP_SWITCH: u16 = 0x42;
+    pub const CONTACT_IDENTIFIER: u16 = 0x51;
+    pub const CONTACT_COUNT: u16 = 0x54;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Usage {
+    pub page: u16,
+    pub id: u16,
+}
+
+impl Usage {
+    pub const fn new(page: u16, id: u16) -> Self {
+        Self { page, id }
+    }
+
+    /// Usages of 4 bytes carry their own page in the upper half.
+    fn from_item(page: u16, value: u32, size: usize) -> Self {
+        if size == 4 {
+            Self::new((value >> 16) as u16, value as u16)
+        } else {
+            Self::new(page, value as u16)
+        }
+    }
+}
+
+/// A field of an input report, made of `count` values of `bit_size` bits each.
+#[derive(Debug, Clone)]
+pub struct Field {
+    pub report_id: u8,
+    /// Offset of the first value, not counting the report ID byte
+    pub bit_offset: usize,
+    pub bit_size: usize,
+    pub count: usize,
+    pub logical_min: i32,
+    pub logical_max: i32,
+    pub is_constant: bool,
+    /// Array fields contain the indices of the active usages instead of one value per usage
+    pub is_array: bool,
+    pub is_relative: bool,
+    /// Index of the innermost collection that contains the field
+    pub collection: usize,
+    usages: Vec<Usage>,
+    usage_range: Option<(Usage, Usage)>,
+}
+
+impl Field {
+    const FLAG_CONSTANT: u32 = 1 << 0;
+    const FLAG_VARIABLE: u32 = 1 << 1;
+    const FLAG_RELATIVE: u32 = 1 << 2;
+
+    fn raw_value(&self, data: &[u8], index: usize) -> Option<u32> {
+        if index >= self.count {
+            return None;
+        }
+
+        let first_bit = self.bit_offset + index * self.bit_size;
+        if first_bit + self.bit_size > data.len() * 8 {
+            return None;
+        }
+
+        let mut value = 0u32;
+        for bit in 0..self.bit_size {
+            let position = first_bit + bit;
+            if (data[position / 8] >> (position % 8)) & 1 != 0 {
+                value |= 1 << bit;
+            }
+        }
+        Some(value)
+    }
+
+    /// Returns the value at `index` in the report data, which excludes the report ID byte. Values
+    /// are sign-extended when the logical range includes negative numbers.
+    pub fn value(&self, data: &[u8], index: usize) -> Option<i32> {
+        let value = self.raw_value(data, index)?;
+        if self.logical_min < 0 && self.bit_size < 32 {
+            let shift = 32 - self.bit_size;
+            Some(((value << shift) as i32) >> shift)
+        } else {
+            Some(value as i32)
+        }
+    }
+
+    /// Returns the usage of the value at `index` of a variable field.
+    pub fn usage(&self, index: usize) -> Option<Usage> {
+        match self.usage_range {
+            Some((min, max)) => {
+                let id = min.id.checked_add(index.try_into().ok()?)?;
+                (id <= max.id).then_some(Usage::new(min.page, id))
+            }
+            None => self.usages.get(index).or(self.usages.last()).copied(),
+        }
+    }
+
+    /// Returns the usage that an array field selects with `value`.
+    pub fn array_usage(&self, value: i32) -> Option<Usage> {
+        if value < self.logical_min || value > self.logical_max {
+            return None;
+        }
+        let index = (value - self.logical_min) as usize;
+        match self.usage_range {
+            Some((min, max)) => {
+                let id = min.id.checked_add(index.try_into().ok()?)?;
+                (id <= max.id).then_some(Usage::new(min.page, id))
+            }
+            None => self.usages.get(index).copied(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct GlobalState {
+    usage_page: u16,
+    logical_min: i32,
+    logical_max: i32,
+    report_size: usize,
+    report_id: u8,
+    report_count: usize,
+}
+
+#[derive(Debug, Default)]
+struct LocalState {
+    usages: Vec<Usage>,
+    usage_min: Option<Usage>,
+    usage_max: Option<Usage>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ReportDescriptor {
+    fields: Vec<Field>,
+    /// Usage of every collection, by index
+    collection_usages: Vec<Option<Usage>>,
+    has_report_ids: bool,
+}
+
+impl ReportDescriptor {
+    const ITEM_TYPE_MAIN: u8 = 0;
+    const ITEM_TYPE_GLOBAL: u8 = 1;
+    const ITEM_TYPE_LOCAL: u8 = 2;
+    const LONG_ITEM_PREFIX: u8 = 0xFE;
+
+    const MAIN_INPUT: u8 = 0x8;
+    const MAIN_COLLECTION: u8 = 0xA;
+    const MAIN_END_COLLECTION: u8 = 0xC;
+
+    const GLOBAL_USAGE_PAGE: u8 = 0x0;
+    const GLOBAL_LOGICAL_MIN: u8 = 0x1;
+    const GLOBAL_LOGICAL_MAX: u8 = 0x2;
+    const GLOBAL_REPORT_SIZE: u8 = 0x7;
+    const GLOBAL_REPORT_ID: u8 = 0x8;
+    const GLOBAL_REPORT_COUNT: u8 = 0x9;
+    const GLOBAL_PUSH: u8 = 0xA;
+    const GLOBAL_POP: u8 = 0xB;
+
+    const LOCAL_USAGE: u8 = 0x0;
+    const LOCAL_USAGE_MIN: u8 = 0x1;
+    const LOCAL_USAGE_MAX: u8 = 0x2;
+
+    pub fn parse(data: &[u8]) -> Result<Self, Error> {
+        let mut fields = vec![];
+        let mut has_report_ids = false;
+
+        let mut global = GlobalState::default();
+        let mut global_stack = vec![];
+        let mut local = LocalState::default();
+
+        // Collection 0 stands for the top level, outside of any collection
+        let mut collections = vec![0];
+        let mut collection_usages = vec![None];
+
+        // Bit offsets of the next field, for every report ID
+        let mut offsets: FlatMap<u8, usize> = FlatMap::new();
+
+        let mut remaining = data;
+        while let Some((&prefix, rest)) = remaining.split_first() {
+            if prefix == Self::LONG_ITEM_PREFIX {
+                let size = *rest.first().ok_or(Error::UnexpectedEnd)? as usize;
+                remaining = rest.get(size + 2..).ok_or(Error::UnexpectedEnd)?;
+                continue;
+            }
+
+            let size = match prefix & 0x3 {
+                3 => 4,
+                size => size as usize,
+            };
+            let item_type = (prefix >> 2) & 0x3;
+            let tag = prefix >> 4;
+
+            let value_bytes = rest.get(..size).ok_or(Error::UnexpectedEnd)?;
+            remaining = &rest[size..];
+
+            let value = value_bytes
+                .iter()
+                .rev()
+                .fold(0u32, |value, byte| (value << 8) | *byte as u32);
+            let signed_value = match size {
+                1 => value as u8 as i8 as i32,
+                2 => value as u16 as i16 as i32,
+                _ => value as i32,
+            };
+
+            match (item_type, tag) {
+                (Self::ITEM_TYPE_MAIN, Self::MAIN_INPUT) => {
+                    let offset = offsets.lookup(&global.report_id).copied().unwrap_or(0);
+                    let bit_size = global.report_size;
+                    let count = global.report_count;
+                    offsets.insert(global.report_id, offset + bit_size * count);
+
+                    let usage_range = match (local.usage_min, local.usage_max) {
+                        (Some(min), Some(max)) => Some((min, max)),
+                        _ => None,
+                    };
+                    fields.push(Field {
+                        report_id: global.report_id,
+                        bit_offset: offset,
+                        bit_size,
+                        count,
+                        logical_min: global.logical_min,
+                        logical_max: global.logical_max,
+                        is_constant: (value & Field::FLAG_CONSTANT) != 0,
+                        is_array: (value & Field::FLAG_VARIABLE) == 0,
+                        is_relative: (value & Field::FLAG_RELATIVE) != 0,
+                        collection: *collections.last().unwrap(),
+                        usages: core::mem::take(&mut local.usages),
+                        usage_range,
+                    });
+                }
+                (Self::ITEM_TYPE_MAIN, Self::MAIN_COLLECTION) => {
+                    collections.push(collection_usages.len());
+                    collection_usages.push(local.usages.first().copied());
+                }
+                (Self::ITEM_TYPE_MAIN, Self::MAIN_END_COLLECTION) => {
+                    if collections.len() == 1 {
+                        return Err(Error::UnbalancedCollection);
+                    }
+                    collections.pop();
+                }
+                (Self::ITEM_TYPE_GLOBAL, Self::GLOBAL_USAGE_PAGE) => {
+                    global.usage_page = value as u16;
+                }
+                (Self::ITEM_TYPE_GLOBAL, Self::GLOBAL_LOGICAL_MIN) => {
+                    global.logical_min = signed_value;
+                }
+                (Self::ITEM_TYPE_GLOBAL, Self::GLOBAL_LOGICAL_MAX) => {
+                    // Only signed if the minimum is negative, so that 0xFF is a valid maximum
+                    global.logical_max = if global.logical_min < 0 {
+                        signed_value
+                    } else {
+                        value as i32
+                    };
+                }
+                (Self::ITEM_TYPE_GLOBAL, Self::GLOBAL_REPORT_SIZE) => {
+                    if value == 0 || value > 32 {
+                        return Err(Error::InvalidReportSize(value));
+                    }
+                    global.report_size = value as usize;
+                }
+                (Self::ITEM_TYPE_GLOBAL, Self::GLOBAL_REPORT_ID) => {
+                    global.report_id = value as u8;
+                    has_report_ids = true;
+                }
+                (Self::ITEM_TYPE_GLOBAL, Self::GLOBAL_REPORT_COUNT) => {
+                    global.report_count = value as usize;
+                }
+                (Self::ITEM_TYPE_GLOBAL, Self::GLOBAL_PUSH) => {
+                    global_stack.push(global.clone());
+                }
+                (Self::ITEM_TYPE_GLOBAL, Self::GLOBAL_POP) => {
+                    global = global_stack.pop().ok_or(Error::EmptyGlobalStack)?;
+                }
+                (Self::ITEM_TYPE_LOCAL, Self::LOCAL_USAGE) => {
+                    local
+                        .usages
+                        .push(Usage::from_item(global.usage_page, value, size));
+                }
+                (Self::ITEM_TYPE_LOCAL, Self::LOCAL_USAGE_MIN) => {
+                    local.usage_min = Some(Usage::from_item(global.usage_page, value, size));
+                }
+                (Self::ITEM_TYPE_LOCAL, Self::LOCAL_USAGE_MAX) => {
+                    local.usage_max = Some(Usage::from_item(global.usage_page, value, size));
+                }
+                _ => {}
+            }
+
+            // Local items only apply to the next main item
+            if item_type == Self::ITEM_TYPE_MAIN {
+                local = LocalState::default();
+            }
+        }
+
+        if collections.len() != 1 {
+            return Err(Error::UnbalancedCollection);
+        }
+
+        Ok(Self {
+            fields,
+            collection_usages,
+            has_report_ids,
+        })
+    }
+
+    /// Splits a report into its report ID and data. Reports have no ID byte if the descriptor
+    /// defines no report IDs, in which case the ID is 0.
+    pub fn split_report<'a>(&self, report: &'a [u8]) -> Option<(u8, &'a [u8])> {
+        if self.has_report_ids {
+            report.split_first().map(|(id, data)| (*id, data))
+        } else {
+            Some((0, report))
+        }
+    }
+
+    pub fn collection_usage(&self, collection: usize) -> Option<Usage> {
+        self.collection_usages.get(collection).copied().flatten()
+    }
+
+    /// Returns the fields of the input report with the given ID.
+    pub fn fields(&self, report_id: u8) -> impl Iterator<Item = &Field> {
+        self.fields
+            .iter()
+            .filter(move |field| field.report_id == report_id)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[rustfmt::skip]
+    const MOUSE_DESCRIPTOR: &[u8] = &[
+        0x05, 0x01,       // Usage Page (Generic Desktop)
+        0x09, 0x02,       // Usage (Mouse)
+        0xA1, 0x01,       // Collection (Application)
+        0x85, 0x02,       //   Report ID (2)
+        0x05, 0x09,       //   Usage Page (Button)
+        0x19, 0x01,       //   Usage Minimum (1)
+        0x29, 0x03,       //   Usage Maximum (3)
+        0x15, 0x00,       //   Logical Minimum (0)
+        0x25, 0x01,       //   Logical Maximum (1)
+        0x95, 0x03,       //   Report Count (3)
+        0x75, 0x01,       //   Report Size (1)
+        0x81, 0x02,       //   Input (Data, Variable, Absolute)
+        0x95, 0x01,       //   Report Count (1)
+        0x75, 0x05,       //   Report Size (5)
+        0x81, 0x01,       //   Input (Constant)
+        0x05, 0x01,       //   Usage Page (Generic Desktop)
+        0x09, 0x30,       //   Usage (X)
+        0x09, 0x31,       //   Usage (Y)
+        0x15, 0x81,       //   Logical Minimum (-127)
+        0x25, 0x7F,       //   Logical Maximum (127)
+        0x75, 0x08,       //   Report Size (8)
+        0x95, 0x02,       //   Report Count (2)
+        0x81, 0x06,       //   Input (Data, Variable, Relative)
+        0xC0,             // End Collection
+    ];
+
+    #[test]
+    fn parse_mouse_descriptor() {
+        let descriptor = ReportDescriptor::parse(MOUSE_DESCRIPTOR).unwrap();
+        assert!(descriptor.fields(0).next().is_none());
+
+        let fields: Vec<&Field> = descriptor.fields(2).collect();
+        assert_eq!(fields.len(), 3);
+
+        let buttons = fields[0];
+        assert_eq!(buttons.bit_offset, 0);
+        assert_eq!((buttons.bit_size, buttons.count), (1, 3));
+        assert!(!buttons.is_array && !buttons.is_constant);
+        assert_eq!(buttons.usage(2), Some(Usage::new(usage_page::BUTTON, 3)));
+        assert_eq!(buttons.usage(3), None);
+
+        assert!(fields[1].is_constant);
+        assert_eq!(fields[1].bit_offset, 3);
+
+        let axes = fields[2];
+        assert_eq!(axes.bit_offset, 8);
+        assert!(axes.is_relative);
+        assert_eq!((axes.logical_min, axes.logical_max), (-127, 127));
+        assert_eq!(
+            axes.usage(1),
+            Some(Usage::new(usage_page::GENERIC_DESKTOP, usage::Y))
+        );
+    }
+
+    #[test]
+    fn extract_values() {
+        let descriptor = ReportDescriptor::parse(MOUSE_DESCRIPTOR).unwrap();
+        let report = [0x02, 0b101, 0x05, 0xFB];
+        let (report_id, data) = descriptor.split_report(&report).unwrap();
+        assert_eq!(report_id, 2);
+
+        let fields: Vec<&Field> = descriptor.fields(report_id).collect();
+        let buttons: Vec<_> = (0..3).map(|i| fields[0].value(data, i).unwrap()).collect();
+        assert_eq!(buttons, vec![1, 0, 1]);
+        assert_eq!(fields[2].value(data, 0), Some(5));
+        assert_eq!(fields[2].value(data, 1), Some(-5));
+        assert_eq!(fields[2].value(data, 2), None);
+        assert_eq!(fields[2].value(&data[..2], 1), None);
+    }
+
+    #[test]
+    fn invalid_descriptors() {
+        assert_eq!(
+            ReportDescriptor::parse(&[0xA1, 0x01]).unwrap_err(),
+            Error::UnbalancedCollection
+        );
+        assert_eq!(
+            ReportDescriptor::parse(&[0xC0]).unwrap_err(),
+            Error::UnbalancedCollection
+        );
+        assert_eq!(
+            ReportDescriptor::parse(&[0x05]).unwrap_err(),
+            Error::UnexpectedEnd
+        );
+        assert_eq!(
+            ReportDescriptor::parse(&[0xB4]).unwrap_err(),
+            Error::EmptyGlobalStack
+        );
+        assert_eq!(
+            ReportDescriptor::parse(&[0x75, 0x40]).unwrap_err(),
+            Error::InvalidReportSize(0x40)
+        );
+    }
+}
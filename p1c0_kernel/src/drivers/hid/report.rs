@@ -0,0 +1,412 @@
+//! Decodes HID input reports into keyboard and pointer events, as described by a report
+//! descriptor.
+
+use super::{
+    descriptor::{usage, usage_page, Field, ReportDescriptor, Usage},
+    keyboard::Scancode,
+};
+use crate::prelude::*;
+
+/// Modifier keys held down, one bit per key in the order of their usages
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Modifiers(u8);
+
+impl Modifiers {
+    pub const LEFT_CTRL: Self = Self(1 << 0);
+    pub const LEFT_SHIFT: Self = Self(1 << 1);
+    pub const LEFT_ALT: Self = Self(1 << 2);
+    pub const LEFT_GUI: Self = Self(1 << 3);
+    pub const RIGHT_CTRL: Self = Self(1 << 4);
+    pub const RIGHT_SHIFT: Self = Self(1 << 5);
+    pub const RIGHT_ALT: Self = Self(1 << 6);
+    pub const RIGHT_GUI: Self = Self(1 << 7);
+
+    const FIRST_SCANCODE: u8 = 0xE0;
+    const LAST_SCANCODE: u8 = 0xE7;
+
+    fn from_scancode(code: Scancode) -> Option<Self> {
+        match code.value() {
+            value @ Self::FIRST_SCANCODE..=Self::LAST_SCANCODE => {
+                Some(Self(1 << (value - Self::FIRST_SCANCODE)))
+            }
+            _ => None,
+        }
+    }
+
+    pub fn contains(&self, other: Self) -> bool {
+        (self.0 & other.0) == other.0
+    }
+
+    pub fn shift(&self) -> bool {
+        (self.0 & (Self::LEFT_SHIFT.0 | Self::RIGHT_SHIFT.0)) != 0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub scancode: Scancode,
+    pub pressed: bool,
+    /// Modifiers held down after the event is applied
+    pub modifiers: Modifiers,
+}
+
+/// A finger touching a trackpad, in the units of the device
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Contact {
+    pub id: u32,
+    pub x: i32,
+    pub y: i32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PointerEvent {
+    Relative {
+        dx: i32,
+        dy: i32,
+        wheel: i32,
+        buttons: u32,
+    },
+    Touch {
+        contacts: Vec<Contact>,
+        buttons: u32,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    Key(KeyEvent),
+    Pointer(PointerEvent),
+}
+
+#[derive(Debug, Default)]
+struct ContactState {
+    collection: usize,
+    id: Option<u32>,
+    x: i32,
+    y: i32,
+    touching: Option<bool>,
+}
+
+/// Values found in a single report
+#[derive(Debug, Default)]
+struct ReportState {
+    keys: Vec<Scancode>,
+    has_keys: bool,
+    rollover: bool,
+    buttons: u32,
+    has_buttons: bool,
+    dx: i32,
+    dy: i32,
+    wheel: i32,
+    has_motion: bool,
+    contact_count: Option<usize>,
+    contacts: Vec<ContactState>,
+}
+
+impl ReportState {
+    fn contact(&mut self, collection: usize) -> &mut ContactState {
+        let index = match self
+            .contacts
+            .iter()
+            .position(|contact| contact.collection == collection)
+        {
+            Some(index) => index,
+            None => {
+                self.contacts.push(ContactState {
+                    collection,
+                    ..Default::default()
+                });
+                self.contacts.len() - 1
+            }
+        };
+        &mut self.contacts[index]
+    }
+}
+
+/// Keeps the state needed to turn reports into events, like the keys currently held down.
+pub struct ReportDecoder {
+    descriptor: ReportDescriptor,
+    pressed_keys: Vec<Scancode>,
+    modifiers: Modifiers,
+}
+
+impl ReportDecoder {
+    /// Error value that keyboards report in every key slot when too many keys are pressed
+    const ROLLOVER_ERROR: u16 = 0x01;
+
+    pub fn new(descriptor: ReportDescriptor) -> Self {
+        Self {
+            descriptor,
+            pressed_keys: vec![],
+            modifiers: Modifiers::default(),
+        }
+    }
+
+    fn is_contact_collection(&self, collection: usize) -> bool {
+        self.descriptor.collection_usage(collection)
+            == Some(Usage::new(usage_page::DIGITIZER, usage::FINGER))
+    }
+
+    fn decode_field(&self, field: &Field, data: &[u8], state: &mut ReportState) {
+        if field.is_array {
+            for index in 0..field.count {
+                let usage = match field
+                    .value(data, index)
+                    .and_then(|value| field.array_usage(value))
+                {
+                    Some(usage) => usage,
+                    None => continue,
+                };
+                if usage.page == usage_page::KEYBOARD {
+                    state.has_keys = true;
+                    match usage.id {
+                        0 => {}
+                        Self::ROLLOVER_ERROR => state.rollover = true,
+                        id => state.keys.push(Scancode::new(id as u8)),
+                    }
+                }
+            }
+            return;
+        }
+
+        let is_contact = self.is_contact_collection(field.collection);
+        for index in 0..field.count {
+            let (usage, value) = match (field.usage(index), field.value(data, index)) {
+                (Some(usage), Some(value)) => (usage, value),
+                _ => continue,
+            };
+
+            match (usage.page, usage.id) {
+                (usage_page::KEYBOARD, id) => {
+                    state.has_keys = true;
+                    if value != 0 {
+                        state.keys.push(Scancode::new(id as u8));
+                    }
+                }
+                (usage_page::BUTTON, id @ 1..=32) => {
+                    state.has_buttons = true;
+                    if value != 0 {
+                        state.buttons |= 1 << (id - 1);
+                    }
+                }
+                (usage_page::DIGITIZER, usage::CONTACT_COUNT) => {
+                    state.contact_count = Some(value as usize);
+                }
+                (usage_page::DIGITIZER, usage::CONTACT_IDENTIFIER) if is_contact => {
+                    state.contact(field.collection).id = Some(value as u32);
+                }
+                (usage_page::DIGITIZER, usage::TIP_SWITCH) if is_contact => {
+                    state.contact(field.collection).touching = Some(value != 0);
+                }
+                (usage_page::GENERIC_DESKTOP, usage::X) if is_contact => {
+                    state.contact(field.collection).x = value;
+                }
+                (usage_page::GENERIC_DESKTOP, usage::Y) if is_contact => {
+                    state.contact(field.collection).y = value;
+                }
+                (usage_page::GENERIC_DESKTOP, usage::X) => {
+                    state.has_motion = true;
+                    state.dx = value;
+                }
+                (usage_page::GENERIC_DESKTOP, usage::Y) => {
+                    state.has_motion = true;
+                    state.dy = value;
+                }
+                (usage_page::GENERIC_DESKTOP, usage::WHEEL) => {
+                    state.has_motion = true;
+                    state.wheel = value;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn key_events(&mut self, keys: Vec<Scancode>, events: &mut Vec<Event>) {
+        let released: Vec<Scancode> = self
+            .pressed_keys
+            .iter()
+            .filter(|code| !keys.contains(code))
+            .copied()
+            .collect();
+        for code in released {
+            if let Some(modifier) = Modifiers::from_scancode(code) {
+                self.modifiers.0 &= !modifier.0;
+            }
+            events.push(Event::Key(KeyEvent {
+                scancode: code,
+                pressed: false,
+                modifiers: self.modifiers,
+            }));
+        }
+
+        for code in keys.iter().filter(|code| !self.pressed_keys.contains(code)) {
+            if let Some(modifier) = Modifiers::from_scancode(*code) {
+                self.modifiers.0 |= modifier.0;
+            }
+            events.push(Event::Key(KeyEvent {
+                scancode: *code,
+                pressed: true,
+                modifiers: self.modifiers,
+            }));
+        }
+
+        self.pressed_keys = keys;
+    }
+
+    /// Decodes an input report, including its report ID byte if the descriptor uses them.
+    pub fn decode(&mut self, report: &[u8]) -> Vec<Event> {
+        let (report_id, data) = match self.descriptor.split_report(report) {
+            Some(split) => split,
+            None => return vec![],
+        };
+
+        let mut state = ReportState::default();
+        for field in self
+            .descriptor
+            .fields(report_id)
+            .filter(|field| !field.is_constant)
+        {
+            self.decode_field(field, data, &mut state);
+        }
+
+        let mut events = vec![];
+        if state.rollover {
+            log_warning!("Too many keys pressed, ignoring keyboard report");
+        } else if state.has_keys {
+            self.key_events(core::mem::take(&mut state.keys), &mut events);
+        }
+
+        if !state.contacts.is_empty() {
+            // Devices without a tip switch report as many contacts as the contact count says
+            let contact_count = state.contact_count.unwrap_or(state.contacts.len());
+            let contacts = state
+                .contacts
+                .iter()
+                .enumerate()
+                .take(contact_count)
+                .filter(|(_, contact)| contact.touching.unwrap_or(true))
+                .map(|(index, contact)| Contact {
+                    id: contact.id.unwrap_or(index as u32),
+                    x: contact.x,
+                    y: contact.y,
+                })
+                .collect();
+            events.push(Event::Pointer(PointerEvent::Touch {
+                contacts,
+                buttons: state.buttons,
+            }));
+        } else if state.has_motion || state.has_buttons {
+            events.push(Event::Pointer(PointerEvent::Relative {
+                dx: state.dx,
+                dy: state.dy,
+                wheel: state.wheel,
+                buttons: state.buttons,
+            }));
+        }
+
+        events
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::drivers::hid::{trackpad_report_descriptor, KEYBOARD_REPORT_DESCRIPTOR};
+
+    const KEY_A: Scancode = Scancode::new(0x04);
+    const KEY_LEFT_SHIFT: Scancode = Scancode::new(0xE1);
+
+    fn key(scancode: Scancode, pressed: bool, modifiers: Modifiers) -> Event {
+        Event::Key(KeyEvent {
+            scancode,
+            pressed,
+            modifiers,
+        })
+    }
+
+    #[test]
+    fn keyboard_reports() {
+        let descriptor = ReportDescriptor::parse(KEYBOARD_REPORT_DESCRIPTOR).unwrap();
+        let mut decoder = ReportDecoder::new(descriptor);
+
+        // Shift + A, as sent by the internal keyboard. The last byte is the fn key state
+        let events = decoder.decode(&[0x01, 0x02, 0x00, 0x04, 0, 0, 0, 0, 0, 0x00]);
+        assert_eq!(
+            events,
+            vec![
+                key(KEY_LEFT_SHIFT, true, Modifiers::LEFT_SHIFT),
+                key(KEY_A, true, Modifiers::LEFT_SHIFT),
+            ]
+        );
+        assert!(Modifiers::LEFT_SHIFT.shift());
+
+        // Repeated reports do not generate events
+        assert!(decoder
+            .decode(&[0x01, 0x02, 0x00, 0x04, 0, 0, 0, 0, 0, 0x00])
+            .is_empty());
+
+        // Release shift, keep A pressed
+        let events = decoder.decode(&[0x01, 0x00, 0x00, 0x04, 0, 0, 0, 0, 0, 0x00]);
+        assert_eq!(
+            events,
+            vec![key(KEY_LEFT_SHIFT, false, Modifiers::default())]
+        );
+
+        // Too many keys pressed is ignored
+        assert!(decoder
+            .decode(&[0x01, 0x00, 0x00, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x00])
+            .is_empty());
+
+        let events = decoder.decode(&[0x01, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(events, vec![key(KEY_A, false, Modifiers::default())]);
+    }
+
+    #[test]
+    fn trackpad_reports() {
+        let descriptor = ReportDescriptor::parse(&trackpad_report_descriptor()).unwrap();
+        let mut decoder = ReportDecoder::new(descriptor);
+
+        const HEADER_SIZE: usize = 48;
+        const FINGER_SIZE: usize = 30;
+        let mut report = vec![0u8; HEADER_SIZE + 5 * FINGER_SIZE];
+        // Clicked, with two fingers down
+        report[1] = 1;
+        report[30] = 2;
+        for (finger, (x, y)) in [(-1200i16, 3400i16), (250, -80)].iter().enumerate() {
+            let offset = HEADER_SIZE + finger * FINGER_SIZE;
+            report[offset + 2..offset + 4].copy_from_slice(&x.to_le_bytes());
+            report[offset + 4..offset + 6].copy_from_slice(&y.to_le_bytes());
+        }
+
+        let events = decoder.decode(&report);
+        assert_eq!(
+            events,
+            vec![Event::Pointer(PointerEvent::Touch {
+                contacts: vec![
+                    Contact {
+                        id: 0,
+                        x: -1200,
+                        y: 3400
+                    },
+                    Contact {
+                        id: 1,
+                        x: 250,
+                        y: -80
+                    },
+                ],
+                buttons: 1,
+            })]
+        );
+
+        // All fingers lifted
+        let events = decoder.decode(&[0u8; HEADER_SIZE + 5 * FINGER_SIZE]);
+        assert_eq!(
+            events,
+            vec![Event::Pointer(PointerEvent::Touch {
+                contacts: vec![],
+                buttons: 0,
+            })]
+        );
+    }
+}
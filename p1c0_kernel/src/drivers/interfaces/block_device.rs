@@ -0,0 +1,17 @@
+use crate::{error, prelude::*};
+
+type Result<T> = core::result::Result<T, Box<dyn error::Error>>;
+
+/// Device that stores data in fixed-size blocks, addressed by their index.
+pub trait BlockDevice {
+    fn block_size(&self) -> usize;
+    fn num_blocks(&self) -> usize;
+
+    /// Reads consecutive blocks starting at `first_block`. The length of `buffer` must be a
+    /// multiple of the block size.
+    fn read_blocks(&self, first_block: usize, buffer: &mut [u8]) -> Result<()>;
+
+    /// Writes consecutive blocks starting at `first_block`. The length of `data` must be a multiple
+    /// of the block size.
+    fn write_blocks(&self, first_block: usize, data: &[u8]) -> Result<()>;
+}
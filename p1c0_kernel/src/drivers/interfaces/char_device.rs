@@ -0,0 +1,12 @@
+use crate::{error, prelude::*};
+
+type Result<T> = core::result::Result<T, Box<dyn error::Error>>;
+
+/// Device that transfers an unstructured stream of bytes.
+pub trait CharDevice {
+    /// Reads the available bytes into `buffer` without blocking. Returns the number of bytes read.
+    fn read(&self, buffer: &mut [u8]) -> Result<usize>;
+
+    /// Writes as many bytes of `data` as the device accepts. Returns the number of bytes written.
+    fn write(&self, data: &[u8]) -> Result<usize>;
+}
@@ -2,4 +2,15 @@ use crate::print;
 
 pub trait Logger {
     fn write_u8(&mut self, c: u8) -> Result<(), print::Error>;
+
+    /// Returns a received character without blocking, if the logger can receive them at all.
+    fn read_u8(&mut self) -> Option<u8> {
+        None
+    }
+
+    /// Changes the baud rate of serial loggers. Returns the rate that was actually set, or `None`
+    /// if the logger is not a serial port.
+    fn set_baud(&mut self, _rate: u32) -> Option<u32> {
+        None
+    }
 }
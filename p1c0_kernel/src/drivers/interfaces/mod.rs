@@ -1,3 +1,5 @@
+pub mod block_device;
+pub mod char_device;
 pub mod interrupt_controller;
 pub mod logger;
 pub mod timer;
@@ -6,7 +8,7 @@ pub mod watchdog;
 use core::time::Duration;
 
 /// The current number of ticks the timer has made since boot
-#[derive(Debug, Ord, PartialOrd, Eq, PartialEq, Copy, Clone)]
+#[derive(Debug, Default, Ord, PartialOrd, Eq, PartialEq, Copy, Clone)]
 pub struct Ticks(u64);
 
 impl Ticks {
@@ -15,6 +17,15 @@ impl Ticks {
     pub(super) fn new(raw_ticks: u64) -> Ticks {
         Self(raw_ticks)
     }
+
+    pub fn saturating_add(self, other: Ticks) -> Ticks {
+        Self(self.0.saturating_add(other.0))
+    }
+
+    /// Returns the ticks elapsed since `earlier`, or zero if `earlier` is later than `self`
+    pub fn saturating_sub(self, earlier: Ticks) -> Ticks {
+        Self(self.0.saturating_sub(earlier.0))
+    }
 }
 
 /// Resolution for a timer.
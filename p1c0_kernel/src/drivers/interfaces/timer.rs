@@ -1,5 +1,8 @@
 use super::{Ticks, TimerResolution};
 
+#[cfg(test)]
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
 pub trait Timer {
     /// Initializes the timer to run at a fixed jiffy interval. This is not related to the timer
     /// resolution, which can and should be much higher than the interval
@@ -21,3 +24,113 @@ pub trait Timer {
         while self.ticks().0 < (start + ticks) {}
     }
 }
+
+/// Timer for host unit tests. Ticks only advance when the test says so, which makes code that
+/// depends on time deterministic. `delay` advances the ticks by the requested duration instead of
+/// spinning.
+#[cfg(test)]
+pub struct MockTimer {
+    ticks: AtomicU64,
+    resolution_hz: AtomicU64,
+    irq_active: AtomicBool,
+}
+
+#[cfg(test)]
+impl MockTimer {
+    /// Frequency of the generic timer on the M1
+    pub const DEFAULT_RESOLUTION_HZ: u64 = 24_000_000;
+
+    pub const fn new(resolution_hz: u64) -> Self {
+        Self {
+            ticks: AtomicU64::new(0),
+            resolution_hz: AtomicU64::new(resolution_hz),
+            irq_active: AtomicBool::new(false),
+        }
+    }
+
+    pub fn set_ticks(&self, ticks: u64) {
+        self.ticks.store(ticks, Ordering::Relaxed);
+    }
+
+    pub fn set_resolution(&self, resolution_hz: u64) {
+        self.resolution_hz.store(resolution_hz, Ordering::Relaxed);
+    }
+
+    pub fn advance(&self, duration: core::time::Duration) {
+        let ticks = self.resolution().duration_to_ticks(duration);
+        self.ticks.fetch_add(ticks.0, Ordering::Relaxed);
+    }
+
+    /// Raises the timer interrupt, as the hardware would do when the jiffy interval expires.
+    pub fn fire_irq(&self) {
+        self.irq_active.store(true, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+impl Timer for MockTimer {
+    fn initialize(&self, _interval: core::time::Duration) {}
+
+    fn resolution(&self) -> TimerResolution {
+        TimerResolution::from_hz(self.resolution_hz.load(Ordering::Relaxed))
+    }
+
+    fn ticks(&self) -> Ticks {
+        Ticks::new(self.ticks.load(Ordering::Relaxed))
+    }
+
+    fn handle_irq(&self) {
+        self.irq_active.store(false, Ordering::Relaxed);
+    }
+
+    fn is_irq_active(&self) -> bool {
+        self.irq_active.load(Ordering::Relaxed)
+    }
+
+    fn delay(&self, time: core::time::Duration) {
+        self.advance(time);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use core::time::Duration;
+
+    #[test]
+    fn mock_timer_advances() {
+        let timer = MockTimer::new(1_000_000);
+        assert_eq!(timer.ticks(), Ticks::new(0));
+
+        timer.advance(Duration::from_millis(3));
+        assert_eq!(timer.ticks(), Ticks::new(3000));
+        assert_eq!(
+            timer.resolution().ticks_to_duration(timer.ticks()),
+            Duration::from_millis(3)
+        );
+
+        timer.delay(Duration::from_micros(500));
+        assert_eq!(
+            timer.resolution().ticks_to_duration(timer.ticks()),
+            Duration::from_micros(3500)
+        );
+
+        timer.set_resolution(MockTimer::DEFAULT_RESOLUTION_HZ);
+        timer.set_ticks(48_000_000);
+        assert_eq!(
+            timer.resolution().ticks_to_duration(timer.ticks()),
+            Duration::from_secs(2)
+        );
+    }
+
+    #[test]
+    fn mock_timer_irq() {
+        let timer = MockTimer::new(MockTimer::DEFAULT_RESOLUTION_HZ);
+        assert!(!timer.is_irq_active());
+        timer.fire_irq();
+        assert!(timer.is_irq_active());
+        timer.handle_irq();
+        assert!(!timer.is_irq_active());
+    }
+}
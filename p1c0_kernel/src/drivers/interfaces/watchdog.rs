@@ -1,3 +1,45 @@
+use crate::sync::spinlock::RwSpinLock;
+
+use core::time::Duration;
+
 pub trait Watchdog {
+    /// Restarts the countdown. Must be called more often than the configured timeout.
     fn pet(&self);
+
+    /// Starts the watchdog, which resets the system if it is not petted within `timeout`.
+    fn configure(&self, timeout: Duration);
+
+    fn disable(&self);
+
+    /// Returns true if the last reset of the system was caused by the watchdog.
+    fn last_reset_was_watchdog(&self) -> bool;
+}
+
+// Assume just 1 watchdog for now
+static WATCHDOG: RwSpinLock<Option<crate::drivers::DeviceRef>> = RwSpinLock::new(None);
+
+pub fn register_watchdog(watchdog: crate::drivers::DeviceRef) {
+    match &*watchdog.lock_read() {
+        crate::drivers::Dev::Watchdog(_) => {}
+        _ => {
+            panic!("Device must be a watchdog");
+        }
+    }
+    WATCHDOG.lock_write().replace(watchdog);
+}
+
+/// Calls `callable` with the registered watchdog. Returns false if there is none.
+pub fn may_do_with_watchdog(callable: impl FnOnce(&dyn Watchdog)) -> bool {
+    let watchdog_guard = WATCHDOG.lock_read();
+    if let Some(watchdog) = watchdog_guard.as_ref() {
+        match &*watchdog.lock_read() {
+            crate::drivers::Dev::Watchdog(watchdog) => {
+                callable(watchdog.as_ref());
+                return true;
+            }
+            _ => unreachable!(),
+        };
+    }
+
+    false
 }
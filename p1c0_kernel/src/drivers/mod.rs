@@ -4,6 +4,9 @@ pub mod generic_timer;
 pub mod gpio;
 pub mod hid;
 pub mod interfaces;
+pub mod power;
+#[cfg(feature = "semihosting")]
+pub mod semihosting;
 pub mod spi;
 pub mod uart;
 pub mod virtio;
@@ -11,11 +14,15 @@ pub mod wdt;
 
 use crate::{adt::AdtNode, prelude::*, sync::spinlock::RwSpinLock};
 
+use interfaces::{block_device::BlockDevice, char_device::CharDevice};
+
 #[derive(Debug)]
 pub enum Error {
     DriverAlreadyRegistered(String),
     NoCompatibleInDevice,
     NoDriverForDevice,
+    /// Returned by drivers when the device depends on another device that is not available yet
+    Deferred,
     DeviceSpecificError(Box<dyn error::Error>),
 }
 
@@ -23,7 +30,7 @@ pub type Result<T> = core::result::Result<T, Error>;
 
 pub type DeviceRef = Arc<RwSpinLock<Dev>>;
 
-trait Driver {
+pub trait Driver {
     fn probe(&self, dev_path: &[AdtNode]) -> Result<DeviceRef>;
 }
 
@@ -34,19 +41,64 @@ pub enum Dev {
     Logger(Box<dyn interfaces::logger::Logger>),
 }
 
-// Generic Device that does not interact with the world
-pub trait Device {}
+impl Dev {
+    pub fn as_block_device(&self) -> Option<&dyn BlockDevice> {
+        match self {
+            Dev::Generic(device) => device.as_block_device(),
+            _ => None,
+        }
+    }
+
+    pub fn as_char_device(&self) -> Option<&dyn CharDevice> {
+        match self {
+            Dev::Generic(device) => device.as_char_device(),
+            _ => None,
+        }
+    }
+}
+
+/// Generic device. Other code can query the capabilities it implements, which are none by default.
+pub trait Device {
+    fn as_block_device(&self) -> Option<&dyn BlockDevice> {
+        None
+    }
 
-// This just keeps devices alive for now, but should also allow to query devices from other devs.
-#[allow(dead_code)]
+    fn as_char_device(&self) -> Option<&dyn CharDevice> {
+        None
+    }
+}
+
+// Probed devices, keyed by the path of their ADT node
 static DEVICES: RwSpinLock<FlatMap<String, DeviceRef>> =
     RwSpinLock::new(FlatMap::new_no_capacity());
 
+/// Returns the path of the node at the end of `dev_path`, which starts at a child of the root node.
+fn node_path(dev_path: &[AdtNode]) -> String {
+    let mut path = String::new();
+    for node in dev_path {
+        path.push('/');
+        path.push_str(node.get_name());
+    }
+    path
+}
+
+/// Makes a device available to `get_device`. A device previously registered with the same path is
+/// replaced.
+pub fn register_device(path: &str, device: DeviceRef) {
+    DEVICES.lock_write().insert(path.to_string(), device);
+}
+
+/// Looks up a device by the path of its ADT node, like `/arm-io/spi3`.
+pub fn get_device(path: &str) -> Option<DeviceRef> {
+    DEVICES.lock_read().lookup(path).cloned()
+}
+
+// Writer-preferring so that registering a driver is not starved by devices being probed
 static DRIVERS: RwSpinLock<FlatMap<String, Box<dyn Driver>>> =
-    RwSpinLock::new(FlatMap::new_no_capacity());
+    RwSpinLock::new_writer_preferring(FlatMap::new_no_capacity());
 
-// Registration of drivers is only allowed from the driver module and submodules
-fn register_driver(compatible: &str, driver: Box<dyn Driver>) -> Result<()> {
+/// Drivers are normally registered by their own module, in an initcall.
+pub fn register_driver(compatible: &str, driver: Box<dyn Driver>) -> Result<()> {
     let mut drivers = DRIVERS.lock_write();
     drivers
         .insert_with_strategy(
@@ -59,22 +111,116 @@ fn register_driver(compatible: &str, driver: Box<dyn Driver>) -> Result<()> {
 }
 
 pub fn probe_device(dev_path: &[AdtNode]) -> Result<()> {
-    // Find a compatible driver and try to probe the device with it.
-    // If that doesn't work we might need to cry and raise an error
+    // Try the compatible drivers in order, until one of them probes the device successfully
     let dev = dev_path
         .last()
         .expect("There's no device to probe!")
         .clone();
     let compatible_list = dev
-        .get_compatible_list()
-        .ok_or(Error::NoCompatibleInDevice)?;
+        .find_property("compatible")
+        .ok_or(Error::NoCompatibleInDevice)?
+        .str_list_value_indexed();
 
-    for compatible_str in compatible_list {
+    for (index, compatible_str) in compatible_list {
         let drivers = DRIVERS.lock_read();
         if let Some(driver) = drivers.lookup(compatible_str) {
-            driver.probe(dev_path)?;
+            // Not fatal, firmware might have left the device enabled anyway
+            if let Err(e) = power::enable_for_node(&dev) {
+                log_warning!(
+                    "Unable to power up {}, probing anyway. Error: {:?}",
+                    dev.get_name(),
+                    e
+                );
+            }
+
+            // Another compatible driver might still handle the device
+            match driver.probe(dev_path) {
+                Ok(device) => {
+                    register_device(&node_path(dev_path), device);
+                    return Ok(());
+                }
+                Err(Error::Deferred) => return Err(Error::Deferred),
+                Err(e) => {
+                    log_warning!(
+                        "Driver for {} (compatible #{}) failed to probe {}. Error: {:?}",
+                        compatible_str,
+                        index,
+                        dev.get_name(),
+                        e
+                    );
+                }
+            }
         }
     }
 
     Err(Error::NoDriverForDevice)
 }
+
+/// Bounds the passes over deferred devices. Every pass but the last one probes at least one device,
+/// so this is only reached with very long dependency chains.
+const MAX_DEFERRED_PROBE_PASSES: usize = 16;
+
+/// Probes the deferred devices again until all of them succeed or a pass makes no progress, which
+/// means the remaining devices depend on each other or on devices that are missing. Devices that
+/// fail with any other error are dropped. Returns the devices that are still deferred.
+pub fn retry_deferred_probes<D>(
+    mut deferred: Vec<D>,
+    mut probe: impl FnMut(&D) -> Result<()>,
+) -> Vec<D> {
+    for _ in 0..MAX_DEFERRED_PROBE_PASSES {
+        let pending = deferred.len();
+        deferred.retain(|dev| matches!(probe(dev), Err(Error::Deferred)));
+        if deferred.is_empty() || deferred.len() == pending {
+            break;
+        }
+    }
+    deferred
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::cell::RefCell;
+
+    #[test]
+    fn deferred_probe_resolves_dependencies() {
+        // The SPI controller depends on the GPIO bank, which is probed after it
+        let probed = RefCell::new(vec![]);
+        let probe = |dev: &&'static str| -> Result<()> {
+            if *dev == "spi" && !probed.borrow().contains(&"gpio") {
+                return Err(Error::Deferred);
+            }
+            probed.borrow_mut().push(*dev);
+            Ok(())
+        };
+
+        let mut deferred = vec![];
+        for dev in ["spi", "gpio"] {
+            if let Err(Error::Deferred) = probe(&dev) {
+                deferred.push(dev);
+            }
+        }
+        assert_eq!(deferred, vec!["spi"]);
+
+        assert!(retry_deferred_probes(deferred, probe).is_empty());
+        assert_eq!(*probed.borrow(), vec!["gpio", "spi"]);
+    }
+
+    #[test]
+    fn deferred_probe_detects_cycles() {
+        let attempts = RefCell::new(0);
+        let probe = |dev: &&str| -> Result<()> {
+            *attempts.borrow_mut() += 1;
+            match *dev {
+                "a" | "b" => Err(Error::Deferred),
+                _ => Err(Error::NoDriverForDevice),
+            }
+        };
+
+        let remaining = retry_deferred_probes(vec!["a", "b", "c"], probe);
+        assert_eq!(remaining, vec!["a", "b"]);
+        // One pass drops the failed device, the next one makes no progress
+        assert_eq!(*attempts.borrow(), 5);
+    }
+}
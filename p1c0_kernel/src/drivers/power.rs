@@ -0,0 +1,327 @@
+//! Power and clock gating through the power manager (PMGR).
+//!
+//! Devices list the gates they depend on in their `clock-gates` and `power-gates` ADT properties.
+//! Every gate is described by an entry in the `devices` property of the PMGR node, which points to
+//! the power state register that controls it. Gates may have parents, which are enabled first.
+
+use crate::{
+    adt::{self, AdtNode},
+    memory::{
+        address::{Address, VirtualAddress},
+        MemoryManager,
+    },
+    prelude::*,
+    sync::spinlock::SpinLock,
+};
+
+use tock_registers::{
+    interfaces::{ReadWriteable, Readable},
+    register_bitfields,
+    registers::ReadWrite,
+};
+
+const PMGR_PATH: &str = "/arm-io/pmgr";
+
+register_bitfields! {u32,
+    PowerState [
+        TARGET OFFSET(0) NUMBITS(4) [
+            Off = 0,
+            Active = 0xF,
+        ],
+        ACTUAL OFFSET(4) NUMBITS(4) [
+            Off = 0,
+            Active = 0xF,
+        ],
+    ]
+}
+
+#[derive(Debug)]
+pub enum Error {
+    PmgrNotFound,
+    InvalidPmgrData,
+    UnknownGate(u32),
+    GateTimeout(u32),
+    MappingFailed,
+}
+
+impl From<adt::Error> for Error {
+    fn from(_: adt::Error) -> Self {
+        Error::InvalidPmgrData
+    }
+}
+
+/// Entry of the `ps-regs` property of the PMGR. Describes a bank of power state registers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct PsRegBank {
+    reg_index: u32,
+    offset: u32,
+}
+
+/// Entry of the `devices` property of the PMGR
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Gate {
+    id: u32,
+    flags: u32,
+    parents: [u32; 2],
+    addr_offset: u8,
+    ps_reg_index: u8,
+    name: &'static str,
+}
+
+impl Gate {
+    const ENTRY_SIZE: usize = 0x30;
+
+    const FLAGS_OFFSET: usize = 0x00;
+    const PARENTS_OFFSET: usize = 0x04;
+    const ADDR_OFFSET_OFFSET: usize = 0x0A;
+    const PS_REG_INDEX_OFFSET: usize = 0x0B;
+    const ID_OFFSET: usize = 0x1A;
+    const NAME_OFFSET: usize = 0x20;
+    const NAME_SIZE: usize = 0x10;
+
+    /// Virtual gates have no register. Enabling them just enables their parents
+    const FLAG_VIRTUAL: u32 = 1 << 4;
+
+    fn from_entry(entry: &'static [u8]) -> Self {
+        let read_u16 = |offset: usize| u16::from_le_bytes([entry[offset], entry[offset + 1]]);
+
+        let name_data = &entry[Self::NAME_OFFSET..Self::NAME_OFFSET + Self::NAME_SIZE];
+        let name_data = name_data
+            .split(|byte| *byte == 0)
+            .next()
+            .unwrap_or(name_data);
+
+        Self {
+            id: read_u16(Self::ID_OFFSET) as u32,
+            flags: u32::from_le_bytes(
+                entry[Self::FLAGS_OFFSET..Self::FLAGS_OFFSET + 4]
+                    .try_into()
+                    .unwrap(),
+            ),
+            parents: [
+                read_u16(Self::PARENTS_OFFSET) as u32,
+                read_u16(Self::PARENTS_OFFSET + 2) as u32,
+            ],
+            addr_offset: entry[Self::ADDR_OFFSET_OFFSET],
+            ps_reg_index: entry[Self::PS_REG_INDEX_OFFSET],
+            name: core::str::from_utf8(name_data).unwrap_or("<invalid>"),
+        }
+    }
+
+    fn is_virtual(&self) -> bool {
+        (self.flags & Self::FLAG_VIRTUAL) != 0
+    }
+
+    /// Offset of the power state register of the gate from the start of the PMGR register range.
+    fn register_offset(&self, bank: &PsRegBank) -> usize {
+        bank.offset as usize + ((self.addr_offset as usize) << 3)
+    }
+}
+
+fn find_gate(devices: &'static [u8], id: u32) -> Option<Gate> {
+    devices
+        .chunks_exact(Gate::ENTRY_SIZE)
+        .map(Gate::from_entry)
+        .find(|gate| gate.id == id)
+}
+
+fn parse_ps_reg_banks(ps_regs: &adt::AdtProperty) -> Result<Vec<PsRegBank>, Error> {
+    let values: Vec<u32> = ps_regs.u32_array_iter()?.collect();
+    if values.len() % 3 != 0 {
+        return Err(Error::InvalidPmgrData);
+    }
+
+    // The third value of each entry is a mask that is not needed here
+    Ok(values
+        .chunks_exact(3)
+        .map(|entry| PsRegBank {
+            reg_index: entry[0],
+            offset: entry[1],
+        })
+        .collect())
+}
+
+/// Virtual addresses of the PMGR register ranges mapped so far, by reg index.
+static PMGR_REGS: SpinLock<FlatMap<u32, VirtualAddress>> =
+    SpinLock::new(FlatMap::new_no_capacity());
+
+struct Pmgr {
+    nodes: heapless::Vec<AdtNode, 8>,
+    devices: &'static [u8],
+    banks: Vec<PsRegBank>,
+}
+
+impl Pmgr {
+    /// Bounds the recursion through gate parents, in case the ADT data is cyclic
+    const MAX_GATE_DEPTH: usize = 8;
+    const MAX_POLL_ITERATIONS: usize = 100_000;
+
+    fn new() -> Result<Self, Error> {
+        let adt = adt::get_adt()?;
+        let nodes: heapless::Vec<AdtNode, 8> = adt.path_iter(PMGR_PATH).collect();
+        let node = match nodes.last() {
+            Some(node) if node.get_name() == "pmgr" => node,
+            _ => return Err(Error::PmgrNotFound),
+        };
+
+        let devices = node
+            .find_property("devices")
+            .ok_or(Error::InvalidPmgrData)?
+            .get_data();
+        let banks = parse_ps_reg_banks(
+            &node
+                .find_property("ps-regs")
+                .ok_or(Error::InvalidPmgrData)?,
+        )?;
+
+        Ok(Self {
+            nodes,
+            devices,
+            banks,
+        })
+    }
+
+    fn power_state_register(
+        &self,
+        gate: &Gate,
+    ) -> Result<&'static ReadWrite<u32, PowerState::Register>, Error> {
+        let bank = self
+            .banks
+            .get(gate.ps_reg_index as usize)
+            .ok_or(Error::InvalidPmgrData)?;
+
+        let mut pmgr_regs = PMGR_REGS.lock();
+        let base = match pmgr_regs.lookup(&bank.reg_index) {
+            Some(base) => *base,
+            None => {
+                let adt = adt::get_adt()?;
+                let (pa, size) = adt
+                    .get_device_addr_from_nodes(&self.nodes, bank.reg_index as usize)
+                    .ok_or(Error::InvalidPmgrData)?;
+                let name = alloc::format!("{}#{}", PMGR_PATH, bank.reg_index);
+                let base = MemoryManager::instance()
+                    .map_io(&name, pa, size)
+                    .map_err(|_| Error::MappingFailed)?
+                    .leak();
+                pmgr_regs.insert(bank.reg_index, base);
+                base
+            }
+        };
+
+        let va = unsafe { base.offset(gate.register_offset(bank)) };
+        Ok(unsafe { &*(va.as_ptr() as *const ReadWrite<u32, PowerState::Register>) })
+    }
+
+    fn enable_gate(&self, id: u32, depth: usize) -> Result<(), Error> {
+        if depth > Self::MAX_GATE_DEPTH {
+            return Err(Error::InvalidPmgrData);
+        }
+
+        let gate = find_gate(self.devices, id).ok_or(Error::UnknownGate(id))?;
+        for parent in gate.parents.iter().filter(|parent| **parent != 0) {
+            self.enable_gate(*parent, depth + 1)?;
+        }
+
+        if gate.is_virtual() {
+            return Ok(());
+        }
+
+        let register = self.power_state_register(&gate)?;
+        if register.matches_all(PowerState::ACTUAL::Active) {
+            return Ok(());
+        }
+
+        log_info!("Enabling power gate {} (id {})", gate.name, gate.id);
+        register.modify(PowerState::TARGET::Active);
+        for _ in 0..Self::MAX_POLL_ITERATIONS {
+            if register.matches_all(PowerState::ACTUAL::Active) {
+                return Ok(());
+            }
+        }
+        Err(Error::GateTimeout(id))
+    }
+}
+
+/// Enables the clock and power gates a device depends on, as listed in its ADT node. Devices
+/// without gates are left alone. All gates are attempted even if one of them fails, and the first
+/// error is returned.
+pub fn enable_for_node(node: &AdtNode) -> Result<(), Error> {
+    let mut gates = vec![];
+    for property_name in ["clock-gates", "power-gates"] {
+        if let Some(property) = node.find_property(property_name) {
+            gates.extend(property.u32_array_iter()?);
+        }
+    }
+
+    if gates.is_empty() {
+        return Ok(());
+    }
+
+    let pmgr = Pmgr::new()?;
+    let mut result = Ok(());
+    for id in gates {
+        if let Err(error) = pmgr.enable_gate(id, 0) {
+            log_warning!(
+                "Unable to enable gate {} for {}. Error: {:?}",
+                id,
+                node.get_name(),
+                error
+            );
+            if result.is_ok() {
+                result = Err(error);
+            }
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn gate_entry(id: u16, flags: u32, parents: [u16; 2], addr_offset: u8, name: &str) -> Vec<u8> {
+        let mut entry = vec![0u8; Gate::ENTRY_SIZE];
+        entry[Gate::FLAGS_OFFSET..Gate::FLAGS_OFFSET + 4].copy_from_slice(&flags.to_le_bytes());
+        entry[Gate::PARENTS_OFFSET..Gate::PARENTS_OFFSET + 2]
+            .copy_from_slice(&parents[0].to_le_bytes());
+        entry[Gate::PARENTS_OFFSET + 2..Gate::PARENTS_OFFSET + 4]
+            .copy_from_slice(&parents[1].to_le_bytes());
+        entry[Gate::ADDR_OFFSET_OFFSET] = addr_offset;
+        entry[Gate::PS_REG_INDEX_OFFSET] = 1;
+        entry[Gate::ID_OFFSET..Gate::ID_OFFSET + 2].copy_from_slice(&id.to_le_bytes());
+        entry[Gate::NAME_OFFSET..Gate::NAME_OFFSET + name.len()].copy_from_slice(name.as_bytes());
+        entry
+    }
+
+    #[test]
+    fn decodes_gates() {
+        let mut devices = gate_entry(0x10, 0, [0, 0], 2, "SOC_DPE");
+        devices.extend(gate_entry(0x2a, Gate::FLAG_VIRTUAL, [0x10, 0], 0, "SPI_P"));
+        devices.extend(gate_entry(0x2b, 0, [0x10, 0x2a], 0x21, "SPI3"));
+        let devices: &'static [u8] = devices.leak();
+
+        let gate = find_gate(devices, 0x2b).unwrap();
+        assert_eq!(
+            gate,
+            Gate {
+                id: 0x2b,
+                flags: 0,
+                parents: [0x10, 0x2a],
+                addr_offset: 0x21,
+                ps_reg_index: 1,
+                name: "SPI3",
+            }
+        );
+        assert!(!gate.is_virtual());
+
+        let bank = PsRegBank {
+            reg_index: 0,
+            offset: 0x100,
+        };
+        assert_eq!(gate.register_offset(&bank), 0x208);
+
+        assert!(find_gate(devices, 0x2a).unwrap().is_virtual());
+        assert_eq!(find_gate(devices, 0x10).unwrap().name, "SOC_DPE");
+        assert!(find_gate(devices, 0x11).is_none());
+    }
+}
@@ -0,0 +1,68 @@
+//! Kernel output through the semihosting stdout of the emulator, so that it shows up directly in
+//! the console of the host without modeling a UART.
+
+use crate::{
+    drivers::{self, interfaces::logger::Logger, Dev, DeviceRef},
+    prelude::*,
+    print,
+    sync::spinlock::RwSpinLock,
+};
+
+use p1c0_macros::initcall;
+
+/// Path the semihosting printer is registered with, in the device registry.
+pub const DEVICE_PATH: &str = "/semihosting/stdout";
+
+const LINE_LENGTH: usize = 128;
+
+/// Output is written a line at a time, since every semihosting call traps into the emulator.
+pub struct SemihostingLogger {
+    line: heapless::Vec<u8, LINE_LENGTH>,
+}
+
+impl SemihostingLogger {
+    pub const fn new() -> Self {
+        Self {
+            line: heapless::Vec::new(),
+        }
+    }
+
+    fn flush(&mut self) {
+        let consumed = print::decode_utf8_lossy(&self.line, |text| {
+            if !text.is_empty() {
+                arm_semihosting::print!("{}", text);
+            }
+        });
+
+        // Keeps an incomplete UTF-8 sequence at the end for the next line
+        self.line = heapless::Vec::from_slice(&self.line[consumed..]).unwrap();
+    }
+}
+
+impl Default for SemihostingLogger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Logger for SemihostingLogger {
+    fn write_u8(&mut self, c: u8) -> Result<(), print::Error> {
+        if self.line.push(c).is_err() {
+            self.flush();
+            self.line.push(c).map_err(|_| print::Error::PrintFailed)?;
+        }
+        if c == b'\n' {
+            self.flush();
+        }
+        Ok(())
+    }
+}
+
+#[initcall(priority = 0)]
+fn semihosting_register_printer() {
+    let dev: DeviceRef = Arc::new(RwSpinLock::new(Dev::Logger(Box::new(
+        SemihostingLogger::new(),
+    ))));
+    drivers::register_device(DEVICE_PATH, dev.clone());
+    print::register_printer(dev);
+}
@@ -1,10 +1,18 @@
 use crate::{
     adt::get_adt,
-    drivers::{generic_timer, interfaces::timer::Timer},
-    memory::{address::Address, MemoryManager},
+    drivers::{
+        generic_timer,
+        interfaces::{timer::Timer, Ticks},
+    },
+    memory::{address::Address, IoMapping, MemoryManager},
 };
 
-use core::{iter::Iterator, mem::MaybeUninit, time::Duration};
+use core::{
+    iter::{Iterator, Peekable},
+    mem::MaybeUninit,
+    slice,
+    time::Duration,
+};
 
 use tock_registers::{
     interfaces::{ReadWriteable, Readable, Writeable},
@@ -141,6 +149,10 @@ const CS_IDLE_DELAY_DEFAULT: Duration = Duration::from_micros(0);
 
 const FIFO_DEPTH: u32 = 16;
 
+/// Transfers that take longer than this fail with `Error::Timeout`. A few milliseconds are enough
+/// for any transfer at the default clock rate, so this only triggers if the device is not there.
+const TIMEOUT_DEFAULT: Duration = Duration::from_secs(1);
+
 #[repr(C)]
 struct SpiRegisters {
     control: ReadWrite<u32, Control::Register>,
@@ -211,49 +223,270 @@ fn pointer_alignment<T>(ptr: *const T) -> usize {
     }
 }
 
+/// Checks that both slices are aligned to the transaction size and hold a whole number of words,
+/// so that they can be accessed one word at a time without UB. Empty slices are never accessed,
+/// so their alignment does not matter.
+fn fits_transaction_size(
+    ts_size: TransactionSize,
+    tx_data: &[u8],
+    rx_data: &[MaybeUninit<u8>],
+) -> bool {
+    let bytes = ts_size.bytes();
+    let is_aligned = |ptr: *const u8, len: usize| len == 0 || pointer_alignment(ptr) >= bytes;
+
+    is_aligned(tx_data.as_ptr(), tx_data.len())
+        && is_aligned(rx_data.as_ptr() as *const u8, rx_data.len())
+        && (tx_data.len() % bytes == 0)
+        && (rx_data.len() % bytes == 0)
+}
+
 /// This function checks the alignment and size of the slices to obtain the best fit
 /// transaction size that does not result in UB
 fn deduct_transaction_size(tx_data: &[u8], rx_data: &[MaybeUninit<u8>]) -> TransactionSize {
-    let tx_alignment = pointer_alignment(tx_data.as_ptr());
-    let rx_alignment = pointer_alignment(tx_data.as_ptr());
-    if tx_alignment >= 4
-        && rx_alignment >= 4
-        && (tx_data.len() % 4 == 0)
-        && (rx_data.len() % 4 == 0)
-    {
-        TransactionSize::Ts4b
-    } else if tx_alignment >= 2
-        && rx_alignment >= 2
-        && (tx_data.len() % 2 == 0)
-        && (rx_data.len() % 2 == 0)
-    {
-        TransactionSize::Ts2b
-    } else {
-        TransactionSize::Ts1b
-    }
+    [TransactionSize::Ts4b, TransactionSize::Ts2b]
+        .into_iter()
+        .find(|ts_size| fits_transaction_size(*ts_size, tx_data, rx_data))
+        .unwrap_or(TransactionSize::Ts1b)
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum TransactionSize {
     Ts1b,
     Ts2b,
     Ts4b,
 }
 
-#[derive(Debug, Clone)]
+impl TransactionSize {
+    const fn bytes(&self) -> usize {
+        match self {
+            TransactionSize::Ts1b => 1,
+            TransactionSize::Ts2b => 2,
+            TransactionSize::Ts4b => 4,
+        }
+    }
+}
+
+/// Size of the words shifted by the controller. Words are transmitted in the configured bit order,
+/// and the bytes of a word are taken from the buffers in big endian order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WordSize {
+    /// Uses the largest word size allowed by the size and alignment of the buffers
+    Auto,
+    Bits8,
+    Bits16,
+    Bits32,
+}
+
+/// Bus configuration used for a transaction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpiConfig {
+    /// Clock polarity. The clock idles high if set
+    pub cpol: bool,
+    /// Clock phase. Data is sampled on the trailing edge of the clock if set
+    pub cpha: bool,
+    pub lsb_first: bool,
+    pub word_size: WordSize,
+}
+
+impl SpiConfig {
+    pub const fn new() -> Self {
+        Self {
+            cpol: false,
+            cpha: false,
+            lsb_first: false,
+            word_size: WordSize::Auto,
+        }
+    }
+
+    fn transaction_size(
+        &self,
+        tx_data: &[u8],
+        rx_data: &[MaybeUninit<u8>],
+    ) -> Result<TransactionSize, Error> {
+        let ts_size = match self.word_size {
+            WordSize::Auto => return Ok(deduct_transaction_size(tx_data, rx_data)),
+            WordSize::Bits8 => TransactionSize::Ts1b,
+            WordSize::Bits16 => TransactionSize::Ts2b,
+            WordSize::Bits32 => TransactionSize::Ts4b,
+        };
+
+        if fits_transaction_size(ts_size, tx_data, rx_data) {
+            Ok(ts_size)
+        } else {
+            Err(Error::InvalidWordSize)
+        }
+    }
+
+    fn register_value(&self, ts_size: TransactionSize) -> u32 {
+        let word_size = match ts_size {
+            TransactionSize::Ts1b => Config::WORD_SIZE::SZ8B,
+            TransactionSize::Ts2b => Config::WORD_SIZE::SZ16B,
+            TransactionSize::Ts4b => Config::WORD_SIZE::SZ32B,
+        };
+
+        // This driver does not use IRQs for now given that AIC bringup is not done
+        (Config::CPOL.val(self.cpol as u32)
+            + Config::CPHA.val(self.cpha as u32)
+            + Config::MODE::POLLED
+            + Config::LSB_FIRST.val(self.lsb_first as u32)
+            + word_size
+            + Config::FIFO_THRESH::TH8B
+            + Config::IE_TXRXTHRESH::CLEAR
+            + Config::IE_RXCOMPLETE::CLEAR
+            + Config::IE_TXCOMPLETE::CLEAR)
+            .value
+    }
+}
+
+impl Default for SpiConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Error {
     AdtNodeNotFound,
     AdtNodeNotCompatible,
     RxUnderrun,
     TxOverflow,
+    /// The buffers are not aligned to the requested word size or do not hold a whole number of
+    /// words
+    InvalidWordSize,
+    /// The transfer did not complete in time, see `Spi::set_timeout`
+    Timeout,
+}
+
+/// Selects a device on the bus. Implementations are used to drive chip select lines that are not
+/// controlled by the SPI peripheral itself, like GPIOs.
+pub trait ChipSelect {
+    fn assert(&self);
+    fn deassert(&self);
+}
+
+struct ChipSelectDelays {
+    cs_to_clock: Duration,
+    clock_to_cs: Duration,
+    cs_inactive: Duration,
+}
+
+/// Runs `transfer` with the chip select asserted, waiting for the given delays around it. The chip
+/// select is deasserted even if the transfer fails.
+fn with_chip_select<S, T>(
+    state: &mut S,
+    delays: &ChipSelectDelays,
+    timer: &impl Timer,
+    mut select: impl FnMut(&mut S, bool),
+    transfer: impl FnOnce(&mut S) -> Result<T, Error>,
+) -> Result<T, Error> {
+    select(state, true);
+    timer.delay(delays.cs_to_clock);
+
+    let result = transfer(state);
+
+    timer.delay(delays.clock_to_cs);
+    select(state, false);
+    timer.delay(delays.cs_inactive);
+    result
+}
+
+/// A full duplex transfer on the bus. Receiving and transmitting run at the same time, so bytes
+/// are received while the command of a `write_then_read` is sent.
+trait Transfer {
+    fn transfer(&mut self, tx_data: &[u8], rx_data: &mut [MaybeUninit<u8>]) -> Result<(), Error>;
+
+    fn write(&mut self, data: &[u8]) -> Result<(), Error> {
+        self.transfer(data, &mut [])
+    }
+
+    fn read(&mut self, buffer: &mut [u8]) -> Result<(), Error> {
+        // See `Spi::transact` for why this is fine
+        let buffer = unsafe { core::mem::transmute(buffer) };
+        self.transfer(&[], buffer)
+    }
+
+    fn write_then_read(&mut self, command: &[u8], buffer: &mut [u8]) -> Result<(), Error> {
+        let mut rx_data = alloc::vec![MaybeUninit::uninit(); command.len() + buffer.len()];
+        self.transfer(command, &mut rx_data)?;
+
+        // Bytes received while the command was sent are discarded
+        for (byte, received) in buffer.iter_mut().zip(&rx_data[command.len()..]) {
+            *byte = unsafe { received.assume_init() };
+        }
+        Ok(())
+    }
+}
+
+/// Point in time after which polling the controller is given up.
+struct Deadline<'a, T: Timer> {
+    timer: &'a T,
+    end: Ticks,
+}
+
+impl<'a, T: Timer> Deadline<'a, T> {
+    fn new(timer: &'a T, timeout: Duration) -> Self {
+        let end = timer
+            .ticks()
+            .saturating_add(timer.resolution().duration_to_ticks(timeout));
+        Self { timer, end }
+    }
+
+    fn check(&self) -> Result<(), Error> {
+        if self.timer.ticks() >= self.end {
+            Err(Error::Timeout)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+fn poll_for_errors(regs: &SpiRegisters) -> Result<(), Error> {
+    let rx_underrun = regs.if_fifo.read(InterruptFlagFifo::RX_UNDERRUN) != 0;
+    let tx_overflow = regs.if_fifo.read(InterruptFlagFifo::TX_OVERFLOW) != 0;
+
+    if rx_underrun {
+        return Err(Error::RxUnderrun);
+    }
+    if tx_overflow {
+        return Err(Error::TxOverflow);
+    }
+
+    Ok(())
+}
+
+fn poll_completion(
+    regs: &SpiRegisters,
+    tx_len: usize,
+    rx_len: usize,
+    deadline: &Deadline<impl Timer>,
+) -> Result<(), Error> {
+    let is_complete = || {
+        (tx_len == 0 || regs.status.is_set(Status::TX_COMPLETE))
+            && (rx_len == 0 || regs.status.is_set(Status::RX_COMPLETE))
+    };
+
+    while !is_complete() {
+        poll_for_errors(regs)?;
+        deadline.check()?;
+    }
+    Ok(())
+}
+
+/// Stops any transfer in progress and leaves the controller idle, with empty FIFOs and CS
+/// deasserted.
+fn reset_controller(regs: &SpiRegisters) {
+    regs.control
+        .write(Control::RUN::CLEAR + Control::TX_RESET::SET + Control::RX_RESET::SET);
+    regs.pin.modify(Pin::CS::DISABLE);
 }
 
 pub struct Spi {
-    regs: &'static mut SpiRegisters,
+    mapping: IoMapping,
     cs_to_clock_delay: Duration,
     clock_to_cs_delay: Duration,
     cs_inactive_delay: Duration,
     clock_rate: Duration,
+    timeout: Duration,
 }
 
 impl Spi {
@@ -273,23 +506,22 @@ impl Spi {
 
         let (pa, _) = adt.get_device_addr(spi_node, 0).unwrap();
 
-        let va = MemoryManager::instance()
+        let mapping = MemoryManager::instance()
             .map_io(spi_node, pa, core::mem::size_of::<SpiRegisters>())
             .expect("The spi device io cannot be mapped");
 
-        let regs: &'static mut SpiRegisters = &mut *(va.as_ptr() as *mut SpiRegisters);
-
         let cs_to_clock_delay = CS_TO_CLK_DELAY_DEFAULT;
         let clock_to_cs_delay = CLK_TO_CS_DELAY_DEFAULT;
         let cs_inactive_delay = CS_IDLE_DELAY_DEFAULT;
         let clock_rate = CLK_RATE_DEFAULT;
 
         let mut instance = Self {
-            regs,
+            mapping,
             cs_to_clock_delay,
             clock_to_cs_delay,
             cs_inactive_delay,
             clock_rate,
+            timeout: TIMEOUT_DEFAULT,
         };
 
         instance.init();
@@ -297,27 +529,28 @@ impl Spi {
         Ok(instance)
     }
 
+    fn regs(&self) -> &SpiRegisters {
+        // # Safety
+        //   The mapping covers the register block and lives as long as `self`.
+        unsafe { &*(self.mapping.va().as_ptr() as *const SpiRegisters) }
+    }
+
     pub fn init(&mut self) {
-        // Reset the RX and TX fifos
-        self.regs
-            .control
-            .write(Control::TX_RESET::SET + Control::RX_RESET::SET);
-
-        // Disable CS pin for now
-        self.regs.pin.modify(Pin::CS::DISABLE);
-        self.regs
+        // Reset the RX and TX fifos and disable CS pin for now
+        reset_controller(self.regs());
+        self.regs()
             .shift_config
             .modify(ShiftConfig::OVERRIDE_CS::CLEAR);
-        self.regs
+        self.regs()
             .pin_config
             .modify(PinConfig::CS_IDLE_VAL::CLEAR + PinConfig::KEEP_CS::SET);
 
         // This driver does not use IRQs for now given that AIC bringup is not done
-        self.regs.ie_xfer.write(
+        self.regs().ie_xfer.write(
             InterruptEnableXfer::TX_COMPLETE::CLEAR + InterruptEnableXfer::RX_COMPLETE::CLEAR,
         );
 
-        self.regs.ie_fifo.write(
+        self.regs().ie_fifo.write(
             InterruptEnableFifo::RX_FULL::CLEAR
                 + InterruptEnableFifo::TX_EMPTY::CLEAR
                 + InterruptEnableFifo::RX_THRESH::CLEAR
@@ -327,23 +560,13 @@ impl Spi {
         );
 
         // Disable delays
-        self.regs.delay_pre.write(DelayPre::ENABLE::CLEAR);
-        self.regs.delay_post.write(DelayPost::ENABLE::CLEAR);
-
-        // Set default configuration. For now we don't expose controls externally for these
-        // settings. We may need to do that in the future, though.
-        self.regs.config.write(
-            Config::CPOL::CLEAR
-                + Config::CPHA::CLEAR
-                + Config::MODE::POLLED
-                // SPI is normally MSB first. We probably won't even need to set this bit ever
-                + Config::LSB_FIRST::CLEAR
-                + Config::WORD_SIZE::SZ8B
-                + Config::FIFO_THRESH::TH8B
-                + Config::IE_TXRXTHRESH::CLEAR
-                + Config::IE_RXCOMPLETE::CLEAR
-                + Config::IE_TXCOMPLETE::CLEAR,
-        );
+        self.regs().delay_pre.write(DelayPre::ENABLE::CLEAR);
+        self.regs().delay_post.write(DelayPost::ENABLE::CLEAR);
+
+        // Set default configuration. Transactions can override it with `transact_with_config`
+        self.regs()
+            .config
+            .set(SpiConfig::default().register_value(TransactionSize::Ts1b));
     }
 
     fn set_cs(&mut self, enable: bool) {
@@ -352,7 +575,7 @@ impl Spi {
         } else {
             Pin::CS::DISABLE
         };
-        self.regs.pin.modify(field);
+        self.regs().pin.modify(field);
     }
 
     /// # Safety
@@ -362,18 +585,18 @@ impl Spi {
     where
         T: Iterator<Item = &'a u8>,
     {
-        let word_count = FIFO_DEPTH - self.regs.fifo_status.read(FifoStatus::LEVEL_TX);
+        let word_count = FIFO_DEPTH - self.regs().fifo_status.read(FifoStatus::LEVEL_TX);
         for _ in 0..word_count {
             if let Some(first_byte) = tx_data_iter.next() {
                 match ts_size {
                     TransactionSize::Ts1b => {
                         let value = *first_byte;
-                        self.regs.tx_data.set(value.into());
+                        self.regs().tx_data.set(value.into());
                     }
                     TransactionSize::Ts2b => {
                         let bytes = [*first_byte, *tx_data_iter.next().unwrap_unchecked()];
                         let value = u16::from_be_bytes(bytes);
-                        self.regs.tx_data.set(value.into());
+                        self.regs().tx_data.set(value.into());
                     }
                     TransactionSize::Ts4b => {
                         let bytes = [
@@ -383,7 +606,7 @@ impl Spi {
                             *tx_data_iter.next().unwrap_unchecked(),
                         ];
                         let value = u32::from_be_bytes(bytes);
-                        self.regs.tx_data.set(value);
+                        self.regs().tx_data.set(value);
                     }
                 }
             } else {
@@ -400,8 +623,8 @@ impl Spi {
     where
         T: Iterator<Item = &'a mut MaybeUninit<u8>>,
     {
-        while self.regs.fifo_status.read(FifoStatus::LEVEL_RX) > 0 {
-            let rx_data = self.regs.rx_data.get();
+        while self.regs().fifo_status.read(FifoStatus::LEVEL_RX) > 0 {
+            let rx_data = self.regs().rx_data.get();
 
             match ts_size {
                 TransactionSize::Ts1b => {
@@ -426,39 +649,6 @@ impl Spi {
         }
     }
 
-    fn poll_for_errors(&self) -> Result<(), Error> {
-        let rx_underrun = self.regs.if_fifo.read(InterruptFlagFifo::RX_UNDERRUN) != 0;
-        let tx_overflow = self.regs.if_fifo.read(InterruptFlagFifo::TX_OVERFLOW) != 0;
-
-        if rx_underrun {
-            return Err(Error::RxUnderrun);
-        }
-        if tx_overflow {
-            return Err(Error::TxOverflow);
-        }
-
-        Ok(())
-    }
-
-    fn poll_completion(&self, tx_len: usize, rx_len: usize) -> Result<(), Error> {
-        if tx_len != 0 && rx_len != 0 {
-            while self.regs.status.read(Status::TX_COMPLETE) == 0
-                || self.regs.status.read(Status::RX_COMPLETE) == 0
-            {
-                self.poll_for_errors()?;
-            }
-        } else if tx_len != 0 {
-            while self.regs.status.read(Status::TX_COMPLETE) == 0 {
-                self.poll_for_errors()?;
-            }
-        } else if rx_len != 0 {
-            while self.regs.status.read(Status::RX_COMPLETE) == 0 {
-                self.poll_for_errors()?;
-            }
-        }
-        Ok(())
-    }
-
     pub fn transact(&mut self, tx_data: &[u8], rx_data: &mut [u8]) -> Result<(), Error> {
         // We know that the data is initialized. Faking as if it wasn't allows us to freely write
         // to it. Since u8 does not implement drop, no problems should arise from the objects not
@@ -483,51 +673,102 @@ impl Spi {
         self.clock_rate = duration;
     }
 
+    /// Sets how long a transfer may take before it fails with `Error::Timeout`.
+    pub fn set_timeout(&mut self, duration: Duration) {
+        self.timeout = duration;
+    }
+
+    /// Sends `data`, ignoring any received bytes.
+    pub fn write(&mut self, data: &[u8]) -> Result<(), Error> {
+        Transfer::write(self, data)
+    }
+
+    /// Fills `buffer` with data from the device without sending anything.
+    pub fn read(&mut self, buffer: &mut [u8]) -> Result<(), Error> {
+        Transfer::read(self, buffer)
+    }
+
+    /// Sends `command` and then reads the response into `buffer`, keeping the device selected
+    /// during the whole exchange.
+    pub fn write_then_read(&mut self, command: &[u8], buffer: &mut [u8]) -> Result<(), Error> {
+        Transfer::write_then_read(self, command, buffer)
+    }
+
     pub fn transact_into_uninit_buffer(
         &mut self,
         tx_data: &[u8],
         rx_data: &mut [MaybeUninit<u8>],
+    ) -> Result<(), Error> {
+        self.transact_inner(None, &SpiConfig::default(), tx_data, rx_data)
+    }
+
+    /// Performs a transaction selecting the device with `cs` instead of the built-in chip select
+    /// line of the controller, which stays deasserted. This is useful for buses with multiple
+    /// devices selected through GPIOs.
+    pub fn transact_with_cs(
+        &mut self,
+        cs: &dyn ChipSelect,
+        tx_data: &[u8],
+        rx_data: &mut [u8],
+    ) -> Result<(), Error> {
+        // See `transact` for why this is fine
+        let rx_data = unsafe { core::mem::transmute(rx_data) };
+        self.transact_inner(Some(cs), &SpiConfig::default(), tx_data, rx_data)
+    }
+
+    /// Performs a transaction with the given bus configuration. The default configuration is
+    /// restored afterwards.
+    pub fn transact_with_config(
+        &mut self,
+        config: &SpiConfig,
+        tx_data: &[u8],
+        rx_data: &mut [u8],
+    ) -> Result<(), Error> {
+        // See `transact` for why this is fine
+        let rx_data = unsafe { core::mem::transmute(rx_data) };
+        self.transact_inner(None, config, tx_data, rx_data)
+    }
+
+    fn select(&mut self, cs: Option<&dyn ChipSelect>, enable: bool) {
+        match (cs, enable) {
+            (Some(cs), true) => cs.assert(),
+            (Some(cs), false) => cs.deassert(),
+            (None, enable) => self.set_cs(enable),
+        }
+    }
+
+    fn transact_inner(
+        &mut self,
+        cs: Option<&dyn ChipSelect>,
+        config: &SpiConfig,
+        tx_data: &[u8],
+        rx_data: &mut [MaybeUninit<u8>],
     ) -> Result<(), Error> {
         if tx_data.is_empty() && rx_data.is_empty() {
             // This is effectively a noop
             return Ok(());
         }
 
-        let ts_size = deduct_transaction_size(tx_data, rx_data);
+        let ts_size = config.transaction_size(tx_data, rx_data)?;
+        let bytes_per_transaction = ts_size.bytes();
+        let (tx_len, rx_len) = (
+            tx_data.len() / bytes_per_transaction,
+            rx_data.len() / bytes_per_transaction,
+        );
 
-        let (tx_len, rx_len) = match ts_size {
-            TransactionSize::Ts1b => {
-                self.regs.config.write(Config::WORD_SIZE::SZ8B);
-                (tx_data.len(), rx_data.len())
-            }
-            TransactionSize::Ts2b => {
-                self.regs.config.write(Config::WORD_SIZE::SZ16B);
-                let bytes_per_transaction = core::mem::size_of::<u16>();
-                (
-                    tx_data.len() / bytes_per_transaction,
-                    rx_data.len() / bytes_per_transaction,
-                )
-            }
-            TransactionSize::Ts4b => {
-                self.regs.config.write(Config::WORD_SIZE::SZ32B);
-                let bytes_per_transaction = core::mem::size_of::<u32>();
-                (
-                    tx_data.len() / bytes_per_transaction,
-                    rx_data.len() / bytes_per_transaction,
-                )
-            }
-        };
+        let saved_config = self.regs().config.get();
+        self.regs().config.set(config.register_value(ts_size));
 
         // Clear status registers
-        self.regs.status.set(0xFFFFFFFF);
-        self.regs.if_fifo.set(0xFFFFFFFF);
-        self.regs.if_xfer.set(0xFFFFFFFF);
+        self.regs().status.set(0xFFFFFFFF);
+        self.regs().if_fifo.set(0xFFFFFFFF);
+        self.regs().if_xfer.set(0xFFFFFFFF);
 
-        self.regs.rx_count.set(rx_len as u32);
-        self.regs.tx_count.set(tx_len as u32);
+        self.regs().rx_count.set(rx_len as u32);
+        self.regs().tx_count.set(tx_len as u32);
 
         let clk_div = (PARENT_CLK_HZ * self.clock_rate.as_nanos() / 1_000_000_000) as u32 - 1;
-        self.regs
+        self.regs()
             .clk_div
             .set(core::cmp::min(clk_div, CLOCK_DIV_MAX));
 
@@ -537,48 +778,376 @@ impl Spi {
             self.push_tx(&mut tx_data_iter, ts_size);
         }
 
-        let timer = generic_timer::get_timer();
-
-        let clock_to_cs_delay = self.clock_to_cs_delay;
-        let cs_to_clock_delay = self.cs_to_clock_delay;
-        let cs_inactive_delay = self.cs_inactive_delay;
-
-        // Start the transfer
-        self.set_cs(true);
-        // TODO(javier-varez): maybe we should allow sleeping here?
-        timer.delay(cs_to_clock_delay);
-        self.regs.control.write(Control::RUN::SET);
-
-        let cleanup = |instance: &mut Self| {
-            // TODO(javier-varez): maybe we should allow sleeping here?
-            timer.delay(clock_to_cs_delay);
-            instance.set_cs(false);
-            instance
-                .regs
-                .control
-                .write(Control::RUN::CLEAR + Control::RX_RESET::SET + Control::TX_RESET::SET);
-            // TODO(javier-varez): maybe we should allow sleeping here?
-            timer.delay(cs_inactive_delay);
+        let delays = ChipSelectDelays {
+            cs_to_clock: self.cs_to_clock_delay,
+            clock_to_cs: self.clock_to_cs_delay,
+            cs_inactive: self.cs_inactive_delay,
         };
 
+        // TODO(javier-varez): maybe we should allow sleeping during the delays?
+        let result = with_chip_select(
+            self,
+            &delays,
+            generic_timer::get_timer(),
+            |instance, enable| instance.select(cs, enable),
+            |instance| {
+                instance.regs().control.write(Control::RUN::SET);
+
+                let deadline = Deadline::new(generic_timer::get_timer(), instance.timeout);
+                let result = instance.run_transfer(
+                    &mut tx_data_iter,
+                    &mut rx_data_iter,
+                    ts_size,
+                    tx_len,
+                    rx_len,
+                    &deadline,
+                );
+
+                instance
+                    .regs()
+                    .control
+                    .write(Control::RUN::CLEAR + Control::RX_RESET::SET + Control::TX_RESET::SET);
+                result
+            },
+        );
+
+        self.regs().config.set(saved_config);
+        result
+    }
+
+    fn run_transfer(
+        &mut self,
+        tx_data_iter: &mut Peekable<slice::Iter<'_, u8>>,
+        rx_data_iter: &mut Peekable<slice::IterMut<'_, MaybeUninit<u8>>>,
+        ts_size: TransactionSize,
+        tx_len: usize,
+        rx_len: usize,
+        deadline: &Deadline<impl Timer>,
+    ) -> Result<(), Error> {
         while tx_data_iter.peek().is_some() || rx_data_iter.peek().is_some() {
             unsafe {
-                self.push_tx(&mut tx_data_iter, ts_size);
-                self.pop_rx(&mut rx_data_iter, ts_size);
+                self.push_tx(tx_data_iter, ts_size);
+                self.pop_rx(rx_data_iter, ts_size);
             }
 
-            self.poll_for_errors().map_err(|err| {
-                cleanup(self);
-                err
-            })?;
+            poll_for_errors(self.regs())?;
+            deadline.check()?;
+        }
+
+        poll_completion(self.regs(), tx_len, rx_len, deadline)
+    }
+}
+
+impl Transfer for Spi {
+    fn transfer(&mut self, tx_data: &[u8], rx_data: &mut [MaybeUninit<u8>]) -> Result<(), Error> {
+        self.transact_into_uninit_buffer(tx_data, rx_data)
+    }
+}
+
+impl Drop for Spi {
+    fn drop(&mut self) {
+        // The IO mapping is released after the controller is reset
+        reset_controller(self.regs());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::{cell::RefCell, vec, vec::Vec};
+
+    #[derive(Debug, PartialEq, Eq)]
+    enum Event {
+        Assert(Duration),
+        Transfer(Duration),
+        Deassert(Duration),
+    }
+
+    struct RecordingChipSelect {
+        events: RefCell<Vec<Event>>,
+    }
+
+    fn now() -> Duration {
+        let timer = generic_timer::get_timer();
+        timer.resolution().ticks_to_duration(timer.ticks())
+    }
+
+    impl ChipSelect for RecordingChipSelect {
+        fn assert(&self) {
+            self.events.borrow_mut().push(Event::Assert(now()));
         }
 
-        self.poll_completion(tx_len, rx_len).map_err(|err| {
-            cleanup(self);
-            err
-        })?;
+        fn deassert(&self) {
+            self.events.borrow_mut().push(Event::Deassert(now()));
+        }
+    }
 
-        cleanup(self);
-        Ok(())
+    fn transfer(result: Result<(), Error>) -> Vec<Event> {
+        let cs = RecordingChipSelect {
+            events: RefCell::new(vec![]),
+        };
+        let delays = ChipSelectDelays {
+            cs_to_clock: Duration::from_micros(10),
+            clock_to_cs: Duration::from_micros(20),
+            cs_inactive: Duration::from_micros(30),
+        };
+
+        let start = now();
+        let timer = generic_timer::get_timer();
+        let _ = with_chip_select(
+            &mut (),
+            &delays,
+            timer,
+            |_, enable| {
+                if enable {
+                    cs.assert()
+                } else {
+                    cs.deassert()
+                }
+            },
+            |_| {
+                cs.events.borrow_mut().push(Event::Transfer(now()));
+                result
+            },
+        );
+
+        // The inactive delay is honored before returning
+        assert_eq!(now() - start, Duration::from_micros(60));
+
+        cs.events
+            .into_inner()
+            .into_iter()
+            .map(|event| match event {
+                Event::Assert(time) => Event::Assert(time - start),
+                Event::Transfer(time) => Event::Transfer(time - start),
+                Event::Deassert(time) => Event::Deassert(time - start),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn config_register_value() {
+        const CPHA: u32 = 1 << 1;
+        const CPOL: u32 = 1 << 2;
+        const LSB_FIRST: u32 = 1 << 13;
+        const WORD_SIZE_16B: u32 = 1 << 15;
+        const WORD_SIZE_32B: u32 = 2 << 15;
+
+        let config = SpiConfig::default();
+        assert_eq!(config.register_value(TransactionSize::Ts1b), 0);
+        assert_eq!(config.register_value(TransactionSize::Ts2b), WORD_SIZE_16B);
+        assert_eq!(config.register_value(TransactionSize::Ts4b), WORD_SIZE_32B);
+
+        let config = SpiConfig {
+            cpol: true,
+            cpha: true,
+            lsb_first: true,
+            word_size: WordSize::Bits16,
+        };
+        assert_eq!(
+            config.register_value(TransactionSize::Ts2b),
+            CPOL | CPHA | LSB_FIRST | WORD_SIZE_16B
+        );
+
+        let config = SpiConfig {
+            cpha: true,
+            ..SpiConfig::default()
+        };
+        assert_eq!(config.register_value(TransactionSize::Ts1b), CPHA);
+    }
+
+    #[repr(align(4))]
+    struct Aligned([u8; 8]);
+
+    #[test]
+    fn word_size_matches_buffers() {
+        let tx = Aligned([0; 8]);
+        let rx = [MaybeUninit::<u8>::uninit(); 0];
+
+        let config = |word_size| SpiConfig {
+            word_size,
+            ..SpiConfig::default()
+        };
+
+        assert_eq!(
+            config(WordSize::Auto).transaction_size(&tx.0, &rx),
+            Ok(TransactionSize::Ts4b)
+        );
+        assert_eq!(
+            config(WordSize::Auto).transaction_size(&tx.0[..6], &rx),
+            Ok(TransactionSize::Ts2b)
+        );
+        assert_eq!(
+            config(WordSize::Auto).transaction_size(&tx.0[1..4], &rx),
+            Ok(TransactionSize::Ts1b)
+        );
+
+        // An explicit word size is used as long as the buffers allow it
+        assert_eq!(
+            config(WordSize::Bits8).transaction_size(&tx.0, &rx),
+            Ok(TransactionSize::Ts1b)
+        );
+        assert_eq!(
+            config(WordSize::Bits16).transaction_size(&tx.0, &rx),
+            Ok(TransactionSize::Ts2b)
+        );
+        assert_eq!(
+            config(WordSize::Bits32).transaction_size(&tx.0[..6], &rx),
+            Err(Error::InvalidWordSize)
+        );
+        assert_eq!(
+            config(WordSize::Bits16).transaction_size(&tx.0[1..3], &rx),
+            Err(Error::InvalidWordSize)
+        );
+
+        // The receive buffer is checked as well
+        let rx = [MaybeUninit::<u8>::uninit(); 3];
+        assert_eq!(
+            config(WordSize::Bits16).transaction_size(&tx.0, &rx),
+            Err(Error::InvalidWordSize)
+        );
+    }
+
+    #[test]
+    fn chip_select_ordering() {
+        let expected = vec![
+            Event::Assert(Duration::from_micros(0)),
+            Event::Transfer(Duration::from_micros(10)),
+            Event::Deassert(Duration::from_micros(30)),
+        ];
+        assert_eq!(transfer(Ok(())), expected);
+
+        // Failed transfers release the chip select as well
+        assert_eq!(transfer(Err(Error::TxOverflow)), expected);
+    }
+
+    #[test]
+    fn reset_stops_the_controller() {
+        // # Safety
+        //   All registers are plain integers, so a zeroed register block is valid
+        let regs: SpiRegisters = unsafe { core::mem::zeroed() };
+        regs.control.write(Control::RUN::SET);
+        regs.pin.write(Pin::KEEP_MOSI::SET + Pin::CS::ENABLE);
+
+        reset_controller(&regs);
+
+        assert!(!regs.control.is_set(Control::RUN));
+        assert!(regs.control.is_set(Control::TX_RESET));
+        assert!(regs.control.is_set(Control::RX_RESET));
+        assert!(regs.pin.matches_all(Pin::CS::DISABLE));
+        // Other pin settings are preserved
+        assert!(regs.pin.is_set(Pin::KEEP_MOSI));
+    }
+
+    /// Echoes the transmitted bytes and then answers with a counter, recording every transfer.
+    #[derive(Default)]
+    struct Loopback {
+        transfers: Vec<(Vec<u8>, usize)>,
+    }
+
+    impl Transfer for Loopback {
+        fn transfer(
+            &mut self,
+            tx_data: &[u8],
+            rx_data: &mut [MaybeUninit<u8>],
+        ) -> Result<(), Error> {
+            self.transfers.push((tx_data.to_vec(), rx_data.len()));
+            for (index, slot) in rx_data.iter_mut().enumerate() {
+                slot.write(tx_data.get(index).copied().unwrap_or(0x80 + index as u8));
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn write_only_transfer() {
+        let mut spi = Loopback::default();
+        spi.write(&[1, 2, 3]).unwrap();
+        assert_eq!(spi.transfers, vec![(vec![1, 2, 3], 0)]);
+    }
+
+    #[test]
+    fn read_only_transfer() {
+        let mut spi = Loopback::default();
+        let mut buffer = [0; 4];
+        spi.read(&mut buffer).unwrap();
+        assert_eq!(spi.transfers, vec![(vec![], 4)]);
+        assert_eq!(buffer, [0x80, 0x81, 0x82, 0x83]);
+    }
+
+    #[test]
+    fn command_response_transfer() {
+        let mut spi = Loopback::default();
+        let mut buffer = [0; 3];
+        spi.write_then_read(&[0xA5, 0x5A], &mut buffer).unwrap();
+
+        // A single transfer clocks in the command and the response
+        assert_eq!(spi.transfers, vec![(vec![0xA5, 0x5A], 5)]);
+        assert_eq!(buffer, [0x82, 0x83, 0x84]);
+
+        // Without a command this is a plain read
+        let mut spi = Loopback::default();
+        spi.write_then_read(&[], &mut buffer).unwrap();
+        assert_eq!(spi.transfers, vec![(vec![], 3)]);
+        assert_eq!(buffer, [0x80, 0x81, 0x82]);
+    }
+
+    /// Moves time forward every time the ticks are read, like a running hardware timer.
+    struct RunningTimer;
+
+    impl Timer for RunningTimer {
+        fn initialize(&self, _interval: Duration) {}
+
+        fn resolution(&self) -> crate::drivers::interfaces::TimerResolution {
+            generic_timer::get_timer().resolution()
+        }
+
+        fn ticks(&self) -> Ticks {
+            let timer = generic_timer::get_timer();
+            timer.advance(Duration::from_micros(100));
+            timer.ticks()
+        }
+
+        fn handle_irq(&self) {}
+
+        fn is_irq_active(&self) -> bool {
+            false
+        }
+    }
+
+    #[test]
+    fn completion_times_out() {
+        // # Safety
+        //   All registers are plain integers, so a zeroed register block is valid
+        let regs: SpiRegisters = unsafe { core::mem::zeroed() };
+        let timeout = Duration::from_millis(10);
+
+        // The status never reports completion
+        let start = now();
+        let deadline = Deadline::new(&RunningTimer, timeout);
+        assert_eq!(poll_completion(&regs, 4, 4, &deadline), Err(Error::Timeout));
+        assert!(now() - start >= timeout);
+
+        // A completed transfer does not wait for the deadline
+        regs.status
+            .write(Status::TX_COMPLETE::SET + Status::RX_COMPLETE::SET);
+        let deadline = Deadline::new(&RunningTimer, timeout);
+        assert_eq!(poll_completion(&regs, 4, 4, &deadline), Ok(()));
+
+        // Only the directions with data are waited for
+        regs.status.write(Status::TX_COMPLETE::SET);
+        let deadline = Deadline::new(&RunningTimer, timeout);
+        assert_eq!(poll_completion(&regs, 4, 0, &deadline), Ok(()));
+        assert_eq!(poll_completion(&regs, 4, 4, &deadline), Err(Error::Timeout));
+
+        // Errors are reported before the timeout expires
+        regs.status.set(0);
+        regs.if_fifo.write(InterruptFlagFifo::TX_OVERFLOW::SET);
+        let deadline = Deadline::new(&RunningTimer, timeout);
+        assert_eq!(
+            poll_completion(&regs, 4, 4, &deadline),
+            Err(Error::TxOverflow)
+        );
     }
 }
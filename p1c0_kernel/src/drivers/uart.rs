@@ -9,17 +9,38 @@ use tock_registers::{
 register_bitfields![u32,
     /// Defines the status register bitfield for the UART
     Status [
+        /// Whether the receive buffer holds a character
+        RXDR OFFSET(0) NUMBITS(1) [],
         /// Whether the current transfer buffer is empty or not
         TXBE OFFSET(1) NUMBITS(1) [],
+        /// Whether the transmitter is done shifting out all characters
+        TXE OFFSET(2) NUMBITS(1) [],
     ],
 ];
 
+/// Parent clock of the UART, used when the ADT node does not have a `clock-frequency` property
+const DEFAULT_CLOCK_HZ: u32 = 24_000_000;
+const MAX_BAUD_DIVISOR: u32 = 0xFFFF;
+/// Baud rates further than this from the requested one are reported
+const MAX_BAUD_ERROR_PERCENT: u32 = 2;
+
+/// Returns the divisor for the closest baud rate to `rate` the UART can achieve, along with that
+/// baud rate. The UART samples each bit 16 times.
+fn baud_divisor(clock_hz: u32, rate: u32) -> (u32, u32) {
+    let rate = rate.max(1) as u64;
+    let cycles_per_bit = (clock_hz as u64 + 8 * rate) / (16 * rate);
+    let divisor = cycles_per_bit.clamp(1, MAX_BAUD_DIVISOR as u64 + 1) as u32 - 1;
+    (divisor, clock_hz / (16 * (divisor + 1)))
+}
+
 #[repr(C)]
 struct UartRegs {
     reserved1: [u32; 4],
     status: ReadOnly<u32, Status::Register>,
     reserved2: [u32; 3],
     tx: ReadWrite<u32>,
+    rx: ReadOnly<u32>,
+    baud_divisor: ReadWrite<u32>,
 }
 
 mod early_uart {
@@ -73,13 +94,13 @@ mod early_uart {
 }
 
 mod late_uart {
-    use super::{Status, UartRegs};
+    use super::{baud_divisor, Status, UartRegs, DEFAULT_CLOCK_HZ, MAX_BAUD_ERROR_PERCENT};
     use crate::{
         adt::AdtNode,
         drivers::{Dev, DeviceRef},
         memory::{address::Address, MemoryManager},
         prelude::*,
-        print,
+        print, shell,
         sync::spinlock::RwSpinLock,
     };
     use alloc::sync::Arc;
@@ -97,10 +118,21 @@ mod late_uart {
             let mut mem_mgr = MemoryManager::instance();
             let vaddr = mem_mgr
                 .map_io(dev_path.last().unwrap().get_name(), device_addr, size)
-                .unwrap();
+                .unwrap()
+                .leak();
+
+            let clock_hz = dev_path
+                .last()
+                .unwrap()
+                .find_property("clock-frequency")
+                .and_then(|prop| prop.u32_value().ok())
+                .unwrap_or(DEFAULT_CLOCK_HZ);
 
             let regs = unsafe { &*(vaddr.as_mut_ptr() as *const _) };
-            let dev = Arc::new(RwSpinLock::new(Dev::Logger(Box::new(Uart { regs }))));
+            let dev = Arc::new(RwSpinLock::new(Dev::Logger(Box::new(Uart {
+                regs,
+                clock_hz,
+            }))));
 
             // On success we register this device as the printer
             print::register_printer(dev.clone());
@@ -111,10 +143,28 @@ mod late_uart {
     #[initcall(priority = 0)]
     fn late_uart_register_driver() {
         super::super::register_driver("uart-1,samsung", Box::new(UartDriver {})).unwrap();
+        shell::register_command("baud", baud_command).unwrap();
+    }
+
+    fn baud_command(args: &[&str]) {
+        let rate = match args.first().map(|arg| arg.parse::<u32>()) {
+            Some(Ok(rate)) if rate != 0 => rate,
+            _ => {
+                println!("Usage: baud <rate>");
+                return;
+            }
+        };
+
+        for printer in print::printers() {
+            if let Dev::Logger(logger) = &mut *printer.lock_write() {
+                logger.set_baud(rate);
+            }
+        }
     }
 
     pub struct Uart {
         regs: &'static UartRegs,
+        clock_hz: u32,
     }
 
     impl Uart {
@@ -124,6 +174,30 @@ mod late_uart {
 
             self.regs.tx.set(character as u32);
         }
+
+        fn getchar(&mut self) -> Option<u8> {
+            if self.regs.status.read(Status::RXDR) == 0 {
+                return None;
+            }
+            Some(self.regs.rx.get() as u8)
+        }
+
+        /// Programs the closest baud rate to `rate` the UART can achieve and returns it. Pending
+        /// characters are sent with the old baud rate.
+        pub fn set_baud(&mut self, rate: u32) -> u32 {
+            let (divisor, actual_rate) = baud_divisor(self.clock_hz, rate);
+            if actual_rate.abs_diff(rate) > rate / 100 * MAX_BAUD_ERROR_PERCENT {
+                log_warning!(
+                    "Baud rate {} is not supported by the UART, using {}",
+                    rate,
+                    actual_rate
+                );
+            }
+
+            while self.regs.status.read(Status::TXE) == 0 {}
+            self.regs.baud_divisor.set(divisor);
+            actual_rate
+        }
     }
 
     impl super::super::interfaces::logger::Logger for Uart {
@@ -131,6 +205,14 @@ mod late_uart {
             self.putchar(c);
             Ok(())
         }
+
+        fn read_u8(&mut self) -> Option<u8> {
+            self.getchar()
+        }
+
+        fn set_baud(&mut self, rate: u32) -> Option<u32> {
+            Some(Uart::set_baud(self, rate))
+        }
     }
 }
 
@@ -142,3 +224,23 @@ pub unsafe fn probe_early() {
 
     print::register_early_printer(uart.as_mut().unwrap());
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn baud_divisors() {
+        // Common rates at the 24 MHz clock of the M1 UART
+        assert_eq!(baud_divisor(DEFAULT_CLOCK_HZ, 9600), (155, 9615));
+        assert_eq!(baud_divisor(DEFAULT_CLOCK_HZ, 115200), (12, 115384));
+        assert_eq!(baud_divisor(DEFAULT_CLOCK_HZ, 230400), (6, 214285));
+        assert_eq!(baud_divisor(DEFAULT_CLOCK_HZ, 1500000), (0, 1500000));
+
+        // Unsupported rates get the closest achievable one
+        assert_eq!(baud_divisor(DEFAULT_CLOCK_HZ, 3000000), (0, 1500000));
+        assert_eq!(baud_divisor(DEFAULT_CLOCK_HZ, 300), (4999, 300));
+        assert_eq!(baud_divisor(DEFAULT_CLOCK_HZ, 1), (MAX_BAUD_DIVISOR, 22));
+        assert_eq!(baud_divisor(DEFAULT_CLOCK_HZ, 0), (MAX_BAUD_DIVISOR, 22));
+    }
+}
@@ -65,7 +65,9 @@ impl Virtio {
             .get_device_addr_from_nodes(path, 0)
             .ok_or(Error::MissingAdtProperty("reg"))?;
 
-        let base_address = MemoryManager::instance().map_io(node.get_name(), pa, size)?;
+        let base_address = MemoryManager::instance()
+            .map_io(node.get_name(), pa, size)?
+            .leak();
         let regs: &'static VirtioMmioRegs::Bank =
             unsafe { &*(base_address.as_ptr() as *const VirtioMmioRegs::Bank) };
 
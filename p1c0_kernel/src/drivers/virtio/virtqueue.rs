@@ -254,7 +254,7 @@ impl<const N: usize, const C: usize> VirtQueue<N, C> {
             let dsc = &self.descriptor_data[dsc_index];
 
             // For this to be truly safe we need to invalidate the cache here
-            crate::arch::cache::invalidate_va_range(
+            crate::arch::cache::invalidate_dcache_range(
                 VirtualAddress::new_unaligned(dsc.as_ptr()),
                 dsc.len(),
             );
@@ -314,7 +314,8 @@ unsafe impl Allocator for DeviceMemoryAllocator {
                 pages.base_address(),
                 pages.num_pages() * crate::arch::mmu::PAGE_SIZE,
             )
-            .map_err(|_| AllocError)?;
+            .map_err(|_| AllocError)?
+            .leak();
 
         let slice = unsafe { core::slice::from_raw_parts_mut(va.as_mut_ptr(), size) };
 
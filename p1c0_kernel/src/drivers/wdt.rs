@@ -1,15 +1,35 @@
-use crate::{memory::address::Address, prelude::*, sync::spinlock::RwSpinLock, syscall, thread};
+use crate::{
+    boot_args, memory::address::Address, prelude::*, sync::spinlock::RwSpinLock, syscall, thread,
+};
+
+use super::interfaces::watchdog::{self, Watchdog};
 
 use p1c0_macros::initcall;
 
-use tock_registers::{interfaces::Writeable, register_bitfields, registers::ReadWrite};
+use core::time::Duration;
+
+use tock_registers::{
+    interfaces::{Readable, Writeable},
+    register_bitfields,
+    registers::ReadWrite,
+};
 
 const COMPATIBLE: &str = "wdt,t6000";
 
+/// Command line argument that leaves the watchdog disabled, useful while debugging.
+pub const DISABLE_CMDLINE_FLAG: &str = "p1c0.nowdt";
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+const KEEPALIVE_PERIOD: Duration = Duration::from_secs(1);
+
 // Defines bitfields for the WDT registers
 register_bitfields![u32,
     /// Controls the state of the watchdog
     Control [
+        IRQ_ENABLE OFFSET(0) NUMBITS(1) [],
+        /// Set when the count reaches the alarm value. Assumed to survive the reset it triggers,
+        /// which makes it tell whether the watchdog caused the last reset
+        IRQ_STATUS OFFSET(1) NUMBITS(1) [],
         ENABLE OFFSET(2) NUMBITS(1) [],
     ],
 ];
@@ -25,6 +45,7 @@ struct WdtRegs {
 
 pub struct Wdt {
     regs: &'static WdtRegs,
+    reset_by_watchdog: bool,
 }
 
 // The watchdog seems to be running at 24 MHz by default.
@@ -34,17 +55,54 @@ pub struct Wdt {
 //   * Enable the watchdog by writing the control register enable bit
 
 impl Wdt {
-    const FREQ_KHZ: u32 = 24_000;
+    const FREQ_HZ: u128 = 24_000_000;
+    const NS_IN_S: u128 = 1_000_000_000;
+
+    /// Returns the alarm count for the given timeout. Timeouts that do not fit in the register
+    /// saturate to the longest one, around 3 minutes.
+    fn timeout_to_alarm(timeout: Duration) -> u32 {
+        let ticks = timeout.as_nanos() * Self::FREQ_HZ / Self::NS_IN_S;
+        ticks.try_into().unwrap_or(u32::MAX)
+    }
 
     fn service(&self) {
         self.regs.count.set(0);
     }
 }
 
-impl super::interfaces::watchdog::Watchdog for Wdt {
+impl Watchdog for Wdt {
     fn pet(&self) {
         self.service()
     }
+
+    fn configure(&self, timeout: Duration) {
+        self.regs.count.set(0);
+        self.regs.alarm.set(Self::timeout_to_alarm(timeout));
+        self.regs.control.write(Control::ENABLE::SET);
+    }
+
+    fn disable(&self) {
+        self.regs.control.write(Control::ENABLE::CLEAR);
+    }
+
+    fn last_reset_was_watchdog(&self) -> bool {
+        self.reset_by_watchdog
+    }
+}
+
+/// Pets the watchdog every `period` from a kernel thread. If the OS halts the thread does not run
+/// anymore, so the watchdog reboots the device.
+pub fn spawn_keepalive_thread(dev: super::DeviceRef, period: Duration) {
+    let period_us = period.as_micros() as u64;
+    thread::Builder::new().name("Wdt").spawn(move || loop {
+        match &*dev.lock_read() {
+            super::Dev::Watchdog(wdt) => wdt.pet(),
+            _ => {
+                panic!("Device MUST be a watchdog")
+            }
+        };
+        syscall::Syscall::sleep_us(period_us);
+    });
 }
 
 struct WdtDriver {}
@@ -58,30 +116,31 @@ impl super::Driver for WdtDriver {
 
         let va = crate::memory::MemoryManager::instance()
             .map_io(name, pa, size)
-            .unwrap();
+            .unwrap()
+            .leak();
 
         let regs = unsafe { &*(va.as_mut_ptr() as *mut WdtRegs) };
 
-        const TIMEOUT_MS: u32 = 5_000;
-        regs.count.set(0);
-        regs.alarm.set(Wdt::FREQ_KHZ * TIMEOUT_MS);
-        regs.control.write(Control::ENABLE::SET);
-
-        // We create a thread and service the watchdog there. If the OS halts the thread would not run, rebooting the device
-        let dev = Arc::new(RwSpinLock::new(super::Dev::Watchdog(Box::new(Wdt {
+        let wdt = Wdt {
             regs,
-        }))));
-        {
-            let dev = dev.clone();
-            thread::Builder::new().name("Wdt").spawn(move || loop {
-                match &*dev.lock_read() {
-                    super::Dev::Watchdog(wdt) => wdt.pet(),
-                    _ => {
-                        panic!("Device MUST be a watchdog")
-                    }
-                };
-                syscall::Syscall::sleep_us(1_000_000);
-            });
+            reset_by_watchdog: regs.control.is_set(Control::IRQ_STATUS),
+        };
+        if wdt.last_reset_was_watchdog() {
+            log_warning!("The last reset was caused by the watchdog");
+        }
+
+        let keepalive = !boot_args::get_flag(DISABLE_CMDLINE_FLAG);
+        if keepalive {
+            wdt.configure(DEFAULT_TIMEOUT);
+        } else {
+            log_warning!("Watchdog disabled from the command line");
+            wdt.disable();
+        }
+
+        let dev = Arc::new(RwSpinLock::new(super::Dev::Watchdog(Box::new(wdt))));
+        watchdog::register_watchdog(dev.clone());
+        if keepalive {
+            spawn_keepalive_thread(dev.clone(), KEEPALIVE_PERIOD);
         }
 
         Ok(dev)
@@ -92,3 +151,17 @@ impl super::Driver for WdtDriver {
 fn wdt_register_driver() {
     super::register_driver(COMPATIBLE, Box::new(WdtDriver {})).unwrap();
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn timeout_to_alarm() {
+        assert_eq!(Wdt::timeout_to_alarm(Duration::from_secs(5)), 120_000_000);
+        assert_eq!(Wdt::timeout_to_alarm(Duration::from_millis(1)), 24_000);
+        assert_eq!(Wdt::timeout_to_alarm(Duration::from_nanos(125)), 3);
+        assert_eq!(Wdt::timeout_to_alarm(Duration::ZERO), 0);
+        assert_eq!(Wdt::timeout_to_alarm(Duration::from_secs(3600)), u32::MAX);
+    }
+}
@@ -1,4 +1,4 @@
-use crate::prelude::*;
+use crate::{arch::relocation::RelaEntry, prelude::*};
 
 macro_rules! read_elf64_half {
     ($buffer: expr, $offset: ident) => {
@@ -68,6 +68,17 @@ pub enum Error {
     UnsupportedElfClass(EClass),
     UnsupportedElfEndianness(EData),
     NoMatchingSection,
+    TruncatedHeader,
+    InvalidProgramHeaderSize(Elf64_Half),
+    ProgramHeadersOutOfBounds,
+    SegmentOutOfBounds {
+        offset: Elf64_Off,
+        filesize: Elf64_Xword,
+    },
+    SegmentFileSizeExceedsMemSize {
+        filesize: Elf64_Xword,
+        memsize: Elf64_Xword,
+    },
 }
 
 #[derive(Clone)]
@@ -209,6 +220,48 @@ impl<'a> ElfParser<'a> {
         }
     }
 
+    /// Checks that the program header table and the file data of every loadable segment are
+    /// within the file, and that no loadable segment has more data in the file than in memory.
+    /// After this `program_header_iter` and `get_segment_data` do not panic.
+    pub fn validate_segments(&self) -> Result<(), Error> {
+        if self.elf_data.len() < ELF64_HEADER_SIZE {
+            return Err(Error::TruncatedHeader);
+        }
+
+        let phoff = read_elf64_off!(self.elf_data, E_PHOFF);
+        let phsize = read_elf64_half!(self.elf_data, E_PHENTSIZE);
+        let phnum = read_elf64_half!(self.elf_data, E_PHNUM);
+        if phnum != 0 && (phsize as usize) < PROGRAM_HEADER_SIZE {
+            return Err(Error::InvalidProgramHeaderSize(phsize));
+        }
+
+        let table_end = (phoff as usize).checked_add(phsize as usize * phnum as usize);
+        if !matches!(table_end, Some(end) if end <= self.elf_data.len()) {
+            return Err(Error::ProgramHeadersOutOfBounds);
+        }
+
+        for header in self.program_header_iter() {
+            if !matches!(header.ty(), Ok(PtType::Load)) {
+                continue;
+            }
+
+            let (offset, filesize) = (header.file_offset(), header.filesize());
+            let end = (offset as usize).checked_add(filesize as usize);
+            if !matches!(end, Some(end) if end <= self.elf_data.len()) {
+                return Err(Error::SegmentOutOfBounds { offset, filesize });
+            }
+
+            if header.memsize() < filesize {
+                return Err(Error::SegmentFileSizeExceedsMemSize {
+                    filesize,
+                    memsize: header.memsize(),
+                });
+            }
+        }
+
+        Ok(())
+    }
+
     pub fn get_segment_data(&self, program_header: &ProgramHeader) -> &[u8] {
         let file_offset = program_header.file_offset() as usize;
         let file_size = program_header.filesize() as usize;
@@ -269,6 +322,50 @@ impl<'a> ElfParser<'a> {
         Err(Error::NoMatchingSection)
     }
 
+    /// Iterates over the dynamic relocations, which are the entries of the `SHT_RELA` sections
+    /// that are loaded with the executable. Relocations for the linker are not loaded and are
+    /// skipped, like sections that are not within the file. The type of the entries is the
+    /// relocation type, the symbol index is dropped, since only relocations that do not need
+    /// symbols are supported.
+    pub fn rela_iter(&self) -> impl Iterator<Item = RelaEntry> + 'a {
+        let elf_data = self.elf_data;
+        self.section_header_iter()
+            .filter(|section| {
+                matches!(section.ty(), Ok(ShType::RelA)) && section.flags() & SHF_ALLOC != 0
+            })
+            .filter_map(move |section| {
+                let start = section.offset() as usize;
+                let end = start.checked_add(section.size() as usize)?;
+                elf_data.get(start..end)
+            })
+            .flat_map(|data| {
+                data.chunks_exact(RELA_ENTRY_SIZE).map(|entry| RelaEntry {
+                    offset: read_elf64_addr!(entry, R_OFFSET) as usize,
+                    ty: (read_elf64_xword!(entry, R_INFO) & 0xFFFF_FFFF) as usize,
+                    addend: read_elf64_xword!(entry, R_ADDEND) as usize,
+                })
+            })
+    }
+
+    /// Returns the contents of the `NT_GNU_BUILD_ID` note, which identifies the build the binary
+    /// comes from. Both `PT_NOTE` segments and `SHT_NOTE` sections are searched.
+    pub fn build_id(&self) -> Option<&'a [u8]> {
+        let segments = self
+            .program_header_iter()
+            .filter(|header| matches!(header.ty(), Ok(PtType::Note)))
+            .map(|header| (header.file_offset() as usize, header.filesize() as usize));
+        let sections = self
+            .section_header_iter()
+            .filter(|section| matches!(section.ty(), Ok(ShType::Note)))
+            .map(|section| (section.offset() as usize, section.size() as usize));
+
+        let elf_data = self.elf_data;
+        segments.chain(sections).find_map(|(offset, size)| {
+            let notes = elf_data.get(offset..offset.checked_add(size)?)?;
+            find_note(notes, GNU_NOTE_NAME, NT_GNU_BUILD_ID)
+        })
+    }
+
     pub fn symbol_table_iter(&self) -> Option<SymbolTableIter> {
         if let Some(symtab) = self
             .section_header_iter()
@@ -334,6 +431,10 @@ impl<'a> ProgramHeader<'a> {
         read_elf64_xword!(self.pheader_data, P_FILESIZE)
     }
 
+    pub fn align(&self) -> Elf64_Xword {
+        read_elf64_xword!(self.pheader_data, P_ALIGN)
+    }
+
     pub fn permissions(&self) -> Permissions {
         pub const PF_R: Elf64_Word = 4;
         pub const PF_W: Elf64_Word = 2;
@@ -365,6 +466,10 @@ impl<'a> SectionHeader<'a> {
         read_elf64_addr!(self.section_header_data, SH_ADDR)
     }
 
+    pub fn flags(&self) -> Elf64_Xword {
+        read_elf64_xword!(self.section_header_data, SH_FLAGS)
+    }
+
     pub fn offset(&self) -> Elf64_Off {
         read_elf64_off!(self.section_header_data, SH_OFFSET)
     }
@@ -485,6 +590,41 @@ impl<'a> Iterator for SymbolTableIter<'a> {
 }
 
 const SHN_UNDEF: usize = 0;
+const ELF64_HEADER_SIZE: usize = 64;
+const PROGRAM_HEADER_SIZE: usize = 56;
+const RELA_ENTRY_SIZE: usize = 24;
+/// The section occupies memory during process execution
+const SHF_ALLOC: Elf64_Xword = 0x2;
+
+const NOTE_HEADER_SIZE: usize = 12;
+const NOTE_ALIGNMENT: usize = 4;
+const GNU_NOTE_NAME: &[u8] = b"GNU\0";
+const NT_GNU_BUILD_ID: Elf64_Word = 3;
+
+/// Returns the descriptor of the note with the given name and type in a sequence of notes. The
+/// name and the descriptor of every note are padded to 4 bytes.
+fn find_note<'a>(mut notes: &'a [u8], name: &[u8], ty: Elf64_Word) -> Option<&'a [u8]> {
+    let align = |offset: usize| (offset + NOTE_ALIGNMENT - 1) & !(NOTE_ALIGNMENT - 1);
+
+    while notes.len() >= NOTE_HEADER_SIZE {
+        let name_size = read_elf64_word!(notes, N_NAMESZ) as usize;
+        let desc_size = read_elf64_word!(notes, N_DESCSZ) as usize;
+        let note_type = read_elf64_word!(notes, N_TYPE);
+
+        let name_end = NOTE_HEADER_SIZE.checked_add(name_size)?;
+        let desc_start = align(name_end);
+        let desc_end = desc_start.checked_add(desc_size)?;
+
+        let note_name = notes.get(NOTE_HEADER_SIZE..name_end)?;
+        let desc = notes.get(desc_start..desc_end)?;
+        if note_type == ty && note_name == name {
+            return Some(desc);
+        }
+
+        notes = notes.get(align(desc_end)..)?;
+    }
+    None
+}
 
 mod file_offsets {
     pub const E_MAGIC0: usize = 0x00;
@@ -514,10 +654,12 @@ mod file_offsets {
         pub const P_PADDR: usize = 0x18;
         pub const P_FILESIZE: usize = 0x20;
         pub const P_MEMSIZE: usize = 0x28;
+        pub const P_ALIGN: usize = 0x30;
 
         // Section header
         pub const SH_NAME: usize = 0x00;
         pub const SH_TYPE: usize = 0x04;
+        pub const SH_FLAGS: usize = 0x08;
         pub const SH_ADDR: usize = 0x10;
         pub const SH_OFFSET: usize = 0x18;
         pub const SH_SIZE: usize = 0x20;
@@ -529,6 +671,16 @@ mod file_offsets {
         pub const ST_INFO: usize = 0x04;
         pub const ST_VALUE: usize = 0x08;
         pub const ST_SIZE: usize = 0x10;
+
+        // Note header
+        pub const N_NAMESZ: usize = 0x00;
+        pub const N_DESCSZ: usize = 0x04;
+        pub const N_TYPE: usize = 0x08;
+
+        // Relocation entry with addend
+        pub const R_OFFSET: usize = 0x00;
+        pub const R_INFO: usize = 0x08;
+        pub const R_ADDEND: usize = 0x10;
     }
 }
 
@@ -681,8 +833,233 @@ impl TryFrom<u8> for SymbolType {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Permissions {
     pub read: bool,
     pub write: bool,
     pub exec: bool,
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const HEADER_SIZE: usize = ELF64_HEADER_SIZE;
+
+    fn note(name: &[u8], ty: u32, desc: &[u8]) -> Vec<u8> {
+        let mut data = vec![];
+        data.extend_from_slice(&(name.len() as u32).to_le_bytes());
+        data.extend_from_slice(&(desc.len() as u32).to_le_bytes());
+        data.extend_from_slice(&ty.to_le_bytes());
+        for field in [name, desc] {
+            data.extend_from_slice(field);
+            data.resize((data.len() + 3) & !3, 0);
+        }
+        data
+    }
+
+    /// Builds an executable with a `PT_LOAD` segment and a `PT_NOTE` segment containing `notes`
+    fn elf_with_notes(notes: &[u8]) -> Vec<u8> {
+        let mut data = vec![0u8; HEADER_SIZE];
+        data[..4].copy_from_slice(b"\x7fELF");
+        data[file_offsets::E_CLASS] = 2;
+        data[file_offsets::E_DATA] = 1;
+        data[file_offsets::elf64::E_TYPE..][..2].copy_from_slice(&2u16.to_le_bytes());
+        data[file_offsets::elf64::E_MACHINE..][..2].copy_from_slice(&183u16.to_le_bytes());
+        data[file_offsets::elf64::E_PHOFF..][..8]
+            .copy_from_slice(&(HEADER_SIZE as u64).to_le_bytes());
+        data[file_offsets::elf64::E_PHENTSIZE..][..2]
+            .copy_from_slice(&(PROGRAM_HEADER_SIZE as u16).to_le_bytes());
+        data[file_offsets::elf64::E_PHNUM..][..2].copy_from_slice(&2u16.to_le_bytes());
+
+        let notes_offset = HEADER_SIZE + 2 * PROGRAM_HEADER_SIZE;
+        for (ty, offset, size) in [(1u32, 0, 0), (4, notes_offset, notes.len())] {
+            let mut header = [0u8; PROGRAM_HEADER_SIZE];
+            header[file_offsets::elf64::P_TYPE..][..4].copy_from_slice(&ty.to_le_bytes());
+            header[file_offsets::elf64::P_OFFSET..][..8]
+                .copy_from_slice(&(offset as u64).to_le_bytes());
+            header[file_offsets::elf64::P_FILESIZE..][..8]
+                .copy_from_slice(&(size as u64).to_le_bytes());
+            data.extend_from_slice(&header);
+        }
+
+        data.extend_from_slice(notes);
+        data
+    }
+
+    /// Overwrites the `PT_LOAD` segment of an ELF built by `elf_with_notes`
+    fn set_load_segment(elf_data: &mut [u8], offset: u64, filesize: u64, memsize: u64) {
+        let header = &mut elf_data[HEADER_SIZE..][..PROGRAM_HEADER_SIZE];
+        header[file_offsets::elf64::P_OFFSET..][..8].copy_from_slice(&offset.to_le_bytes());
+        header[file_offsets::elf64::P_FILESIZE..][..8].copy_from_slice(&filesize.to_le_bytes());
+        header[file_offsets::elf64::P_MEMSIZE..][..8].copy_from_slice(&memsize.to_le_bytes());
+    }
+
+    #[test]
+    fn valid_segments() {
+        let mut elf_data = elf_with_notes(&note(GNU_NOTE_NAME, 1, &[0; 16]));
+        let len = elf_data.len() as u64;
+        set_load_segment(&mut elf_data, 0, len, 0x4000);
+
+        let elf = ElfParser::from_slice(&elf_data).unwrap();
+        assert!(elf.validate_segments().is_ok());
+        let load = elf.program_header_iter().next().unwrap();
+        assert_eq!(elf.get_segment_data(&load).len(), elf_data.len());
+    }
+
+    #[test]
+    fn segments_out_of_bounds() {
+        let mut elf_data = elf_with_notes(&[]);
+        let len = elf_data.len() as u64;
+
+        set_load_segment(&mut elf_data, 8, len, len + 8);
+        let elf = ElfParser::from_slice(&elf_data).unwrap();
+        assert!(matches!(
+            elf.validate_segments(),
+            Err(Error::SegmentOutOfBounds { offset: 8, filesize }) if filesize == len
+        ));
+
+        // Offset and size overflow when added
+        set_load_segment(&mut elf_data, u64::MAX - 8, 16, 16);
+        let elf = ElfParser::from_slice(&elf_data).unwrap();
+        assert!(matches!(
+            elf.validate_segments(),
+            Err(Error::SegmentOutOfBounds { .. })
+        ));
+
+        set_load_segment(&mut elf_data, 0, 16, 8);
+        let elf = ElfParser::from_slice(&elf_data).unwrap();
+        assert!(matches!(
+            elf.validate_segments(),
+            Err(Error::SegmentFileSizeExceedsMemSize {
+                filesize: 16,
+                memsize: 8
+            })
+        ));
+    }
+
+    #[test]
+    fn truncated_program_headers() {
+        let elf_data = elf_with_notes(&[]);
+
+        // The second program header is cut in half
+        let truncated = &elf_data[..HEADER_SIZE + PROGRAM_HEADER_SIZE + 20];
+        let elf = ElfParser::from_slice(truncated).unwrap();
+        assert!(matches!(
+            elf.validate_segments(),
+            Err(Error::ProgramHeadersOutOfBounds)
+        ));
+
+        let elf = ElfParser::from_slice(&elf_data[..40]).unwrap();
+        assert!(matches!(
+            elf.validate_segments(),
+            Err(Error::TruncatedHeader)
+        ));
+
+        let mut elf_data = elf_data.clone();
+        elf_data[file_offsets::elf64::E_PHENTSIZE] = 8;
+        let elf = ElfParser::from_slice(&elf_data).unwrap();
+        assert!(matches!(
+            elf.validate_segments(),
+            Err(Error::InvalidProgramHeaderSize(8))
+        ));
+    }
+
+    /// Appends section headers for `RELA` sections with the given flags, offsets and sizes
+    fn add_rela_sections(elf_data: &mut Vec<u8>, sections: &[(u64, u64, u64)]) {
+        const SECTION_HEADER_SIZE: usize = 64;
+        const SHT_RELA: u32 = 4;
+
+        let shoff = elf_data.len() as u64;
+        elf_data[file_offsets::elf64::E_SHOFF..][..8].copy_from_slice(&shoff.to_le_bytes());
+        elf_data[file_offsets::elf64::E_SHENTSIZE..][..2]
+            .copy_from_slice(&(SECTION_HEADER_SIZE as u16).to_le_bytes());
+        elf_data[file_offsets::elf64::E_SHNUM..][..2]
+            .copy_from_slice(&(sections.len() as u16).to_le_bytes());
+
+        for (flags, offset, size) in sections {
+            let mut header = [0u8; SECTION_HEADER_SIZE];
+            header[file_offsets::elf64::SH_TYPE..][..4].copy_from_slice(&SHT_RELA.to_le_bytes());
+            header[file_offsets::elf64::SH_FLAGS..][..8].copy_from_slice(&flags.to_le_bytes());
+            header[file_offsets::elf64::SH_OFFSET..][..8].copy_from_slice(&offset.to_le_bytes());
+            header[file_offsets::elf64::SH_SIZE..][..8].copy_from_slice(&size.to_le_bytes());
+            header[file_offsets::elf64::SH_ENTSIZE..][..8]
+                .copy_from_slice(&(RELA_ENTRY_SIZE as u64).to_le_bytes());
+            elf_data.extend_from_slice(&header);
+        }
+    }
+
+    #[test]
+    fn dynamic_relocations() {
+        use crate::arch::relocation::R_AARCH64_RELATIVE;
+
+        let rela = |offset: u64, ty: u64, addend: u64| {
+            [offset, ty, addend]
+                .iter()
+                .flat_map(|value| value.to_le_bytes())
+                .collect::<Vec<_>>()
+        };
+
+        let mut elf_data = elf_with_notes(&[]);
+        let dynamic_offset = elf_data.len() as u64;
+        elf_data.extend(rela(0x10, R_AARCH64_RELATIVE as u64, 0x100));
+        elf_data.extend(rela(0x18, R_AARCH64_RELATIVE as u64, 0x200));
+        let static_offset = elf_data.len() as u64;
+        elf_data.extend(rela(0x20, 257, 0));
+
+        // The section headers are appended right after the relocations
+        let file_size = elf_data.len() as u64 + 4 * 64;
+        add_rela_sections(
+            &mut elf_data,
+            &[
+                (SHF_ALLOC, dynamic_offset, 2 * RELA_ENTRY_SIZE as u64),
+                // Relocations for the linker, like `.rela.text` in objects
+                (0, static_offset, RELA_ENTRY_SIZE as u64),
+                // Out of bounds or overflowing sections are ignored
+                (SHF_ALLOC, file_size - 8, RELA_ENTRY_SIZE as u64),
+                (SHF_ALLOC, u64::MAX - 8, RELA_ENTRY_SIZE as u64),
+            ],
+        );
+
+        let elf = ElfParser::from_slice(&elf_data).unwrap();
+        let entries: Vec<_> = elf
+            .rela_iter()
+            .map(|entry| (entry.offset, entry.ty, entry.addend))
+            .collect();
+        assert_eq!(
+            entries,
+            vec![
+                (0x10, R_AARCH64_RELATIVE, 0x100),
+                (0x18, R_AARCH64_RELATIVE, 0x200)
+            ]
+        );
+    }
+
+    #[test]
+    fn build_id_note() {
+        let build_id = [0xde, 0xad, 0xbe, 0xef, 0x01, 0x23, 0x45, 0x67, 0x89];
+
+        // Other notes before it, with names and descriptors that need padding
+        let mut notes = note(b"p1c0\0", NT_GNU_BUILD_ID, &[1, 2, 3]);
+        notes.extend(note(GNU_NOTE_NAME, 1, &[0; 16]));
+        notes.extend(note(GNU_NOTE_NAME, NT_GNU_BUILD_ID, &build_id));
+
+        let elf_data = elf_with_notes(&notes);
+        let elf = ElfParser::from_slice(&elf_data).unwrap();
+        assert_eq!(elf.build_id(), Some(&build_id[..]));
+    }
+
+    #[test]
+    fn missing_build_id() {
+        let elf_data = elf_with_notes(&note(GNU_NOTE_NAME, 1, &[0; 16]));
+        let elf = ElfParser::from_slice(&elf_data).unwrap();
+        assert_eq!(elf.build_id(), None);
+
+        // A truncated note is ignored
+        let mut notes = note(GNU_NOTE_NAME, NT_GNU_BUILD_ID, &[0; 20]);
+        notes.truncate(20);
+        let elf_data = elf_with_notes(&notes);
+        let elf = ElfParser::from_slice(&elf_data).unwrap();
+        assert_eq!(elf.build_id(), None);
+    }
+}
@@ -1,9 +1,21 @@
+use crate::{prelude::*, shell};
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
 use embedded_graphics::{
-    geometry::Size,
+    draw_target::DrawTarget,
+    geometry::{Dimensions, Point, Size},
     image::ImageRaw,
-    mono_font::{mapping::StrGlyphMapping, DecorationDimensions, MonoFont},
+    mono_font::{
+        mapping::{GlyphMapping, StrGlyphMapping},
+        DecorationDimensions, MonoFont,
+    },
+    primitives::Rectangle,
+    Pixel,
 };
 
+use p1c0_macros::initcall;
+
 const CHARS_PER_ROW: u32 = 32;
 
 /// Character ranges for all fonts.
@@ -24,3 +36,269 @@ pub const FIRA_CODE_30: MonoFont = MonoFont {
     underline: DecorationDimensions::new(15, 1),
     strikethrough: DecorationDimensions::new(10, 1),
 };
+
+/// Shown in place of characters that have no glyph in a font, or of invalid UTF-8 sequences.
+pub const REPLACEMENT_CHARACTER: char = '\u{FFFD}';
+
+/// Fonts map characters outside of their glyph set to the glyph of `?`.
+pub fn has_glyph(font: &MonoFont<'_>, c: char) -> bool {
+    c == '?' || font.glyph_mapping.index(c) != font.glyph_mapping.index('?')
+}
+
+/// Returns the characters of `text` as they are rendered with `font`, with the ones that have no
+/// glyph replaced by `REPLACEMENT_CHARACTER`.
+pub fn glyph_chars<'a>(font: &'a MonoFont<'a>, text: &'a str) -> impl Iterator<Item = char> + 'a {
+    text.chars().map(|c| {
+        if has_glyph(font, c) {
+            c
+        } else {
+            REPLACEMENT_CHARACTER
+        }
+    })
+}
+
+/// Iterator over the characters of UTF-8 encoded bytes. Each invalid sequence results in a single
+/// `REPLACEMENT_CHARACTER`, like `String::from_utf8_lossy` does.
+pub struct Utf8Chars<'a> {
+    valid: core::str::Chars<'a>,
+    /// Bytes after `valid`, starting with an invalid sequence if not empty
+    rest: &'a [u8],
+}
+
+pub fn decode_utf8(bytes: &[u8]) -> Utf8Chars<'_> {
+    Utf8Chars {
+        valid: "".chars(),
+        rest: bytes,
+    }
+}
+
+impl<'a> Iterator for Utf8Chars<'a> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        if let Some(c) = self.valid.next() {
+            return Some(c);
+        }
+        if self.rest.is_empty() {
+            return None;
+        }
+
+        match core::str::from_utf8(self.rest) {
+            Ok(valid) => {
+                self.valid = valid.chars();
+                self.rest = &[];
+            }
+            Err(error) if error.valid_up_to() > 0 => {
+                let (valid, rest) = self.rest.split_at(error.valid_up_to());
+                // Safety: from_utf8 validated these bytes
+                self.valid = unsafe { core::str::from_utf8_unchecked(valid) }.chars();
+                self.rest = rest;
+            }
+            Err(error) => {
+                // A truncated sequence at the end has no error length and spans the rest of bytes
+                let invalid_length = error.error_len().unwrap_or(self.rest.len());
+                self.rest = &self.rest[invalid_length..];
+                return Some(REPLACEMENT_CHARACTER);
+            }
+        }
+        self.next()
+    }
+}
+
+pub const MIN_SCALE: u32 = 1;
+pub const MAX_SCALE: u32 = 4;
+
+static SCALE: AtomicU32 = AtomicU32::new(MIN_SCALE);
+
+/// Sets the integer factor text is scaled by on the display console, clamped to
+/// `MIN_SCALE..=MAX_SCALE`. Returns the scale that was set.
+pub fn set_scale(scale: u32) -> u32 {
+    let scale = scale.clamp(MIN_SCALE, MAX_SCALE);
+    SCALE.store(scale, Ordering::Relaxed);
+    scale
+}
+
+pub fn scale() -> u32 {
+    SCALE.load(Ordering::Relaxed)
+}
+
+/// Draw target that enlarges everything drawn on it by an integer factor, around `origin`. Each
+/// pixel becomes a square of `scale` by `scale` pixels (nearest-neighbor scaling), so text drawn
+/// at `origin` with a bitmap font is rendered with glyphs `scale` times larger.
+pub struct Scaled<'a, T> {
+    target: &'a mut T,
+    origin: Point,
+    scale: u32,
+}
+
+impl<'a, T> Scaled<'a, T> {
+    pub fn new(target: &'a mut T, origin: Point, scale: u32) -> Self {
+        Self {
+            target,
+            origin,
+            scale: scale.clamp(MIN_SCALE, MAX_SCALE),
+        }
+    }
+}
+
+impl<'a, T: DrawTarget> DrawTarget for Scaled<'a, T> {
+    type Color = T::Color;
+    type Error = T::Error;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        let scale = self.scale as i32;
+        let origin = self.origin;
+        self.target
+            .draw_iter(pixels.into_iter().flat_map(move |Pixel(point, color)| {
+                let corner = origin + (point - origin) * scale;
+                (0..scale * scale).map(move |index| {
+                    Pixel(corner + Point::new(index % scale, index / scale), color)
+                })
+            }))
+    }
+}
+
+impl<'a, T: Dimensions> Dimensions for Scaled<'a, T> {
+    fn bounding_box(&self) -> Rectangle {
+        let target = self.target.bounding_box();
+        Rectangle::new(
+            self.origin + (target.top_left - self.origin) / self.scale as i32,
+            target.size / self.scale,
+        )
+    }
+}
+
+fn fontscale_command(args: &[&str]) {
+    match args.first().map(|arg| arg.parse::<u32>()) {
+        None => println!("Font scale: {}", scale()),
+        Some(Ok(requested)) => {
+            let scale = set_scale(requested);
+            if scale != requested {
+                println!("Font scale is limited to {}..={}", MIN_SCALE, MAX_SCALE);
+            }
+        }
+        Some(Err(_)) => println!("Usage: fontscale [scale]"),
+    }
+}
+
+#[initcall(priority = 0)]
+fn font_register_commands() {
+    shell::register_command("fontscale", fontscale_command).unwrap();
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use core::convert::Infallible;
+    use embedded_graphics::{
+        geometry::OriginDimensions,
+        mono_font::{ascii::FONT_7X14, MonoTextStyle},
+        pixelcolor::BinaryColor,
+        text::{Baseline, Text},
+        Drawable,
+    };
+    use std::{vec, vec::Vec};
+
+    /// Monochrome bitmap of `WIDTH` x `HEIGHT` pixels
+    struct Bitmap(Vec<bool>);
+
+    const WIDTH: u32 = 16;
+    const HEIGHT: u32 = 32;
+
+    impl Bitmap {
+        fn new() -> Self {
+            Self(vec![false; (WIDTH * HEIGHT) as usize])
+        }
+
+        fn get(&self, x: u32, y: u32) -> bool {
+            self.0[(y * WIDTH + x) as usize]
+        }
+    }
+
+    impl DrawTarget for Bitmap {
+        type Color = BinaryColor;
+        type Error = Infallible;
+
+        fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+        where
+            I: IntoIterator<Item = Pixel<Self::Color>>,
+        {
+            for Pixel(Point { x, y }, color) in pixels {
+                assert!((0..WIDTH as i32).contains(&x) && (0..HEIGHT as i32).contains(&y));
+                self.0[(y as u32 * WIDTH + x as u32) as usize] = color.is_on();
+            }
+            Ok(())
+        }
+    }
+
+    impl OriginDimensions for Bitmap {
+        fn size(&self) -> Size {
+            Size::new(WIDTH, HEIGHT)
+        }
+    }
+
+    fn rasterize(
+        target: &mut impl DrawTarget<Color = BinaryColor, Error = Infallible>,
+        origin: Point,
+    ) {
+        let style = MonoTextStyle::new(&FONT_7X14, BinaryColor::On);
+        Text::with_baseline("A", origin, style, Baseline::Top)
+            .draw(target)
+            .unwrap();
+    }
+
+    #[test]
+    fn glyph_scaling() {
+        let origin = Point::new(1, 2);
+        let mut unscaled = Bitmap::new();
+        rasterize(&mut unscaled, origin);
+        let mut scaled = Bitmap::new();
+        rasterize(&mut Scaled::new(&mut scaled, origin, 2), origin);
+
+        assert!(unscaled.0.iter().any(|pixel| *pixel));
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                // Every pixel of the scaled glyph comes from the unscaled one, at half the distance
+                // from the origin
+                let expected = x >= 1 && y >= 2 && unscaled.get(1 + (x - 1) / 2, 2 + (y - 2) / 2);
+                assert_eq!(scaled.get(x, y), expected, "pixel ({}, {})", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn utf8_replacement() {
+        // Valid multibyte sequences, an invalid byte and a sequence truncated at the end
+        let bytes = b"ok \xc3\xa9 \xe2\x86\x92 \xff end \xe6\x97";
+        let decoded: String = decode_utf8(bytes).collect();
+        assert_eq!(decoded, "ok é → \u{FFFD} end \u{FFFD}");
+        assert_eq!(decode_utf8(b"").count(), 0);
+
+        // é is part of ISO 8859-1, the arrow is not
+        let replacements = |font| {
+            glyph_chars(font, &decoded)
+                .filter(|c| *c == REPLACEMENT_CHARACTER)
+                .count()
+        };
+        assert_eq!(replacements(&FIRA_CODE_30), 3);
+        assert_eq!(replacements(&FONT_7X14), 4);
+        assert_eq!(
+            glyph_chars(&FIRA_CODE_30, &decoded).count(),
+            decoded.chars().count()
+        );
+        assert!(has_glyph(&FONT_7X14, '?'));
+    }
+
+    #[test]
+    fn scale_is_bounded() {
+        assert_eq!(set_scale(0), MIN_SCALE);
+        assert_eq!(set_scale(MAX_SCALE + 1), MAX_SCALE);
+        assert_eq!(set_scale(2), 2);
+        assert_eq!(scale(), 2);
+        set_scale(MIN_SCALE);
+    }
+}
@@ -1,6 +1,9 @@
 use crate::{
     adt,
-    arch::{exceptions, read_pc},
+    arch::{
+        self, cpu, exceptions, pan, read_pc,
+        relocation::{self, RelaEntry},
+    },
     backtrace,
     boot_args::BootArgs,
     chickens, drivers,
@@ -18,18 +21,7 @@ use p1c0_macros::initcall;
 
 use core::time::Duration;
 
-use aarch64_cpu::{
-    asm,
-    registers::{CurrentEL, CNTHCTL_EL2, CNTVOFF_EL2, ELR_EL2, HCR_EL2, SPSR_EL2, SP_EL1},
-};
-use tock_registers::interfaces::{ReadWriteable, Readable, Writeable};
-
-#[repr(C)]
-struct RelaEntry {
-    offset: usize,
-    ty: usize,
-    addend: usize,
-}
+use tock_registers::interfaces::ReadWriteable;
 
 /// This is the original base passed by iBoot into the kernel. Does NOT change after kernel
 /// relocation.
@@ -37,31 +29,6 @@ static mut BASE: *const u8 = core::ptr::null();
 
 static mut RELOCATION_DONE: bool = false;
 
-fn transition_to_el1(stack_bottom: *const ()) -> ! {
-    // Do not trap timer to EL2.
-    CNTHCTL_EL2.write(CNTHCTL_EL2::EL1PCTEN::SET + CNTHCTL_EL2::EL1PCEN::SET);
-    CNTVOFF_EL2.set(0);
-
-    // EL1 is Aarch64
-    HCR_EL2.modify(HCR_EL2::RW::EL1IsAarch64);
-
-    SPSR_EL2.write(
-        SPSR_EL2::D::Masked
-            + SPSR_EL2::A::Masked
-            + SPSR_EL2::I::Masked
-            + SPSR_EL2::F::Masked
-            + SPSR_EL2::M::EL1h, // We "came" from el1h
-    );
-
-    // Link register is el1_entry
-    ELR_EL2.set(el1_entry as *const () as u64);
-
-    // TODO(javier-varez): Set proper stack pointer here...
-    SP_EL1.set(stack_bottom as u64);
-
-    asm::eret();
-}
-
 extern "C" {
     fn kernel_main();
     static _rela_start: u8;
@@ -69,14 +36,7 @@ extern "C" {
     static _stack_bot: u8;
 
     // # SAFETY: This function assumes the new address is in high memory!
-    fn relocate_and_jump_to_relocated_kernel(
-        old_base: usize,
-        new_base: usize,
-        rela_start: *const RelaEntry,
-        rela_end: *const RelaEntry,
-        high_kernel_addr: usize,
-        high_stack_addr: usize,
-    ) -> !;
+    fn jump_to_relocated_kernel(high_kernel_addr: usize, high_stack_addr: usize) -> !;
 }
 
 unsafe fn jump_to_high_kernel() -> ! {
@@ -103,17 +63,15 @@ unsafe fn jump_to_high_kernel() -> ! {
         .try_into_logical()
         .expect("The stack bottom does not have a high kernel address");
 
-    // Relocate ourselves again to the correct location
+    // Relocate ourselves again to the correct location. The image is still written through the
+    // original base, since it is identity mapped. Addresses of symbols must not be taken after
+    // this, they would already point to the high kernel addresses.
+    relocation::apply_rela_at(BASE as usize, new_base.as_usize(), rela_start, rela_size)
+        .expect("Kernel relocations are supported");
+
     // From this point onwards the execution is redirected to the new kernel_prelude entrypoint.
-    // We restore the initial stack using the new base address and.
-    relocate_and_jump_to_relocated_kernel(
-        BASE as usize,
-        new_base.as_usize(),
-        rela_start,
-        rela_end,
-        high_kernel_addr.as_usize(),
-        high_stack.as_usize(),
-    );
+    // We restore the initial stack using the new base address.
+    jump_to_relocated_kernel(high_kernel_addr.as_usize(), high_stack.as_usize());
 }
 
 unsafe fn kernel_prelude() {
@@ -132,6 +90,9 @@ unsafe fn kernel_prelude() {
 
     // Enable FPU usage both in EL1 and EL0
     CPACR.modify(CPACR::FPEN::Enable);
+    pan::init();
+    // The relocated kernel installs the EL1 vector table
+    arch::require_exception_level(arch::ExceptionLevel::OS, "Kernel prelude");
     memory::MemoryManager::instance().late_init();
     exceptions::handling_init();
 
@@ -144,18 +105,18 @@ unsafe fn kernel_prelude() {
     kernel_main();
 }
 
-fn probe_subdevices<const SIZE: usize>(devs: &mut heapless::Vec<adt::AdtNode, SIZE>) {
+fn probe_subdevices<const SIZE: usize>(
+    devs: &mut heapless::Vec<adt::AdtNode, SIZE>,
+    deferred: &mut Vec<heapless::Vec<adt::AdtNode, SIZE>>,
+) {
     let parent = devs.last().unwrap().clone();
     for subdevices in parent.child_iter() {
         devs.push(subdevices).expect("Exceeded recursion size");
-        match drivers::probe_device(devs) {
-            Ok(_) => {}
-            Err(drivers::Error::DeviceSpecificError(dev_error)) => {
-                log_warning!("Unable to probe device. Error: {:?}", dev_error);
-            }
-            Err(_) => {}
+        // Failing drivers are logged by probe_device, devices without a driver are just skipped
+        if let Err(drivers::Error::Deferred) = drivers::probe_device(devs) {
+            deferred.push(devs.clone());
         }
-        probe_subdevices(devs);
+        probe_subdevices(devs, deferred);
         devs.pop();
     }
 }
@@ -163,12 +124,22 @@ fn probe_subdevices<const SIZE: usize>(devs: &mut heapless::Vec<adt::AdtNode, SI
 fn probe_devices() {
     let adt = adt::get_adt().unwrap();
     let mut devs: heapless::Vec<adt::AdtNode, 8> = adt.path_iter("/arm-io").collect();
-    probe_subdevices(&mut devs);
+    let mut deferred = vec![];
+    probe_subdevices(&mut devs, &mut deferred);
+
+    let deferred = drivers::retry_deferred_probes(deferred, |devs| drivers::probe_device(devs));
+    for devs in deferred {
+        log_warning!(
+            "Device {} was never probed, its dependencies are not available",
+            devs.last().unwrap().get_name()
+        );
+    }
 }
 
 /// # Safety
 ///   This function must be called with the MMU off while running in EL1. It will relocate itself
 unsafe extern "C" fn el1_entry() -> ! {
+    arch::require_exception_level(arch::ExceptionLevel::OS, "MMU initialization");
     memory::MemoryManager::early_init();
 
     // Right after initializing the MMU we need to relocate ourselves into the high_kernel_addr
@@ -177,7 +148,7 @@ unsafe extern "C" fn el1_entry() -> ! {
 }
 
 #[no_mangle]
-pub extern "C" fn start_rust(boot_args: &BootArgs, base: *const u8, stack_bottom: *const ()) -> ! {
+pub extern "C" fn start_rust(boot_args: &BootArgs, base: *const u8) -> ! {
     // Warning: Be very careful of the work that is done at this stage. At this point in time the
     // kernel is about to be relocated and doesn't have an enabled MMU. What this means is that most
     // of the operations will not work or will not be compatible (e.g.: addresses) with the
@@ -195,19 +166,23 @@ pub extern "C" fn start_rust(boot_args: &BootArgs, base: *const u8, stack_bottom
     //   It is safe to call probe early here since we are in a single-threaded context.
     unsafe { uart::probe_early() };
 
+    // The UART is usable from here on, so this is the earliest point where the error can be seen
+    if let Err(error) = boot_args.validate() {
+        panic!(
+            "Incompatible boot arguments (revision {}, version {}): {:?}",
+            boot_args.revision, boot_args.version, error
+        );
+    }
+
     chickens::init_cpu();
 
-    match CurrentEL.read_as_enum(CurrentEL::EL).expect("Valid EL") {
-        CurrentEL::EL::Value::EL2 => {
-            transition_to_el1(stack_bottom);
-        }
-        CurrentEL::EL::Value::EL1 => {
-            unsafe { el1_entry() };
-        }
-        _ => {
-            panic!();
-        }
-    }
+    // # Safety
+    //   We are still in a single-threaded context and nobody has queried the features yet.
+    unsafe { cpu::init() };
+
+    // Does nothing if the bootloader already started the kernel at EL1
+    arch::drop_to_el1();
+    unsafe { el1_entry() };
 }
 
 #[inline]
@@ -0,0 +1,151 @@
+//! Queue of input events, decoupled from the drivers that produce them.
+//!
+//! Drivers push the events they decode and consumers, like a shell, drain them with `poll_event`
+//! without owning the drivers.
+
+pub use crate::drivers::hid::report::{Event, KeyEvent, Modifiers, PointerEvent};
+
+use crate::collections::mpsc::{FullPolicy, Mpsc};
+
+struct EventQueue {
+    events: Mpsc<Event>,
+}
+
+impl EventQueue {
+    const CAPACITY: usize = 64;
+
+    const fn new() -> Self {
+        Self {
+            events: Mpsc::new(Self::CAPACITY, FullPolicy::DropOldest),
+        }
+    }
+
+    /// Merges `event` into `last` if the result is equivalent to receiving both of them. Pointer
+    /// motion can be merged as long as the buttons do not change.
+    fn coalesce(last: &mut Event, event: &Event) -> bool {
+        match (last, event) {
+            (
+                Event::Pointer(PointerEvent::Relative {
+                    dx,
+                    dy,
+                    wheel,
+                    buttons,
+                }),
+                Event::Pointer(PointerEvent::Relative {
+                    dx: new_dx,
+                    dy: new_dy,
+                    wheel: new_wheel,
+                    buttons: new_buttons,
+                }),
+            ) if buttons == new_buttons => {
+                *dx += new_dx;
+                *dy += new_dy;
+                *wheel += new_wheel;
+                true
+            }
+            (
+                Event::Pointer(PointerEvent::Touch { contacts, buttons }),
+                Event::Pointer(PointerEvent::Touch {
+                    contacts: new_contacts,
+                    buttons: new_buttons,
+                }),
+            ) if buttons == new_buttons => {
+                contacts.clone_from(new_contacts);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Queues an event. When the queue is full the event is merged with the last one if possible,
+    /// or else the oldest event is dropped.
+    fn push(&self, event: Event) {
+        // Dropping the oldest event never fails
+        let _ = self.events.try_push_or_merge(event, Self::coalesce);
+    }
+
+    fn pop(&self) -> Option<Event> {
+        self.events.pop()
+    }
+}
+
+static EVENTS: EventQueue = EventQueue::new();
+
+pub fn push_event(event: Event) {
+    EVENTS.push(event);
+}
+
+/// Returns the oldest event that has not been read yet.
+pub fn poll_event() -> Option<Event> {
+    EVENTS.pop()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::drivers::hid::{keyboard::Scancode, report::Modifiers};
+
+    fn key(value: u8) -> Event {
+        Event::Key(KeyEvent {
+            scancode: Scancode::new(value),
+            pressed: true,
+            modifiers: Modifiers::default(),
+        })
+    }
+
+    fn motion(dx: i32, buttons: u32) -> Event {
+        Event::Pointer(PointerEvent::Relative {
+            dx,
+            dy: -dx,
+            wheel: 0,
+            buttons,
+        })
+    }
+
+    #[test]
+    fn events_are_drained_in_order() {
+        let queue = EventQueue::new();
+        assert_eq!(queue.pop(), None);
+
+        queue.push(key(4));
+        queue.push(motion(3, 0));
+        queue.push(key(5));
+
+        assert_eq!(queue.pop(), Some(key(4)));
+        assert_eq!(queue.pop(), Some(motion(3, 0)));
+        assert_eq!(queue.pop(), Some(key(5)));
+        assert_eq!(queue.pop(), None);
+    }
+
+    #[test]
+    fn overflow_coalesces_pointer_events() {
+        let queue = EventQueue::new();
+        for _ in 0..EventQueue::CAPACITY - 1 {
+            queue.push(key(4));
+        }
+        queue.push(motion(1, 0));
+        queue.push(motion(2, 0));
+        queue.push(motion(3, 0));
+        assert_eq!(queue.events.len(), EventQueue::CAPACITY);
+
+        let events: Vec<Event> = core::iter::from_fn(|| queue.pop()).collect();
+        assert_eq!(events.last(), Some(&motion(6, 0)));
+    }
+
+    #[test]
+    fn overflow_drops_oldest_event() {
+        let queue = EventQueue::new();
+        for i in 0..EventQueue::CAPACITY {
+            queue.push(key(i as u8));
+        }
+
+        // A button press cannot be merged with the previous motion
+        queue.push(motion(1, 1));
+        assert_eq!(queue.events.len(), EventQueue::CAPACITY);
+        assert_eq!(queue.pop(), Some(key(1)));
+
+        let events: Vec<Event> = core::iter::from_fn(|| queue.pop()).collect();
+        assert_eq!(events.last(), Some(&motion(1, 1)));
+    }
+}
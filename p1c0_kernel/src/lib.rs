@@ -7,9 +7,13 @@
 pub mod adt;
 pub mod arch;
 pub mod backtrace;
+#[cfg(any(test, feature = "benchmark"))]
+pub mod benchmark;
 pub mod boot_args;
+pub mod channel;
 pub mod chickens;
 mod collections;
+pub mod console;
 pub mod crc;
 pub mod drivers;
 pub mod elf;
@@ -18,6 +22,7 @@ pub mod filesystem;
 mod font;
 pub mod hash;
 pub mod init;
+pub mod input;
 pub mod log;
 pub mod macros;
 pub mod memory;
@@ -25,6 +30,8 @@ pub mod prelude;
 pub mod print;
 pub mod process;
 pub mod registers;
+pub mod selftest;
+pub mod shell;
 pub mod sync;
 pub mod syscall;
 pub mod thread;
@@ -33,11 +40,8 @@ pub mod thread;
 pub fn _print(args: core::fmt::Arguments) {
     match print::_print(args) {
         Ok(_) => {}
-        Err(print::Error::WriterLocked) => {
-            // TODO(javier-varez): How do we push this to the user?
-        }
-        Err(print::Error::BufferFull) => {
-            panic!("Print buffer full!");
+        Err(print::Error::WriterLocked | print::Error::BufferFull) => {
+            // Output backpressure is not fatal, the dropped output is counted in `print::stats`
         }
         Err(e) => {
             panic!("Print failed with error: {:?}", e);
@@ -3,6 +3,7 @@ pub mod address_space;
 pub mod kalloc;
 pub mod map;
 pub mod physical_page_allocator;
+pub mod shared;
 
 use crate::{
     arch::{
@@ -10,12 +11,17 @@ use crate::{
         mmu::{PAGE_BITS, PAGE_SIZE},
     },
     boot_args::get_boot_args,
+    prelude::*,
+    process,
     sync::spinlock::{SpinLock, SpinLockGuard},
+    thread,
 };
 use address::{Address, LogicalAddress, PhysicalAddress, VirtualAddress};
 use address_space::MemoryRange;
 use physical_page_allocator::{PhysicalMemoryRegion, PhysicalPageAllocator};
 
+use core::ops::Range;
+
 pub fn num_pages_from_bytes(bytes: usize) -> usize {
     if bytes & (PAGE_SIZE - 1) == 0 {
         bytes >> PAGE_BITS
@@ -24,12 +30,64 @@ pub fn num_pages_from_bytes(bytes: usize) -> usize {
     }
 }
 
+/// Splits a physical memory range into chunks that do not cross page boundaries. For each chunk
+/// `f` receives the page that contains it, the offset of the chunk inside the page and the range
+/// of the chunk relative to the start of the physical memory range.
+fn for_each_page_chunk(
+    pa: PhysicalAddress,
+    size_bytes: usize,
+    mut f: impl FnMut(PhysicalAddress, usize, Range<usize>),
+) {
+    let mut offset = 0;
+    while offset < size_bytes {
+        let current = unsafe { pa.offset(offset) };
+        let page = current.align_to_page();
+        let page_offset = current.as_usize() - page.as_usize();
+        let chunk_size = core::cmp::min(PAGE_SIZE - page_offset, size_bytes - offset);
+
+        f(page, page_offset, offset..offset + chunk_size);
+        offset += chunk_size;
+    }
+}
+
+/// Removes the fast map page when dropped, so that the temporary mapping is also restored if the
+/// user of the mapping panics.
+struct FastMapGuard<'a> {
+    address_space: &'a mut address_space::KernelAddressSpace,
+}
+
+impl<'a> FastMapGuard<'a> {
+    fn new(
+        address_space: &'a mut address_space::KernelAddressSpace,
+        pa: PhysicalAddress,
+        permissions: GlobalPermissions,
+    ) -> Self {
+        address_space
+            .fast_page_map(pa, permissions, Attributes::Normal)
+            .unwrap();
+        Self { address_space }
+    }
+}
+
+impl<'a> Drop for FastMapGuard<'a> {
+    fn drop(&mut self) {
+        self.address_space.fast_page_unmap().unwrap();
+    }
+}
+
 #[derive(Clone, Debug)]
 pub enum Error {
     ArchitectureSpecific(arch::mmu::Error),
     AddressSpaceError(address_space::Error),
     PageAllocationError(physical_page_allocator::Error),
     TranslationError,
+    /// The current process cannot access the memory given to `copy_from_user` or `copy_to_user`.
+    /// Syscalls report it as `EFAULT`.
+    BadUserAddress,
+    /// A string copied with `copy_user_cstr` does not end within its maximum length.
+    UserStringTooLong,
+    /// A string copied with `copy_user_cstr` is not valid UTF-8.
+    InvalidUserString,
 }
 
 #[derive(Debug, PartialEq, PartialOrd)]
@@ -56,26 +114,34 @@ impl From<physical_page_allocator::Error> for Error {
     }
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Attributes {
     Normal = 0,
     DevicenGnRnE = 1,
     DevicenGnRE = 2,
+    /// Normal memory that is not cached, where writes can be combined. Meant for memory written
+    /// sequentially in large chunks, like the framebuffer.
+    NormalNonCacheable = 3,
 }
 
 impl TryFrom<u64> for Attributes {
     type Error = ();
+
+    /// Inverse of `attributes as u64`, which is also the MAIR index of the attributes.
     fn try_from(value: u64) -> Result<Self, Self::Error> {
-        match value {
-            0 => Ok(Attributes::Normal),
-            1 => Ok(Attributes::DevicenGnRE),
-            2 => Ok(Attributes::DevicenGnRnE),
-            _ => Err(()),
-        }
+        [
+            Attributes::Normal,
+            Attributes::DevicenGnRnE,
+            Attributes::DevicenGnRE,
+            Attributes::NormalNonCacheable,
+        ]
+        .into_iter()
+        .find(|attributes| *attributes as u64 == value)
+        .ok_or(())
     }
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Permissions {
     None,
     RWX,
@@ -84,13 +150,48 @@ pub enum Permissions {
     RO,
 }
 
-#[derive(Clone, Copy, Debug)]
+impl Permissions {
+    pub fn is_writable(&self) -> bool {
+        matches!(self, Permissions::RW | Permissions::RWX)
+    }
+
+    #[must_use]
+    pub fn without_write(self) -> Self {
+        match self {
+            Permissions::RW => Permissions::RO,
+            Permissions::RWX => Permissions::RX,
+            perm => perm,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct GlobalPermissions {
     pub unprivileged: Permissions,
     pub privileged: Permissions,
 }
 
 impl GlobalPermissions {
+    pub fn is_writable(&self) -> bool {
+        self.privileged.is_writable() || self.unprivileged.is_writable()
+    }
+
+    #[must_use]
+    pub fn without_write(self) -> Self {
+        Self {
+            unprivileged: self.unprivileged.without_write(),
+            privileged: self.privileged.without_write(),
+        }
+    }
+
+    /// Checks that the permissions can be mapped by the MMU. Unprivileged code cannot be given
+    /// more access than privileged code, and privileged code cannot execute memory that
+    /// unprivileged code can write.
+    pub fn validate(&self) -> Result<(), Error> {
+        arch::mmu::validate_permissions(*self)?;
+        Ok(())
+    }
+
     pub fn new_only_privileged(privileged: Permissions) -> Self {
         Self {
             unprivileged: Permissions::None,
@@ -110,6 +211,39 @@ impl GlobalPermissions {
     }
 }
 
+/// IO mapping created by `MemoryManager::map_io`. The mapping is released when the guard is
+/// dropped, unless it is leaked to keep it for the rest of the kernel lifetime.
+#[must_use]
+pub struct IoMapping {
+    va: VirtualAddress,
+    size_bytes: usize,
+}
+
+impl IoMapping {
+    pub fn va(&self) -> VirtualAddress {
+        self.va
+    }
+
+    pub fn size_bytes(&self) -> usize {
+        self.size_bytes
+    }
+
+    /// Keeps the mapping forever and returns its address.
+    pub fn leak(self) -> VirtualAddress {
+        let va = self.va;
+        core::mem::forget(self);
+        va
+    }
+}
+
+impl Drop for IoMapping {
+    fn drop(&mut self) {
+        MemoryManager::instance()
+            .unmap_io(self.va)
+            .expect("The IO mapping is valid while the guard exists");
+    }
+}
+
 static MEMORY_MANAGER: SpinLock<MemoryManager> = SpinLock::new(MemoryManager::new());
 
 pub struct MemoryManager {
@@ -149,16 +283,9 @@ impl MemoryManager {
 
     fn add_default_mappings(&mut self) {
         let adt = crate::adt::get_adt().unwrap();
-        let chosen = adt.find_node("/chosen").expect("There is a chosen node");
-        let dram_base = chosen
-            .find_property("dram-base")
-            .and_then(|prop| prop.usize_value().ok())
-            .map(|addr| addr as *const u8)
-            .expect("There is no dram base");
-        let dram_size = chosen
-            .find_property("dram-size")
-            .and_then(|prop| prop.usize_value().ok())
-            .expect("There is no dram base");
+        let (dram_base, dram_size) =
+            map::dram_region(&adt).unwrap_or_else(|error| panic!("No DRAM region: {}", error));
+        let dram_base = dram_base.as_ptr();
 
         // Add initial identity mapping. To be removed after relocation.
         self.kernel_address_space
@@ -179,7 +306,7 @@ impl MemoryManager {
 
         // Map mmio ranges as defined in the ADT
         let root_address_cells = adt.find_node("/").and_then(|node| node.get_address_cells());
-        let node = adt.find_node("/arm-io").expect("There is not an arm-io");
+        let node = adt.expect_node("/arm-io");
         let range_iter = node.range_iter(root_address_cells);
         for range in range_iter {
             let mmio_region_base = range.get_parent_addr() as *const u8;
@@ -203,16 +330,9 @@ impl MemoryManager {
         let low_table = self.kernel_address_space.low_table();
 
         let adt = crate::adt::get_adt().unwrap();
-        let chosen = adt.find_node("/chosen").expect("There is a chosen node");
-        let dram_base = chosen
-            .find_property("dram-base")
-            .and_then(|prop| prop.usize_value().ok())
-            .map(|addr| addr as *const u8)
-            .expect("There is no dram base");
-        let dram_size = chosen
-            .find_property("dram-size")
-            .and_then(|prop| prop.usize_value().ok())
-            .expect("There is no dram base");
+        let (dram_base, dram_size) =
+            map::dram_region(&adt).unwrap_or_else(|error| panic!("No DRAM region: {}", error));
+        let dram_base = dram_base.as_ptr();
 
         low_table
             .unmap_region(
@@ -224,7 +344,7 @@ impl MemoryManager {
 
         // Unmap mmio ranges as defined in the ADT
         let root_address_cells = adt.find_node("/").and_then(|node| node.get_address_cells());
-        let node = adt.find_node("/arm-io").expect("There is not an arm-io");
+        let node = adt.expect_node("/arm-io");
         let range_iter = node.range_iter(root_address_cells);
         for range in range_iter {
             let mmio_region_base = range.get_parent_addr() as *const u8;
@@ -264,16 +384,21 @@ impl MemoryManager {
     pub unsafe fn late_init(&mut self) {
         // Make sure the global allocator is available after this, since we will need it
         kalloc::init();
+        log_debug!(
+            "Early allocator used {} of {} bytes",
+            arch::mmu::early_allocator_high_water_mark(),
+            arch::mmu::EARLY_ALLOCATOR_SIZE
+        );
+
         self.initialize_address_space()
             .expect("Kernel sections can be mapped");
 
         // Map ADT
         let boot_args = crate::boot_args::get_boot_args();
+        let device_tree_size = boot_args.device_tree_size();
         let device_tree =
-            boot_args.device_tree as usize - boot_args.virt_base + boot_args.phys_base;
-        let device_tree_size = boot_args.device_tree_size as usize;
-        let device_tree =
-            PhysicalAddress::from_unaligned_ptr(device_tree as *const _).align_to_page();
+            PhysicalAddress::from_unaligned_ptr(boot_args.device_tree_phys_addr() as *const _)
+                .align_to_page();
         self.kernel_address_space
             .high_table()
             .map_region(
@@ -316,6 +441,8 @@ impl MemoryManager {
         attributes: Attributes,
         permissions: Permissions,
     ) -> Result<(), Error> {
+        GlobalPermissions::new_only_privileged(permissions).validate()?;
+
         // Getting the logical range must succeed because we got ownership of the pages and this is
         // a logical mapping (one-to-one address)
         let logical_range = self
@@ -357,6 +484,8 @@ impl MemoryManager {
         attributes: Attributes,
         permissions: Permissions,
     ) -> Result<(), Error> {
+        GlobalPermissions::new_only_privileged(permissions).validate()?;
+
         // Request pages from the PhysicalPageAllocator
         let region = self.physical_page_allocator.request_pages(
             la.into_physical(),
@@ -391,13 +520,21 @@ impl MemoryManager {
     }
 
     // Maps memory in the virtual memory region (out of the logical region) as device memory with
-    // RW permissions
+    // RW permissions. If the physical range is already mapped by another IO range the existing
+    // mapping is shared, and it is only unmapped after all its users release it.
+    //
+    // The returned guard must not be dropped while the memory manager is locked.
     pub fn map_io(
         &mut self,
         name: &str,
         pa: PhysicalAddress,
         size_bytes: usize,
-    ) -> Result<VirtualAddress, Error> {
+    ) -> Result<IoMapping, Error> {
+        if let Some(va) = self.kernel_address_space.share_io_range(pa, size_bytes) {
+            log_debug!("IO range `{}` shares an existing mapping at {}", name, va);
+            return Ok(IoMapping { va, size_bytes });
+        }
+
         let va = self
             .kernel_address_space
             .allocate_io_range(name, pa, size_bytes)?;
@@ -413,12 +550,27 @@ impl MemoryManager {
             )
             .expect("MMU cannot map requested region");
 
-        Ok(va)
+        Ok(IoMapping { va, size_bytes })
+    }
+
+    /// Releases an IO mapping, unmapping it if this was its last user. Called when an `IoMapping`
+    /// is dropped.
+    fn unmap_io(&mut self, va: VirtualAddress) -> Result<(), Error> {
+        if let Some(range) = self.kernel_address_space.release_io_range(va)? {
+            self.kernel_address_space
+                .high_table()
+                .unmap_region(range.va, range.size_bytes)?;
+            arch::mmu::flush_tlb();
+        }
+        Ok(())
     }
 
+    /// Removes the range with the given name and unmaps it. Shared IO ranges are only unmapped
+    /// when their last user is removed.
     pub fn remove_mapping_by_name(&mut self, name: &str) -> Result<(), Error> {
-        let (table, range) = self.kernel_address_space.remove_range_by_name(name)?;
-        table.unmap_region(range.virtual_address(), range.size_bytes())?;
+        if let Some((table, range)) = self.kernel_address_space.remove_range_by_name(name)? {
+            table.unmap_region(range.virtual_address(), range.size_bytes())?;
+        }
 
         Ok(())
     }
@@ -462,6 +614,10 @@ impl MemoryManager {
         Ok(pmr)
     }
 
+    pub fn num_free_pages(&self) -> usize {
+        self.physical_page_allocator.num_free_pages()
+    }
+
     pub fn release_pages(
         &mut self,
         physical_memory_region: PhysicalMemoryRegion,
@@ -527,20 +683,60 @@ impl MemoryManager {
         permissions: GlobalPermissions,
         mut f: impl FnMut(VirtualAddress) -> T,
     ) -> T {
-        self.kernel_address_space
-            .fast_page_map(pa, permissions, Attributes::Normal)
-            .unwrap();
+        let _guard = FastMapGuard::new(&mut self.kernel_address_space, pa, permissions);
+        f(map::FASTMAP_PAGE)
+    }
 
-        let val = f(map::FASTMAP_PAGE);
+    /// Copies `data` to physical memory starting at `pa`, which does not need to be page aligned.
+    /// Pages are mapped one at a time through the fast map page.
+    pub fn write_physical(&mut self, pa: PhysicalAddress, data: &[u8]) {
+        for_each_page_chunk(pa, data.len(), |page, page_offset, range| {
+            self.do_with_fast_map(
+                page,
+                GlobalPermissions::new_only_privileged(Permissions::RW),
+                |va| unsafe {
+                    core::ptr::copy_nonoverlapping(
+                        data[range.clone()].as_ptr(),
+                        va.offset(page_offset).as_mut_ptr(),
+                        range.len(),
+                    );
+                },
+            );
+        });
+    }
 
-        self.kernel_address_space.fast_page_unmap().unwrap();
-        val
+    /// Fills `data` with the contents of physical memory starting at `pa`, which does not need to
+    /// be page aligned. Pages are mapped one at a time through the fast map page.
+    pub fn read_physical(&mut self, pa: PhysicalAddress, data: &mut [u8]) {
+        for_each_page_chunk(pa, data.len(), |page, page_offset, range| {
+            self.do_with_fast_map(
+                page,
+                GlobalPermissions::new_only_privileged(Permissions::RO),
+                |va| unsafe {
+                    core::ptr::copy_nonoverlapping(
+                        va.offset(page_offset).as_ptr(),
+                        data[range.clone()].as_mut_ptr(),
+                        range.len(),
+                    );
+                },
+            );
+        });
     }
 
     pub fn map_kernel_low_pages(&mut self) {
         arch::mmu::switch_process_translation_table(self.kernel_address_space.low_table());
     }
 
+    /// Returns the metadata of the kernel range with the given name.
+    pub fn find_range_by_name(&self, name: &str) -> Option<address_space::RangeInfo> {
+        self.kernel_address_space.find_range_by_name(name)
+    }
+
+    /// Returns the metadata of the kernel range that contains the given address.
+    pub fn find_range_containing(&self, va: VirtualAddress) -> Option<address_space::RangeInfo> {
+        self.kernel_address_space.find_range_containing(va)
+    }
+
     pub fn translate_kernel_address(&self, va: VirtualAddress) -> Result<PhysicalAddress, Error> {
         if !va.is_high_address() {
             return Err(Error::TranslationError);
@@ -549,3 +745,385 @@ impl MemoryManager {
         Ok(self.kernel_address_space.resolve_address(va)?)
     }
 }
+
+/// Checks that the current process can access the given memory, like `copy_from_user` and
+/// `copy_to_user` do before copying. Kernel threads issue syscalls with kernel pointers, which are
+/// trusted.
+pub fn check_user_access(user_ptr: *const u8, size_bytes: usize, write: bool) -> Result<(), Error> {
+    if size_bytes == 0 {
+        return Ok(());
+    }
+
+    if user_ptr.is_null() {
+        return Err(Error::BadUserAddress);
+    }
+
+    match thread::current_pid() {
+        Some(pid) => process::do_with_process(&pid, |process| {
+            check_process_access(process.address_space(), user_ptr, size_bytes, write)
+        }),
+        None => Ok(()),
+    }
+}
+
+fn check_process_access(
+    address_space: &mut address_space::ProcessAddressSpace,
+    user_ptr: *const u8,
+    size_bytes: usize,
+    write: bool,
+) -> Result<(), Error> {
+    address_space
+        .prepare_user_access(user_ptr as usize, size_bytes, write)
+        .map_err(|_| Error::BadUserAddress)
+}
+
+/// Fills `buffer` with the memory of the current process at `user_ptr`. Returns
+/// `Error::BadUserAddress` instead of faulting if the process cannot read all of it.
+pub fn copy_from_user(user_ptr: *const u8, buffer: &mut [u8]) -> Result<(), Error> {
+    if buffer.is_empty() {
+        return Ok(());
+    }
+
+    check_user_access(user_ptr, buffer.len(), false)?;
+    arch::with_user_access(|| unsafe {
+        core::ptr::copy_nonoverlapping(user_ptr, buffer.as_mut_ptr(), buffer.len());
+    });
+    Ok(())
+}
+
+/// Writes `data` to the memory of the current process at `user_ptr`. Returns
+/// `Error::BadUserAddress` instead of faulting if the process cannot write all of it.
+pub fn copy_to_user(user_ptr: *mut u8, data: &[u8]) -> Result<(), Error> {
+    if data.is_empty() {
+        return Ok(());
+    }
+
+    check_user_access(user_ptr, data.len(), true)?;
+    arch::with_user_access(|| unsafe {
+        core::ptr::copy_nonoverlapping(data.as_ptr(), user_ptr, data.len());
+    });
+    Ok(())
+}
+
+/// Reads a NUL-terminated string one page at a time with `copy`, so that no page after the end of
+/// the string is accessed.
+fn read_user_cstr(
+    addr: usize,
+    max_len: usize,
+    mut copy: impl FnMut(usize, &mut [u8]) -> Result<(), Error>,
+) -> Result<String, Error> {
+    // Up to `max_len` characters plus the NUL terminator
+    let max_bytes = max_len.saturating_add(1);
+
+    let mut bytes = vec![];
+    while bytes.len() < max_bytes {
+        let current = addr.checked_add(bytes.len()).ok_or(Error::BadUserAddress)?;
+        let chunk_len = (PAGE_SIZE - current % PAGE_SIZE).min(max_bytes - bytes.len());
+
+        let start = bytes.len();
+        bytes.resize(start + chunk_len, 0);
+        copy(current, &mut bytes[start..])?;
+
+        if let Some(nul) = bytes[start..].iter().position(|byte| *byte == 0) {
+            bytes.truncate(start + nul);
+            return String::from_utf8(bytes).map_err(|_| Error::InvalidUserString);
+        }
+    }
+
+    Err(Error::UserStringTooLong)
+}
+
+/// Copies a NUL-terminated string of at most `max_len` bytes, not counting the terminator, from
+/// the current process. Fails without faulting if the string runs into memory that the process
+/// cannot read.
+pub fn copy_user_cstr(user_ptr: *const u8, max_len: usize) -> Result<String, Error> {
+    read_user_cstr(user_ptr as usize, max_len, |addr, buffer| {
+        copy_from_user(addr as *const u8, buffer)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const MEMORY_BASE: usize = 0x80000000;
+
+    #[test]
+    fn global_permissions_validation() {
+        use Permissions::*;
+
+        const ALL: [Permissions; 5] = [None, RWX, RW, RX, RO];
+        const VALID: [(Permissions, Permissions); 10] = [
+            (RW, None),
+            (RWX, None),
+            (RX, None),
+            (RO, None),
+            (RW, RW),
+            (RW, RWX),
+            (RX, RX),
+            (RX, RO),
+            (RO, RX),
+            (RO, RO),
+        ];
+
+        for privileged in ALL {
+            for unprivileged in ALL {
+                let permissions = GlobalPermissions {
+                    privileged,
+                    unprivileged,
+                };
+                let valid = VALID.contains(&(privileged, unprivileged));
+                assert_eq!(
+                    permissions.validate().is_ok(),
+                    valid,
+                    "{:?} should be {}",
+                    permissions,
+                    if valid { "valid" } else { "invalid" }
+                );
+            }
+        }
+
+        // Permissions given to processes are always valid, except when there are none
+        for permissions in [RWX, RW, RX, RO] {
+            assert!(GlobalPermissions::new_for_process(permissions)
+                .validate()
+                .is_ok());
+        }
+        assert!(matches!(
+            GlobalPermissions::new_for_process(None).validate(),
+            Err(Error::ArchitectureSpecific(
+                arch::mmu::Error::InvalidPermissions
+            ))
+        ));
+    }
+
+    // Emulates physical memory with a buffer, copying in the same way as `write_physical` and
+    // `read_physical`, but mapping each page to its location in the buffer.
+    fn write_memory(memory: &mut [u8], pa: PhysicalAddress, data: &[u8]) {
+        for_each_page_chunk(pa, data.len(), |page, page_offset, range| {
+            assert!(page.is_page_aligned());
+            assert!(page_offset + range.len() <= PAGE_SIZE);
+            let start = page.as_usize() - MEMORY_BASE + page_offset;
+            memory[start..start + range.len()].copy_from_slice(&data[range]);
+        });
+    }
+
+    fn read_memory(memory: &[u8], pa: PhysicalAddress, data: &mut [u8]) {
+        for_each_page_chunk(pa, data.len(), |page, page_offset, range| {
+            assert!(page.is_page_aligned());
+            assert!(page_offset + range.len() <= PAGE_SIZE);
+            let start = page.as_usize() - MEMORY_BASE + page_offset;
+            data[range.clone()].copy_from_slice(&memory[start..start + range.len()]);
+        });
+    }
+
+    #[test]
+    fn page_chunks() {
+        let pa = PhysicalAddress::from_unaligned_ptr((MEMORY_BASE + 0x100) as *const _);
+        let mut chunks = vec![];
+        for_each_page_chunk(pa, 2 * PAGE_SIZE, |page, page_offset, range| {
+            chunks.push((page.as_usize(), page_offset, range));
+        });
+        assert_eq!(
+            chunks,
+            vec![
+                (MEMORY_BASE, 0x100, 0..PAGE_SIZE - 0x100),
+                (
+                    MEMORY_BASE + PAGE_SIZE,
+                    0,
+                    PAGE_SIZE - 0x100..2 * PAGE_SIZE - 0x100
+                ),
+                (
+                    MEMORY_BASE + 2 * PAGE_SIZE,
+                    0,
+                    2 * PAGE_SIZE - 0x100..2 * PAGE_SIZE
+                ),
+            ]
+        );
+
+        let mut num_chunks = 0;
+        for_each_page_chunk(pa, 0, |_, _, _| num_chunks += 1);
+        assert_eq!(num_chunks, 0);
+    }
+
+    #[test]
+    fn write_and_read_back_multiple_pages() {
+        let mut memory = vec![0u8; 4 * PAGE_SIZE];
+        let data: Vec<u8> = (0..2 * PAGE_SIZE + 0x345).map(|i| i as u8).collect();
+
+        let pa = PhysicalAddress::from_unaligned_ptr((MEMORY_BASE + 0x123) as *const _);
+        write_memory(&mut memory, pa, &data);
+        assert!(memory[..0x123].iter().all(|byte| *byte == 0));
+        assert!(memory[0x123 + data.len()..].iter().all(|byte| *byte == 0));
+
+        let mut read_back = vec![0u8; data.len()];
+        read_memory(&memory, pa, &mut read_back);
+        assert_eq!(read_back, data);
+    }
+
+    #[test]
+    fn copy_user_memory() {
+        // Without a current process the pointers come from kernel threads
+        let source = [1u8, 2, 3, 4];
+        let mut buffer = [0u8; 4];
+        copy_from_user(source.as_ptr(), &mut buffer).unwrap();
+        assert_eq!(buffer, source);
+
+        let mut destination = [0u8; 4];
+        copy_to_user(destination.as_mut_ptr(), &[5, 6, 7, 8]).unwrap();
+        assert_eq!(destination, [5, 6, 7, 8]);
+
+        assert!(matches!(
+            copy_from_user(core::ptr::null(), &mut buffer),
+            Err(Error::BadUserAddress)
+        ));
+        assert!(matches!(
+            copy_to_user(core::ptr::null_mut(), &[1]),
+            Err(Error::BadUserAddress)
+        ));
+
+        // Nothing is accessed for empty copies
+        copy_from_user(core::ptr::null(), &mut []).unwrap();
+        copy_to_user(core::ptr::null_mut(), &[]).unwrap();
+    }
+
+    #[test]
+    fn process_user_access() {
+        use address_space::ProcessAddressSpace;
+
+        arch::mmu::initialize_for_test();
+
+        let mut address_space = ProcessAddressSpace::new();
+        let data_va = VirtualAddress::try_from_ptr(0x10000000 as *const _).unwrap();
+        let data_pa = PhysicalAddress::try_from_ptr(0x80000000 as *const _).unwrap();
+        address_space
+            .map_section(
+                ".data",
+                data_va,
+                PhysicalMemoryRegion::new(data_pa, 2),
+                2 * PAGE_SIZE,
+                GlobalPermissions::new_for_process(Permissions::RW),
+            )
+            .unwrap();
+        let rodata_va = VirtualAddress::try_from_ptr(0x20000000 as *const _).unwrap();
+        let rodata_pa = PhysicalAddress::try_from_ptr(0x90000000 as *const _).unwrap();
+        address_space
+            .map_section(
+                ".rodata",
+                rodata_va,
+                PhysicalMemoryRegion::new(rodata_pa, 1),
+                PAGE_SIZE,
+                GlobalPermissions::new_for_process(Permissions::RO),
+            )
+            .unwrap();
+
+        let data = 0x10000000 as *const u8;
+        let rodata = 0x20000000 as *const u8;
+        assert!(check_process_access(&mut address_space, data, 2 * PAGE_SIZE, true).is_ok());
+        assert!(check_process_access(&mut address_space, rodata, 16, false).is_ok());
+
+        // Read-only, past the end of the mapping, unmapped and kernel memory
+        for (ptr, size_bytes, write) in [
+            (rodata, 16, true),
+            (data, 2 * PAGE_SIZE + 1, false),
+            (0x30000000 as *const u8, 16, false),
+            (0xFFFF000000000000 as *const u8, 16, false),
+        ] {
+            assert!(matches!(
+                check_process_access(&mut address_space, ptr, size_bytes, write),
+                Err(Error::BadUserAddress)
+            ));
+        }
+    }
+
+    /// Memory readable by `read_user_cstr` in tests, starting at `base`. The rest is unmapped.
+    fn user_memory(base: usize, data: &[u8]) -> impl FnMut(usize, &mut [u8]) -> Result<(), Error> {
+        let data = data.to_vec();
+        move |addr, buffer| {
+            let offset = addr
+                .checked_sub(base)
+                .filter(|offset| offset + buffer.len() <= data.len())
+                .ok_or(Error::BadUserAddress)?;
+            buffer.copy_from_slice(&data[offset..offset + buffer.len()]);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn user_cstr() {
+        let base = 0x10000000;
+        let mut data = b"hello\0garbage".to_vec();
+        data.resize(PAGE_SIZE, 0);
+
+        assert_eq!(
+            read_user_cstr(base, 64, user_memory(base, &data)).unwrap(),
+            "hello"
+        );
+        // The maximum length does not include the terminator
+        assert_eq!(
+            read_user_cstr(base, 5, user_memory(base, &data)).unwrap(),
+            "hello"
+        );
+        assert_eq!(
+            read_user_cstr(base + 5, 0, user_memory(base, &data)).unwrap(),
+            ""
+        );
+
+        // The real helper copies from kernel memory when there is no current process
+        assert_eq!(copy_user_cstr(data.as_ptr(), 64).unwrap(), "hello");
+    }
+
+    #[test]
+    fn user_cstr_too_long() {
+        let base = 0x10000000;
+        let data = b"hello\0".to_vec();
+
+        assert!(matches!(
+            read_user_cstr(base, 4, user_memory(base, &data)),
+            Err(Error::UserStringTooLong)
+        ));
+        assert!(matches!(
+            read_user_cstr(base, 0, user_memory(base, &data)),
+            Err(Error::UserStringTooLong)
+        ));
+    }
+
+    #[test]
+    fn user_cstr_across_pages() {
+        let base = 0x10000000;
+        let start = base + PAGE_SIZE - 3;
+
+        // The string continues in the second page
+        let mut data = vec![b'x'; 2 * PAGE_SIZE];
+        data[PAGE_SIZE - 3..PAGE_SIZE + 3].copy_from_slice(b"abcdef");
+        data[PAGE_SIZE + 3] = 0;
+        assert_eq!(
+            read_user_cstr(start, 64, user_memory(base, &data)).unwrap(),
+            "abcdef"
+        );
+
+        // The second page is not mapped
+        assert!(matches!(
+            read_user_cstr(start, 64, user_memory(base, &data[..PAGE_SIZE])),
+            Err(Error::BadUserAddress)
+        ));
+
+        // The terminator is found in the first page, so the second one is never read
+        data[PAGE_SIZE - 1] = 0;
+        assert_eq!(
+            read_user_cstr(start, 64, user_memory(base, &data[..PAGE_SIZE])).unwrap(),
+            "ab"
+        );
+    }
+
+    #[test]
+    fn user_cstr_invalid_utf8() {
+        let base = 0x10000000;
+        let mut data = vec![0xff, 0xfe, 0];
+        data.resize(PAGE_SIZE, 0);
+        assert!(matches!(
+            read_user_cstr(base, 64, user_memory(base, &data)),
+            Err(Error::InvalidUserString)
+        ));
+    }
+}
@@ -10,20 +10,33 @@ use crate::{
     prelude::*,
 };
 
-use core::str::FromStr;
+use core::{fmt::Write, str::FromStr};
 
 use heapless::String;
 
 const MAX_NAME_LENGTH: usize = 32;
 
+/// Region of the process address space where anonymous mappings are placed. It ends right where
+/// the stack of the process begins.
+const ANONYMOUS_BASE: usize = 0xE00000000000;
+const ANONYMOUS_END: usize = 0xF00000000000;
+const ANONYMOUS_RANGE_PREFIX: &str = "anon@";
+/// Addresses translated by the address table of a process are below this limit.
+const USER_ADDRESS_LIMIT: usize = 1 << 48;
+const SHARED_RANGE_PREFIX: &str = "shm:";
+
 #[derive(Clone, Debug)]
 pub enum Error {
     ArchSpecificError(mmu::Error),
     MemoryRangeNotFound(String<MAX_NAME_LENGTH>),
     MemoryRangeAlreadyExists(String<MAX_NAME_LENGTH>),
-    MemoryRangeOverlaps(String<MAX_NAME_LENGTH>),
+    Overlap {
+        existing_name: String<MAX_NAME_LENGTH>,
+        new_name: String<MAX_NAME_LENGTH>,
+    },
     NameTooLong,
     InvalidAddress,
+    AddressSpaceExhausted,
 }
 
 impl From<mmu::Error> for Error {
@@ -32,14 +45,55 @@ impl From<mmu::Error> for Error {
     }
 }
 
+/// Memory that is no longer mapped after removing ranges from a process address space.
+#[derive(Debug)]
+pub enum ReleasedMemory {
+    /// Physical memory owned by the process, which can be freed.
+    Owned(PhysicalMemoryRegion),
+    /// A mapping of the shared memory region with the given name.
+    Shared(String<MAX_NAME_LENGTH>),
+}
+
+impl From<VirtualMemoryRange> for ReleasedMemory {
+    fn from(range: VirtualMemoryRange) -> Self {
+        match range.ownership {
+            Ownership::Shared(name) => ReleasedMemory::Shared(name),
+            Ownership::Owned | Ownership::Anonymous => ReleasedMemory::Owned(range.pmr),
+        }
+    }
+}
+
+/// Who owns the physical memory of a virtual range, decided when the range is mapped.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(super) enum Ownership {
+    /// Memory owned by the address space, mapped at a fixed address like ELF sections.
+    Owned,
+    /// Memory owned by the address space and mapped with `map_anonymous`, which can be unmapped.
+    Anonymous,
+    /// A mapping of the shared memory region with the given name, created with `map_shared`.
+    Shared(String<MAX_NAME_LENGTH>),
+}
+
+/// Metadata of a named range of an address space.
+#[derive(Clone, Debug)]
+pub struct RangeInfo {
+    pub name: String<MAX_NAME_LENGTH>,
+    pub va: VirtualAddress,
+    pub size_bytes: usize,
+    pub attributes: Attributes,
+    pub permissions: GlobalPermissions,
+}
+
 pub(super) struct VirtualMemoryRange {
     pub va: VirtualAddress,
     pub size_bytes: usize,
     pub name: String<MAX_NAME_LENGTH>,
-    pub _attributes: Attributes,
-    pub _permissions: GlobalPermissions,
-    pub _pmr: PhysicalMemoryRegion,
-    // We can later add operations based on backed descriptors here
+    pub attributes: Attributes,
+    pub permissions: GlobalPermissions,
+    pub pmr: PhysicalMemoryRegion,
+    pub ownership: Ownership,
+    /// One entry per page when dirty tracking is enabled for the range.
+    pub dirty_pages: Option<Vec<bool>>,
 }
 
 pub(super) struct LogicalMemoryRange {
@@ -56,6 +110,16 @@ pub(super) struct MMIORange {
     pub pa: PhysicalAddress,
     pub size_bytes: usize,
     pub name: String<32>,
+    /// Number of users of the range. It is unmapped when the last one releases it.
+    pub users: usize,
+}
+
+impl MMIORange {
+    fn contains_physical(&self, pa: PhysicalAddress, size_bytes: usize) -> bool {
+        let start = self.pa.as_usize();
+        let end = start + self.size_bytes;
+        pa.as_usize() >= start && pa.as_usize() + size_bytes <= end
+    }
 }
 
 pub(super) enum GenericMemoryRange {
@@ -101,6 +165,43 @@ pub trait MemoryRange {
     }
 }
 
+impl LogicalMemoryRange {
+    fn info(&self) -> RangeInfo {
+        RangeInfo {
+            name: self.name.clone(),
+            va: self.virtual_address(),
+            size_bytes: self.size_bytes,
+            attributes: self.attributes,
+            permissions: GlobalPermissions::new_only_privileged(self.permissions),
+        }
+    }
+}
+
+impl VirtualMemoryRange {
+    fn info(&self) -> RangeInfo {
+        RangeInfo {
+            name: self.name.clone(),
+            va: self.va,
+            size_bytes: self.size_bytes,
+            attributes: self.attributes,
+            permissions: self.permissions,
+        }
+    }
+}
+
+impl MMIORange {
+    fn info(&self) -> RangeInfo {
+        // IO ranges are always mapped by `MemoryManager::map_io` with these attributes
+        RangeInfo {
+            name: self.name.clone(),
+            va: self.va,
+            size_bytes: self.size_bytes,
+            attributes: Attributes::DevicenGnRnE,
+            permissions: GlobalPermissions::new_only_privileged(Permissions::RW),
+        }
+    }
+}
+
 impl MemoryRange for LogicalMemoryRange {
     fn virtual_address(&self) -> VirtualAddress {
         self.la.into_virtual()
@@ -157,7 +258,6 @@ pub(super) struct KernelAddressSpace {
     virtual_ranges: Vec<VirtualMemoryRange>,
     logical_ranges: Vec<LogicalMemoryRange>,
     mmio_ranges: Vec<MMIORange>,
-    mmio_offset: usize,
 }
 
 impl KernelAddressSpace {
@@ -168,17 +268,21 @@ impl KernelAddressSpace {
             virtual_ranges: vec![],
             logical_ranges: vec![],
             mmio_ranges: vec![],
-            mmio_offset: 0,
         }
     }
 
-    fn check_overlaps(&self, va: VirtualAddress, size_bytes: usize) -> Result<(), Error> {
-        if let Some(range) = self
-            .logical_ranges
-            .iter()
-            .find(|range| range.overlaps(va, size_bytes))
-        {
-            return Err(Error::MemoryRangeOverlaps(range.name.clone()));
+    /// Fails if the given VA range overlaps any logical, virtual or IO range of the address space.
+    fn check_overlaps(
+        &self,
+        name: &str,
+        va: VirtualAddress,
+        size_bytes: usize,
+    ) -> Result<(), Error> {
+        if let Some(range) = self.find_range(|_, range| range.overlaps(va, size_bytes)) {
+            return Err(Error::Overlap {
+                existing_name: range.name,
+                new_name: String::from_str(name).map_err(|_| Error::NameTooLong)?,
+            });
         }
 
         Ok(())
@@ -206,6 +310,39 @@ impl KernelAddressSpace {
         ))
     }
 
+    fn find_range(&self, predicate: impl Fn(&str, &dyn MemoryRange) -> bool) -> Option<RangeInfo> {
+        if let Some(range) = self
+            .logical_ranges
+            .iter()
+            .find(|range| predicate(&range.name, *range))
+        {
+            return Some(range.info());
+        }
+
+        if let Some(range) = self
+            .virtual_ranges
+            .iter()
+            .find(|range| predicate(&range.name, *range))
+        {
+            return Some(range.info());
+        }
+
+        self.mmio_ranges
+            .iter()
+            .find(|range| predicate(&range.name, *range))
+            .map(MMIORange::info)
+    }
+
+    /// Returns the metadata of the range with the given name.
+    pub fn find_range_by_name(&self, name: &str) -> Option<RangeInfo> {
+        self.find_range(|range_name, _| range_name == name)
+    }
+
+    /// Returns the metadata of the range that contains the given address.
+    pub fn find_range_containing(&self, va: VirtualAddress) -> Option<RangeInfo> {
+        self.find_range(|_, range| range.overlaps(va, 1))
+    }
+
     pub fn add_logical_range(
         &mut self,
         name: &str,
@@ -223,7 +360,7 @@ impl KernelAddressSpace {
             permissions
         );
 
-        self.check_overlaps(la.into_virtual(), size_bytes)?;
+        self.check_overlaps(name, la.into_virtual(), size_bytes)?;
 
         if self.find_by_name(name).is_ok() {
             return Err(Error::MemoryRangeAlreadyExists(name.into()));
@@ -248,22 +385,16 @@ impl KernelAddressSpace {
         pa: PhysicalAddress,
         size_bytes: usize,
     ) -> Result<VirtualAddress, Error> {
-        let num_pages = num_pages_from_bytes(size_bytes);
-
-        if self.mmio_offset + num_pages * PAGE_SIZE > MMIO_SIZE {
-            panic!("MMIO Range is exhausted!");
-        }
-
-        let offset = self.mmio_offset;
-        let va = unsafe { MMIO_BASE.offset(offset) };
-
-        self.mmio_offset += num_pages * PAGE_SIZE;
+        let va = self
+            .find_free_io_range(size_bytes)
+            .expect("MMIO Range is exhausted!");
 
         let range = MMIORange {
             va,
             pa,
             name: String::from_str(name).map_err(|_| Error::NameTooLong)?,
             size_bytes,
+            users: 1,
         };
         self.mmio_ranges.push(range);
 
@@ -277,10 +408,79 @@ impl KernelAddressSpace {
         Ok(va)
     }
 
+    /// Returns the lowest virtual address of the IO region with `size_bytes` unused after it, so
+    /// that the addresses of released IO ranges are reused.
+    fn find_free_io_range(&self, size_bytes: usize) -> Option<VirtualAddress> {
+        let size_bytes = num_pages_from_bytes(size_bytes) * PAGE_SIZE;
+        let mut used: Vec<(usize, usize)> = self
+            .mmio_ranges
+            .iter()
+            .map(|range| {
+                let start = range.va.offset_from(MMIO_BASE) as usize;
+                (
+                    start,
+                    start + num_pages_from_bytes(range.size_bytes) * PAGE_SIZE,
+                )
+            })
+            .collect();
+        used.sort_unstable();
+
+        let mut offset = 0;
+        for (start, end) in used {
+            if start - offset >= size_bytes {
+                break;
+            }
+            offset = end;
+        }
+
+        if offset + size_bytes > MMIO_SIZE {
+            return None;
+        }
+        Some(unsafe { MMIO_BASE.offset(offset) })
+    }
+
+    /// Looks for an IO range that already maps the given physical range. If there is one, it
+    /// gets a new user and the virtual address of `pa` inside it is returned.
+    pub fn share_io_range(
+        &mut self,
+        pa: PhysicalAddress,
+        size_bytes: usize,
+    ) -> Option<VirtualAddress> {
+        let range = self
+            .mmio_ranges
+            .iter_mut()
+            .find(|range| range.contains_physical(pa, size_bytes))?;
+        range.users += 1;
+
+        let offset = pa.as_usize() - range.pa.as_usize();
+        Some(unsafe { range.va.offset(offset) })
+    }
+
+    /// Drops a user of the IO range that contains `va`. The range is removed and returned once it
+    /// has no users left, so that it can be unmapped. Its virtual addresses can then be handed out
+    /// again by `allocate_io_range`.
+    pub fn release_io_range(&mut self, va: VirtualAddress) -> Result<Option<MMIORange>, Error> {
+        let index = self
+            .mmio_ranges
+            .iter()
+            .position(|range| range.overlaps(va, 1))
+            .ok_or(Error::InvalidAddress)?;
+
+        let range = &mut self.mmio_ranges[index];
+        range.users -= 1;
+        if range.users != 0 {
+            return Ok(None);
+        }
+        Ok(Some(self.mmio_ranges.remove(index)))
+    }
+
+    /// Removes the range with the given name, returning it along with the table it is mapped in.
+    /// IO ranges are shared, so for them this drops a user and only returns the range once it has
+    /// no users left, like `release_io_range`.
     pub fn remove_range_by_name(
         &mut self,
         name: &str,
-    ) -> Result<(&mut LevelTable, GenericMemoryRange), Error> {
+    ) -> Result<Option<(&mut LevelTable, GenericMemoryRange)>, Error> {
         if let Some((index, _range)) = self
             .logical_ranges
             .iter_mut()
@@ -288,7 +488,7 @@ impl KernelAddressSpace {
             .find(|(_idx, range)| range.name == name)
         {
             let range = self.logical_ranges.remove(index);
-            return Ok((&mut self.high_address_table, range.into()));
+            return Ok(Some((&mut self.high_address_table, range.into())));
         }
 
         if let Some((index, _range)) = self
@@ -298,17 +498,17 @@ impl KernelAddressSpace {
             .find(|(_idx, range)| range.name == name)
         {
             let range = self.virtual_ranges.remove(index);
-            return Ok((&mut self.high_address_table, range.into()));
+            return Ok(Some((&mut self.high_address_table, range.into())));
         }
 
-        if let Some((index, _range)) = self
+        if let Some(va) = self
             .mmio_ranges
-            .iter_mut()
-            .enumerate()
-            .find(|(_idx, range)| range.name == name)
+            .iter()
+            .find(|range| range.name == name)
+            .map(|range| range.va)
         {
-            let range = self.mmio_ranges.remove(index);
-            return Ok((&mut self.high_address_table, range.into()));
+            let range = self.release_io_range(va)?;
+            return Ok(range.map(|range| (&mut self.high_address_table, range.into())));
         }
 
         Err(Error::MemoryRangeNotFound(
@@ -375,6 +575,16 @@ impl KernelAddressSpace {
     }
 }
 
+/// Returns `size_bytes` rounded up to whole pages if the memory starting at `va` with that size is
+/// within user memory.
+pub fn user_range_size(va: VirtualAddress, size_bytes: usize) -> Option<usize> {
+    let size_bytes = num_pages_from_bytes(size_bytes).checked_mul(PAGE_SIZE)?;
+    va.as_usize()
+        .checked_add(size_bytes)
+        .filter(|end| *end <= USER_ADDRESS_LIMIT)?;
+    Some(size_bytes)
+}
+
 pub struct ProcessAddressSpace {
     address_table: Box<LevelTable>,
     // FIXME(javier-varez): Using vec here is most likely not a good idea for performance reasons.
@@ -396,13 +606,21 @@ impl ProcessAddressSpace {
         Self::default()
     }
 
-    fn check_overlaps(&self, va: VirtualAddress, size_bytes: usize) -> Result<(), Error> {
+    fn check_overlaps(
+        &self,
+        name: &str,
+        va: VirtualAddress,
+        size_bytes: usize,
+    ) -> Result<(), Error> {
         if let Some(range) = self
             .memory_ranges
             .iter()
             .find(|range| range.overlaps(va, size_bytes))
         {
-            return Err(Error::MemoryRangeOverlaps(range.name.clone()));
+            return Err(Error::Overlap {
+                existing_name: range.name.clone(),
+                new_name: String::from_str(name).map_err(|_| Error::NameTooLong)?,
+            });
         }
 
         Ok(())
@@ -430,8 +648,9 @@ impl ProcessAddressSpace {
         size_bytes: usize,
         attributes: Attributes,
         permissions: GlobalPermissions,
+        ownership: Ownership,
     ) -> Result<(), Error> {
-        self.check_overlaps(va, size_bytes)?;
+        self.check_overlaps(name, va, size_bytes)?;
 
         if self.find_by_name(name).is_ok() {
             return Err(Error::MemoryRangeAlreadyExists(name.into()));
@@ -441,9 +660,11 @@ impl ProcessAddressSpace {
             va,
             name: String::from_str(name).map_err(|_| Error::NameTooLong)?,
             size_bytes,
-            _attributes: attributes,
-            _permissions: permissions,
-            _pmr: pmr,
+            attributes,
+            permissions,
+            pmr,
+            ownership,
+            dirty_pages: None,
         };
         self.memory_ranges.push(memory_range);
 
@@ -461,11 +682,990 @@ impl ProcessAddressSpace {
         pmr: PhysicalMemoryRegion,
         size_bytes: usize,
         permissions: GlobalPermissions,
+    ) -> Result<(), Error> {
+        self.map_range(name, va, pmr, size_bytes, permissions, Ownership::Owned)
+    }
+
+    fn map_range(
+        &mut self,
+        name: &str,
+        va: VirtualAddress,
+        pmr: PhysicalMemoryRegion,
+        size_bytes: usize,
+        permissions: GlobalPermissions,
+        ownership: Ownership,
     ) -> Result<(), Error> {
         let pa = pmr.base_address();
         self.address_table
             .map_region(va, pa, size_bytes, Attributes::Normal, permissions)
             .unwrap();
-        self.add_virtual_range(name, va, pmr, size_bytes, Attributes::Normal, permissions)
+        self.add_virtual_range(
+            name,
+            va,
+            pmr,
+            size_bytes,
+            Attributes::Normal,
+            permissions,
+            ownership,
+        )
+    }
+
+    /// Finds the lowest free virtual range of the given size for anonymous mappings.
+    fn find_anonymous_va(&self, size_bytes: usize) -> Option<VirtualAddress> {
+        let mut candidate = ANONYMOUS_BASE;
+        while candidate + size_bytes <= ANONYMOUS_END {
+            let va = VirtualAddress::try_from_ptr(candidate as *const _).ok()?;
+            match self
+                .memory_ranges
+                .iter()
+                .find(|range| range.overlaps(va, size_bytes))
+            {
+                Some(range) => {
+                    let end = range.end_virtual_address().as_usize();
+                    candidate = num_pages_from_bytes(end) * PAGE_SIZE;
+                }
+                None => return Some(va),
+            }
+        }
+        None
+    }
+
+    /// Maps the given physical memory at a virtual address chosen by the address space and returns
+    /// it. These mappings can later be removed with `unmap`.
+    pub fn map_anonymous(
+        &mut self,
+        pmr: PhysicalMemoryRegion,
+        size_bytes: usize,
+        permissions: GlobalPermissions,
+    ) -> Result<VirtualAddress, Error> {
+        let va = self
+            .find_anonymous_va(size_bytes)
+            .ok_or(Error::AddressSpaceExhausted)?;
+
+        let mut name: String<MAX_NAME_LENGTH> = String::new();
+        write!(name, "{}{:x}", ANONYMOUS_RANGE_PREFIX, va.as_usize())
+            .map_err(|_| Error::NameTooLong)?;
+
+        self.map_range(
+            &name,
+            va,
+            pmr,
+            size_bytes,
+            permissions,
+            Ownership::Anonymous,
+        )?;
+        Ok(va)
+    }
+
+    /// Maps the shared memory region with the given name like `map_anonymous`. The address space
+    /// does not own the physical memory of the region, which is reported as shared when unmapped.
+    /// A region can only be mapped once in each address space.
+    pub fn map_shared(
+        &mut self,
+        name: &str,
+        pmr: PhysicalMemoryRegion,
+        size_bytes: usize,
+        permissions: GlobalPermissions,
+    ) -> Result<VirtualAddress, Error> {
+        let mut range_name: String<MAX_NAME_LENGTH> = String::new();
+        write!(range_name, "{}{}", SHARED_RANGE_PREFIX, name).map_err(|_| Error::NameTooLong)?;
+        if self.find_by_name(&range_name).is_ok() {
+            return Err(Error::MemoryRangeAlreadyExists(range_name));
+        }
+
+        let va = self
+            .find_anonymous_va(size_bytes)
+            .ok_or(Error::AddressSpaceExhausted)?;
+        let ownership = Ownership::Shared(String::from_str(name).map_err(|_| Error::NameTooLong)?);
+        self.map_range(&range_name, va, pmr, size_bytes, permissions, ownership)?;
+        Ok(va)
+    }
+
+    /// Removes a mapping created with `map_anonymous` or `map_shared`. The mapping needs to be
+    /// removed as a whole, so `va` and `size_bytes` must match the ones of the mapping.
+    pub fn unmap(
+        &mut self,
+        va: VirtualAddress,
+        size_bytes: usize,
+    ) -> Result<ReleasedMemory, Error> {
+        let index = self
+            .memory_ranges
+            .iter()
+            .position(|range| {
+                range.va == va
+                    && range.ownership != Ownership::Owned
+                    && num_pages_from_bytes(range.size_bytes) == num_pages_from_bytes(size_bytes)
+            })
+            .ok_or(Error::InvalidAddress)?;
+
+        let range = self.memory_ranges.remove(index);
+        self.address_table
+            .unmap_region(range.va, range.size_bytes)?;
+        mmu::flush_tlb();
+        Ok(range.into())
+    }
+
+    /// Checks that userspace can access the given memory, so that the kernel can access it on its
+    /// behalf without faulting. Pages with a clear access flag or tracked for writes are resolved
+    /// here, as if userspace had accessed them first.
+    pub fn prepare_user_access(
+        &mut self,
+        addr: usize,
+        size_bytes: usize,
+        write: bool,
+    ) -> Result<(), Error> {
+        let end = addr
+            .checked_add(size_bytes)
+            .filter(|end| *end <= USER_ADDRESS_LIMIT)
+            .ok_or(Error::InvalidAddress)?;
+
+        let mut page = addr & !(PAGE_SIZE - 1);
+        while page < end {
+            let va = VirtualAddress::new_unaligned(page as *const _);
+            let mapping = self.address_table.query(va).ok_or(Error::InvalidAddress)?;
+            let permissions = mapping.permissions.unprivileged;
+
+            if permissions == Permissions::None
+                || (!mapping.accessed && !self.handle_access_flag_fault(va))
+                || (write && !permissions.is_writable() && !self.handle_write_fault(va))
+            {
+                return Err(Error::InvalidAddress);
+            }
+
+            page += PAGE_SIZE;
+        }
+        Ok(())
+    }
+
+    /// Returns the metadata of every range of the address space, in no particular order.
+    pub fn iter_sections(&self) -> impl Iterator<Item = RangeInfo> + '_ {
+        self.memory_ranges.iter().map(VirtualMemoryRange::info)
+    }
+
+    /// Changes the permissions of mapped pages. The pages must be within a single range, which
+    /// must not be tracking dirty pages. The permissions of the range are only updated if all of
+    /// it is protected. Returns the size of the protected memory, rounded up to whole pages.
+    pub fn protect(
+        &mut self,
+        va: VirtualAddress,
+        size_bytes: usize,
+        permissions: GlobalPermissions,
+    ) -> Result<usize, Error> {
+        if !va.is_page_aligned() || size_bytes == 0 {
+            return Err(Error::InvalidAddress);
+        }
+        let size_bytes = user_range_size(va, size_bytes).ok_or(Error::InvalidAddress)?;
+        let end = va.as_usize() + size_bytes;
+
+        let range = self
+            .memory_ranges
+            .iter_mut()
+            .find(|range| {
+                va.as_usize() >= range.va.as_usize()
+                    && end
+                        <= num_pages_from_bytes(range.end_virtual_address().as_usize()) * PAGE_SIZE
+            })
+            .ok_or(Error::InvalidAddress)?;
+        if range.dirty_pages.is_some() {
+            return Err(Error::InvalidAddress);
+        }
+
+        self.address_table.protect(va, size_bytes, permissions)?;
+        if range.va == va && num_pages_from_bytes(range.size_bytes) * PAGE_SIZE == size_bytes {
+            range.permissions = permissions;
+        }
+        mmu::flush_tlb();
+        Ok(size_bytes)
+    }
+
+    /// Maps a section with the access flag clear. The first access to each page raises an access
+    /// flag fault that is resolved by `handle_access_flag_fault`.
+    pub fn map_section_on_demand(
+        &mut self,
+        name: &str,
+        va: VirtualAddress,
+        pmr: PhysicalMemoryRegion,
+        size_bytes: usize,
+        permissions: GlobalPermissions,
+    ) -> Result<(), Error> {
+        self.map_section(name, va, pmr, size_bytes, permissions)?;
+        self.address_table.set_access_flag(va, size_bytes, false)?;
+        Ok(())
+    }
+
+    /// Sets the access flag of the page containing `va`. Returns false if the address does not
+    /// belong to any range of this address space.
+    pub fn handle_access_flag_fault(&mut self, va: VirtualAddress) -> bool {
+        if !self.memory_ranges.iter().any(|range| range.overlaps(va, 1)) {
+            return false;
+        }
+
+        let mut page = va;
+        let page = page.floor_to_alignment(PAGE_SIZE);
+        if self
+            .address_table
+            .set_access_flag(page, PAGE_SIZE, true)
+            .is_err()
+        {
+            return false;
+        }
+
+        mmu::flush_tlb_page(page);
+        true
+    }
+
+    /// Unmaps all memory ranges and hands their memory to `free`. Translation tables are freed
+    /// when the address space is dropped, so it must not be active in any CPU.
+    pub fn release(mut self, mut free: impl FnMut(ReleasedMemory)) {
+        for range in core::mem::take(&mut self.memory_ranges) {
+            self.address_table
+                .unmap_region(range.va, range.size_bytes)
+                .expect("Memory ranges are always mapped");
+            free(range.into());
+        }
+    }
+
+    /// Starts tracking writes to the given range. Writable pages are mapped without write
+    /// permissions until they are first written, which is resolved by `handle_write_fault`.
+    pub fn enable_dirty_tracking(&mut self, name: &str) -> Result<(), Error> {
+        let range = match self
+            .memory_ranges
+            .iter_mut()
+            .find(|range| range.name == name)
+        {
+            Some(range) => range,
+            None => {
+                return Err(Error::MemoryRangeNotFound(
+                    String::from_str(name).map_err(|_| Error::NameTooLong)?,
+                ))
+            }
+        };
+
+        // Read-only ranges never get dirty
+        if !range.permissions.is_writable() {
+            return Ok(());
+        }
+
+        range.dirty_pages = Some(vec![false; num_pages_from_bytes(range.size_bytes)]);
+        self.address_table.protect(
+            range.va,
+            range.size_bytes,
+            range.permissions.without_write(),
+        )?;
+        mmu::flush_tlb();
+        Ok(())
+    }
+
+    /// Restores the write permissions of a tracked page on the first write to it and marks it as
+    /// dirty. Returns false if the address is not tracked, meaning the fault is a real permission
+    /// violation.
+    pub fn handle_write_fault(&mut self, va: VirtualAddress) -> bool {
+        let range = match self
+            .memory_ranges
+            .iter_mut()
+            .find(|range| range.overlaps(va, 1))
+        {
+            Some(range) => range,
+            None => return false,
+        };
+
+        let dirty_pages = match range.dirty_pages.as_mut() {
+            Some(dirty_pages) => dirty_pages,
+            None => return false,
+        };
+
+        let page_index = (va.as_usize() - range.va.as_usize()) / PAGE_SIZE;
+        if dirty_pages[page_index] {
+            // The page is already writable, so something else is going on
+            return false;
+        }
+
+        let page = unsafe { range.va.offset(page_index * PAGE_SIZE) };
+        if self
+            .address_table
+            .protect(page, PAGE_SIZE, range.permissions)
+            .is_err()
+        {
+            return false;
+        }
+
+        dirty_pages[page_index] = true;
+        mmu::flush_tlb_page(page);
+        true
+    }
+
+    /// Returns all pages written since dirty tracking was enabled or since the last call to this
+    /// function. The returned pages are write protected again to keep tracking them.
+    pub fn take_dirty_pages(&mut self) -> Vec<VirtualAddress> {
+        let mut pages = vec![];
+        for range in self.memory_ranges.iter_mut() {
+            let dirty_pages = match range.dirty_pages.as_mut() {
+                Some(dirty_pages) => dirty_pages,
+                None => continue,
+            };
+
+            for (page_index, dirty) in dirty_pages.iter_mut().enumerate() {
+                if !*dirty {
+                    continue;
+                }
+
+                let page = unsafe { range.va.offset(page_index * PAGE_SIZE) };
+                self.address_table
+                    .protect(page, PAGE_SIZE, range.permissions.without_write())
+                    .expect("Tracked pages are always mapped");
+                mmu::flush_tlb_page(page);
+
+                *dirty = false;
+                pages.push(page);
+            }
+        }
+        pages
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::memory::map::KERNEL_LOGICAL_BASE;
+
+    fn kernel_address_space_with_ranges() -> KernelAddressSpace {
+        let mut address_space = KernelAddressSpace::new();
+        let text_la =
+            LogicalAddress::try_from_ptr((KERNEL_LOGICAL_BASE.as_usize() + 0x4000) as *const _)
+                .unwrap();
+        address_space
+            .add_logical_range(
+                ".text",
+                text_la,
+                2 * PAGE_SIZE,
+                Attributes::Normal,
+                Permissions::RX,
+                None,
+            )
+            .unwrap();
+
+        let uart_pa = PhysicalAddress::try_from_ptr(0x235200000 as *const _).unwrap();
+        address_space
+            .allocate_io_range("uart", uart_pa, PAGE_SIZE)
+            .unwrap();
+        address_space
+    }
+
+    fn process_address_space_with_ranges() -> ProcessAddressSpace {
+        mmu::initialize_for_test();
+
+        let mut address_space = ProcessAddressSpace::new();
+        let data_va = VirtualAddress::try_from_ptr(0x10000000 as *const _).unwrap();
+        let data_pa = PhysicalAddress::try_from_ptr(0x80000000 as *const _).unwrap();
+        address_space
+            .map_section(
+                ".data",
+                data_va,
+                PhysicalMemoryRegion::new(data_pa, 4),
+                4 * PAGE_SIZE,
+                GlobalPermissions::new_for_process(Permissions::RW),
+            )
+            .unwrap();
+
+        let rodata_va = VirtualAddress::try_from_ptr(0x20000000 as *const _).unwrap();
+        let rodata_pa = PhysicalAddress::try_from_ptr(0x90000000 as *const _).unwrap();
+        address_space
+            .map_section(
+                ".rodata",
+                rodata_va,
+                PhysicalMemoryRegion::new(rodata_pa, 1),
+                PAGE_SIZE,
+                GlobalPermissions::new_for_process(Permissions::RO),
+            )
+            .unwrap();
+        address_space
+    }
+
+    fn is_writable(address_space: &mut ProcessAddressSpace, va: VirtualAddress) -> bool {
+        address_space
+            .address_table()
+            .query(va)
+            .expect("Address is mapped")
+            .permissions
+            .is_writable()
+    }
+
+    #[test]
+    fn dirty_tracking_write_faults() {
+        let mut address_space = process_address_space_with_ranges();
+        let data_va = VirtualAddress::try_from_ptr(0x10000000 as *const _).unwrap();
+
+        address_space.enable_dirty_tracking(".data").unwrap();
+        for page in 0..4 {
+            let va = unsafe { data_va.offset(page * PAGE_SIZE) };
+            assert!(!is_writable(&mut address_space, va));
+        }
+        assert!(address_space.take_dirty_pages().is_empty());
+
+        // Simulate write faults in the middle of the second and fourth pages
+        let second_page = unsafe { data_va.offset(PAGE_SIZE) };
+        let fourth_page = unsafe { data_va.offset(3 * PAGE_SIZE) };
+        assert!(address_space.handle_write_fault(unsafe { second_page.offset(0x10) }));
+        assert!(address_space.handle_write_fault(unsafe { fourth_page.offset(0x80) }));
+        assert!(is_writable(&mut address_space, second_page));
+        assert!(is_writable(&mut address_space, fourth_page));
+        assert!(!is_writable(&mut address_space, data_va));
+
+        // A second fault on a page that is already writable is not a tracking fault
+        assert!(!address_space.handle_write_fault(second_page));
+
+        assert_eq!(
+            address_space.take_dirty_pages(),
+            vec![second_page, fourth_page]
+        );
+        assert!(!is_writable(&mut address_space, second_page));
+        assert!(!is_writable(&mut address_space, fourth_page));
+        assert!(address_space.take_dirty_pages().is_empty());
+
+        // Tracking continues after taking the dirty pages
+        assert!(address_space.handle_write_fault(second_page));
+        assert_eq!(address_space.take_dirty_pages(), vec![second_page]);
+    }
+
+    #[test]
+    fn user_access_ranges() {
+        let mut address_space = process_address_space_with_ranges();
+
+        // Within a range, also across pages and unaligned
+        assert!(address_space
+            .prepare_user_access(0x10000000, 4 * PAGE_SIZE, true)
+            .is_ok());
+        assert!(address_space
+            .prepare_user_access(0x10000000 + PAGE_SIZE - 8, 16, true)
+            .is_ok());
+        assert!(address_space
+            .prepare_user_access(0x20000010, 0x100, false)
+            .is_ok());
+
+        // Read-only memory cannot be written
+        assert!(matches!(
+            address_space.prepare_user_access(0x20000010, 0x100, true),
+            Err(Error::InvalidAddress)
+        ));
+
+        // Ranges past the end of a mapping, unmapped memory and kernel addresses
+        assert!(matches!(
+            address_space.prepare_user_access(0x10000000 + 4 * PAGE_SIZE - 8, 16, false),
+            Err(Error::InvalidAddress)
+        ));
+        assert!(matches!(
+            address_space.prepare_user_access(0, 8, false),
+            Err(Error::InvalidAddress)
+        ));
+        assert!(matches!(
+            address_space.prepare_user_access(0xFFFF000000000000, 8, false),
+            Err(Error::InvalidAddress)
+        ));
+        assert!(matches!(
+            address_space.prepare_user_access(usize::MAX - 4, 8, false),
+            Err(Error::InvalidAddress)
+        ));
+    }
+
+    #[test]
+    fn user_access_resolves_tracked_pages() {
+        let mut address_space = process_address_space_with_ranges();
+        let data_va = VirtualAddress::try_from_ptr(0x10000000 as *const _).unwrap();
+
+        address_space.enable_dirty_tracking(".data").unwrap();
+        assert!(address_space
+            .prepare_user_access(0x10000000, 8, false)
+            .is_ok());
+        assert!(address_space.take_dirty_pages().is_empty());
+
+        assert!(address_space
+            .prepare_user_access(0x10000000, 8, true)
+            .is_ok());
+        assert!(is_writable(&mut address_space, data_va));
+        assert_eq!(address_space.take_dirty_pages(), vec![data_va]);
+    }
+
+    #[test]
+    fn iterate_sections() {
+        let address_space = process_address_space_with_ranges();
+
+        let mut sections: Vec<(String<MAX_NAME_LENGTH>, usize, usize, GlobalPermissions)> =
+            address_space
+                .iter_sections()
+                .map(|section| {
+                    (
+                        section.name,
+                        section.va.as_usize(),
+                        section.size_bytes,
+                        section.permissions,
+                    )
+                })
+                .collect();
+        sections.sort_by_key(|section| section.1);
+
+        assert_eq!(
+            sections,
+            vec![
+                (
+                    String::from_str(".data").unwrap(),
+                    0x10000000,
+                    4 * PAGE_SIZE,
+                    GlobalPermissions::new_for_process(Permissions::RW)
+                ),
+                (
+                    String::from_str(".rodata").unwrap(),
+                    0x20000000,
+                    PAGE_SIZE,
+                    GlobalPermissions::new_for_process(Permissions::RO)
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn protect_mapped_pages() {
+        let mut address_space = process_address_space_with_ranges();
+        let data_va = VirtualAddress::try_from_ptr(0x10000000 as *const _).unwrap();
+        let second_page = unsafe { data_va.offset(PAGE_SIZE) };
+        let rx = GlobalPermissions::new_for_process(Permissions::RX);
+
+        assert_eq!(
+            address_space.protect(second_page, 1, rx).unwrap(),
+            PAGE_SIZE
+        );
+        assert_eq!(
+            address_space
+                .address_table()
+                .query(second_page)
+                .unwrap()
+                .permissions,
+            rx
+        );
+        assert!(is_writable(&mut address_space, data_va));
+        // Protecting part of a range keeps the permissions of the range
+        assert!(address_space.memory_ranges[0].permissions.is_writable());
+
+        address_space.protect(data_va, 4 * PAGE_SIZE, rx).unwrap();
+        assert_eq!(address_space.memory_ranges[0].permissions, rx);
+
+        // Unmapped memory, memory across ranges and unaligned addresses are rejected
+        let unmapped_va = unsafe { data_va.offset(4 * PAGE_SIZE) };
+        assert!(matches!(
+            address_space.protect(unmapped_va, PAGE_SIZE, rx),
+            Err(Error::InvalidAddress)
+        ));
+        assert!(matches!(
+            address_space.protect(second_page, 4 * PAGE_SIZE, rx),
+            Err(Error::InvalidAddress)
+        ));
+        assert!(matches!(
+            address_space.protect(VirtualAddress::new_unaligned(0x10000010 as *const _), 1, rx),
+            Err(Error::InvalidAddress)
+        ));
+
+        // Sizes that overflow or end past user memory are rejected
+        assert_eq!(user_range_size(data_va, 1), Some(PAGE_SIZE));
+        assert_eq!(user_range_size(data_va, usize::MAX), None);
+        assert_eq!(
+            user_range_size(data_va, USER_ADDRESS_LIMIT - data_va.as_usize()),
+            Some(USER_ADDRESS_LIMIT - data_va.as_usize())
+        );
+        assert!(matches!(
+            address_space.protect(data_va, usize::MAX, rx),
+            Err(Error::InvalidAddress)
+        ));
+        assert!(matches!(
+            address_space.protect(data_va, usize::MAX - data_va.as_usize() - 1, rx),
+            Err(Error::InvalidAddress)
+        ));
+        assert!(matches!(
+            address_space.protect(data_va, USER_ADDRESS_LIMIT, rx),
+            Err(Error::InvalidAddress)
+        ));
+
+        let rw = GlobalPermissions::new_for_process(Permissions::RW);
+        address_space.protect(data_va, 4 * PAGE_SIZE, rw).unwrap();
+        address_space.enable_dirty_tracking(".data").unwrap();
+        assert!(matches!(
+            address_space.protect(data_va, PAGE_SIZE, rx),
+            Err(Error::InvalidAddress)
+        ));
+    }
+
+    #[test]
+    fn release_returns_pages() {
+        use crate::memory::physical_page_allocator::{Options, PhysicalPageAllocator};
+
+        mmu::initialize_for_test();
+
+        let mut allocator = PhysicalPageAllocator::new();
+        let dram_base = PhysicalAddress::try_from_ptr(0x80000000 as *const _).unwrap();
+        allocator
+            .add_region(dram_base, 64, Options::Default)
+            .unwrap();
+        let baseline = allocator.num_free_pages();
+
+        for _ in 0..4 {
+            let mut address_spaces = vec![];
+            for _ in 0..3 {
+                let mut address_space = ProcessAddressSpace::new();
+                let text_va = VirtualAddress::try_from_ptr(0x10000000 as *const _).unwrap();
+                let stack_va = VirtualAddress::try_from_ptr(0x7F0000000 as *const _).unwrap();
+
+                let pmr = allocator.request_any_pages(2, Options::Default).unwrap();
+                address_space
+                    .map_section(
+                        ".text",
+                        text_va,
+                        pmr,
+                        2 * PAGE_SIZE - 0x10,
+                        GlobalPermissions::new_for_process(Permissions::RX),
+                    )
+                    .unwrap();
+
+                let pmr = allocator.request_any_pages(4, Options::Default).unwrap();
+                address_space
+                    .map_section(
+                        ".stack",
+                        stack_va,
+                        pmr,
+                        4 * PAGE_SIZE,
+                        GlobalPermissions::new_for_process(Permissions::RW),
+                    )
+                    .unwrap();
+                address_spaces.push(address_space);
+            }
+            assert_eq!(allocator.num_free_pages(), baseline - 3 * 6);
+
+            for address_space in address_spaces {
+                address_space.release(|memory| match memory {
+                    ReleasedMemory::Owned(pmr) => {
+                        allocator.release_pages(pmr, Options::Default).unwrap()
+                    }
+                    ReleasedMemory::Shared(_) => panic!("There are no shared mappings"),
+                });
+            }
+            assert_eq!(allocator.num_free_pages(), baseline);
+        }
+    }
+
+    #[test]
+    fn anonymous_mappings() {
+        let mut address_space = process_address_space_with_ranges();
+        let permissions = GlobalPermissions::new_for_process(Permissions::RW);
+        let pa = PhysicalAddress::try_from_ptr(0xA0000000 as *const _).unwrap();
+        let first_va = VirtualAddress::try_from_ptr(ANONYMOUS_BASE as *const _).unwrap();
+        let second_va = unsafe { first_va.offset(2 * PAGE_SIZE) };
+
+        let va = address_space
+            .map_anonymous(PhysicalMemoryRegion::new(pa, 2), 2 * PAGE_SIZE, permissions)
+            .unwrap();
+        assert_eq!(va, first_va);
+
+        // Sizes are rounded up to full pages
+        let pa = unsafe { pa.offset(2 * PAGE_SIZE) };
+        let va = address_space
+            .map_anonymous(PhysicalMemoryRegion::new(pa, 1), 0x10, permissions)
+            .unwrap();
+        assert_eq!(va, second_va);
+
+        let mapping = address_space.address_table().query(second_va).unwrap();
+        assert_eq!(mapping.pa, pa);
+        assert!(mapping.permissions.is_writable());
+
+        // Only whole anonymous mappings can be removed
+        let data_va = VirtualAddress::try_from_ptr(0x10000000 as *const _).unwrap();
+        assert!(matches!(
+            address_space.unmap(data_va, 4 * PAGE_SIZE),
+            Err(Error::InvalidAddress)
+        ));
+        assert!(matches!(
+            address_space.unmap(first_va, PAGE_SIZE),
+            Err(Error::InvalidAddress)
+        ));
+
+        let pmr = match address_space.unmap(first_va, 2 * PAGE_SIZE).unwrap() {
+            ReleasedMemory::Owned(pmr) => pmr,
+            ReleasedMemory::Shared(_) => panic!("Anonymous mappings are not shared"),
+        };
+        assert_eq!(pmr.num_pages(), 2);
+        assert!(address_space.address_table().query(first_va).is_none());
+
+        // The freed range is reused
+        let va = address_space
+            .map_anonymous(pmr, PAGE_SIZE, permissions)
+            .unwrap();
+        assert_eq!(va, first_va);
+    }
+
+    #[test]
+    fn shared_mappings() {
+        let mut address_space = process_address_space_with_ranges();
+        let permissions = GlobalPermissions::new_for_process(Permissions::RW);
+        let pa = PhysicalAddress::try_from_ptr(0xA0000000 as *const _).unwrap();
+        let pmr = PhysicalMemoryRegion::new(pa, 2);
+
+        let va = address_space
+            .map_shared("ipc", pmr.clone(), 2 * PAGE_SIZE, permissions)
+            .unwrap();
+        assert_eq!(address_space.address_table().query(va).unwrap().pa, pa);
+
+        // The same region cannot be mapped twice in one address space
+        assert!(matches!(
+            address_space.map_shared("ipc", pmr.clone(), 2 * PAGE_SIZE, permissions),
+            Err(Error::MemoryRangeAlreadyExists(_))
+        ));
+        assert!(matches!(
+            address_space.map_shared(
+                "a name that does not fit in the range",
+                pmr,
+                2 * PAGE_SIZE,
+                permissions
+            ),
+            Err(Error::NameTooLong)
+        ));
+
+        match address_space.unmap(va, 2 * PAGE_SIZE).unwrap() {
+            ReleasedMemory::Shared(name) => assert_eq!(name, "ipc"),
+            ReleasedMemory::Owned(_) => panic!("Shared mappings are not owned"),
+        }
+        assert!(address_space.address_table().query(va).is_none());
+    }
+
+    #[test]
+    fn ownership_does_not_depend_on_names() {
+        mmu::initialize_for_test();
+
+        let mut address_space = ProcessAddressSpace::new();
+        let permissions = GlobalPermissions::new_for_process(Permissions::RW);
+        let va = VirtualAddress::try_from_ptr(0x10000000 as *const _).unwrap();
+        let pa = PhysicalAddress::try_from_ptr(0x80000000 as *const _).unwrap();
+
+        // ELF sections may use the names of anonymous and shared mappings
+        address_space
+            .map_section(
+                "shm:data",
+                va,
+                PhysicalMemoryRegion::new(pa, 1),
+                PAGE_SIZE,
+                permissions,
+            )
+            .unwrap();
+        assert!(matches!(
+            address_space.unmap(va, PAGE_SIZE),
+            Err(Error::InvalidAddress)
+        ));
+
+        let mut released = vec![];
+        address_space.release(|memory| released.push(memory));
+        assert!(matches!(
+            released.as_slice(),
+            [ReleasedMemory::Owned(pmr)] if pmr.base_address() == pa
+        ));
+    }
+
+    #[test]
+    fn write_faults_outside_tracked_ranges() {
+        let mut address_space = process_address_space_with_ranges();
+        let data_va = VirtualAddress::try_from_ptr(0x10000000 as *const _).unwrap();
+        let rodata_va = VirtualAddress::try_from_ptr(0x20000000 as *const _).unwrap();
+        let unmapped_va = VirtualAddress::try_from_ptr(0x30000000 as *const _).unwrap();
+
+        // Tracking is not enabled yet
+        assert!(!address_space.handle_write_fault(data_va));
+
+        address_space.enable_dirty_tracking(".data").unwrap();
+        address_space.enable_dirty_tracking(".rodata").unwrap();
+        assert!(!address_space.handle_write_fault(rodata_va));
+        assert!(!address_space.handle_write_fault(unmapped_va));
+        assert!(matches!(
+            address_space.enable_dirty_tracking(".bss"),
+            Err(Error::MemoryRangeNotFound(_))
+        ));
+        assert!(address_space.take_dirty_pages().is_empty());
+    }
+
+    #[test]
+    fn kernel_range_lookup_by_name() {
+        let address_space = kernel_address_space_with_ranges();
+
+        let text = address_space.find_range_by_name(".text").unwrap();
+        assert_eq!(text.name, ".text");
+        assert_eq!(text.va.as_usize(), KERNEL_LOGICAL_BASE.as_usize() + 0x4000);
+        assert_eq!(text.size_bytes, 2 * PAGE_SIZE);
+        assert!(matches!(text.attributes, Attributes::Normal));
+        assert!(matches!(text.permissions.privileged, Permissions::RX));
+        assert!(matches!(text.permissions.unprivileged, Permissions::None));
+
+        let uart = address_space.find_range_by_name("uart").unwrap();
+        assert_eq!(uart.va, MMIO_BASE);
+        assert!(matches!(uart.attributes, Attributes::DevicenGnRnE));
+        assert!(uart.permissions.is_writable());
+
+        assert!(address_space.find_range_by_name(".data").is_none());
+    }
+
+    #[test]
+    fn kernel_range_lookup_by_address() {
+        let address_space = kernel_address_space_with_ranges();
+        let text_va =
+            VirtualAddress::try_from_ptr((KERNEL_LOGICAL_BASE.as_usize() + 0x4000) as *const _)
+                .unwrap();
+
+        let range = address_space
+            .find_range_containing(unsafe { text_va.offset(PAGE_SIZE + 0x10) })
+            .unwrap();
+        assert_eq!(range.name, ".text");
+
+        // The end of a range is not part of it
+        assert!(address_space
+            .find_range_containing(unsafe { text_va.offset(2 * PAGE_SIZE) })
+            .is_none());
+        assert!(address_space
+            .find_range_containing(VirtualAddress::new_unaligned(
+                (text_va.as_usize() - 1) as *const _
+            ))
+            .is_none());
+
+        let range = address_space
+            .find_range_containing(unsafe { MMIO_BASE.offset(0x80) })
+            .unwrap();
+        assert_eq!(range.name, "uart");
+    }
+
+    #[test]
+    fn overlapping_kernel_ranges() {
+        let mut address_space = kernel_address_space_with_ranges();
+        let text_la = |offset: usize| {
+            LogicalAddress::try_from_ptr(
+                (KERNEL_LOGICAL_BASE.as_usize() + 0x4000 + offset) as *const _,
+            )
+            .unwrap()
+        };
+
+        let result = address_space.add_logical_range(
+            ".bss",
+            text_la(PAGE_SIZE),
+            2 * PAGE_SIZE,
+            Attributes::Normal,
+            Permissions::RW,
+            None,
+        );
+        match result {
+            Err(Error::Overlap {
+                existing_name,
+                new_name,
+            }) => {
+                assert_eq!(existing_name, ".text");
+                assert_eq!(new_name, ".bss");
+            }
+            _ => panic!("Overlapping ranges must be rejected"),
+        }
+        assert!(address_space.find_range_by_name(".bss").is_none());
+
+        // Ranges that start right where another one ends don't overlap
+        address_space
+            .add_logical_range(
+                ".data",
+                text_la(2 * PAGE_SIZE),
+                PAGE_SIZE,
+                Attributes::Normal,
+                Permissions::RW,
+                None,
+            )
+            .unwrap();
+        address_space
+            .add_logical_range(
+                ".stack",
+                LogicalAddress::try_from_ptr(KERNEL_LOGICAL_BASE.as_ptr()).unwrap(),
+                PAGE_SIZE,
+                Attributes::Normal,
+                Permissions::RW,
+                None,
+            )
+            .unwrap();
+        assert!(address_space.find_range_by_name(".data").is_some());
+        assert!(address_space.find_range_by_name(".stack").is_some());
+    }
+
+    #[test]
+    fn shared_io_ranges() {
+        let mut address_space = kernel_address_space_with_ranges();
+        let uart_pa = PhysicalAddress::try_from_ptr(0x235200000 as *const _).unwrap();
+
+        // Mapping the same region again returns the existing range
+        let va = address_space.share_io_range(uart_pa, PAGE_SIZE).unwrap();
+        assert_eq!(va, MMIO_BASE);
+
+        // As does mapping part of it
+        let va = address_space
+            .share_io_range(unsafe { uart_pa.offset(0x100) }, 0x10)
+            .unwrap();
+        assert_eq!(va, unsafe { MMIO_BASE.offset(0x100) });
+
+        assert!(address_space
+            .share_io_range(unsafe { uart_pa.offset(0x100) }, PAGE_SIZE)
+            .is_none());
+        assert_eq!(address_space.mmio_ranges.len(), 1);
+        assert_eq!(address_space.mmio_ranges[0].users, 3);
+
+        // Only the last user removes the range
+        assert!(address_space.release_io_range(va).unwrap().is_none());
+        assert!(address_space.release_io_range(MMIO_BASE).unwrap().is_none());
+        let range = address_space.release_io_range(MMIO_BASE).unwrap().unwrap();
+        assert_eq!(range.name, "uart");
+        assert!(address_space.find_range_by_name("uart").is_none());
+        assert!(matches!(
+            address_space.release_io_range(MMIO_BASE),
+            Err(Error::InvalidAddress)
+        ));
+    }
+
+    #[test]
+    fn io_range_reuse() {
+        let mut address_space = kernel_address_space_with_ranges();
+        let pa = |offset: usize| {
+            PhysicalAddress::try_from_ptr((0x240000000 + offset) as *const _).unwrap()
+        };
+
+        let spi = address_space
+            .allocate_io_range("spi", pa(0), 2 * PAGE_SIZE)
+            .unwrap();
+        assert_eq!(spi, unsafe { MMIO_BASE.offset(PAGE_SIZE) });
+        let i2c = address_space
+            .allocate_io_range("i2c", pa(0x100000), 0x100)
+            .unwrap();
+        assert_eq!(i2c, unsafe { MMIO_BASE.offset(3 * PAGE_SIZE) });
+
+        // Released ranges leave a gap that new ranges fill if they fit in it
+        address_space.release_io_range(spi).unwrap().unwrap();
+        let gpio = address_space
+            .allocate_io_range("gpio", pa(0x200000), 3 * PAGE_SIZE)
+            .unwrap();
+        assert_eq!(gpio, unsafe { MMIO_BASE.offset(4 * PAGE_SIZE) });
+        let dart = address_space
+            .allocate_io_range("dart", pa(0x300000), 2 * PAGE_SIZE)
+            .unwrap();
+        assert_eq!(dart, spi);
+
+        assert!(address_space.find_free_io_range(MMIO_SIZE).is_none());
+    }
+
+    #[test]
+    fn io_range_removal_by_name() {
+        let mut address_space = kernel_address_space_with_ranges();
+        let uart_pa = PhysicalAddress::try_from_ptr(0x235200000 as *const _).unwrap();
+        address_space.share_io_range(uart_pa, PAGE_SIZE).unwrap();
+
+        // The range stays while it has other users
+        assert!(address_space
+            .remove_range_by_name("uart")
+            .unwrap()
+            .is_none());
+        assert!(address_space.find_range_by_name("uart").is_some());
+
+        let (_, range) = address_space.remove_range_by_name("uart").unwrap().unwrap();
+        assert_eq!(range.virtual_address(), MMIO_BASE);
+        assert!(address_space.find_range_by_name("uart").is_none());
     }
 }
@@ -1,9 +1,10 @@
-use crate::sync::spinlock::SpinLock;
+use crate::{log_error, sync::spinlock::SpinLock};
 
 use core::{
     alloc::{GlobalAlloc, Layout},
     mem::MaybeUninit,
     ops::{Deref, DerefMut},
+    sync::atomic::{AtomicUsize, Ordering},
 };
 
 #[cfg(not(test))]
@@ -24,7 +25,20 @@ extern "C" {
 pub unsafe fn init() {
     let arena_size = (&_arena_size) as *const u8 as usize;
     let arena_start = (&_arena_start) as *const _ as *mut u8;
-    ALLOCATOR.lock().init(arena_start, arena_size);
+    ALLOCATOR.init(arena_start, arena_size);
+}
+
+/// Usage of the kernel heap. Sizes include the padding added by the allocator to each allocation,
+/// but not the gaps left between allocations to satisfy alignment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeapStats {
+    pub allocated_bytes: usize,
+    pub free_bytes: usize,
+    pub high_water_mark: usize,
+}
+
+pub fn stats() -> HeapStats {
+    ALLOCATOR.stats()
 }
 
 fn aligned_address_with_layout(
@@ -232,36 +246,113 @@ impl HeapAllocator {
     }
 }
 
-#[repr(transparent)]
-struct LockedHeapAllocator(SpinLock<HeapAllocator>);
+/// Counters are updated with relaxed atomics outside of the allocator lock, so that they are cheap
+/// enough to be always enabled. They might be briefly inconsistent with each other.
+struct HeapCounters {
+    size: AtomicUsize,
+    allocated: AtomicUsize,
+    high_water_mark: AtomicUsize,
+}
+
+impl HeapCounters {
+    const fn new() -> Self {
+        Self {
+            size: AtomicUsize::new(0),
+            allocated: AtomicUsize::new(0),
+            high_water_mark: AtomicUsize::new(0),
+        }
+    }
+
+    unsafe fn alloc(&self, heap: &mut HeapAllocator, layout: Layout) -> *mut u8 {
+        let ptr = heap.alloc(layout);
+        if !ptr.is_null() {
+            let size = HeapAllocator::adapt_layout(layout).size();
+            let allocated = self.allocated.fetch_add(size, Ordering::Relaxed) + size;
+            self.high_water_mark.fetch_max(allocated, Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, heap: &mut HeapAllocator, ptr: *mut u8, layout: Layout) {
+        heap.dealloc(ptr, layout);
+        let size = HeapAllocator::adapt_layout(layout).size();
+        self.allocated.fetch_sub(size, Ordering::Relaxed);
+    }
+
+    fn stats(&self) -> HeapStats {
+        let allocated_bytes = self.allocated.load(Ordering::Relaxed);
+        HeapStats {
+            allocated_bytes,
+            free_bytes: self
+                .size
+                .load(Ordering::Relaxed)
+                .saturating_sub(allocated_bytes),
+            high_water_mark: self.high_water_mark.load(Ordering::Relaxed),
+        }
+    }
+}
+
+struct LockedHeapAllocator {
+    heap: SpinLock<HeapAllocator>,
+    counters: HeapCounters,
+}
 
 impl LockedHeapAllocator {
     const fn new() -> Self {
-        Self(SpinLock::new(HeapAllocator::new()))
+        Self {
+            heap: SpinLock::new(HeapAllocator::new()),
+            counters: HeapCounters::new(),
+        }
+    }
+
+    unsafe fn init(&self, base_addr: *mut u8, size: usize) {
+        self.lock().init(base_addr, size);
+        self.counters.size.store(size, Ordering::Relaxed);
+    }
+
+    fn stats(&self) -> HeapStats {
+        self.counters.stats()
     }
 }
 
 impl Deref for LockedHeapAllocator {
     type Target = SpinLock<HeapAllocator>;
     fn deref(&self) -> &Self::Target {
-        &self.0
+        &self.heap
     }
 }
 
 impl DerefMut for LockedHeapAllocator {
     fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.0
+        &mut self.heap
     }
 }
 
+/// Called when the heap cannot satisfy an allocation, right before the null pointer is returned
+/// and the default allocation error handler aborts. The lock is not held here, and logging does
+/// not allocate.
+fn on_allocation_failure(layout: Layout, stats: HeapStats) {
+    log_error!(
+        "Kernel heap exhausted allocating {} bytes with alignment {}. Allocated {} bytes, free {} bytes, high water mark {} bytes",
+        layout.size(),
+        layout.align(),
+        stats.allocated_bytes,
+        stats.free_bytes,
+        stats.high_water_mark
+    );
+}
+
 unsafe impl GlobalAlloc for LockedHeapAllocator {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        self.lock().alloc(layout)
+        let ptr = self.counters.alloc(&mut self.lock(), layout);
+        if ptr.is_null() {
+            on_allocation_failure(layout, self.stats());
+        }
+        ptr
     }
 
-    /// We just don't free any memory! Leaking is safe after all, isn't it? =D
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
-        self.lock().dealloc(ptr, layout)
+        self.counters.dealloc(&mut self.lock(), ptr, layout);
     }
 }
 
@@ -354,6 +445,62 @@ mod test {
         }
     }
 
+    #[test]
+    fn heap_stats_high_water_mark() {
+        let mut arena = vec![0u128; 256];
+        let arena_size = arena.len() * std::mem::size_of::<u128>();
+        let mut heap = HeapAllocator::new();
+        let counters = HeapCounters::new();
+        unsafe { heap.init(arena.as_mut_ptr() as *mut _, arena_size) };
+        counters.size.store(arena_size, Ordering::Relaxed);
+
+        assert_eq!(
+            counters.stats(),
+            HeapStats {
+                allocated_bytes: 0,
+                free_bytes: arena_size,
+                high_water_mark: 0,
+            }
+        );
+
+        let small_layout = Layout::new::<u32>();
+        let large_layout = Layout::new::<[u8; 100]>();
+        let small = unsafe { counters.alloc(&mut heap, small_layout) };
+        let large = unsafe { counters.alloc(&mut heap, large_layout) };
+        assert!(!small.is_null());
+        assert!(!large.is_null());
+
+        // Allocations are padded to the alignment of the free list entries
+        assert_eq!(
+            counters.stats(),
+            HeapStats {
+                allocated_bytes: 16 + 112,
+                free_bytes: arena_size - 128,
+                high_water_mark: 128,
+            }
+        );
+
+        unsafe { counters.dealloc(&mut heap, large, large_layout) };
+        assert_eq!(
+            counters.stats(),
+            HeapStats {
+                allocated_bytes: 16,
+                free_bytes: arena_size - 16,
+                high_water_mark: 128,
+            }
+        );
+
+        unsafe { counters.dealloc(&mut heap, small, small_layout) };
+        assert_eq!(counters.stats().allocated_bytes, 0);
+        assert_eq!(counters.stats().high_water_mark, 128);
+
+        // Failed allocations are not accounted for
+        let huge_layout = Layout::from_size_align(2 * arena_size, 16).unwrap();
+        assert!(unsafe { counters.alloc(&mut heap, huge_layout) }.is_null());
+        assert_eq!(counters.stats().allocated_bytes, 0);
+        assert_eq!(counters.stats().high_water_mark, 128);
+    }
+
     #[test]
     fn heap_test_initial_state() {
         let test = HeapTest::new();
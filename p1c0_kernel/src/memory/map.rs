@@ -2,7 +2,11 @@ use super::{
     address::{LogicalAddress, PhysicalAddress, VirtualAddress},
     GlobalPermissions, Permissions,
 };
-use crate::arch::mmu::PAGE_SIZE;
+use crate::{
+    adt::{self, get_adt, Adt},
+    arch::mmu::PAGE_SIZE,
+    prelude::*,
+};
 
 /// This is the base address for logical addresses.
 pub const KERNEL_LOGICAL_BASE: LogicalAddress =
@@ -151,3 +155,115 @@ pub fn stack_range() -> (PhysicalAddress, usize) {
     };
     (start, size_bytes)
 }
+
+/// Kind of a region of physical memory described by the ADT.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegionKind {
+    /// DRAM handed over by the bootloader.
+    Dram,
+    /// Memory reserved by a node under `/reserved-memory`.
+    Reserved,
+}
+
+/// Returns the DRAM region the bootloader describes in the `dram-base` and `dram-size` properties
+/// of `/chosen`.
+pub fn dram_region(adt: &Adt) -> Result<(PhysicalAddress, usize), adt::Error> {
+    let chosen = adt.require_node("/chosen")?;
+    let dram_base = chosen.require_property("dram-base")?.usize_value()?;
+    let dram_size = chosen.require_property("dram-size")?.usize_value()?;
+    Ok((
+        PhysicalAddress::from_unaligned_ptr(dram_base as *const u8),
+        dram_size,
+    ))
+}
+
+fn regions_from_adt(adt: &Adt) -> Vec<(PhysicalAddress, usize, RegionKind)> {
+    let mut regions = vec![];
+    if let Ok((dram_base, dram_size)) = dram_region(adt) {
+        regions.push((dram_base, dram_size, RegionKind::Dram));
+    }
+
+    if let Some(reserved_memory) = adt.find_node("/reserved-memory") {
+        let address_cells = reserved_memory.get_address_cells();
+        let size_cells = reserved_memory.get_size_cells();
+        for node in reserved_memory.child_iter() {
+            for reg in node.reg_iter(address_cells, size_cells) {
+                regions.push((
+                    PhysicalAddress::from_unaligned_ptr(reg.get_addr() as *const u8),
+                    reg.get_size(),
+                    RegionKind::Reserved,
+                ));
+            }
+        }
+    }
+    regions
+}
+
+/// Returns the physical memory regions described by the ADT: the DRAM given by the bootloader
+/// followed by every region under `/reserved-memory`. Reserved regions usually overlap the DRAM.
+pub fn physical_regions() -> Vec<(PhysicalAddress, usize, RegionKind)> {
+    get_adt()
+        .map(|adt| regions_from_adt(&adt))
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::adt::test::TestNode;
+
+    #[test]
+    fn regions_from_synthetic_adt() {
+        let adt = TestNode::new("device-tree")
+            .child(
+                TestNode::new("chosen")
+                    .property("dram-base", 0x8_0000_0000u64.to_le_bytes().to_vec())
+                    .property("dram-size", 0x2_0000_0000u64.to_le_bytes().to_vec()),
+            )
+            .child(
+                TestNode::new("reserved-memory")
+                    .property("#address-cells", 2u32.to_le_bytes().to_vec())
+                    .property("#size-cells", 1u32.to_le_bytes().to_vec())
+                    .child(
+                        TestNode::new("framebuffer").property(
+                            "reg",
+                            [
+                                0x9_0000_0000u64.to_le_bytes().as_slice(),
+                                &0x80_0000u32.to_le_bytes(),
+                            ]
+                            .concat(),
+                        ),
+                    ),
+            )
+            .build();
+
+        let regions = regions_from_adt(&adt);
+        assert_eq!(
+            regions,
+            vec![
+                (
+                    PhysicalAddress::from_unaligned_ptr(0x8_0000_0000 as *const u8),
+                    0x2_0000_0000,
+                    RegionKind::Dram
+                ),
+                (
+                    PhysicalAddress::from_unaligned_ptr(0x9_0000_0000 as *const u8),
+                    0x80_0000,
+                    RegionKind::Reserved
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn regions_without_reserved_memory() {
+        let adt = TestNode::new("device-tree")
+            .child(TestNode::new("chosen"))
+            .build();
+        assert!(matches!(
+            dram_region(&adt),
+            Err(adt::Error::MissingProperty { .. })
+        ));
+        assert!(regions_from_adt(&adt).is_empty());
+    }
+}
@@ -264,6 +264,10 @@ impl PhysicalPageAllocator {
         }
     }
 
+    pub fn num_free_pages(&self) -> usize {
+        self.regions.iter().map(|region| region.num_pages).sum()
+    }
+
     pub fn request_pages(
         &mut self,
         pa: PhysicalAddress,
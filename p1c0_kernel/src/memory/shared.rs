@@ -0,0 +1,167 @@
+use super::{
+    num_pages_from_bytes, physical_page_allocator::PhysicalMemoryRegion, AllocPolicy, MemoryManager,
+};
+use crate::{arch::mmu::PAGE_SIZE, prelude::*, sync::spinlock::SpinLock};
+
+#[derive(Debug)]
+pub enum Error {
+    RegionAlreadyExists,
+    RegionNotFound,
+    InvalidSize,
+    MemoryError(super::Error),
+}
+
+impl From<super::Error> for Error {
+    fn from(e: super::Error) -> Self {
+        Error::MemoryError(e)
+    }
+}
+
+/// Physical memory shared between processes, kept alive while there is at least one mapping of it.
+pub struct SharedRegion {
+    pmr: PhysicalMemoryRegion,
+    num_mappings: usize,
+}
+
+impl SharedRegion {
+    pub fn pmr(&self) -> &PhysicalMemoryRegion {
+        &self.pmr
+    }
+
+    pub fn size_bytes(&self) -> usize {
+        self.pmr.num_pages() * PAGE_SIZE
+    }
+}
+
+/// Name table of shared memory regions.
+pub struct SharedRegions {
+    regions: FlatMap<String, SharedRegion>,
+}
+
+impl SharedRegions {
+    pub const fn new() -> Self {
+        Self {
+            regions: FlatMap::new_no_capacity(),
+        }
+    }
+
+    /// Adds a new region that is already mapped once by its creator.
+    pub fn create(
+        &mut self,
+        name: &str,
+        pmr: PhysicalMemoryRegion,
+    ) -> Result<&SharedRegion, Error> {
+        self.regions
+            .insert_with_strategy(
+                name.to_string(),
+                SharedRegion {
+                    pmr,
+                    num_mappings: 1,
+                },
+                flat_map::InsertStrategy::NoReplaceResize,
+            )
+            .map_err(|_| Error::RegionAlreadyExists)?;
+        Ok(self.regions.lookup(name).unwrap())
+    }
+
+    /// Takes a new reference to the region for an additional mapping.
+    pub fn acquire(&mut self, name: &str) -> Result<&SharedRegion, Error> {
+        let region = self.regions.lookup_mut(name).ok_or(Error::RegionNotFound)?;
+        region.num_mappings += 1;
+        Ok(region)
+    }
+
+    /// Drops a reference to the region. Returns the physical memory of the region once the last
+    /// mapping is gone, and it is up to the caller to free it.
+    pub fn release(&mut self, name: &str) -> Result<Option<PhysicalMemoryRegion>, Error> {
+        let region = self.regions.lookup_mut(name).ok_or(Error::RegionNotFound)?;
+        region.num_mappings -= 1;
+        if region.num_mappings > 0 {
+            return Ok(None);
+        }
+
+        let region = self.regions.remove(name).unwrap();
+        Ok(Some(region.pmr))
+    }
+}
+
+impl Default for SharedRegions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+static SHARED_REGIONS: SpinLock<SharedRegions> = SpinLock::new(SharedRegions::new());
+
+/// Allocates zero-filled physical memory for a new shared region and registers it with the given
+/// name. The caller is expected to map the region right away, since it counts as one mapping.
+pub fn create(name: &str, size_bytes: usize) -> Result<PhysicalMemoryRegion, Error> {
+    if size_bytes == 0 {
+        return Err(Error::InvalidSize);
+    }
+
+    let pmr = MemoryManager::instance()
+        .request_any_pages(num_pages_from_bytes(size_bytes), AllocPolicy::ZeroFill)?;
+
+    let result = SHARED_REGIONS
+        .lock()
+        .create(name, pmr.clone())
+        .map(|region| region.pmr().clone());
+    if result.is_err() {
+        MemoryManager::instance().release_pages(pmr)?;
+    }
+    result
+}
+
+/// Takes a reference to an existing region in order to map it.
+pub fn acquire(name: &str) -> Result<PhysicalMemoryRegion, Error> {
+    SHARED_REGIONS
+        .lock()
+        .acquire(name)
+        .map(|region| region.pmr().clone())
+}
+
+/// Drops a reference to a region after unmapping it, freeing the region with the last reference.
+pub fn release(name: &str) -> Result<(), Error> {
+    let pmr = SHARED_REGIONS.lock().release(name)?;
+    if let Some(pmr) = pmr {
+        MemoryManager::instance().release_pages(pmr)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::memory::address::PhysicalAddress;
+
+    #[test]
+    fn reference_counting() {
+        let mut regions = SharedRegions::new();
+        let pa = PhysicalAddress::try_from_ptr(0x80000000 as *const _).unwrap();
+        let pmr = PhysicalMemoryRegion::new(pa, 2);
+
+        let region = regions.create("ipc", pmr.clone()).unwrap();
+        assert_eq!(region.pmr(), &pmr);
+        assert_eq!(region.size_bytes(), 2 * PAGE_SIZE);
+        assert!(matches!(
+            regions.create("ipc", pmr.clone()),
+            Err(Error::RegionAlreadyExists)
+        ));
+
+        assert_eq!(regions.acquire("ipc").unwrap().pmr(), &pmr);
+        assert!(matches!(
+            regions.acquire("other"),
+            Err(Error::RegionNotFound)
+        ));
+
+        // The memory is only returned with the last reference
+        assert_eq!(regions.release("ipc").unwrap(), None);
+        assert_eq!(regions.release("ipc").unwrap(), Some(pmr.clone()));
+        assert!(matches!(regions.release("ipc"), Err(Error::RegionNotFound)));
+        assert!(matches!(regions.acquire("ipc"), Err(Error::RegionNotFound)));
+
+        // The name can be reused afterwards
+        regions.create("ipc", pmr).unwrap();
+    }
+}
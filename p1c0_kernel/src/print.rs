@@ -1,12 +1,22 @@
 use crate::{
-    collections::ring_buffer::{self, RingBuffer},
+    collections::{
+        history_buffer::HistoryBuffer,
+        ring_buffer::{self, RingBuffer},
+    },
     drivers::{Dev, DeviceRef},
     init::is_kernel_relocated,
+    prelude::*,
+    shell,
     sync::spinlock::SpinLock,
     syscall::Syscall,
 };
 
-use core::fmt::Write;
+use p1c0_macros::initcall;
+
+use core::{
+    fmt::Write,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
 
 #[derive(Debug)]
 pub enum Error {
@@ -41,20 +51,165 @@ pub trait Print {
 // However, given it runs in a single-threaded context it should be mostly ok.
 static mut EARLY_PRINT: Option<*mut dyn EarlyPrint> = None;
 
-static PRINT: SpinLock<Option<DeviceRef>> = SpinLock::new(None);
+/// Loggers registered with `register_printer`, which are also read for input.
+static PRINT: SpinLock<Vec<DeviceRef>> = SpinLock::new(Vec::new());
+
+/// Backend for the kernel output. `_print` only buffers the output, which the printer thread then
+/// writes to all the registered writers. Writers may print themselves: that output is buffered too,
+/// so it can't deadlock on the writers.
+pub trait Writer: Send {
+    fn write_bytes(&mut self, data: &[u8]) -> Result<(), Error>;
+
+    /// Writes output while the system is stopped, like during a panic. Must not wait on locks,
+    /// since their owner may never release them.
+    ///
+    /// # Safety
+    ///   Only callable from a single-threaded context
+    unsafe fn force_write_bytes(&mut self, data: &[u8]) -> Result<(), Error> {
+        self.write_bytes(data)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WriterId(u64);
+
+struct WriterRegistry {
+    writers: Vec<(WriterId, Box<dyn Writer>)>,
+    next_id: u64,
+}
+
+impl WriterRegistry {
+    const fn new() -> Self {
+        Self {
+            writers: Vec::new(),
+            next_id: 0,
+        }
+    }
+
+    fn add(&mut self, writer: Box<dyn Writer>) -> WriterId {
+        let id = WriterId(self.next_id);
+        self.next_id += 1;
+        self.writers.push((id, writer));
+        id
+    }
+
+    fn remove(&mut self, id: WriterId) -> Option<Box<dyn Writer>> {
+        let index = self
+            .writers
+            .iter()
+            .position(|(writer_id, _)| *writer_id == id)?;
+        Some(self.writers.remove(index).1)
+    }
+
+    /// Writes to all writers, in the order they were added. A failing writer does not prevent the
+    /// others from getting the output.
+    fn write_bytes(&mut self, data: &[u8]) {
+        for (_, writer) in self.writers.iter_mut() {
+            let _ = writer.write_bytes(data);
+        }
+    }
+
+    /// # Safety
+    ///   Only callable from a single-threaded context
+    unsafe fn force_write_bytes(&mut self, data: &[u8]) {
+        for (_, writer) in self.writers.iter_mut() {
+            let _ = writer.force_write_bytes(data);
+        }
+    }
+}
+
+static WRITERS: SpinLock<WriterRegistry> = SpinLock::new(WriterRegistry::new());
+
+/// Bytes the printer thread takes from the buffer at once
+const PRINTER_CHUNK_SIZE: usize = 64;
 
 const BUFFER_SIZE: usize = 1024 * 256;
 static BUFFER: RingBuffer<BUFFER_SIZE> = RingBuffer::new();
 static LOG_WRITER: SpinLock<Option<LogWriter>> = SpinLock::new(None);
 
+/// The last output written to the log, kept even if the printer does not keep up with it, so that
+/// it can be dumped on a panic.
+const HISTORY_SIZE: usize = 1024 * 64;
+static HISTORY: SpinLock<HistoryBuffer<HISTORY_SIZE>> = SpinLock::new(HistoryBuffer::new());
+/// Set when output could not be pushed to `BUFFER`, which means only `HISTORY` has all of it.
+static OUTPUT_DROPPED: AtomicBool = AtomicBool::new(false);
+
+static DROPPED_BYTES: AtomicUsize = AtomicUsize::new(0);
+static DROPPED_MESSAGES: AtomicUsize = AtomicUsize::new(0);
+
+/// Output that never made it to the printer, because the buffer was full or the writer was locked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PrintStats {
+    pub dropped_bytes: usize,
+    pub dropped_messages: usize,
+}
+
+pub fn stats() -> PrintStats {
+    PrintStats {
+        dropped_bytes: DROPPED_BYTES.load(Ordering::Relaxed),
+        dropped_messages: DROPPED_MESSAGES.load(Ordering::Relaxed),
+    }
+}
+
+fn record_dropped(bytes: usize) {
+    DROPPED_BYTES.fetch_add(bytes, Ordering::Relaxed);
+    DROPPED_MESSAGES.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Pushes as many bytes as fit in the buffer, never blocking. Once a byte does not fit the rest are
+/// dropped too, so that the output does not get interleaved if the reader makes room meanwhile.
+/// Returns the number of dropped bytes.
+fn push_bytes<const SIZE: usize>(
+    writer: &mut ring_buffer::Writer<'_, SIZE>,
+    bytes: &[u8],
+) -> usize {
+    for (index, byte) in bytes.iter().enumerate() {
+        if writer.push(*byte).is_err() {
+            return bytes.len() - index;
+        }
+    }
+    0
+}
+
+/// Counts the bytes of formatted output, without storing it
+struct ByteCounter(usize);
+
+impl Write for ByteCounter {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        self.0 += s.len();
+        Ok(())
+    }
+}
+
+fn formatted_len(args: core::fmt::Arguments) -> usize {
+    let mut counter = ByteCounter(0);
+    let _ = counter.write_fmt(args);
+    counter.0
+}
+
 struct LogWriter<'a> {
     writer: ring_buffer::Writer<'a, BUFFER_SIZE>,
+    /// Bytes dropped since the last call to `_print`
+    dropped_bytes: usize,
 }
 
 impl<'a> Write for LogWriter<'a> {
     fn write_str(&mut self, s: &str) -> core::fmt::Result {
-        for c in s.bytes() {
-            self.writer.push(c).map_err(|_| core::fmt::Error)?;
+        // Never wait for the history, so that logging from an interrupt or a panic can't deadlock
+        if let Ok(mut history) = HISTORY.try_lock() {
+            history.extend(s.as_bytes());
+        }
+
+        // Not returning an error when the buffer is full, so that the rest of the output still
+        // makes it to the history
+        let dropped_bytes = if self.dropped_bytes == 0 {
+            push_bytes(&mut self.writer, s.as_bytes())
+        } else {
+            s.len()
+        };
+        if dropped_bytes != 0 {
+            self.dropped_bytes += dropped_bytes;
+            OUTPUT_DROPPED.store(true, Ordering::Relaxed);
         }
         Ok(())
     }
@@ -63,7 +218,13 @@ impl<'a> Write for LogWriter<'a> {
 #[doc(hidden)]
 pub fn _print(args: core::fmt::Arguments) -> Result<(), Error> {
     if is_kernel_relocated() {
-        let mut writer = LOG_WRITER.try_lock().map_err(|_| Error::WriterLocked)?;
+        let mut writer = match LOG_WRITER.try_lock() {
+            Ok(writer) => writer,
+            Err(_) => {
+                record_dropped(formatted_len(args));
+                return Err(Error::WriterLocked);
+            }
+        };
 
         if writer.is_none() {
             let buffer_writer = BUFFER
@@ -71,14 +232,17 @@ pub fn _print(args: core::fmt::Arguments) -> Result<(), Error> {
                 .expect("The buffer should not be split");
             writer.replace(LogWriter {
                 writer: buffer_writer,
+                dropped_bytes: 0,
             });
         }
 
-        writer
-            .as_mut()
-            .unwrap()
-            .write_fmt(args)
-            .map_err(|_| Error::BufferFull)?;
+        let writer = writer.as_mut().unwrap();
+        writer.dropped_bytes = 0;
+        writer.write_fmt(args).map_err(|_| Error::PrintFailed)?;
+        if writer.dropped_bytes != 0 {
+            record_dropped(writer.dropped_bytes);
+            return Err(Error::BufferFull);
+        }
     } else {
         // We check if there is an EarlyPrint implementation and use that.
 
@@ -102,70 +266,284 @@ pub unsafe fn register_early_printer<T: EarlyPrint>(printer: &'static mut T) {
     EARLY_PRINT.replace(printer);
 }
 
-#[inline]
-pub fn register_printer(printer: DeviceRef) {
-    let mut reader = BUFFER.split_reader().expect("The buffer is already split!");
+/// Writes the output to a logger device
+struct LoggerWriter(DeviceRef);
 
-    match &*printer.lock_read() {
-        Dev::Logger(_) => {}
-        _ => {
-            panic!("Printer must be a Dev::Logger instance");
+impl LoggerWriter {
+    fn write_to_logger(dev: &mut Dev, data: &[u8]) -> Result<(), Error> {
+        match dev {
+            Dev::Logger(logger) => data.iter().try_for_each(|val| logger.write_u8(*val)),
+            _ => {
+                panic!("Printer must be a Dev::Logger instance");
+            }
         }
     }
+}
+
+impl Writer for LoggerWriter {
+    fn write_bytes(&mut self, data: &[u8]) -> Result<(), Error> {
+        Self::write_to_logger(&mut self.0.lock_write(), data)
+    }
+
+    unsafe fn force_write_bytes(&mut self, data: &[u8]) -> Result<(), Error> {
+        let mut result = Ok(());
+        self.0
+            .access_inner_without_locking(|dev| result = Self::write_to_logger(dev, data));
+        result
+    }
+}
+
+/// Adds a backend for the kernel output, which gets all output printed from now on. The first
+/// writer starts the printer thread.
+pub fn add_writer(writer: Box<dyn Writer>) -> WriterId {
+    let (id, is_first_writer) = {
+        let mut writers = WRITERS.lock();
+        let id = writers.add(writer);
+        (id, writers.next_id == 1)
+    };
+    if !is_first_writer {
+        // The printer thread is already running
+        return id;
+    }
 
+    let mut reader = BUFFER.split_reader().expect("The buffer is already split!");
     crate::thread::Builder::new()
         .name("Printer")
-        .spawn(move || {
-            PRINT.lock().replace(printer);
-            loop {
+        .spawn(move || loop {
+            let mut chunk = [0; PRINTER_CHUNK_SIZE];
+            let mut length = 0;
+            while length < PRINTER_CHUNK_SIZE {
                 match reader.pop() {
                     Ok(val) => {
-                        let mut lock = PRINT.lock();
-                        if let Some(lock) = lock.as_mut() {
-                            let mut lock = lock.lock_write();
-                            match &mut *lock {
-                                Dev::Logger(logger) => {
-                                    logger.write_u8(val).unwrap();
-                                }
-                                _ => {
-                                    panic!("Printer must be a Dev::Logger instance");
-                                }
-                            };
-                        }
-                    }
-                    Err(ring_buffer::Error::WouldBlock) => {
-                        // TODO(javier-varez): Sleep here waiting for condition to happen instead of looping
-                        // At the time of this writing there is no mechanism to do this.
-                        // We can at least yield to the scheduler again
-                        Syscall::yield_exec();
-                        continue;
+                        chunk[length] = val;
+                        length += 1;
                     }
+                    Err(ring_buffer::Error::WouldBlock) => break,
                     Err(e) => {
                         panic!("Error reading from the print buffer, {:?}", e);
                     }
                 }
             }
+
+            if length == 0 {
+                // TODO(javier-varez): Sleep here waiting for condition to happen instead of looping
+                // At the time of this writing there is no mechanism to do this.
+                // We can at least yield to the scheduler again
+                Syscall::yield_exec();
+                continue;
+            }
+            WRITERS.lock().write_bytes(&chunk[..length]);
         });
+    id
+}
+
+/// Removes a writer, returning it if it was registered.
+pub fn remove_writer(id: WriterId) -> Option<Box<dyn Writer>> {
+    WRITERS.lock().remove(id)
 }
 
+/// Adds a logger device as a writer of the kernel output. Its input is read by `read_u8`.
+#[inline]
+pub fn register_printer(printer: DeviceRef) {
+    match &*printer.lock_read() {
+        Dev::Logger(_) => {}
+        _ => {
+            panic!("Printer must be a Dev::Logger instance");
+        }
+    }
+
+    PRINT.lock().push(printer.clone());
+    add_writer(Box::new(LoggerWriter(printer)));
+}
+
+/// Returns the registered printers, in registration order.
+pub fn printers() -> Vec<DeviceRef> {
+    PRINT.lock().clone()
+}
+
+/// Returns a character received by any of the printers, if there is any. Never blocks.
+pub fn read_u8() -> Option<u8> {
+    PRINT
+        .lock()
+        .iter()
+        .find_map(|printer| match &mut *printer.lock_write() {
+            Dev::Logger(logger) => logger.read_u8(),
+            _ => {
+                panic!("Printer must be a Dev::Logger instance");
+            }
+        })
+}
+
+/// Writes the pending output to the writers. If output was dropped because the writers did not
+/// keep up, the log history is written instead, so that no context is lost.
+///
 /// # Safety
 ///   Only callable from a single-threaded context if the reader thread is stuck
 pub unsafe fn force_flush() {
     let mut reader = BUFFER.split_reader_unchecked();
-    PRINT.access_inner_without_locking(|printer| {
-        printer
-            .as_ref()
-            .unwrap()
-            .access_inner_without_locking(|printer| {
-                let logger = match &mut *printer {
-                    Dev::Logger(logger) => logger,
-                    _ => {
-                        panic!("Printer must be a Dev::Logger instance");
+    let output_dropped = OUTPUT_DROPPED.swap(false, Ordering::Relaxed);
+    WRITERS.access_inner_without_locking(|writers| {
+        if output_dropped {
+            // The pending output is part of the history
+            while reader.pop().is_ok() {}
+
+            writers.force_write_bytes(b"\n--- Output was dropped, dumping the log history ---\n");
+            HISTORY.access_inner_without_locking(|history| {
+                let mut chunk = [0; PRINTER_CHUNK_SIZE];
+                let mut length = 0;
+                for val in history.iter() {
+                    chunk[length] = val;
+                    length += 1;
+                    if length == PRINTER_CHUNK_SIZE {
+                        writers.force_write_bytes(&chunk);
+                        length = 0;
                     }
-                };
-                while let Ok(val) = reader.pop() {
-                    logger.write_u8(val).unwrap();
                 }
+                writers.force_write_bytes(&chunk[..length]);
             });
+            writers.force_write_bytes(b"\n--- End of the log history ---\n");
+        } else {
+            while let Ok(val) = reader.pop() {
+                writers.force_write_bytes(&[val]);
+            }
+        }
     });
 }
+
+/// Passes the text of `bytes` to `emit`, replacing each invalid UTF-8 sequence with U+FFFD. An
+/// incomplete sequence at the end is not emitted, since the rest of it may still come. Returns the
+/// number of bytes that were consumed.
+pub fn decode_utf8_lossy(bytes: &[u8], mut emit: impl FnMut(&str)) -> usize {
+    let mut rest = bytes;
+    loop {
+        match core::str::from_utf8(rest) {
+            Ok(text) => {
+                emit(text);
+                return bytes.len();
+            }
+            Err(error) => {
+                let (valid, invalid) = rest.split_at(error.valid_up_to());
+                // Safety: from_utf8 validated these bytes
+                emit(unsafe { core::str::from_utf8_unchecked(valid) });
+
+                match error.error_len() {
+                    Some(length) => {
+                        emit(char::REPLACEMENT_CHARACTER.encode_utf8(&mut [0; 4]));
+                        rest = &invalid[length..];
+                    }
+                    None => return bytes.len() - invalid.len(),
+                }
+            }
+        }
+    }
+}
+
+fn printstats_command(_args: &[&str]) {
+    let stats = stats();
+    println!(
+        "Dropped {} bytes in {} messages",
+        stats.dropped_bytes, stats.dropped_messages
+    );
+}
+
+#[initcall(priority = 0)]
+fn print_register_commands() {
+    shell::register_command("printstats", printstats_command).unwrap();
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::{sync::Mutex, vec::Vec};
+
+    fn decode(bytes: &[u8]) -> (String, usize) {
+        let mut text = String::new();
+        let consumed = decode_utf8_lossy(bytes, |chunk| text.push_str(chunk));
+        (text, consumed)
+    }
+
+    #[test]
+    fn lossy_utf8_decoding() {
+        assert_eq!(decode(b"hello\n"), (String::from("hello\n"), 6));
+
+        // The text after an invalid byte is kept
+        assert_eq!(
+            decode(b"before\xffafter\n"),
+            (String::from("before\u{fffd}after\n"), 13)
+        );
+        assert_eq!(
+            decode(b"\xff\xfe\xe2\x82"),
+            (String::from("\u{fffd}\u{fffd}"), 2)
+        );
+
+        // An incomplete sequence at the end is left for later
+        assert_eq!(decode(b"cost: \xe2\x82"), (String::from("cost: "), 6));
+        assert_eq!(
+            decode("cost: \u{20ac}".as_bytes()),
+            (String::from("cost: \u{20ac}"), 9)
+        );
+    }
+
+    #[test]
+    fn full_buffer_drops_output() {
+        let buffer: RingBuffer<16> = RingBuffer::new();
+        let (mut writer, mut reader) = buffer.split().unwrap();
+
+        // One slot of the ring buffer is always left empty
+        assert_eq!(push_bytes(&mut writer, b"0123456789"), 0);
+        assert_eq!(push_bytes(&mut writer, b"abcdefghij"), 5);
+        assert_eq!(push_bytes(&mut writer, b"xyz"), 3);
+
+        let mut output = Vec::new();
+        while let Ok(byte) = reader.pop() {
+            output.push(byte);
+        }
+        assert_eq!(output, b"0123456789abcde");
+
+        // Pushing works again once there is room
+        assert_eq!(push_bytes(&mut writer, b"xyz"), 0);
+    }
+
+    /// Records all output, shared with the test
+    struct RecordingWriter(Arc<Mutex<Vec<u8>>>);
+
+    impl Writer for RecordingWriter {
+        fn write_bytes(&mut self, data: &[u8]) -> Result<(), Error> {
+            self.0.lock().unwrap().extend_from_slice(data);
+            Ok(())
+        }
+    }
+
+    struct FailingWriter;
+
+    impl Writer for FailingWriter {
+        fn write_bytes(&mut self, _data: &[u8]) -> Result<(), Error> {
+            Err(Error::PrintFailed)
+        }
+    }
+
+    #[test]
+    fn output_fans_out_to_writers() {
+        let mut registry = WriterRegistry::new();
+        let first = Arc::new(Mutex::new(Vec::new()));
+        let second = Arc::new(Mutex::new(Vec::new()));
+        let first_id = registry.add(Box::new(RecordingWriter(first.clone())));
+        registry.add(Box::new(FailingWriter));
+        let second_id = registry.add(Box::new(RecordingWriter(second.clone())));
+        assert_ne!(first_id, second_id);
+
+        registry.write_bytes(b"abc");
+        assert!(registry.remove(first_id).is_some());
+        assert!(registry.remove(first_id).is_none());
+        unsafe { registry.force_write_bytes(b"def") };
+
+        assert_eq!(*first.lock().unwrap(), b"abc");
+        assert_eq!(*second.lock().unwrap(), b"abcdef");
+    }
+
+    #[test]
+    fn formatted_output_length() {
+        assert_eq!(formatted_len(format_args!("{}-{:04}", "abc", 7)), 8);
+    }
+}
@@ -1,25 +1,39 @@
 use crate::{
-    arch::{exceptions::ExceptionContext, mmu::PAGE_SIZE},
+    arch::{
+        self, cache,
+        exceptions::ExceptionContext,
+        mmu::PAGE_SIZE,
+        relocation::{self, RelaEntry},
+    },
+    boot_args,
+    channel::{self, EndpointTable},
     elf::{self, ElfParser},
     memory::{
         self,
-        address::{Address, VirtualAddress},
-        address_space::{self, ProcessAddressSpace},
+        address::{Address, PhysicalAddress, VirtualAddress},
+        address_space::{self, ProcessAddressSpace, RangeInfo, ReleasedMemory},
         num_pages_from_bytes,
         physical_page_allocator::PhysicalMemoryRegion,
-        GlobalPermissions, MemoryManager, Permissions,
+        shared, GlobalPermissions, MemoryManager, Permissions,
     },
     prelude::*,
+    shell,
     sync::spinlock::SpinLock,
     thread::{self, ThreadHandle},
 };
 
-use core::sync::atomic::{AtomicU64, Ordering};
+use p1c0_macros::initcall;
+
+use core::{
+    fmt::Write,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+};
 
 #[derive(Debug)]
 pub enum Error {
     AddressSpaceError(address_space::Error),
     MemoryError(memory::Error),
+    SharedMemoryError(shared::Error),
     ThreadError(thread::Error),
     NoCurrentProcess,
     InvalidBase,
@@ -27,6 +41,13 @@ pub enum Error {
     UnsupportedExecutable,
     UnalignedLoadableSegment,
     NoEntryPoint,
+    InvalidPermissions,
+    InvalidSize,
+    RelocationError(relocation::Error),
+    /// The target of a relocation is not in any of the loaded segments
+    InvalidRelocation(usize),
+    /// A mapping is writable and executable while the W^X policy is enforced
+    WritableAndExecutable,
 }
 
 impl From<address_space::Error> for Error {
@@ -41,6 +62,12 @@ impl From<memory::Error> for Error {
     }
 }
 
+impl From<shared::Error> for Error {
+    fn from(e: shared::Error) -> Self {
+        Error::SharedMemoryError(e)
+    }
+}
+
 impl From<thread::Error> for Error {
     fn from(e: thread::Error) -> Self {
         Error::ThreadError(e)
@@ -52,8 +79,52 @@ pub enum State {
     Killed(u64),
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProcessState {
+    Running,
+    /// None of the threads of the process is ready to run
+    Sleeping,
+    Killed(u64),
+}
+
+/// Snapshot of the state of a process, as returned by `list`.
+#[derive(Debug, Clone)]
+pub struct ProcessInfo {
+    pub pid: ProcessHandle,
+    pub name: String,
+    pub state: ProcessState,
+    pub num_threads: usize,
+}
+
 static NUM_PROCESSES: AtomicU64 = AtomicU64::new(0);
 
+/// Command line flag that enforces the W^X policy for process mappings from boot.
+pub const W_XOR_X_CMDLINE_FLAG: &str = "p1c0.wxorx";
+
+static ENFORCE_W_XOR_X: AtomicBool = AtomicBool::new(false);
+
+/// Enables or disables the W^X policy, which rejects process mappings that are both writable and
+/// executable. It is disabled by default, since executables with code and data in the same page
+/// need such mappings.
+pub fn set_w_xor_x_policy(enforce: bool) {
+    ENFORCE_W_XOR_X.store(enforce, Ordering::Relaxed);
+}
+
+pub fn w_xor_x_policy() -> bool {
+    ENFORCE_W_XOR_X.load(Ordering::Relaxed)
+}
+
+fn check_w_xor_x(name: &str, permissions: Permissions, enforce: bool) -> Result<(), Error> {
+    if enforce && matches!(permissions, Permissions::RWX) {
+        log_error!(
+            "Section `{}` is writable and executable, which the W^X policy does not allow",
+            name
+        );
+        return Err(Error::WritableAndExecutable);
+    }
+    Ok(())
+}
+
 static PROCESSES: SpinLock<IntrusiveList<Process>> = SpinLock::new(IntrusiveList::new());
 
 #[derive(Clone, Debug, Eq, PartialEq)]
@@ -65,8 +136,251 @@ impl ProcessHandle {
     }
 }
 
+/// Signals that can be sent to a process with the `kill` syscall. The values match the usual
+/// POSIX signal numbers.
+///
+/// Signals are delivered when one of the threads of the target process returns to userspace. If a
+/// signal terminates the process, `wait_pid` returns the exit code given by `exit_code()`, which
+/// has the `SIGNAL_EXIT_CODE_FLAG` bit set so it can be told apart from regular exit codes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u64)]
+pub enum Signal {
+    /// Terminates the process. It cannot be handled.
+    Kill = 9,
+    /// Requests the process to terminate. It invokes the signal handler of the process if there
+    /// is one, otherwise the process is terminated.
+    Term = 15,
+}
+
+pub const SIGNAL_EXIT_CODE_FLAG: u64 = 1 << 63;
+
+impl Signal {
+    fn mask(self) -> u64 {
+        1 << (self as u64)
+    }
+
+    pub fn exit_code(self) -> u64 {
+        SIGNAL_EXIT_CODE_FLAG | self as u64
+    }
+
+    /// Returns the signal that terminated a process given the exit code reported by `wait_pid`.
+    pub fn from_exit_code(exit_code: u64) -> Option<Self> {
+        if exit_code & SIGNAL_EXIT_CODE_FLAG == 0 {
+            return None;
+        }
+        (exit_code & !SIGNAL_EXIT_CODE_FLAG).try_into().ok()
+    }
+}
+
+impl TryFrom<u64> for Signal {
+    type Error = ();
+    fn try_from(value: u64) -> Result<Self, Self::Error> {
+        match value {
+            9 => Ok(Signal::Kill),
+            15 => Ok(Signal::Term),
+            _ => Err(()),
+        }
+    }
+}
+
+/// User context interrupted by a signal handler, restored by the `signal_return` syscall.
+struct SignalFrame {
+    elr: u64,
+    spsr: u64,
+    sp_el0: u64,
+    gpr: [u64; 31],
+}
+
+impl SignalFrame {
+    fn save(cx: &ExceptionContext) -> Self {
+        Self {
+            elr: cx.elr_el1,
+            spsr: cx.spsr_el1.as_raw(),
+            sp_el0: cx.sp_el0,
+            gpr: cx.gpr,
+        }
+    }
+
+    fn restore(&self, cx: &mut ExceptionContext) {
+        cx.elr_el1 = self.elr;
+        cx.spsr_el1.read_from_raw(self.spsr);
+        cx.sp_el0 = self.sp_el0;
+        cx.gpr = self.gpr;
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum SignalAction {
+    Terminate(Signal),
+    Handle(Signal, VirtualAddress),
+}
+
+#[derive(Default)]
+struct SignalState {
+    pending: u64,
+    handler: Option<VirtualAddress>,
+    // Only one signal is handled at a time, others remain pending until the handler returns
+    frame: Option<SignalFrame>,
+}
+
+impl SignalState {
+    fn raise(&mut self, signal: Signal) {
+        self.pending |= signal.mask();
+    }
+
+    /// Removes the next signal to deliver from the pending set and decides what to do with it.
+    fn take_next(&mut self) -> Option<SignalAction> {
+        if self.pending & Signal::Kill.mask() != 0 {
+            self.pending = 0;
+            return Some(SignalAction::Terminate(Signal::Kill));
+        }
+
+        if self.pending & Signal::Term.mask() != 0 {
+            return match self.handler {
+                Some(_) if self.frame.is_some() => None,
+                Some(handler) => {
+                    self.pending &= !Signal::Term.mask();
+                    Some(SignalAction::Handle(Signal::Term, handler))
+                }
+                None => {
+                    self.pending = 0;
+                    Some(SignalAction::Terminate(Signal::Term))
+                }
+            };
+        }
+
+        None
+    }
+}
+
+fn process_permissions(permissions: elf::Permissions) -> Permissions {
+    match permissions {
+        elf::Permissions {
+            read: true,
+            write: true,
+            exec: false,
+        } => Permissions::RW,
+        elf::Permissions {
+            read: true,
+            write: false,
+            exec: false,
+        } => Permissions::RO,
+        elf::Permissions {
+            read: _,
+            write: false,
+            exec: true,
+        } => Permissions::RX,
+        elf::Permissions {
+            read: true,
+            write: true,
+            exec: true,
+        } => Permissions::RWX,
+        elf::Permissions { read, write, exec } => {
+            let read = if read { "R" } else { "-" };
+            let write = if write { "W" } else { "-" };
+            let exec = if exec { "X" } else { "-" };
+            panic!(
+                "Unsupported set of permissions found in elf {}{}{}",
+                read, write, exec
+            );
+        }
+    }
+}
+
+/// The ELF spec requires the virtual address of a segment to be congruent with its file offset
+/// modulo the alignment. Alignments of 0 and 1 mean no alignment is required.
+fn is_segment_aligned(file_offset: u64, vaddr: u64, align: u64) -> bool {
+    align <= 1 || file_offset % align == vaddr % align
+}
+
+/// A `PT_LOAD` segment of an executable
+struct LoadableSegment<'a> {
+    name: &'a str,
+    vaddr: usize,
+    memsize: usize,
+    data: &'a [u8],
+    permissions: elf::Permissions,
+}
+
+/// Pages mapped for one or more loadable segments. Segments that share a page are mapped together
+/// with the permissions of all of them, since permissions apply to whole pages.
+struct SegmentMapping<'a> {
+    name: &'a str,
+    va: usize,
+    size_bytes: usize,
+    permissions: elf::Permissions,
+    /// Data of the segments along with its offset from `va`
+    chunks: Vec<(usize, &'a [u8])>,
+}
+
+fn plan_segment_mappings(mut segments: Vec<LoadableSegment<'_>>) -> Vec<SegmentMapping<'_>> {
+    let page_down = |addr: usize| addr & !(PAGE_SIZE - 1);
+    let page_up = |addr: usize| (addr + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+
+    segments.sort_by_key(|segment| segment.vaddr);
+
+    let mut mappings: Vec<SegmentMapping> = vec![];
+    for segment in segments {
+        let start = page_down(segment.vaddr);
+        let end = page_up(segment.vaddr + segment.memsize);
+
+        match mappings.last_mut() {
+            Some(mapping) if start < mapping.va + mapping.size_bytes => {
+                log_debug!(
+                    "Segment `{}` shares a page with `{}`",
+                    segment.name,
+                    mapping.name
+                );
+                mapping.size_bytes = mapping.size_bytes.max(end - mapping.va);
+                mapping.permissions = elf::Permissions {
+                    read: mapping.permissions.read || segment.permissions.read,
+                    write: mapping.permissions.write || segment.permissions.write,
+                    exec: mapping.permissions.exec || segment.permissions.exec,
+                };
+                mapping
+                    .chunks
+                    .push((segment.vaddr - mapping.va, segment.data));
+            }
+            _ => mappings.push(SegmentMapping {
+                name: segment.name,
+                va: start,
+                size_bytes: end - start,
+                permissions: segment.permissions,
+                chunks: vec![(segment.vaddr - start, segment.data)],
+            }),
+        }
+    }
+    mappings
+}
+
+/// A section mapped by `Builder::map_section`, along with the physical memory that backs it.
+struct LoadedSection {
+    va: VirtualAddress,
+    pa: PhysicalAddress,
+    size_bytes: usize,
+}
+
+/// Returns the physical address backing `size_bytes` at `va`, if they are all within one of the
+/// sections.
+fn loaded_section_pa(
+    sections: &[LoadedSection],
+    va: usize,
+    size_bytes: usize,
+) -> Option<PhysicalAddress> {
+    sections.iter().find_map(|section| {
+        let offset = va.checked_sub(section.va.as_usize())?;
+        if offset.checked_add(size_bytes)? > section.size_bytes {
+            return None;
+        }
+        Some(PhysicalAddress::from_unaligned_ptr(
+            (section.pa.as_usize() + offset) as *const _,
+        ))
+    })
+}
+
 pub struct Builder {
     address_space: ProcessAddressSpace,
+    sections: Vec<LoadedSection>,
     arguments: Vec<String>,
     environment: FlatMap<String, String>,
     entrypoint: Option<VirtualAddress>,
@@ -78,6 +392,7 @@ impl Default for Builder {
     fn default() -> Self {
         Self {
             address_space: ProcessAddressSpace::new(),
+            sections: vec![],
             arguments: vec![],
             environment: FlatMap::new(),
             entrypoint: None,
@@ -106,36 +421,12 @@ impl Builder {
         self.aslr_base = Some(aslr_base);
     }
 
-    fn copy_section(&mut self, pmr: &PhysicalMemoryRegion, data: &[u8]) {
-        // Initialize the physical page
-        let mut remaining_bytes = data.len();
-        let mut current_offset = 0;
-
-        for i in 0..pmr.num_pages() {
-            let pa = unsafe { pmr.base_address().offset(i * PAGE_SIZE) };
-            let chunk_size = if remaining_bytes >= PAGE_SIZE {
-                PAGE_SIZE
-            } else {
-                remaining_bytes
-            };
-
-            let page_data = &data[current_offset..];
-
-            // Try to perform a fast mapping of the page to load the contents
-            MemoryManager::instance().do_with_fast_map(
-                pa,
-                GlobalPermissions::new_only_privileged(Permissions::RW),
-                |va| unsafe {
-                    core::ptr::copy_nonoverlapping(page_data.as_ptr(), va.as_mut_ptr(), chunk_size);
-                },
-            );
-
-            remaining_bytes -= chunk_size;
-            current_offset += chunk_size;
-        }
-
-        assert_eq!(remaining_bytes, 0);
-        assert_eq!(current_offset, data.len());
+    fn copy_section(&mut self, pmr: &PhysicalMemoryRegion, offset: usize, data: &[u8]) {
+        assert!(offset + data.len() <= pmr.num_pages() * PAGE_SIZE);
+        let pa = PhysicalAddress::from_unaligned_ptr(
+            (pmr.base_address().as_usize() + offset) as *const _,
+        );
+        MemoryManager::instance().write_physical(pa, data);
     }
 
     pub fn map_section(
@@ -145,26 +436,62 @@ impl Builder {
         size_bytes: usize,
         data: &[u8],
         permissions: Permissions,
+    ) -> Result<(), Error> {
+        self.map_section_chunks(name, va, size_bytes, &[(0, data)], permissions)
+    }
+
+    /// Like `map_section`, but the data is made of chunks, each copied at its offset from `va`.
+    /// The rest of the section is zero-filled.
+    fn map_section_chunks(
+        &mut self,
+        name: &str,
+        va: VirtualAddress,
+        size_bytes: usize,
+        chunks: &[(usize, &[u8])],
+        permissions: Permissions,
     ) -> Result<(), Error> {
         log_debug!("Mapping section `{}` for new process", name);
 
-        // TODO(javier-varez): In reality this should be done lazily in most cases
-        assert!(size_bytes >= data.len());
+        check_w_xor_x(name, permissions, w_xor_x_policy())?;
+        let permissions = GlobalPermissions::new_for_process(permissions);
+        permissions.validate()?;
 
         let num_pages = num_pages_from_bytes(size_bytes);
         let pmr = MemoryManager::instance()
             .request_any_pages(num_pages, memory::AllocPolicy::ZeroFill)?;
 
-        self.copy_section(&pmr, data);
-
-        self.address_space.map_section(
-            name,
+        // TODO(javier-varez): In reality this should be done lazily in most cases
+        for (offset, data) in chunks {
+            assert!(size_bytes >= offset + data.len());
+            self.copy_section(&pmr, *offset, data);
+        }
+        self.sections.push(LoadedSection {
             va,
-            pmr,
+            pa: pmr.base_address(),
             size_bytes,
-            GlobalPermissions::new_for_process(permissions),
-        )?;
+        });
+
+        self.address_space
+            .map_section(name, va, pmr, size_bytes, permissions)?;
+
+        Ok(())
+    }
 
+    /// Applies the dynamic relocations of an executable loaded `base` bytes above its link
+    /// address. The sections are already mapped in the process, so the values are written to the
+    /// physical memory backing them.
+    fn apply_relocations(
+        &mut self,
+        entries: impl Iterator<Item = RelaEntry>,
+        base: usize,
+    ) -> Result<(), Error> {
+        for entry in entries {
+            let value = entry.value(base).map_err(Error::RelocationError)?;
+            let va = entry.offset.wrapping_add(base);
+            let pa = loaded_section_pa(&self.sections, va, core::mem::size_of::<usize>())
+                .ok_or(Error::InvalidRelocation(va))?;
+            MemoryManager::instance().write_physical(pa, &value.to_le_bytes());
+        }
         Ok(())
     }
 
@@ -306,8 +633,11 @@ impl Builder {
             thread_list: vec![],
             state: State::Running,
             pid,
+            name: self.arguments.first().cloned().unwrap_or_default(),
             aslr_base,
             elf_data: self.elf_data,
+            signals: SignalState::default(),
+            endpoints: EndpointTable::new(),
         })));
 
         // Lock before we create threads or we might get preempted before the process is valid, but
@@ -337,8 +667,9 @@ impl Builder {
             log_warning!("Elf file is not executable, bailing");
             return Err(Error::UnsupportedExecutable);
         }
+        elf.validate_segments().map_err(Error::ElfError)?;
 
-        let mut process_builder = Builder::new();
+        let mut segments = vec![];
         for header in elf.program_header_iter() {
             let header_type = header.ty().map_err(Error::ElfError)?;
             if matches!(header_type, elf::PtType::Load) {
@@ -350,58 +681,41 @@ impl Builder {
                     header.filesize()
                 );
 
-                let vaddr = (header.vaddr() as usize + aslr) as *const _;
-                let vaddr = VirtualAddress::try_from_ptr(vaddr)
-                    .map_err(|_| Error::UnalignedLoadableSegment)?;
-
-                let segment_data = elf.get_segment_data(&header);
-
-                let permissions = match header.permissions() {
-                    elf::Permissions {
-                        read: true,
-                        write: true,
-                        exec: false,
-                    } => Permissions::RW,
-                    elf::Permissions {
-                        read: true,
-                        write: false,
-                        exec: false,
-                    } => Permissions::RO,
-                    elf::Permissions {
-                        read: _,
-                        write: false,
-                        exec: true,
-                    } => Permissions::RX,
-                    elf::Permissions {
-                        read: true,
-                        write: true,
-                        exec: true,
-                    } => Permissions::RWX,
-                    elf::Permissions { read, write, exec } => {
-                        let read = if read { "R" } else { "-" };
-                        let write = if write { "W" } else { "-" };
-                        let exec = if exec { "X" } else { "-" };
-                        panic!(
-                            "Unsupported set of permissions found in elf {}{}{}",
-                            read, write, exec
-                        );
-                    }
-                };
+                if !is_segment_aligned(header.file_offset(), header.vaddr(), header.align()) {
+                    return Err(Error::UnalignedLoadableSegment);
+                }
 
-                process_builder.map_section(
-                    elf.matching_section_name(&header)
+                segments.push(LoadableSegment {
+                    name: elf
+                        .matching_section_name(&header)
                         .map_err(Error::ElfError)?
                         .unwrap_or(""),
-                    vaddr,
-                    header.memsize() as usize,
-                    segment_data,
-                    permissions,
-                )?;
+                    vaddr: header.vaddr() as usize,
+                    memsize: header.memsize() as usize,
+                    data: elf.get_segment_data(&header),
+                    permissions: header.permissions(),
+                });
             } else {
                 log_warning!("Unhandled ELF program header with type {:?}", header_type);
             }
         }
 
+        let mut process_builder = Builder::new();
+        for mapping in plan_segment_mappings(segments) {
+            let va = VirtualAddress::try_from_ptr((mapping.va + aslr) as *const _)
+                .map_err(|_| Error::UnalignedLoadableSegment)?;
+
+            process_builder.map_section_chunks(
+                mapping.name,
+                va,
+                mapping.size_bytes,
+                &mapping.chunks,
+                process_permissions(mapping.permissions),
+            )?;
+        }
+
+        process_builder.apply_relocations(elf.rela_iter(), aslr)?;
+
         process_builder.set_aslr_base(VirtualAddress::new_unaligned(aslr as *const _));
         let vaddr = (elf.entry_point() as usize + aslr) as *const _;
         process_builder.set_entrypoint(VirtualAddress::new_unaligned(vaddr));
@@ -417,8 +731,13 @@ pub struct Process {
     thread_list: Vec<ThreadHandle>,
     state: State,
     pid: u64,
+    /// The first argument of the process, like `argv[0]`
+    name: String,
     aslr_base: VirtualAddress,
     elf_data: Vec<u8>,
+    signals: SignalState,
+    /// Channel endpoints the process can use
+    endpoints: EndpointTable,
 }
 
 impl Process {
@@ -436,10 +755,7 @@ impl Process {
 
     pub fn exit_code(&self) -> Option<u64> {
         match self.state {
-            State::Killed(return_value) => {
-                // TODO(javier-varez): Reap process here somehow
-                Some(return_value)
-            }
+            State::Killed(return_value) => Some(return_value),
             State::Running => None,
         }
     }
@@ -495,24 +811,473 @@ pub(crate) fn kill_current_process(
             return Err(Error::NoCurrentProcess);
         }
     };
-    let mut processes = PROCESSES.lock();
 
-    let killed_proc = processes.iter_mut().find(|p| p.pid == pid.0).unwrap();
+    // Don't free process but instead keep it in a zombie state until states are collected. The
+    // lock is released before exiting threads, since scheduling the next thread might need it.
+    let (mut thread_list, endpoints) = {
+        let mut processes = PROCESSES.lock();
+        let killed_proc = processes.iter_mut().find(|p| p.pid == pid.0).unwrap();
 
-    log_info!(
-        "Killing process with PID {}, exit code 0x{:x}",
-        killed_proc.pid,
-        error_code
-    );
+        log_info!(
+            "Killing process with PID {}, exit code 0x{:x}",
+            killed_proc.pid,
+            error_code
+        );
+
+        killed_proc.state = State::Killed(error_code);
+        (
+            core::mem::take(&mut killed_proc.thread_list),
+            killed_proc.endpoints.take_all(),
+        )
+    };
+
+    // Nobody else can use the endpoints of the process, so its channels are closed right away
+    for endpoint in endpoints {
+        if channel::close(endpoint).is_ok() {
+            thread::wake_threads_waiting_on_channel(endpoint.channel_id());
+        }
+    }
 
-    thread::wake_threads_waiting_on_pid(&pid, error_code);
-    thread::exit_matching_threads(&mut killed_proc.thread_list, cx)?;
+    let has_waiters = thread::wake_threads_waiting_on_pid(&pid, error_code);
+    thread::exit_matching_threads(&mut thread_list, cx)?;
 
-    // Don't free process but instead keep it in a zombie state until states are collected
-    killed_proc.state = State::Killed(error_code);
+    // The exit code has already been collected by the waiters. At this point none of the threads
+    // of the process exist and its translation table is no longer active, so it can be reaped.
+    if has_waiters {
+        reap_process(&pid);
+    }
     Ok(())
 }
 
+/// Removes a killed process from the process list, returning its physical pages to the memory
+/// manager and freeing its address space. Running processes are left untouched.
+pub(crate) fn reap_process(handle: &ProcessHandle) {
+    let reaped = PROCESSES.lock().drain_filter(|process| {
+        process.pid == handle.0 && matches!(process.state, State::Killed(_))
+    });
+
+    reaped.release(|process| {
+        let mut process = unsafe { process.into_box() };
+        assert!(process.thread_list.is_empty());
+
+        log_debug!("Reaping process with PID {}", process.pid);
+        core::mem::take(&mut process.address_space).release(|memory| {
+            free_released_memory(memory).expect("Memory of the process cannot be released");
+        });
+    });
+}
+
+fn free_released_memory(memory: ReleasedMemory) -> Result<(), Error> {
+    match memory {
+        ReleasedMemory::Owned(pmr) => MemoryManager::instance().release_pages(pmr)?,
+        ReleasedMemory::Shared(name) => shared::release(&name)?,
+    }
+    Ok(())
+}
+
+/// Runs `f` with the channel endpoints of the current process. Returns `None` if the current thread
+/// is a kernel thread, which can use any endpoint.
+pub(crate) fn with_current_endpoints<T>(mut f: impl FnMut(&mut EndpointTable) -> T) -> Option<T> {
+    let pid = thread::current_pid()?;
+    Some(do_with_process(&pid, |process| f(&mut process.endpoints)))
+}
+
+/// Resolves an access flag fault at the given address in the address space of the current process.
+pub(crate) fn handle_access_flag_fault(va: VirtualAddress) -> bool {
+    match thread::current_pid() {
+        Some(pid) => do_with_process(&pid, |process| {
+            process.address_space.handle_access_flag_fault(va)
+        }),
+        None => false,
+    }
+}
+
+/// Resolves a write fault caused by dirty tracking in the address space of the current process.
+pub(crate) fn handle_write_fault(va: VirtualAddress) -> bool {
+    match thread::current_pid() {
+        Some(pid) => do_with_process(&pid, |process| process.address_space.handle_write_fault(va)),
+        None => false,
+    }
+}
+
+/// Marks the signal as pending in the given process. Signals sent to killed processes are ignored.
+pub(crate) fn send_signal(handle: &ProcessHandle, signal: Signal) {
+    do_with_process(handle, |process| {
+        if matches!(process.state, State::Running) {
+            process.signals.raise(signal);
+        }
+    });
+}
+
+/// Registers the signal handler of the current process. A null handler restores the default
+/// behavior of terminating the process.
+///
+/// The handler is entered with the signal number in x0 and must return with the `signal_return`
+/// syscall instead of a regular return, which is why userspace registers a trampoline here.
+pub(crate) fn set_signal_handler(handler: VirtualAddress) -> Result<(), Error> {
+    let pid = thread::current_pid().ok_or(Error::NoCurrentProcess)?;
+    do_with_process(&pid, |process| {
+        process.signals.handler = if handler.is_null() {
+            None
+        } else {
+            Some(handler)
+        };
+    });
+    Ok(())
+}
+
+/// Resumes the context that was interrupted by the signal handler of the current process.
+pub(crate) fn return_from_signal_handler(cx: &mut ExceptionContext) -> Result<(), Error> {
+    let pid = thread::current_pid().ok_or(Error::NoCurrentProcess)?;
+    let frame = do_with_process(&pid, |process| process.signals.frame.take());
+    match frame {
+        Some(frame) => frame.restore(cx),
+        None => {
+            log_warning!("signal_return called outside of a signal handler");
+        }
+    }
+    Ok(())
+}
+
+/// Delivers pending signals of the current process. Must be called right before returning to
+/// userspace, when `cx` holds the user context of the current thread.
+pub(crate) fn deliver_pending_signals(cx: &mut ExceptionContext) {
+    let pid = match thread::current_pid() {
+        Some(pid) => pid,
+        None => return,
+    };
+
+    let action = do_with_process(&pid, |process| match process.signals.take_next() {
+        Some(SignalAction::Handle(signal, handler)) => {
+            process.signals.frame = Some(SignalFrame::save(cx));
+            Some(SignalAction::Handle(signal, handler))
+        }
+        action => action,
+    });
+
+    match action {
+        Some(SignalAction::Terminate(signal)) => {
+            log_info!("Process with PID {} terminated by {:?}", pid.0, signal);
+            kill_current_process(cx, signal.exit_code()).unwrap();
+        }
+        Some(SignalAction::Handle(signal, handler)) => {
+            cx.elr_el1 = handler.as_u64();
+            cx.gpr[0] = signal as u64;
+        }
+        None => {}
+    }
+}
+
+/// Protection flags of anonymous mappings requested by userspace.
+pub const PROT_READ: u64 = 1 << 0;
+pub const PROT_WRITE: u64 = 1 << 1;
+pub const PROT_EXEC: u64 = 1 << 2;
+
+/// Translates protection flags to the permissions of a process mapping. Combinations that
+/// `GlobalPermissions::new_for_process` cannot express, like write-only memory, are rejected.
+fn permissions_from_prot(prot: u64) -> Option<Permissions> {
+    match prot {
+        PROT_READ => Some(Permissions::RO),
+        p if p == PROT_READ | PROT_WRITE => Some(Permissions::RW),
+        PROT_EXEC => Some(Permissions::RX),
+        p if p == PROT_READ | PROT_EXEC => Some(Permissions::RX),
+        p if p == PROT_READ | PROT_WRITE | PROT_EXEC => Some(Permissions::RWX),
+        _ => None,
+    }
+}
+
+/// Maps zero-filled memory into the address space of the current process and returns its address.
+/// Converts the protection flags of a new mapping of the current process, enforcing the W^X policy
+/// like for the sections of the executable.
+fn mapping_permissions(name: &str, prot: u64) -> Result<Permissions, Error> {
+    let permissions = permissions_from_prot(prot).ok_or(Error::InvalidPermissions)?;
+    check_w_xor_x(name, permissions, w_xor_x_policy())?;
+    Ok(permissions)
+}
+
+pub(crate) fn map_anonymous(size_bytes: usize, prot: u64) -> Result<VirtualAddress, Error> {
+    let permissions = mapping_permissions("mmap", prot)?;
+    if size_bytes == 0 {
+        return Err(Error::InvalidSize);
+    }
+    let pid = thread::current_pid().ok_or(Error::NoCurrentProcess)?;
+
+    let num_pages = num_pages_from_bytes(size_bytes);
+    let pmr =
+        MemoryManager::instance().request_any_pages(num_pages, memory::AllocPolicy::ZeroFill)?;
+
+    let result = do_with_process(&pid, |process| {
+        process.address_space.map_anonymous(
+            pmr.clone(),
+            num_pages * PAGE_SIZE,
+            GlobalPermissions::new_for_process(permissions),
+        )
+    });
+
+    match result {
+        Ok(va) => Ok(va),
+        Err(e) => {
+            MemoryManager::instance().release_pages(pmr)?;
+            Err(e.into())
+        }
+    }
+}
+
+/// Creates a named shared memory region and maps it into the current process.
+pub(crate) fn create_shared(
+    name: &str,
+    size_bytes: usize,
+    prot: u64,
+) -> Result<VirtualAddress, Error> {
+    let permissions = mapping_permissions(name, prot)?;
+    let pid = thread::current_pid().ok_or(Error::NoCurrentProcess)?;
+
+    let pmr = shared::create(name, size_bytes)?;
+    map_shared_region(&pid, name, pmr, permissions)
+}
+
+/// Maps an existing shared memory region into the current process.
+pub(crate) fn map_shared(name: &str, prot: u64) -> Result<VirtualAddress, Error> {
+    let permissions = mapping_permissions(name, prot)?;
+    let pid = thread::current_pid().ok_or(Error::NoCurrentProcess)?;
+
+    let pmr = shared::acquire(name)?;
+    map_shared_region(&pid, name, pmr, permissions)
+}
+
+fn map_shared_region(
+    pid: &ProcessHandle,
+    name: &str,
+    pmr: PhysicalMemoryRegion,
+    permissions: Permissions,
+) -> Result<VirtualAddress, Error> {
+    let size_bytes = pmr.num_pages() * PAGE_SIZE;
+    let result = do_with_process(pid, |process| {
+        process.address_space.map_shared(
+            name,
+            pmr.clone(),
+            size_bytes,
+            GlobalPermissions::new_for_process(permissions),
+        )
+    });
+
+    match result {
+        Ok(va) => Ok(va),
+        Err(e) => {
+            // Drop the reference taken for this mapping
+            shared::release(name)?;
+            Err(e.into())
+        }
+    }
+}
+
+/// Removes a mapping created by `map_anonymous`, `create_shared` or `map_shared` from the current
+/// process, freeing its memory if no one else uses it.
+pub(crate) fn unmap_memory(va: VirtualAddress, size_bytes: usize) -> Result<(), Error> {
+    let pid = thread::current_pid().ok_or(Error::NoCurrentProcess)?;
+    let memory = do_with_process(&pid, |process| process.address_space.unmap(va, size_bytes))?;
+    free_released_memory(memory)
+}
+
+/// Changes the protection flags of mapped memory of the current process. The memory must belong to
+/// a single mapping, although it does not need to cover all of it.
+pub(crate) fn protect_memory(
+    va: VirtualAddress,
+    size_bytes: usize,
+    prot: u64,
+) -> Result<(), Error> {
+    let permissions = mapping_permissions("mprotect", prot)?;
+    // Sizes that overflow or reach past user memory are invalid, as opposed to unmapped memory
+    if size_bytes == 0 || address_space::user_range_size(va, size_bytes).is_none() {
+        return Err(Error::InvalidSize);
+    }
+
+    let permissions = GlobalPermissions::new_for_process(permissions);
+    permissions.validate()?;
+
+    let pid = thread::current_pid().ok_or(Error::NoCurrentProcess)?;
+    let size_bytes = do_with_process(&pid, |process| {
+        process.address_space.protect(va, size_bytes, permissions)
+    })?;
+
+    // The address space of the current process is active, so its memory can be reached from here
+    if matches!(permissions.unprivileged, Permissions::RX | Permissions::RWX) {
+        arch::with_user_access(|| cache::sync_icache_range(va, size_bytes));
+    }
+    Ok(())
+}
+
+/// Returns a snapshot of all processes, including the killed ones that have not been reaped yet.
+pub fn list() -> Vec<ProcessInfo> {
+    let threads = thread::list();
+    let processes: Vec<(u64, String, Option<u64>, usize)> = PROCESSES
+        .lock()
+        .iter()
+        .map(|process| {
+            (
+                process.pid,
+                process.name.clone(),
+                process.exit_code(),
+                process.thread_list.len(),
+            )
+        })
+        .collect();
+
+    processes
+        .into_iter()
+        .map(|(pid, name, exit_code, num_threads)| {
+            let pid = ProcessHandle(pid);
+            let state = match exit_code {
+                Some(exit_code) => ProcessState::Killed(exit_code),
+                None => process_state(&pid, &threads),
+            };
+            ProcessInfo {
+                pid,
+                name,
+                state,
+                num_threads,
+            }
+        })
+        .collect()
+}
+
+fn process_state(pid: &ProcessHandle, threads: &[thread::ThreadInfo]) -> ProcessState {
+    let is_runnable = threads.iter().any(|thread| {
+        thread.process.as_ref() == Some(pid)
+            && matches!(
+                thread.state,
+                thread::ThreadState::Running | thread::ThreadState::Ready
+            )
+    });
+
+    if is_runnable {
+        ProcessState::Running
+    } else {
+        ProcessState::Sleeping
+    }
+}
+
+/// Returns a snapshot of the memory map of a process, sorted by address. The process list is only
+/// locked while taking the snapshot.
+pub fn memory_map(pid: &ProcessHandle) -> Option<Vec<RangeInfo>> {
+    let mut sections: Vec<RangeInfo> = PROCESSES
+        .lock()
+        .iter()
+        .find(|process| process.pid == pid.0)?
+        .address_space
+        .iter_sections()
+        .collect();
+
+    sections.sort_by_key(|section| section.va.as_usize());
+    Some(sections)
+}
+
+fn permissions_str(permissions: Permissions) -> &'static str {
+    match permissions {
+        Permissions::None => "---",
+        Permissions::RO => "r--",
+        Permissions::RW => "rw-",
+        Permissions::RX => "r-x",
+        Permissions::RWX => "rwx",
+    }
+}
+
+/// Formats a memory map like `/proc/self/maps` does, with one section per line:
+///
+/// ```text
+/// 0000000000010000-0000000000014000 r-x .text
+/// ```
+pub fn format_memory_map(sections: &[RangeInfo]) -> String {
+    let mut output = String::new();
+    for section in sections {
+        writeln!(
+            output,
+            "{:016x}-{:016x} {} {}",
+            section.va.as_usize(),
+            section.va.as_usize() + section.size_bytes,
+            permissions_str(section.permissions.unprivileged),
+            section.name
+        )
+        .unwrap();
+    }
+    output
+}
+
+/// Returns the formatted memory map of the current process.
+pub(crate) fn current_memory_map() -> Result<String, Error> {
+    let pid = thread::current_pid().ok_or(Error::NoCurrentProcess)?;
+    let sections = memory_map(&pid).ok_or(Error::NoCurrentProcess)?;
+    Ok(format_memory_map(&sections))
+}
+
+/// `maps <pid>` prints the memory map of a process.
+fn maps_command(args: &[&str]) {
+    let pid = match args.first().map(|pid| pid.parse::<u64>()) {
+        Some(Ok(pid)) => pid,
+        _ => {
+            println!("Usage: maps <pid>");
+            return;
+        }
+    };
+
+    match memory_map(&ProcessHandle(pid)) {
+        Some(sections) => print!("{}", format_memory_map(&sections)),
+        None => println!("No process with PID {}", pid),
+    }
+}
+
+fn ps_command(_args: &[&str]) {
+    println!("{:>5} {:>8} {:<10} NAME", "PID", "THREADS", "STATE");
+    for process in list() {
+        let state = match process.state {
+            ProcessState::Killed(exit_code) => alloc::format!("Killed({:#x})", exit_code),
+            state => alloc::format!("{:?}", state),
+        };
+        println!(
+            "{:>5} {:>8} {:<10} {}",
+            process.pid.get_raw(),
+            process.num_threads,
+            state,
+            process.name
+        );
+    }
+
+    println!();
+    println!(
+        "{:>5} {:>5} {:<10} {:>12} NAME",
+        "TID", "PID", "STATE", "TIME"
+    );
+    for thread in thread::list() {
+        let pid = match &thread.process {
+            Some(pid) => alloc::format!("{}", pid.get_raw()),
+            None => "-".to_string(),
+        };
+        println!(
+            "{:>5} {:>5} {:<10} {:>12} {}",
+            thread.tid,
+            pid,
+            alloc::format!("{:?}", thread.state),
+            alloc::format!("{:?}", thread.cpu_time),
+            thread.name().unwrap_or("<anonymous>")
+        );
+    }
+    println!("Idle time: {:?}", thread::idle_time());
+}
+
+#[initcall(priority = 0)]
+fn process_register_commands() {
+    shell::register_command("ps", ps_command).unwrap();
+    shell::register_command("maps", maps_command).unwrap();
+}
+
+#[initcall(priority = 0)]
+fn process_configure_policies() {
+    if boot_args::get_flag(W_XOR_X_CMDLINE_FLAG) {
+        log_info!("W^X policy enforced for processes");
+        set_w_xor_x_policy(true);
+    }
+}
+
 pub(crate) fn validate_pid(pid: u64) -> Option<ProcessHandle> {
     PROCESSES
         .lock()
@@ -520,3 +1285,346 @@ pub(crate) fn validate_pid(pid: u64) -> Option<ProcessHandle> {
         .find(|process| process.pid == pid)
         .map(|process| ProcessHandle(process.pid))
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn memory_map_format() {
+        let section = |name, va: usize, size_bytes, permissions| RangeInfo {
+            name: heapless::String::from(name),
+            va: VirtualAddress::try_from_ptr(va as *const _).unwrap(),
+            size_bytes,
+            attributes: memory::Attributes::Normal,
+            permissions: GlobalPermissions::new_for_process(permissions),
+        };
+
+        let sections = [
+            section(".text", 0x10000, PAGE_SIZE, Permissions::RX),
+            section(".data", 0x14000, 2 * PAGE_SIZE, Permissions::RW),
+            section(".args", 0xF80000000000, PAGE_SIZE, Permissions::RO),
+        ];
+        assert_eq!(
+            format_memory_map(&sections),
+            "0000000000010000-0000000000014000 r-x .text\n\
+             0000000000014000-000000000001c000 rw- .data\n\
+             0000f80000000000-0000f80000004000 r-- .args\n"
+        );
+        assert_eq!(format_memory_map(&[]), "");
+    }
+
+    #[test]
+    fn anonymous_mapping_permissions() {
+        assert!(matches!(
+            permissions_from_prot(PROT_READ),
+            Some(Permissions::RO)
+        ));
+        assert!(matches!(
+            permissions_from_prot(PROT_READ | PROT_WRITE),
+            Some(Permissions::RW)
+        ));
+        assert!(matches!(
+            permissions_from_prot(PROT_READ | PROT_EXEC),
+            Some(Permissions::RX)
+        ));
+        assert!(matches!(
+            permissions_from_prot(PROT_READ | PROT_WRITE | PROT_EXEC),
+            Some(Permissions::RWX)
+        ));
+        assert!(permissions_from_prot(0).is_none());
+        assert!(permissions_from_prot(PROT_WRITE).is_none());
+        assert!(permissions_from_prot(PROT_WRITE | PROT_EXEC).is_none());
+        assert!(permissions_from_prot(1 << 3).is_none());
+    }
+
+    #[test]
+    fn w_xor_x_policy_permissions() {
+        for permissions in [Permissions::RW, Permissions::RX, Permissions::RO] {
+            assert!(check_w_xor_x(".text", permissions, true).is_ok());
+            assert!(check_w_xor_x(".text", permissions, false).is_ok());
+        }
+
+        assert!(check_w_xor_x(".text", Permissions::RWX, false).is_ok());
+        assert!(matches!(
+            check_w_xor_x(".text", Permissions::RWX, true),
+            Err(Error::WritableAndExecutable)
+        ));
+    }
+
+    #[test]
+    fn w_xor_x_policy_mappings() {
+        const RWX: u64 = PROT_READ | PROT_WRITE | PROT_EXEC;
+
+        // Without the policy the request gets as far as looking up the current process
+        assert!(matches!(
+            map_anonymous(PAGE_SIZE, RWX),
+            Err(Error::NoCurrentProcess)
+        ));
+
+        set_w_xor_x_policy(true);
+        let mmap = map_anonymous(PAGE_SIZE, RWX);
+        let shm_create = create_shared("jit", PAGE_SIZE, RWX);
+        let shm_map = map_shared("jit", RWX);
+        let mprotect = protect_memory(
+            VirtualAddress::try_from_ptr(0x10000000 as *const _).unwrap(),
+            PAGE_SIZE,
+            RWX,
+        );
+        let mmap_rx = map_anonymous(PAGE_SIZE, PROT_READ | PROT_EXEC);
+        set_w_xor_x_policy(false);
+
+        for result in [mmap, shm_create, shm_map] {
+            assert!(matches!(result, Err(Error::WritableAndExecutable)));
+        }
+        assert!(matches!(mprotect, Err(Error::WritableAndExecutable)));
+        assert!(matches!(mmap_rx, Err(Error::NoCurrentProcess)));
+    }
+
+    #[test]
+    fn protect_memory_sizes() {
+        let va = VirtualAddress::try_from_ptr(0x10000000 as *const _).unwrap();
+        let rx = PROT_READ | PROT_EXEC;
+
+        for size_bytes in [0, usize::MAX, usize::MAX - PAGE_SIZE, 1 << 48] {
+            assert!(matches!(
+                protect_memory(va, size_bytes, rx),
+                Err(Error::InvalidSize)
+            ));
+        }
+
+        // Valid sizes get as far as looking up the current process
+        assert!(matches!(
+            protect_memory(va, PAGE_SIZE, rx),
+            Err(Error::NoCurrentProcess)
+        ));
+    }
+
+    #[test]
+    fn w_xor_x_policy_segments() {
+        const RX: elf::Permissions = elf::Permissions {
+            read: true,
+            write: false,
+            exec: true,
+        };
+        const RW: elf::Permissions = elf::Permissions {
+            read: true,
+            write: true,
+            exec: false,
+        };
+
+        let text = [0u8; 0x100];
+        let data = [0u8; 0x100];
+        let segment = |name, vaddr, permissions, data| LoadableSegment {
+            name,
+            vaddr,
+            memsize: 0x100,
+            data,
+            permissions,
+        };
+        let check = |segments| {
+            plan_segment_mappings(segments)
+                .iter()
+                .try_for_each(|mapping| {
+                    check_w_xor_x(mapping.name, process_permissions(mapping.permissions), true)
+                })
+        };
+
+        // Separate pages for code and data are allowed
+        assert!(check(vec![
+            segment(".text", 0x1000000, RX, &text[..]),
+            segment(".data", 0x1004000, RW, &data[..]),
+        ])
+        .is_ok());
+
+        // Code and data sharing a page need a writable and executable mapping
+        assert!(check(vec![
+            segment(".text", 0x1000000, RX, &text[..]),
+            segment(".data", 0x1000100, RW, &data[..]),
+        ])
+        .is_err());
+
+        // As do segments that are writable and executable themselves
+        let rwx = elf::Permissions {
+            read: true,
+            write: true,
+            exec: true,
+        };
+        assert!(check(vec![segment(".text", 0x1000000, rwx, &text[..])]).is_err());
+    }
+
+    #[test]
+    fn segment_alignment() {
+        assert!(is_segment_aligned(0x3a10, 0x1003a10, 0x4000));
+        assert!(is_segment_aligned(0x3a10, 0x1007a10, 0x4000));
+        assert!(!is_segment_aligned(0x3a10, 0x1003a00, 0x4000));
+        assert!(is_segment_aligned(0x3a10, 0x1003a00, 0));
+        assert!(is_segment_aligned(0x3a10, 0x1003a00, 1));
+    }
+
+    #[test]
+    fn segments_sharing_pages() {
+        const RX: elf::Permissions = elf::Permissions {
+            read: true,
+            write: false,
+            exec: true,
+        };
+        const RO: elf::Permissions = elf::Permissions {
+            read: true,
+            write: false,
+            exec: false,
+        };
+        const RW: elf::Permissions = elf::Permissions {
+            read: true,
+            write: true,
+            exec: false,
+        };
+
+        let text = [1u8; 0x3a10];
+        let rodata = [2u8; 0x200];
+        let data = [3u8; 0x80];
+        let segment = |name, vaddr, memsize, data, permissions| LoadableSegment {
+            name,
+            vaddr,
+            memsize,
+            data,
+            permissions,
+        };
+
+        // Data follows right after the text in the same page, with a .bss larger than a page.
+        // Rodata does not start at a page boundary either and comes first in the headers.
+        let mappings = plan_segment_mappings(vec![
+            segment(".rodata", 0x1008100, 0x200, &rodata[..], RO),
+            segment(".text", 0x1000000, 0x3a10, &text[..], RX),
+            segment(".data", 0x1003a10, 0x4100, &data[..], RW),
+        ]);
+        assert_eq!(mappings.len(), 2);
+
+        let text_and_data = &mappings[0];
+        assert_eq!(text_and_data.name, ".text");
+        assert_eq!(text_and_data.va, 0x1000000);
+        assert_eq!(text_and_data.size_bytes, 0x8000);
+        assert_eq!(
+            text_and_data.permissions,
+            elf::Permissions {
+                read: true,
+                write: true,
+                exec: true,
+            }
+        );
+        assert_eq!(text_and_data.chunks.len(), 2);
+        assert_eq!(text_and_data.chunks[0], (0, &text[..]));
+        assert_eq!(text_and_data.chunks[1], (0x3a10, &data[..]));
+
+        let rodata_mapping = &mappings[1];
+        assert_eq!(rodata_mapping.name, ".rodata");
+        assert_eq!(rodata_mapping.va, 0x1008000);
+        assert_eq!(rodata_mapping.size_bytes, PAGE_SIZE);
+        assert_eq!(rodata_mapping.permissions, RO);
+        assert_eq!(rodata_mapping.chunks, vec![(0x100, &rodata[..])]);
+    }
+
+    #[test]
+    fn relocation_targets() {
+        let section = |va: usize, pa: usize, size_bytes| LoadedSection {
+            va: VirtualAddress::new_unaligned(va as *const _),
+            pa: PhysicalAddress::from_unaligned_ptr(pa as *const _),
+            size_bytes,
+        };
+        let sections = [
+            section(0x1000000, 0x8_0000_0000, 0x4000),
+            section(0x1008000, 0x8_0010_0000, 0x100),
+        ];
+
+        let pa = |va| loaded_section_pa(&sections, va, 8).map(|pa| pa.as_usize());
+        assert_eq!(pa(0x1000000), Some(0x8_0000_0000));
+        assert_eq!(pa(0x1003ff8), Some(0x8_0000_3ff8));
+        assert_eq!(pa(0x1008010), Some(0x8_0010_0010));
+
+        // Out of every section or crossing the end of one
+        assert_eq!(pa(0xfffff8), None);
+        assert_eq!(pa(0x1003ffc), None);
+        assert_eq!(pa(0x1004000), None);
+        assert_eq!(pa(0x10080fc), None);
+    }
+
+    #[test]
+    fn signal_exit_codes() {
+        assert_eq!(Signal::Kill.exit_code(), SIGNAL_EXIT_CODE_FLAG | 9);
+        assert_eq!(
+            Signal::from_exit_code(Signal::Kill.exit_code()),
+            Some(Signal::Kill)
+        );
+        assert_eq!(
+            Signal::from_exit_code(Signal::Term.exit_code()),
+            Some(Signal::Term)
+        );
+
+        // Regular exit codes are not signals
+        assert_eq!(Signal::from_exit_code(0), None);
+        assert_eq!(Signal::from_exit_code(9), None);
+        assert_eq!(Signal::from_exit_code(0xdeadc0de), None);
+        assert_eq!(Signal::from_exit_code(SIGNAL_EXIT_CODE_FLAG | 2), None);
+    }
+
+    #[test]
+    fn default_signal_actions() {
+        let mut signals = SignalState::default();
+        assert_eq!(signals.take_next(), None);
+
+        signals.raise(Signal::Term);
+        assert_eq!(
+            signals.take_next(),
+            Some(SignalAction::Terminate(Signal::Term))
+        );
+        assert_eq!(signals.take_next(), None);
+
+        // SIGKILL takes precedence and cannot be handled
+        signals.handler = Some(VirtualAddress::new_unaligned(0x1000 as *const _));
+        signals.raise(Signal::Term);
+        signals.raise(Signal::Kill);
+        assert_eq!(
+            signals.take_next(),
+            Some(SignalAction::Terminate(Signal::Kill))
+        );
+        assert_eq!(signals.take_next(), None);
+    }
+
+    #[test]
+    fn handled_signals() {
+        let handler = VirtualAddress::new_unaligned(0x1000 as *const _);
+        let mut signals = SignalState {
+            handler: Some(handler),
+            ..SignalState::default()
+        };
+
+        signals.raise(Signal::Term);
+        assert_eq!(
+            signals.take_next(),
+            Some(SignalAction::Handle(Signal::Term, handler))
+        );
+
+        // While the handler runs other signals remain pending
+        let mut cx = ExceptionContext {
+            elr_el1: 0x2000,
+            sp_el0: 0x3000,
+            ..ExceptionContext::default()
+        };
+        cx.gpr[0] = 1;
+        cx.gpr[30] = 0x2004;
+        signals.frame = Some(SignalFrame::save(&cx));
+        signals.raise(Signal::Term);
+        assert_eq!(signals.take_next(), None);
+
+        // Returning from the handler restores the interrupted context
+        let mut handler_cx = ExceptionContext::default();
+        signals.frame.take().unwrap().restore(&mut handler_cx);
+        assert_eq!(handler_cx.elr_el1, 0x2000);
+        assert_eq!(handler_cx.sp_el0, 0x3000);
+        assert_eq!(handler_cx.gpr, cx.gpr);
+        assert_eq!(
+            signals.take_next(),
+            Some(SignalAction::Handle(Signal::Term, handler))
+        );
+    }
+}
@@ -264,3 +264,88 @@ mod cpacr {
 }
 
 pub use cpacr::CPACR;
+
+mod id_aa64pfr0_el1 {
+    tock_registers::register_bitfields! { u64,
+        pub ID_AA64PFR0_EL1 [
+            EL0 OFFSET(0) NUMBITS(4) [],
+            EL1 OFFSET(4) NUMBITS(4) [],
+            EL2 OFFSET(8) NUMBITS(4) [],
+            EL3 OFFSET(12) NUMBITS(4) [],
+        ]
+    }
+
+    crate::define_register!(ID_AA64PFR0_EL1, ID_AA64PFR0_EL1::Register, 3, 0, 0, 4, 0);
+}
+
+pub use id_aa64pfr0_el1::ID_AA64PFR0_EL1;
+
+mod id_aa64isar1_el1 {
+    tock_registers::register_bitfields! { u64,
+        pub ID_AA64ISAR1_EL1 [
+            APA OFFSET(4) NUMBITS(4) [],
+            API OFFSET(8) NUMBITS(4) [],
+            GPA OFFSET(24) NUMBITS(4) [],
+            GPI OFFSET(28) NUMBITS(4) [],
+        ]
+    }
+
+    crate::define_register!(ID_AA64ISAR1_EL1, ID_AA64ISAR1_EL1::Register, 3, 0, 0, 6, 1);
+}
+
+pub use id_aa64isar1_el1::ID_AA64ISAR1_EL1;
+
+mod id_aa64mmfr0_el1 {
+    tock_registers::register_bitfields! { u64,
+        pub ID_AA64MMFR0_EL1 [
+            PARANGE OFFSET(0) NUMBITS(4) [],
+            TGRAN16 OFFSET(20) NUMBITS(4) [],
+            TGRAN64 OFFSET(24) NUMBITS(4) [],
+            TGRAN4 OFFSET(28) NUMBITS(4) [],
+        ]
+    }
+
+    crate::define_register!(ID_AA64MMFR0_EL1, ID_AA64MMFR0_EL1::Register, 3, 0, 0, 7, 0);
+}
+
+pub use id_aa64mmfr0_el1::ID_AA64MMFR0_EL1;
+
+mod id_aa64mmfr1_el1 {
+    tock_registers::register_bitfields! { u64,
+        pub ID_AA64MMFR1_EL1 [
+            HAFDBS OFFSET(0) NUMBITS(4) [],
+            VH OFFSET(8) NUMBITS(4) [],
+            PAN OFFSET(20) NUMBITS(4) [],
+        ]
+    }
+
+    crate::define_register!(ID_AA64MMFR1_EL1, ID_AA64MMFR1_EL1::Register, 3, 0, 0, 7, 1);
+}
+
+pub use id_aa64mmfr1_el1::ID_AA64MMFR1_EL1;
+
+mod id_aa64mmfr2_el1 {
+    tock_registers::register_bitfields! { u64,
+        pub ID_AA64MMFR2_EL1 [
+            CNP OFFSET(0) NUMBITS(4) [],
+            UAO OFFSET(4) NUMBITS(4) [],
+            BBM OFFSET(52) NUMBITS(4) [],
+        ]
+    }
+
+    crate::define_register!(ID_AA64MMFR2_EL1, ID_AA64MMFR2_EL1::Register, 3, 0, 0, 7, 2);
+}
+
+pub use id_aa64mmfr2_el1::ID_AA64MMFR2_EL1;
+
+mod pan {
+    tock_registers::register_bitfields! { u64,
+        pub PAN [
+            PAN OFFSET(22) NUMBITS(1) [],
+        ]
+    }
+
+    crate::define_register!(PAN, PAN::Register, 3, 0, 4, 2, 3);
+}
+
+pub use pan::PAN;
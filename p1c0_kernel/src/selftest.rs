@@ -0,0 +1,103 @@
+//! On-device sanity checks that can be run on a regular boot, without the test framework.
+//!
+//! They are enabled by passing `CMDLINE_FLAG` in the kernel command line. Every check is
+//! independent, so a failing check is logged and the remaining checks still run.
+
+use crate::{
+    adt,
+    arch::mmu,
+    drivers::{generic_timer::get_timer, interfaces::timer::Timer},
+    memory::{address::VirtualAddress, map::ADT_VIRTUAL_BASE, MemoryManager},
+    prelude::*,
+};
+
+/// Command line argument that enables the self-test on boot.
+pub const CMDLINE_FLAG: &str = "p1c0.selftest";
+
+type Check = fn() -> Result<(), &'static str>;
+
+const CHECKS: &[(&str, Check)] = &[
+    ("mmu", check_mmu),
+    ("adt", check_adt),
+    ("timer", check_timer),
+    ("heap", check_heap),
+];
+
+fn check_mmu() -> Result<(), &'static str> {
+    if !mmu::is_initialized() {
+        return Err("MMU is not initialized");
+    }
+
+    let memory_manager = MemoryManager::instance();
+    let kernel_text = VirtualAddress::new_unaligned(run as *const _);
+    if memory_manager
+        .translate_kernel_address(kernel_text)
+        .is_err()
+    {
+        return Err("Kernel text is not mapped");
+    }
+
+    if memory_manager
+        .translate_kernel_address(ADT_VIRTUAL_BASE)
+        .is_err()
+    {
+        return Err("ADT is not mapped");
+    }
+    Ok(())
+}
+
+fn check_adt() -> Result<(), &'static str> {
+    let adt = adt::get_adt().map_err(|_| "ADT cannot be parsed")?;
+    let root = adt.find_node("/").ok_or("ADT has no root node")?;
+    if root.child_iter().next().is_none() {
+        return Err("ADT root node has no children");
+    }
+    Ok(())
+}
+
+fn check_timer() -> Result<(), &'static str> {
+    // Bounded, so that a stopped timer fails the check instead of hanging the boot
+    const MAX_ITERATIONS: usize = 1_000_000;
+
+    let timer = get_timer();
+    if timer.resolution().into_hz() == 0 {
+        return Err("Timer has no frequency");
+    }
+
+    let start = timer.ticks();
+    for _ in 0..MAX_ITERATIONS {
+        if timer.ticks() > start {
+            return Ok(());
+        }
+        core::hint::spin_loop();
+    }
+    Err("Timer is not advancing")
+}
+
+fn check_heap() -> Result<(), &'static str> {
+    let data: Vec<usize> = (0..1024).collect();
+    if data.iter().enumerate().all(|(i, value)| i == *value) {
+        Ok(())
+    } else {
+        Err("Heap allocation is corrupted")
+    }
+}
+
+/// Runs all checks and logs their results. Returns the name of the first failing check.
+pub fn run() -> Result<(), &'static str> {
+    let mut result = Ok(());
+    for (name, check) in CHECKS {
+        match check() {
+            Ok(()) => {
+                log_info!("Self-test `{}` passed", name);
+            }
+            Err(reason) => {
+                log_error!("Self-test `{}` failed: {}", name, reason);
+                if result.is_ok() {
+                    result = Err(*name);
+                }
+            }
+        }
+    }
+    result
+}
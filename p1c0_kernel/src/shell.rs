@@ -0,0 +1,166 @@
+//! Debug shell. Kernel subsystems register commands by name, which are then run from lines read
+//! from the console. The first word of a line selects the command and the remaining words are
+//! passed to it as arguments, so `adt dump /arm-io` runs the `adt` command with `["dump",
+//! "/arm-io"]`.
+
+use crate::{console, prelude::*, sync::spinlock::RwSpinLock};
+
+use p1c0_macros::initcall;
+
+pub type Command = fn(&[&str]);
+
+#[derive(Debug)]
+pub enum Error {
+    CommandAlreadyRegistered(String),
+    UnknownCommand(String),
+}
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+const PROMPT: &str = "p1c0> ";
+
+/// Splits a line into its words. Empty lines result in no words.
+fn parse_argv(line: &str) -> Vec<&str> {
+    line.split_whitespace().collect()
+}
+
+pub struct CommandRegistry {
+    commands: FlatMap<String, Command>,
+}
+
+impl CommandRegistry {
+    pub const fn new() -> Self {
+        Self {
+            commands: FlatMap::new_no_capacity(),
+        }
+    }
+
+    pub fn register(&mut self, name: &str, command: Command) -> Result<()> {
+        self.commands
+            .insert_with_strategy(
+                name.to_string(),
+                command,
+                flat_map::InsertStrategy::NoReplaceResize,
+            )
+            .map_err(|_| Error::CommandAlreadyRegistered(name.to_string()))
+    }
+
+    /// Looks up the command for a line and returns it together with its arguments. Empty lines
+    /// return `None`.
+    fn resolve<'a>(&self, line: &'a str) -> Result<Option<(Command, Vec<&'a str>)>> {
+        let argv = parse_argv(line);
+        let name = match argv.first() {
+            Some(name) => *name,
+            None => return Ok(None),
+        };
+
+        let command = *self
+            .commands
+            .lookup(name)
+            .ok_or_else(|| Error::UnknownCommand(name.to_string()))?;
+        Ok(Some((command, argv[1..].to_vec())))
+    }
+
+    /// Returns the names of the registered commands, sorted.
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.commands.iter().map(|(name, _)| name.clone()).collect();
+        names.sort_unstable();
+        names
+    }
+}
+
+impl Default for CommandRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Writer-preferring like `DRIVERS`, which requires read locks not to be nested. Commands do not run
+// with the lock held (see `dispatch`), so a command like `help` can take it again.
+static COMMANDS: RwSpinLock<CommandRegistry> =
+    RwSpinLock::new_writer_preferring(CommandRegistry::new());
+
+/// Commands are normally registered by their own module, in an initcall.
+pub fn register_command(name: &str, command: Command) -> Result<()> {
+    COMMANDS.lock_write().register(name, command)
+}
+
+/// Runs the command for a line. Empty lines are ignored.
+pub fn dispatch(line: &str) -> Result<()> {
+    // The lock is released before running the command, so that commands may use the shell too
+    let resolved = COMMANDS.lock_read().resolve(line)?;
+    if let Some((command, args)) = resolved {
+        command(&args);
+    }
+    Ok(())
+}
+
+/// Reads lines from the console and runs them as commands, forever. Must be called from a thread.
+pub fn run() -> ! {
+    loop {
+        print!("{}", PROMPT);
+        let line = console::read_line();
+        if let Err(Error::UnknownCommand(name)) = dispatch(&line) {
+            println!("Unknown command `{}`. Type `help` to list commands", name);
+        }
+    }
+}
+
+fn help(_args: &[&str]) {
+    for name in COMMANDS.lock_read().names() {
+        println!("{}", name);
+    }
+}
+
+#[initcall(priority = 0)]
+fn shell_register_commands() {
+    register_command("help", help).unwrap();
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::sync::Mutex;
+
+    static RECEIVED_ARGS: Mutex<Vec<String>> = Mutex::new(vec![]);
+
+    fn record_args(args: &[&str]) {
+        let mut received = RECEIVED_ARGS.lock().unwrap();
+        received.clear();
+        received.extend(args.iter().map(|arg| arg.to_string()));
+    }
+
+    fn nop(_args: &[&str]) {}
+
+    #[test]
+    fn argv_is_split_on_whitespace() {
+        assert_eq!(
+            parse_argv("  translate\t0x1000   "),
+            vec!["translate", "0x1000"]
+        );
+        assert!(parse_argv(" \t ").is_empty());
+    }
+
+    #[test]
+    fn dispatch_to_registered_command() {
+        let mut registry = CommandRegistry::new();
+        registry.register("adt", record_args).unwrap();
+        registry.register("ps", nop).unwrap();
+        assert!(matches!(
+            registry.register("adt", nop),
+            Err(Error::CommandAlreadyRegistered(name)) if name == "adt"
+        ));
+        assert_eq!(registry.names(), vec!["adt", "ps"]);
+
+        let (command, args) = registry.resolve(" adt  dump /arm-io").unwrap().unwrap();
+        command(&args);
+        assert_eq!(*RECEIVED_ARGS.lock().unwrap(), vec!["dump", "/arm-io"]);
+
+        assert!(registry.resolve("   ").unwrap().is_none());
+        assert!(matches!(
+            registry.resolve("mem stats"),
+            Err(Error::UnknownCommand(name)) if name == "mem"
+        ));
+    }
+}
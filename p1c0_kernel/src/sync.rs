@@ -1 +1,5 @@
+pub mod critical_section;
+pub mod once;
 pub mod spinlock;
+
+pub use critical_section::{critical_section, CriticalSection};
@@ -0,0 +1,61 @@
+#[cfg(all(not(test), target_arch = "aarch64"))]
+use aarch64_cpu::{asm::barrier, registers::DAIF};
+#[cfg(all(not(test), target_arch = "aarch64"))]
+use tock_registers::interfaces::{ReadWriteable, Readable, Writeable};
+
+/// Masks IRQs and FIQs while it is alive, restoring the previous mask when dropped. Since the
+/// previous mask is restored instead of unmasking interrupts, critical sections can be nested.
+///
+/// Locks acquired inside a critical section must not be held across scheduling points (sleeping,
+/// yielding or any other blocking syscall). The next thread would run with interrupts masked and
+/// could spin forever on the lock.
+pub struct CriticalSection {
+    #[cfg_attr(any(test, not(target_arch = "aarch64")), allow(dead_code))]
+    saved_daif: u64,
+}
+
+impl CriticalSection {
+    #[must_use]
+    pub fn enter() -> Self {
+        #[cfg(all(not(test), target_arch = "aarch64"))]
+        {
+            let saved_daif = DAIF.get();
+            DAIF.modify(DAIF::I::Masked + DAIF::F::Masked);
+            barrier::isb(barrier::SY);
+            Self { saved_daif }
+        }
+
+        // Host tests have no interrupts to mask
+        #[cfg(any(test, not(target_arch = "aarch64")))]
+        Self { saved_daif: 0 }
+    }
+}
+
+impl Drop for CriticalSection {
+    fn drop(&mut self) {
+        #[cfg(all(not(test), target_arch = "aarch64"))]
+        {
+            // Memory accesses of the critical section must complete before interrupts are unmasked
+            barrier::dsb(barrier::ISHST);
+            DAIF.set(self.saved_daif);
+        }
+    }
+}
+
+/// Runs `f` with IRQs and FIQs masked. This is the primitive to protect data shared with interrupt
+/// handlers. See `CriticalSection` for the restrictions that apply to `f`.
+pub fn critical_section<R>(f: impl FnOnce() -> R) -> R {
+    let _critical_section = CriticalSection::enter();
+    f()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn nested_critical_sections() {
+        let result = critical_section(|| critical_section(|| 42));
+        assert_eq!(result, 42);
+    }
+}
@@ -0,0 +1,155 @@
+use core::{cell::UnsafeCell, mem::MaybeUninit, sync::atomic};
+
+const INCOMPLETE: u8 = 0;
+const RUNNING: u8 = 1;
+const COMPLETE: u8 = 2;
+
+/// A value that is initialized exactly once, on first access.
+///
+/// The first caller of `get_or_init` runs the initializer while other callers spin until the value
+/// is ready. There is no poisoning: if the initializer panics, the next caller runs its own
+/// initializer instead.
+///
+/// It does not mask interrupts, so it must not be accessed from an interrupt handler that might
+/// interrupt the initializer.
+pub struct Once<T> {
+    state: atomic::AtomicU8,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+impl<T> Once<T> {
+    pub const fn new() -> Self {
+        Self {
+            state: atomic::AtomicU8::new(INCOMPLETE),
+            value: UnsafeCell::new(MaybeUninit::uninit()),
+        }
+    }
+
+    /// Returns the value if it has been initialized already. This only reads the state of the
+    /// `Once`, so it can be called before the MMU is enabled.
+    pub fn get(&self) -> Option<&T> {
+        if self.state.load(atomic::Ordering::Acquire) == COMPLETE {
+            Some(unsafe { (*self.value.get()).assume_init_ref() })
+        } else {
+            None
+        }
+    }
+
+    pub fn is_completed(&self) -> bool {
+        self.get().is_some()
+    }
+
+    pub fn get_or_init(&self, f: impl FnOnce() -> T) -> &T {
+        let mut f = Some(f);
+        loop {
+            match self.state.compare_exchange_weak(
+                INCOMPLETE,
+                RUNNING,
+                atomic::Ordering::Acquire,
+                atomic::Ordering::Acquire,
+            ) {
+                Ok(_) => {
+                    // Goes back to incomplete if the initializer panics
+                    let reset = ResetOnDrop(&self.state);
+                    let value = (f.take().unwrap())();
+                    unsafe { (*self.value.get()).write(value) };
+                    core::mem::forget(reset);
+
+                    self.state.store(COMPLETE, atomic::Ordering::Release);
+                }
+                Err(COMPLETE) => {}
+                Err(_) => {
+                    core::hint::spin_loop();
+                    continue;
+                }
+            }
+
+            return unsafe { (*self.value.get()).assume_init_ref() };
+        }
+    }
+}
+
+impl<T> Default for Once<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Drop for Once<T> {
+    fn drop(&mut self) {
+        if *self.state.get_mut() == COMPLETE {
+            unsafe { self.value.get_mut().assume_init_drop() };
+        }
+    }
+}
+
+unsafe impl<T: Send> Send for Once<T> {}
+
+unsafe impl<T: Send + Sync> Sync for Once<T> {}
+
+struct ResetOnDrop<'a>(&'a atomic::AtomicU8);
+
+impl<'a> Drop for ResetOnDrop<'a> {
+    fn drop(&mut self) {
+        self.0.store(INCOMPLETE, atomic::Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::{sync::Arc, thread, vec::Vec};
+
+    #[test]
+    fn initializes_once() {
+        let once = Once::new();
+        assert!(once.get().is_none());
+        assert!(!once.is_completed());
+
+        assert_eq!(*once.get_or_init(|| 1), 1);
+        assert_eq!(*once.get_or_init(|| 2), 1);
+        assert_eq!(once.get(), Some(&1));
+        assert!(once.is_completed());
+    }
+
+    #[test]
+    fn no_poisoning() {
+        let once = Once::new();
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            once.get_or_init(|| panic!("Initializer failed"));
+        }));
+        assert!(result.is_err());
+        assert!(once.get().is_none());
+
+        assert_eq!(*once.get_or_init(|| 3), 3);
+    }
+
+    #[test]
+    fn concurrent_initialization() {
+        static NUM_CALLS: atomic::AtomicUsize = atomic::AtomicUsize::new(0);
+        let once = Arc::new(Once::new());
+
+        let threads: Vec<_> = (0..8)
+            .map(|i| {
+                let once = once.clone();
+                thread::spawn(move || {
+                    *once.get_or_init(|| {
+                        NUM_CALLS.fetch_add(1, atomic::Ordering::Relaxed);
+                        thread::sleep(std::time::Duration::from_millis(10));
+                        i
+                    })
+                })
+            })
+            .collect();
+
+        let values: Vec<_> = threads
+            .into_iter()
+            .map(|thread| thread.join().unwrap())
+            .collect();
+
+        assert_eq!(NUM_CALLS.load(atomic::Ordering::Relaxed), 1);
+        assert!(values.iter().all(|value| *value == values[0]));
+        assert_eq!(once.get(), Some(&values[0]));
+    }
+}
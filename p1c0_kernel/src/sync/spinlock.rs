@@ -1,59 +1,101 @@
-use core::{cell::UnsafeCell, sync::atomic};
+use crate::drivers::{generic_timer::get_timer, interfaces::timer::Timer};
 
+use core::{cell::UnsafeCell, sync::atomic, time::Duration};
+
+#[cfg(all(not(test), target_arch = "aarch64"))]
 use aarch64_cpu::{asm::barrier, registers::DAIF};
+#[cfg(all(not(test), target_arch = "aarch64"))]
 use tock_registers::interfaces::{Readable, Writeable};
 
 static CRITICAL_NESTING: atomic::AtomicU32 = atomic::AtomicU32::new(0);
+#[cfg(all(not(test), target_arch = "aarch64"))]
 static mut SAVED_DAIF: u64 = 0;
 
 #[derive(Debug)]
 pub enum Error {
     WouldBlock,
+    TimedOut,
 }
 
 type Result<T> = core::result::Result<T, Error>;
 
+/// Calls `try_lock` until it succeeds or the timeout expires. The generic timer is read directly,
+/// so no other lock is taken while waiting.
+fn lock_with_timeout<G>(timeout: Duration, mut try_lock: impl FnMut() -> Result<G>) -> Result<G> {
+    let timer = get_timer();
+    let resolution = timer.resolution();
+    let start = resolution.ticks_to_duration(timer.ticks());
+    let deadline = resolution.duration_to_ticks(start + timeout);
+
+    loop {
+        match try_lock() {
+            Ok(guard) => return Ok(guard),
+            Err(_) if timer.ticks() >= deadline => return Err(Error::TimedOut),
+            Err(_) => core::hint::spin_loop(),
+        }
+    }
+}
+
 fn get_then_mask_daif() -> u64 {
-    let saved_daif = DAIF.get();
+    #[cfg(all(not(test), target_arch = "aarch64"))]
+    {
+        let saved_daif = DAIF.get();
+
+        DAIF.write(DAIF::D::Masked + DAIF::I::Masked + DAIF::A::Masked + DAIF::F::Masked);
+        barrier::dsb(barrier::ISHST);
+        saved_daif
+    }
 
-    DAIF.write(DAIF::D::Masked + DAIF::I::Masked + DAIF::A::Masked + DAIF::F::Masked);
-    barrier::dsb(barrier::ISHST);
-    saved_daif
+    // Host tests have no exceptions to mask
+    #[cfg(any(test, not(target_arch = "aarch64")))]
+    0
 }
 
-fn restore_saved_daif(saved_daif: u64) {
-    // Restore daif. This gives the processor a chance to run some
-    // interrupt/exceptions while looping
-    DAIF.set(saved_daif);
+fn restore_saved_daif(_saved_daif: u64) {
+    #[cfg(all(not(test), target_arch = "aarch64"))]
+    {
+        // Restore daif. This gives the processor a chance to run some
+        // interrupt/exceptions while looping
+        DAIF.set(_saved_daif);
 
-    // Add a barrier here to ensure that subsequent memory accesses really execute
-    // out of the critical section
-    barrier::dsb(barrier::ISHST);
+        // Add a barrier here to ensure that subsequent memory accesses really execute
+        // out of the critical section
+        barrier::dsb(barrier::ISHST);
+    }
 }
 
-fn increment_critical_nesting(saved_daif: u64) {
-    assert_eq!(DAIF.read(DAIF::D), 1);
-    assert_eq!(DAIF.read(DAIF::A), 1);
-    assert_eq!(DAIF.read(DAIF::I), 1);
-    assert_eq!(DAIF.read(DAIF::F), 1);
+fn increment_critical_nesting(_saved_daif: u64) {
+    #[cfg(all(not(test), target_arch = "aarch64"))]
+    {
+        assert_eq!(DAIF.read(DAIF::D), 1);
+        assert_eq!(DAIF.read(DAIF::A), 1);
+        assert_eq!(DAIF.read(DAIF::I), 1);
+        assert_eq!(DAIF.read(DAIF::F), 1);
+    }
 
     let prev_nesting = CRITICAL_NESTING.fetch_add(1, atomic::Ordering::Acquire);
     if prev_nesting == u32::MAX {
         panic!("We have reached the maximum value for CRITICAL_NESTING. This is MOST LIKELY a bug in user code");
     } else if prev_nesting == 0 {
         // Save the daif value for later when it is unlocked
-        unsafe { SAVED_DAIF = saved_daif };
+        #[cfg(all(not(test), target_arch = "aarch64"))]
+        unsafe {
+            SAVED_DAIF = _saved_daif
+        };
     }
 }
 
 fn decrement_critical_nesting() {
     let prev_nesting = CRITICAL_NESTING.fetch_sub(1, atomic::Ordering::Release);
     if prev_nesting == 1 {
-        // Add a barrier here to ensure that memory accesses finish before enabling exceptions
-        barrier::dsb(barrier::ISHST);
+        #[cfg(all(not(test), target_arch = "aarch64"))]
+        {
+            // Add a barrier here to ensure that memory accesses finish before enabling exceptions
+            barrier::dsb(barrier::ISHST);
 
-        // Restore daif settings
-        unsafe { DAIF.set(SAVED_DAIF) };
+            // Restore daif settings
+            unsafe { DAIF.set(SAVED_DAIF) };
+        }
     }
 }
 
@@ -87,6 +129,12 @@ impl<T: ?Sized> SpinLock<T> {
         }
     }
 
+    /// Spins until the lock is acquired or the timeout expires. Useful when blocking forever could
+    /// deadlock, like in interrupt handlers.
+    pub fn lock_timeout(&self, timeout: Duration) -> Result<SpinLockGuard<'_, T>> {
+        lock_with_timeout(timeout, || self.try_lock())
+    }
+
     pub fn try_lock(&self) -> Result<SpinLockGuard<'_, T>> {
         let saved_daif = get_then_mask_daif();
 
@@ -149,22 +197,41 @@ impl<'a, T: ?Sized> Drop for SpinLockGuard<'a, T> {
 
 pub struct RwSpinLock<T: ?Sized> {
     lock: atomic::AtomicU32,
+    writer_preferring: bool,
     data: UnsafeCell<T>,
 }
 
 impl<T> RwSpinLock<T> {
+    /// Creates a lock that lets new readers in as long as the lock is not held by a writer. Writers
+    /// may starve if there are always readers holding the lock.
     pub const fn new(data: T) -> Self {
         Self {
             lock: atomic::AtomicU32::new(0),
+            writer_preferring: false,
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    /// Creates a lock that stops letting new readers in once a writer is waiting in `lock_write`.
+    /// The writer acquires the lock as soon as the current readers release it, so writers cannot
+    /// starve.
+    ///
+    /// Because of this, a thread must not take a second read lock while it holds one, since a
+    /// writer might be waiting for the first one to be released.
+    pub const fn new_writer_preferring(data: T) -> Self {
+        Self {
+            lock: atomic::AtomicU32::new(0),
+            writer_preferring: true,
             data: UnsafeCell::new(data),
         }
     }
 }
 
 impl<T: ?Sized> RwSpinLock<T> {
-    const WRITE_LOCK_FLAG: u32 = 1;
-    const NUM_READERS_OFFSET: u32 = 1;
-    const NUM_READERS_MASK: u32 = 0xFFFFFFFE;
+    const WRITE_LOCK_FLAG: u32 = 1 << 0;
+    const WRITER_WAITING_FLAG: u32 = 1 << 1;
+    const NUM_READERS_OFFSET: u32 = 2;
+    const NUM_READERS_MASK: u32 = 0xFFFFFFFC;
 
     /// # Safety
     ///   In order for this to be safe you need to manually ensure that there is no other thread
@@ -178,7 +245,9 @@ impl<T: ?Sized> RwSpinLock<T> {
             let saved_daif = get_then_mask_daif();
 
             let lock = self.lock.load(atomic::Ordering::Relaxed);
-            if (lock & Self::WRITE_LOCK_FLAG) != 0 {
+            let writer_waiting = self.writer_preferring && (lock & Self::WRITER_WAITING_FLAG) != 0;
+            if (lock & Self::WRITE_LOCK_FLAG) != 0 || writer_waiting {
+                restore_saved_daif(saved_daif);
                 return Err(Error::WouldBlock);
             }
 
@@ -252,15 +321,28 @@ impl<T: ?Sized> RwSpinLock<T> {
     }
 
     pub fn try_lock_write(&self) -> Result<WriteGuard<'_, T>> {
+        self.try_lock_write_inner(false)
+    }
+
+    /// Attempts to lock for writing. If the lock is busy and `wait` is set, writer-preferring locks
+    /// are flagged so that new readers are kept out until a writer gets the lock.
+    fn try_lock_write_inner(&self, wait: bool) -> Result<WriteGuard<'_, T>> {
         loop {
             let saved_daif = get_then_mask_daif();
 
             let lock = self.lock.load(atomic::Ordering::Relaxed);
             if ((lock & Self::WRITE_LOCK_FLAG) != 0) || ((lock & Self::NUM_READERS_MASK) != 0) {
+                if wait && self.writer_preferring {
+                    self.lock
+                        .fetch_or(Self::WRITER_WAITING_FLAG, atomic::Ordering::Relaxed);
+                }
+                restore_saved_daif(saved_daif);
                 return Err(Error::WouldBlock);
             }
 
-            let new_lock = lock | Self::WRITE_LOCK_FLAG;
+            // Acquiring the lock satisfies any waiting writer. Other writers that are still waiting
+            // set the flag again on their next attempt.
+            let new_lock = (lock | Self::WRITE_LOCK_FLAG) & !Self::WRITER_WAITING_FLAG;
 
             match self.lock.compare_exchange_weak(
                 lock,
@@ -322,11 +404,26 @@ impl<T: ?Sized> RwSpinLock<T> {
 
     pub fn lock_write(&self) -> WriteGuard<'_, T> {
         loop {
-            if let Ok(guard) = self.try_lock_write() {
+            if let Ok(guard) = self.try_lock_write_inner(true) {
                 return guard;
             }
         }
     }
+
+    pub fn lock_read_timeout(&self, timeout: Duration) -> Result<ReadGuard<'_, T>> {
+        lock_with_timeout(timeout, || self.try_lock_read())
+    }
+
+    /// Like `lock_write`, a writer-preferring lock keeps new readers out while this is waiting.
+    pub fn lock_write_timeout(&self, timeout: Duration) -> Result<WriteGuard<'_, T>> {
+        let result = lock_with_timeout(timeout, || self.try_lock_write_inner(true));
+        if result.is_err() {
+            // Let readers in again. Other waiting writers set the flag again on their next attempt
+            self.lock
+                .fetch_and(!Self::WRITER_WAITING_FLAG, atomic::Ordering::Relaxed);
+        }
+        result
+    }
 }
 
 unsafe impl<T: ?Sized> Send for RwSpinLock<T> {}
@@ -1,5 +1,20 @@
 use crate::{
-    arch::exceptions::ExceptionContext, prelude::*, process, sync::spinlock::SpinLock, thread,
+    arch::exceptions::ExceptionContext,
+    channel,
+    drivers::{
+        generic_timer::get_timer,
+        interfaces::{timer::Timer, watchdog},
+    },
+    font,
+    memory::{
+        self,
+        address::{Address, VirtualAddress},
+        address_space,
+    },
+    prelude::*,
+    process,
+    sync::spinlock::SpinLock,
+    thread,
 };
 
 macro_rules! gen_syscall_caller {
@@ -357,6 +372,19 @@ define_syscalls!(
     [6, PutString, puts, handle_puts, (*const u8, usize)],
     [7, WaitPid, wait_pid, handle_wait_pid, (u64) -> u64],
     [8, Exit, exit, handle_exit, (u64)],
+    [9, Kill, kill, handle_kill, (u64, u64) -> u64],
+    [10, SignalHandler, signal_handler, handle_signal_handler, (*const u8)],
+    [11, SignalReturn, signal_return, handle_signal_return, ()],
+    [12, Mmap, mmap, handle_mmap, (usize, u64) -> u64],
+    [13, Munmap, munmap, handle_munmap, (*const u8, usize) -> u64],
+    [14, ShmCreate, shm_create, handle_shm_create, (*const u8, usize, usize, u64) -> u64],
+    [15, ShmMap, shm_map, handle_shm_map, (*const u8, usize, u64) -> u64],
+    [16, ChannelCreate, channel_create, handle_channel_create, (*mut u64, *mut u64) -> u64],
+    [17, ChannelSend, channel_send, handle_channel_send, (u64, *const u8, usize) -> u64],
+    [18, ChannelRecv, channel_recv, handle_channel_recv, (u64, *mut u8, usize) -> u64],
+    [19, ChannelClose, channel_close, handle_channel_close, (u64) -> u64],
+    [20, Mprotect, mprotect, handle_mprotect, (*const u8, usize, u64) -> u64],
+    [21, MemoryMap, memory_map, handle_memory_map, (*mut u8, usize) -> u64],
     [0x8000, Multiply, multiply, handle_multiply, (u32, u32) -> u32],
 );
 
@@ -368,13 +396,56 @@ fn handle_noop(_cx: &mut ExceptionContext) {
     log_info!("Syscall Noop");
 }
 
+/// Long enough for the watchdog to expire with its default timeout
+const REBOOT_GRACE_PERIOD: core::time::Duration = core::time::Duration::from_secs(6);
+const REBOOT_WATCHDOG_TIMEOUT: core::time::Duration = core::time::Duration::from_millis(1);
+
+#[derive(Debug, PartialEq, Eq)]
+enum RebootError {
+    NoWatchdog,
+    StillRunning,
+}
+
+/// Calls `wait_for_reset`, which returns if the system did not reset. In that case the watchdog is
+/// armed with a minimal timeout and waited for again. Only returns if both attempts fail.
+fn reboot_with_fallback(
+    mut wait_for_reset: impl FnMut(),
+    arm_watchdog: impl FnOnce() -> bool,
+) -> RebootError {
+    wait_for_reset();
+
+    log_warning!("The system did not reset, forcing it with the watchdog");
+    if !arm_watchdog() {
+        return RebootError::NoWatchdog;
+    }
+    wait_for_reset();
+    RebootError::StillRunning
+}
+
 fn handle_reboot(_cx: &mut ExceptionContext) {
     log_warning!("Syscall Reboot - Rebooting computer");
     unsafe {
         print::force_flush();
     }
 
-    // We hang here never servicing the WDT again, causing a reboot
+    // We spin here never servicing the WDT again, causing a reboot
+    let wait_for_reset = || {
+        let timer = get_timer();
+        let resolution = timer.resolution();
+        let start = resolution.ticks_to_duration(timer.ticks());
+        let deadline = resolution.duration_to_ticks(start + REBOOT_GRACE_PERIOD);
+        while timer.ticks() < deadline {
+            core::hint::spin_loop();
+        }
+    };
+    let arm_watchdog =
+        || watchdog::may_do_with_watchdog(|watchdog| watchdog.configure(REBOOT_WATCHDOG_TIMEOUT));
+
+    let error = reboot_with_fallback(wait_for_reset, arm_watchdog);
+    log_error!("Unable to reboot: {:?}", error);
+    unsafe {
+        print::force_flush();
+    }
     loop {
         aarch64_cpu::asm::wfi();
     }
@@ -406,17 +477,26 @@ fn handle_puts(_cx: &mut ExceptionContext, str_ptr: *const u8, length: usize) {
         return;
     }
 
-    // We have to trust the user process... If a fault happens, it will be delivered to it anyway
-    let slice = unsafe { core::slice::from_raw_parts(str_ptr, length) };
-    if let Ok(string) = core::str::from_utf8(slice) {
-        // TODO(javier-varez): Of course this needs to be redirected to stdout instead of using the klog system...
+    // Validate the string before allocating a buffer for it, since userspace chooses the length
+    if let Err(e) = memory::check_user_access(str_ptr, length, false) {
+        log_warning!("puts with invalid string: {:?}", e);
+        return;
+    }
 
-        log_info!(
-            "Message from userspace pid {:?}: {}",
-            thread::current_pid(),
-            string
-        );
+    let mut bytes = vec![0; length];
+    if let Err(e) = memory::copy_from_user(str_ptr, &mut bytes) {
+        log_warning!("puts with invalid string: {:?}", e);
+        return;
     }
+    // Invalid UTF-8 is shown with replacement characters instead of dropping the message
+    let string: String = font::decode_utf8(&bytes).collect();
+    // TODO(javier-varez): Of course this needs to be redirected to stdout instead of using the klog system...
+
+    log_info!(
+        "Message from userspace pid {:?}: {}",
+        thread::current_pid(),
+        string
+    );
 }
 
 fn handle_wait_pid(cx: &mut ExceptionContext, pid: u64) -> u64 {
@@ -434,7 +514,10 @@ fn handle_wait_pid(cx: &mut ExceptionContext, pid: u64) -> u64 {
 
     let exit_code = process::do_with_process(&pid, |process| process.exit_code());
     match exit_code {
-        Some(val) => val,
+        Some(val) => {
+            process::reap_process(&pid);
+            val
+        }
         None => {
             thread::wait_for_pid_in_current_thread(cx, pid);
             cx.gpr[0]
@@ -446,3 +529,356 @@ fn handle_exit(cx: &mut ExceptionContext, exit_code: u64) {
     // This can only be called from a process. Calling it from the kernel itself causes a panic
     process::kill_current_process(cx, exit_code).unwrap();
 }
+
+fn handle_kill(_cx: &mut ExceptionContext, pid: u64, signal: u64) -> u64 {
+    let pid = match process::validate_pid(pid) {
+        None => {
+            return 0xFFFF;
+        }
+        Some(val) => val,
+    };
+
+    let signal = match process::Signal::try_from(signal) {
+        Err(_) => {
+            return 0xFFFE;
+        }
+        Ok(val) => val,
+    };
+
+    // If the target is the current process the signal is delivered when the syscall returns
+    process::send_signal(&pid, signal);
+    0
+}
+
+fn handle_signal_handler(_cx: &mut ExceptionContext, handler: *const u8) {
+    // This can only be called from a process. Calling it from the kernel itself causes a panic
+    process::set_signal_handler(VirtualAddress::new_unaligned(handler)).unwrap();
+}
+
+fn handle_signal_return(cx: &mut ExceptionContext) {
+    process::return_from_signal_handler(cx).unwrap();
+}
+
+fn handle_mmap(_cx: &mut ExceptionContext, size_bytes: usize, prot: u64) -> u64 {
+    // A null address signals the error to userspace
+    match process::map_anonymous(size_bytes, prot) {
+        Ok(va) => va.as_u64(),
+        Err(e) => {
+            log_warning!("mmap of 0x{:x} bytes failed: {:?}", size_bytes, e);
+            0
+        }
+    }
+}
+
+fn handle_munmap(_cx: &mut ExceptionContext, addr: *const u8, size_bytes: usize) -> u64 {
+    let va = match VirtualAddress::try_from_ptr(addr) {
+        Ok(va) => va,
+        Err(_) => {
+            return 0xFFFF;
+        }
+    };
+
+    match process::unmap_memory(va, size_bytes) {
+        Ok(()) => 0,
+        Err(e) => {
+            log_warning!("munmap of {} failed: {:?}", va, e);
+            0xFFFF
+        }
+    }
+}
+
+/// Error codes of `mprotect`. The values match the ones used by Linux.
+pub const EFAULT: u64 = 14;
+pub const EINVAL: u64 = 22;
+
+/// Memory that is not mapped is reported with `EFAULT`, any other invalid argument with `EINVAL`.
+fn mprotect_error_code(error: &process::Error) -> u64 {
+    match error {
+        process::Error::AddressSpaceError(address_space::Error::InvalidAddress) => EFAULT,
+        _ => EINVAL,
+    }
+}
+
+fn handle_mprotect(
+    _cx: &mut ExceptionContext,
+    addr: *const u8,
+    size_bytes: usize,
+    prot: u64,
+) -> u64 {
+    let va = match VirtualAddress::try_from_ptr(addr) {
+        Ok(va) => va,
+        Err(_) => {
+            return EINVAL;
+        }
+    };
+
+    match process::protect_memory(va, size_bytes, prot) {
+        Ok(()) => 0,
+        Err(e) => {
+            log_warning!("mprotect of {} failed: {:?}", va, e);
+            mprotect_error_code(&e)
+        }
+    }
+}
+
+/// Writes the memory map of the calling process to the buffer and returns the length of the whole
+/// map, which is larger than the buffer if the map was truncated. Returns 0 on error.
+fn handle_memory_map(_cx: &mut ExceptionContext, buffer_ptr: *mut u8, length: usize) -> u64 {
+    let map = match process::current_memory_map() {
+        Ok(map) => map,
+        Err(e) => {
+            log_warning!("Reading the memory map failed: {:?}", e);
+            return 0;
+        }
+    };
+
+    let copy_length = length.min(map.len());
+    match memory::copy_to_user(buffer_ptr, &map.as_bytes()[..copy_length]) {
+        Ok(()) => map.len() as u64,
+        Err(e) => {
+            log_warning!("Writing the memory map failed: {:?}", e);
+            0
+        }
+    }
+}
+
+/// Names of shared memory regions are short, since they are part of the name of the mapping.
+const MAX_USER_STR_LENGTH: usize = 64;
+
+/// Copies a NUL-terminated string of `length` bytes passed by userspace. Returns `None` if it
+/// cannot be read, is longer than `MAX_USER_STR_LENGTH`, does not end at `length` or is not valid
+/// UTF-8.
+fn user_str(str_ptr: *const u8, length: usize) -> Option<String> {
+    if length > MAX_USER_STR_LENGTH {
+        return None;
+    }
+
+    memory::copy_user_cstr(str_ptr, length)
+        .ok()
+        .filter(|string| string.len() == length)
+}
+
+fn handle_shm_create(
+    _cx: &mut ExceptionContext,
+    name_ptr: *const u8,
+    name_length: usize,
+    size_bytes: usize,
+    prot: u64,
+) -> u64 {
+    let name = match user_str(name_ptr, name_length) {
+        Some(name) => name,
+        None => {
+            return 0;
+        }
+    };
+
+    // A null address signals the error to userspace
+    match process::create_shared(&name, size_bytes, prot) {
+        Ok(va) => va.as_u64(),
+        Err(e) => {
+            log_warning!("Creating shared region `{}` failed: {:?}", name, e);
+            0
+        }
+    }
+}
+
+fn handle_shm_map(
+    _cx: &mut ExceptionContext,
+    name_ptr: *const u8,
+    name_length: usize,
+    prot: u64,
+) -> u64 {
+    let name = match user_str(name_ptr, name_length) {
+        Some(name) => name,
+        None => {
+            return 0;
+        }
+    };
+
+    match process::map_shared(&name, prot) {
+        Ok(va) => va.as_u64(),
+        Err(e) => {
+            log_warning!("Mapping shared region `{}` failed: {:?}", name, e);
+            0
+        }
+    }
+}
+
+fn handle_channel_create(_cx: &mut ExceptionContext, tx_ptr: *mut u64, rx_ptr: *mut u64) -> u64 {
+    if tx_ptr.is_null() || rx_ptr.is_null() {
+        return channel::ERROR_INVALID_ENDPOINT;
+    }
+
+    let (tx, rx) = channel::create();
+
+    let result = memory::copy_to_user(tx_ptr as *mut u8, &tx.get_raw().to_ne_bytes())
+        .and_then(|()| memory::copy_to_user(rx_ptr as *mut u8, &rx.get_raw().to_ne_bytes()));
+    if result.is_err() {
+        // Nobody can use the channel, so it is freed right away
+        let _ = channel::close(tx);
+        let _ = channel::close(rx);
+        return channel::ERROR_FAULT;
+    }
+
+    process::with_current_endpoints(|endpoints| {
+        endpoints.insert(tx);
+        endpoints.insert(rx);
+    });
+    0
+}
+
+/// Checks that the current process can use the endpoint. Kernel threads can use any endpoint.
+fn owns_endpoint(endpoint: channel::Endpoint) -> bool {
+    process::with_current_endpoints(|endpoints| endpoints.contains(endpoint)).unwrap_or(true)
+}
+
+fn handle_channel_send(
+    _cx: &mut ExceptionContext,
+    endpoint: u64,
+    data_ptr: *const u8,
+    length: usize,
+) -> u64 {
+    let endpoint = channel::Endpoint::from_raw(endpoint);
+    if !owns_endpoint(endpoint) {
+        return channel::ERROR_INVALID_ENDPOINT;
+    }
+
+    if length > channel::Channel::MAX_MESSAGE_SIZE || (length > 0 && data_ptr.is_null()) {
+        return channel::ERROR_INVALID_SIZE;
+    }
+
+    let mut data = vec![0; length];
+    if memory::copy_from_user(data_ptr, &mut data).is_err() {
+        return channel::ERROR_FAULT;
+    }
+
+    match channel::send(endpoint, &data) {
+        Ok(()) => {
+            thread::wake_threads_waiting_on_channel(endpoint.channel_id());
+            0
+        }
+        Err(e) => e.code(),
+    }
+}
+
+/// Returns the length of the received message. Blocks the calling thread while the channel is
+/// empty.
+fn handle_channel_recv(
+    cx: &mut ExceptionContext,
+    endpoint: u64,
+    buffer_ptr: *mut u8,
+    length: usize,
+) -> u64 {
+    let endpoint = channel::Endpoint::from_raw(endpoint);
+    if !owns_endpoint(endpoint) {
+        return channel::ERROR_INVALID_ENDPOINT;
+    }
+
+    if length > 0 && buffer_ptr.is_null() {
+        return channel::ERROR_INVALID_SIZE;
+    }
+
+    // Checked before receiving, so that the message is not lost if the buffer is invalid
+    if memory::check_user_access(buffer_ptr, length, true).is_err() {
+        return channel::ERROR_FAULT;
+    }
+
+    // Messages are received in the kernel first, none of them is larger than this
+    let mut buffer = vec![0; length.min(channel::Channel::MAX_MESSAGE_SIZE)];
+
+    match channel::try_receive(endpoint, &mut buffer) {
+        Ok(Some(message_length)) => {
+            match memory::copy_to_user(buffer_ptr, &buffer[..message_length]) {
+                Ok(()) => message_length as u64,
+                Err(_) => channel::ERROR_FAULT,
+            }
+        }
+        Ok(None) => {
+            // The syscall is issued again once a message arrives
+            thread::wait_for_channel_in_current_thread(cx, endpoint.channel_id());
+            cx.gpr[0]
+        }
+        Err(e) => e.code(),
+    }
+}
+
+fn handle_channel_close(_cx: &mut ExceptionContext, endpoint: u64) -> u64 {
+    let endpoint = channel::Endpoint::from_raw(endpoint);
+    let owned =
+        process::with_current_endpoints(|endpoints| endpoints.remove(endpoint)).unwrap_or(true);
+    if !owned {
+        return channel::ERROR_INVALID_ENDPOINT;
+    }
+
+    match channel::close(endpoint) {
+        Ok(()) => {
+            // Blocked receivers need to find out that the sender is gone
+            thread::wake_threads_waiting_on_channel(endpoint.channel_id());
+            0
+        }
+        Err(e) => e.code(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::cell::RefCell;
+
+    #[test]
+    fn reboot_falls_back_to_watchdog() {
+        let events = RefCell::new(vec![]);
+        let error = reboot_with_fallback(
+            || events.borrow_mut().push("wait"),
+            || {
+                events.borrow_mut().push("arm");
+                true
+            },
+        );
+
+        assert_eq!(error, RebootError::StillRunning);
+        assert_eq!(*events.borrow(), vec!["wait", "arm", "wait"]);
+    }
+
+    #[test]
+    fn reboot_without_watchdog() {
+        let waits = RefCell::new(0);
+        let error = reboot_with_fallback(|| *waits.borrow_mut() += 1, || false);
+
+        assert_eq!(error, RebootError::NoWatchdog);
+        assert_eq!(*waits.borrow(), 1);
+    }
+
+    #[test]
+    fn user_strings() {
+        // Kernel threads pass kernel pointers, so the host memory can be read directly
+        let name = b"region\0";
+        assert_eq!(user_str(name.as_ptr(), 6).as_deref(), Some("region"));
+
+        // The string must end exactly at the given length
+        assert_eq!(user_str(name.as_ptr(), 5), None);
+        assert_eq!(user_str(b"reg\0ion\0".as_ptr(), 7), None);
+
+        let long_name = [b'a'; MAX_USER_STR_LENGTH + 2];
+        assert_eq!(user_str(long_name.as_ptr(), MAX_USER_STR_LENGTH + 1), None);
+    }
+
+    #[test]
+    fn mprotect_error_codes() {
+        assert_eq!(
+            mprotect_error_code(&process::Error::AddressSpaceError(
+                address_space::Error::InvalidAddress
+            )),
+            EFAULT
+        );
+        assert_eq!(
+            mprotect_error_code(&process::Error::InvalidPermissions),
+            EINVAL
+        );
+        assert_eq!(
+            mprotect_error_code(&process::Error::WritableAndExecutable),
+            EINVAL
+        );
+    }
+}
@@ -29,6 +29,23 @@ use tock_registers::interfaces::Readable;
 #[derive(Debug, PartialEq, Clone)]
 pub enum Error {
     ThreadNotFound,
+    NameAlreadyInUse(ThreadName),
+}
+
+/// Longer thread names are truncated
+pub const MAX_THREAD_NAME_LENGTH: usize = 32;
+
+pub type ThreadName = String<MAX_THREAD_NAME_LENGTH>;
+
+/// Truncates the name to `MAX_THREAD_NAME_LENGTH` bytes, keeping whole characters.
+fn truncated_name(name: &str) -> ThreadName {
+    let mut truncated = ThreadName::new();
+    for c in name.chars() {
+        if truncated.push(c).is_err() {
+            break;
+        }
+    }
+    truncated
 }
 
 enum Stack {
@@ -60,9 +77,9 @@ impl Stack {
         }
     }
 
-    fn len(&self) -> usize {
+    fn size_bytes(&self) -> usize {
         match &self {
-            Stack::KernelThread(stack) => stack.len(),
+            Stack::KernelThread(stack) => core::mem::size_of_val(&stack[..]),
             Stack::ProcessThread(_, size) => *size,
         }
     }
@@ -70,7 +87,7 @@ impl Stack {
     fn validator(&self) -> StackValidator {
         StackValidator {
             range_base: self.base(),
-            range_len: self.len(),
+            range_len: self.size_bytes(),
         }
     }
 }
@@ -87,7 +104,94 @@ impl address::Validator for StackValidator {
         let range_len = self.range_len;
 
         let ptr = ptr.as_usize();
-        (ptr >= range_base) || (ptr < (range_base + range_len))
+        (ptr >= range_base) && (ptr < (range_base + range_len))
+    }
+}
+
+pub type Priority = u8;
+
+pub const MIN_PRIORITY: Priority = 0;
+pub const DEFAULT_PRIORITY: Priority = 8;
+pub const MAX_PRIORITY: Priority = 15;
+
+/// Number of times a ready thread can be passed over before its priority is raised by one level.
+/// This guarantees that low priority threads eventually run.
+const AGING_PERIOD: u32 = 4;
+
+struct SchedulingState {
+    priority: Priority,
+    /// Number of scheduling decisions that picked another thread while this one was ready
+    passed_over: u32,
+}
+
+impl SchedulingState {
+    fn new(priority: Priority) -> Self {
+        Self {
+            priority,
+            passed_over: 0,
+        }
+    }
+
+    fn effective_priority(&self) -> u32 {
+        let aged = self.priority as u32 + self.passed_over / AGING_PERIOD;
+        aged.min(MAX_PRIORITY as u32)
+    }
+}
+
+/// Returns the position in the ready queue of the thread to run next. The thread with the highest
+/// effective priority is picked and ties are resolved in queue order, which round-robins threads of
+/// the same priority since preempted threads go to the back of the queue.
+fn select_next<'a>(ready: impl Iterator<Item = &'a SchedulingState>) -> Option<usize> {
+    let mut selected: Option<(usize, u32)> = None;
+    for (index, state) in ready.enumerate() {
+        let priority = state.effective_priority();
+        match selected {
+            Some((_, selected_priority)) if selected_priority >= priority => {}
+            _ => selected = Some((index, priority)),
+        }
+    }
+    selected.map(|(index, _)| index)
+}
+
+/// Ages all ready threads that were passed over in favor of the selected one.
+fn age_passed_over<'a>(ready: impl Iterator<Item = &'a mut SchedulingState>, selected: usize) {
+    for (index, state) in ready.enumerate() {
+        if index == selected {
+            state.passed_over = 0;
+        } else {
+            state.passed_over = state.passed_over.saturating_add(1);
+        }
+    }
+}
+
+/// Time a thread has spent running, accounted at every context switch.
+#[derive(Default)]
+struct CpuTime {
+    total: Ticks,
+    switched_in_at: Option<Ticks>,
+}
+
+impl CpuTime {
+    fn switch_in(&mut self, now: Ticks) {
+        self.switched_in_at = Some(now);
+    }
+
+    fn switch_out(&mut self, now: Ticks) {
+        if let Some(switched_in_at) = self.switched_in_at.take() {
+            self.total = self
+                .total
+                .saturating_add(now.saturating_sub(switched_in_at));
+        }
+    }
+
+    /// Includes the time since the thread was switched in if it is currently running.
+    fn total(&self, now: Ticks) -> Ticks {
+        match self.switched_in_at {
+            Some(switched_in_at) => self
+                .total
+                .saturating_add(now.saturating_sub(switched_in_at)),
+            None => self.total,
+        }
     }
 }
 
@@ -95,15 +199,19 @@ enum BlockReason {
     Sleep(Ticks),
     Join(ThreadHandle),
     WaitForPid(ProcessHandle),
+    ChannelReceive(u64),
 }
 
 pub struct ThreadControlBlock {
     tid: u64,
-    name: String<32>,
+    name: ThreadName,
     process: Option<ProcessHandle>,
     entry: Option<Box<dyn FnOnce()>>,
     stack: Stack,
     is_idle_thread: bool,
+    /// For the idle thread this is the time the CPU has been idle
+    cpu_time: CpuTime,
+    scheduling: SchedulingState,
 
     // Blocking conditions
     block_reason: Option<BlockReason>,
@@ -123,6 +231,57 @@ impl ThreadControlBlock {
             Some(&self.name)
         }
     }
+
+    fn wake_deadline(&self) -> Option<Ticks> {
+        match self.block_reason {
+            Some(BlockReason::Sleep(ticks)) => Some(ticks),
+            _ => None,
+        }
+    }
+
+    fn info(&self, state: ThreadState, now: Ticks) -> ThreadInfo {
+        ThreadInfo {
+            tid: self.tid,
+            name: self.name.clone(),
+            process: self.process.clone(),
+            state,
+            priority: self.scheduling.priority,
+            cpu_time: get_timer()
+                .resolution()
+                .ticks_to_duration(self.cpu_time.total(now)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThreadState {
+    Running,
+    Ready,
+    Sleeping,
+    /// Waiting for another thread, a process or a channel
+    Blocked,
+}
+
+/// Snapshot of the state of a thread, as returned by `list`.
+#[derive(Debug, Clone)]
+pub struct ThreadInfo {
+    pub tid: u64,
+    pub name: ThreadName,
+    pub process: Option<ProcessHandle>,
+    pub state: ThreadState,
+    pub priority: Priority,
+    /// Time the thread has spent running
+    pub cpu_time: Duration,
+}
+
+impl ThreadInfo {
+    pub fn name(&self) -> Option<&str> {
+        if self.name.is_empty() {
+            None
+        } else {
+            Some(&self.name)
+        }
+    }
 }
 
 type Tcb = OwnedMutPtr<IntrusiveItem<ThreadControlBlock>>;
@@ -133,6 +292,10 @@ static ACTIVE_THREADS: SpinLock<IntrusiveList<ThreadControlBlock>> =
 static BLOCKED_THREADS: SpinLock<IntrusiveList<ThreadControlBlock>> =
     SpinLock::new(IntrusiveList::new());
 
+/// Threads blocked in `sleep_current_thread`, sorted by wake-up deadline
+static SLEEPING_THREADS: SpinLock<IntrusiveList<ThreadControlBlock>> =
+    SpinLock::new(IntrusiveList::new());
+
 static CURRENT_THREAD: SpinLock<Option<Tcb>> = SpinLock::new(None);
 static IDLE_THREAD: SpinLock<Option<Tcb>> = SpinLock::new(None);
 
@@ -148,14 +311,20 @@ extern "C" fn thread_start(thread_control_block: &mut ThreadControlBlock) {
 pub struct ThreadHandle(u64);
 
 impl ThreadHandle {
+    pub fn tid(&self) -> u64 {
+        self.0
+    }
+
     pub fn join(self) {
         Syscall::thread_join(self.0);
     }
 }
 
 pub struct Builder {
-    name: Option<String<32>>,
+    name: Option<ThreadName>,
     stack_size: Option<usize>,
+    unique_name: bool,
+    priority: Priority,
 }
 
 impl Default for Builder {
@@ -169,12 +338,33 @@ impl Builder {
         Self {
             name: None,
             stack_size: None,
+            unique_name: false,
+            priority: DEFAULT_PRIORITY,
         }
     }
 
+    /// Names longer than `MAX_THREAD_NAME_LENGTH` bytes are truncated.
     #[must_use]
     pub fn name(mut self, name: &str) -> Self {
-        self.name = Some(String::from(name));
+        let truncated = truncated_name(name);
+        if truncated.len() < name.len() {
+            log_warning!("Thread name `{}` truncated to `{}`", name, truncated);
+        }
+        self.name = Some(truncated);
+        self
+    }
+
+    /// Threads with a higher priority run first. Priorities above `MAX_PRIORITY` are clamped.
+    #[must_use]
+    pub fn priority(mut self, priority: Priority) -> Self {
+        self.priority = priority.min(MAX_PRIORITY);
+        self
+    }
+
+    /// Makes `try_spawn` fail if another thread already has the same name.
+    #[must_use]
+    pub fn unique_name(mut self) -> Self {
+        self.unique_name = true;
         self
     }
 
@@ -198,6 +388,7 @@ impl Builder {
 
         let name = self.name.unwrap_or_default();
         let stack_size = self.stack_size.unwrap_or(DEFAULT_STACK_SIZE);
+        let priority = self.priority;
         let stack = Stack::new(stack_size);
         let stack_ptr = stack.top();
         let elr = thread_start as usize;
@@ -215,23 +406,53 @@ impl Builder {
             block_reason: None,
             regs,
             elr: elr as u64,
-            spsr: spsr.get(),
+            spsr: arch::pan::kernel_thread_spsr(spsr.get()),
             stack_ptr,
             is_idle_thread: false,
+            cpu_time: CpuTime::default(),
+            scheduling: SchedulingState::new(priority),
         })));
         tcb.regs[0] = (&mut **tcb) as *mut ThreadControlBlock as u64;
 
         tcb
     }
 
-    pub fn spawn<F>(self, thread: F) -> ThreadHandle
+    pub fn try_spawn<F>(self, thread: F) -> Result<ThreadHandle, Error>
     where
         F: FnOnce() + Send + 'static,
     {
+        // The name is checked with all thread lists locked, so that no other thread with the same
+        // name can be spawned in between.
+        let current_thread = CURRENT_THREAD.lock();
+        let mut threads = ACTIVE_THREADS.lock();
+        let blocked_threads = BLOCKED_THREADS.lock();
+        let sleeping_threads = SLEEPING_THREADS.lock();
+
+        if let (true, Some(name)) = (self.unique_name, &self.name) {
+            let mut all_threads = current_thread
+                .iter()
+                .map(|tcb| &**tcb)
+                .chain(threads.iter())
+                .chain(blocked_threads.iter())
+                .chain(sleeping_threads.iter());
+            if all_threads.any(|tcb| tcb.name == *name) {
+                return Err(Error::NameAlreadyInUse(name.clone()));
+            }
+        }
+
         let tcb = self.create(thread);
         let tid = tcb.tid;
-        ACTIVE_THREADS.lock().push(tcb);
-        ThreadHandle(tid)
+        threads.push(tcb);
+        Ok(ThreadHandle(tid))
+    }
+
+    /// Spawns the thread. Panics if a unique name was requested and it is already in use.
+    pub fn spawn<F>(self, thread: F) -> ThreadHandle
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.try_spawn(thread)
+            .expect("Unable to spawn thread with a unique name")
     }
 }
 
@@ -271,6 +492,8 @@ pub(crate) fn new_for_process(
         spsr: spsr.get(),
         stack_ptr,
         is_idle_thread: false,
+        cpu_time: CpuTime::default(),
+        scheduling: SchedulingState::new(DEFAULT_PRIORITY),
     })));
     tcb.regs[0] = argc as u64;
     tcb.regs[1] = argv.as_u64();
@@ -297,7 +520,7 @@ pub fn initialize() -> ! {
     let thread = ACTIVE_THREADS.lock().pop().expect("No threads found!");
     current_thread.replace(thread);
 
-    let tcb = current_thread.as_ref().unwrap();
+    let tcb = current_thread.as_mut().unwrap();
 
     // TODO(javier-varez): This should be a regular context switch or otherwise there are no guarantees on the value of registers on entry...
     let mut cx = ExceptionContext::default();
@@ -308,13 +531,15 @@ pub fn initialize() -> ! {
 }
 
 fn save_thread_context(thread: &mut Tcb, cx: &ExceptionContext) {
+    thread.cpu_time.switch_out(get_timer().ticks());
     thread.spsr = cx.spsr_el1.as_raw();
     thread.stack_ptr = cx.sp_el0;
     thread.regs.copy_from_slice(&cx.gpr[..]);
     thread.elr = cx.elr_el1;
 }
 
-fn restore_thread_context(cx: &mut ExceptionContext, thread: &Tcb) {
+fn restore_thread_context(cx: &mut ExceptionContext, thread: &mut Tcb) {
+    thread.cpu_time.switch_in(get_timer().ticks());
     cx.spsr_el1.read_from_raw(thread.spsr);
     cx.sp_el0 = thread.stack_ptr;
     cx.gpr.copy_from_slice(&thread.regs[..]);
@@ -330,19 +555,36 @@ fn restore_thread_context(cx: &mut ExceptionContext, thread: &Tcb) {
     }
 }
 
+/// Pops the threads whose deadline has passed from the head of a list sorted by deadline. Only
+/// the threads that wake up are visited.
+fn pop_expired<T>(
+    sleeping: &mut IntrusiveList<T>,
+    now: Ticks,
+    deadline: impl Fn(&T) -> Ticks,
+) -> IntrusiveList<T> {
+    let mut expired = IntrusiveList::new();
+    while let Some(thread) = sleeping.front() {
+        if deadline(thread) > now {
+            break;
+        }
+        expired.push(sleeping.pop().unwrap());
+    }
+    expired
+}
+
 fn wake_asleep_threads() {
     let current_ticks = get_timer().ticks();
-    let unblocked_threads = BLOCKED_THREADS.lock().drain_filter(|thread| {
-        if let BlockReason::Sleep(ticks) = thread.block_reason.as_ref().unwrap() {
-            return *ticks <= current_ticks;
-        }
-        false
+    let unblocked_threads = pop_expired(&mut SLEEPING_THREADS.lock(), current_ticks, |thread| {
+        thread
+            .wake_deadline()
+            .expect("Sleeping thread without a deadline")
     });
 
     ACTIVE_THREADS.lock().join(unblocked_threads);
 }
 
-pub(crate) fn wake_threads_waiting_on_pid(pid: &ProcessHandle, exit_code: u64) {
+/// Returns true if any thread was waiting on the process.
+pub(crate) fn wake_threads_waiting_on_pid(pid: &ProcessHandle, exit_code: u64) -> bool {
     let mut unblocked_threads = BLOCKED_THREADS.lock().drain_filter(|thread| {
         if let BlockReason::WaitForPid(p) = thread.block_reason.as_ref().unwrap() {
             return p == pid;
@@ -355,23 +597,39 @@ pub(crate) fn wake_threads_waiting_on_pid(pid: &ProcessHandle, exit_code: u64) {
         thread.regs[0] = exit_code;
     });
 
+    let has_waiters = !unblocked_threads.is_empty();
+    ACTIVE_THREADS.lock().join(unblocked_threads);
+    has_waiters
+}
+
+/// Wakes up the threads blocked on the given channel, which retry their syscall once scheduled.
+pub(crate) fn wake_threads_waiting_on_channel(channel_id: u64) {
+    let unblocked_threads = BLOCKED_THREADS.lock().drain_filter(|thread| {
+        if let BlockReason::ChannelReceive(id) = thread.block_reason.as_ref().unwrap() {
+            return *id == channel_id;
+        }
+        false
+    });
+
     ACTIVE_THREADS.lock().join(unblocked_threads);
 }
 
 fn schedule_next_thread() -> Tcb {
     wake_asleep_threads();
 
-    // This is the actual round-robin scheduling algo... For now it works, but it is obviously not
-    // optimal
-    ACTIVE_THREADS
-        .lock()
-        .pop()
-        .unwrap_or_else(|| IDLE_THREAD.lock().take().unwrap())
+    let mut threads = ACTIVE_THREADS.lock();
+    match select_next(threads.iter().map(|tcb| &tcb.scheduling)) {
+        Some(index) => {
+            age_passed_over(threads.iter_mut().map(|tcb| &mut tcb.scheduling), index);
+            threads.remove(index).unwrap()
+        }
+        None => IDLE_THREAD.lock().take().unwrap(),
+    }
 }
 
 pub fn run_scheduler(cx: &mut ExceptionContext) {
     // This should run scheduler and perform context switch.
-    // At this point the simplest form of round robin scheduling is implemented.
+    // The highest priority ready thread runs next, see `select_next`.
 
     let mut current_thread = CURRENT_THREAD.lock();
 
@@ -392,8 +650,8 @@ pub fn run_scheduler(cx: &mut ExceptionContext) {
         ACTIVE_THREADS.lock().push(thread);
     }
 
-    let thread = schedule_next_thread();
-    restore_thread_context(cx, &thread);
+    let mut thread = schedule_next_thread();
+    restore_thread_context(cx, &mut thread);
     current_thread.replace(thread);
 }
 
@@ -417,10 +675,12 @@ pub fn sleep_current_thread(cx: &mut ExceptionContext, duration: Duration) {
     let target_ticks = timer_res.duration_to_ticks(time_since_epoch + duration);
 
     thread.block_reason = Some(BlockReason::Sleep(target_ticks));
-    BLOCKED_THREADS.lock().push(thread);
+    SLEEPING_THREADS
+        .lock()
+        .insert_sorted_by_key(thread, |thread| thread.wake_deadline());
 
-    let thread = schedule_next_thread();
-    restore_thread_context(cx, &thread);
+    let mut thread = schedule_next_thread();
+    restore_thread_context(cx, &mut thread);
     current_thread.replace(thread);
 }
 
@@ -451,13 +711,13 @@ pub fn exit_current_thread(cx: &mut ExceptionContext) {
     // Exit the thread
     exit_thread(thread);
 
-    let thread = schedule_next_thread();
-    restore_thread_context(cx, &thread);
+    let mut thread = schedule_next_thread();
+    restore_thread_context(cx, &mut thread);
     current_thread.replace(thread);
 }
 
 fn validate_thread_handle(tid: u64) -> bool {
-    // TODO(javier-varez): This could be made way more efficient than a linear search in two
+    // TODO(javier-varez): This could be made way more efficient than a linear search in three
     // containers.
     if ACTIVE_THREADS.lock().iter().any(|thread| thread.tid == tid) {
         return true;
@@ -471,6 +731,14 @@ fn validate_thread_handle(tid: u64) -> bool {
         return true;
     }
 
+    if SLEEPING_THREADS
+        .lock()
+        .iter()
+        .any(|thread| thread.tid == tid)
+    {
+        return true;
+    }
+
     false
 }
 
@@ -492,38 +760,71 @@ pub fn join_thread(cx: &mut ExceptionContext, tid: u64) {
     thread.block_reason = Some(BlockReason::Join(ThreadHandle(tid)));
     BLOCKED_THREADS.lock().push(thread);
 
-    let thread = schedule_next_thread();
-    restore_thread_context(cx, &thread);
+    let mut thread = schedule_next_thread();
+    restore_thread_context(cx, &mut thread);
     current_thread.replace(thread);
 }
 
-pub fn print_thread_info() {
+/// Returns a snapshot of all threads. The idle thread is only listed while it runs.
+pub fn list() -> Vec<ThreadInfo> {
+    // All lists are locked at once so that no thread is missed while moving between them. Only
+    // the snapshot is taken with the locks held, callers format it afterwards.
     let current_thread = CURRENT_THREAD.lock();
     let threads = ACTIVE_THREADS.lock();
     let blocked_threads = BLOCKED_THREADS.lock();
+    let sleeping_threads = SLEEPING_THREADS.lock();
 
-    log_info!("Thread information:");
+    let now = get_timer().ticks();
+    let mut list = vec![];
     if let Some(tcb) = &*current_thread {
-        if let Some(name) = tcb.name() {
-            log_info!("\tCurrent thread: {}, tid: {}", name, tcb.tid);
-        } else {
-            log_info!("\tCurrent thread tid: {}", tcb.tid);
-        }
+        list.push(tcb.info(ThreadState::Running, now));
     }
+    list.extend(threads.iter().map(|tcb| tcb.info(ThreadState::Ready, now)));
+    list.extend(
+        blocked_threads
+            .iter()
+            .map(|tcb| tcb.info(ThreadState::Blocked, now)),
+    );
+    list.extend(
+        sleeping_threads
+            .iter()
+            .map(|tcb| tcb.info(ThreadState::Sleeping, now)),
+    );
+    list
+}
 
-    for tcb in threads.iter() {
-        if let Some(name) = tcb.name() {
-            log_info!("\tThread: {}, tid: {}", name, tcb.tid);
-        } else {
-            log_info!("\tAnonymous thread, tid: {}", tcb.tid);
-        }
-    }
+/// Returns the time the CPU has spent in the idle thread since the scheduler started.
+pub fn idle_time() -> Duration {
+    let now = get_timer().ticks();
+    let current_thread = CURRENT_THREAD.lock();
+    let idle_thread = IDLE_THREAD.lock();
+    let idle_ticks = current_thread
+        .iter()
+        .chain(idle_thread.iter())
+        .find(|tcb| tcb.is_idle_thread)
+        .map(|tcb| tcb.cpu_time.total(now))
+        .unwrap_or_default();
+    get_timer().resolution().ticks_to_duration(idle_ticks)
+}
 
-    for tcb in blocked_threads.iter() {
-        if let Some(name) = tcb.name() {
-            log_info!("\tBlocked thread: {}, tid: {}", name, tcb.tid);
-        } else {
-            log_info!("\tAnonymous blocked thread, tid: {}", tcb.tid);
+/// Looks up a thread by name. If several threads share the name, any of them may be returned.
+pub fn find_by_name(name: &str) -> Option<ThreadHandle> {
+    list()
+        .into_iter()
+        .find(|info| info.name() == Some(name))
+        .map(|info| ThreadHandle(info.tid))
+}
+
+pub fn print_thread_info() {
+    log_info!("Thread information:");
+    for info in list() {
+        match info.name() {
+            Some(name) => {
+                log_info!("\tThread: {}, tid: {}, {:?}", name, info.tid, info.state);
+            }
+            None => {
+                log_info!("\tAnonymous thread, tid: {}, {:?}", info.tid, info.state);
+            }
         }
     }
 }
@@ -563,6 +864,14 @@ fn find_thread(handle: ThreadHandle) -> Option<Tcb> {
         return Some(thread);
     }
 
+    if let Some(thread) = SLEEPING_THREADS
+        .lock()
+        .drain_filter(|thread| thread.tid == handle.0)
+        .pop()
+    {
+        return Some(thread);
+    }
+
     None
 }
 
@@ -581,8 +890,8 @@ pub(crate) fn exit_matching_threads(
         }
     }
 
-    let thread = schedule_next_thread();
-    restore_thread_context(cx, &thread);
+    let mut thread = schedule_next_thread();
+    restore_thread_context(cx, &mut thread);
     CURRENT_THREAD.lock().replace(thread);
 
     Ok(())
@@ -618,7 +927,192 @@ pub(crate) fn wait_for_pid_in_current_thread(cx: &mut ExceptionContext, pid: Pro
     thread.block_reason = Some(BlockReason::WaitForPid(pid));
     BLOCKED_THREADS.lock().push(thread);
 
-    let thread = schedule_next_thread();
-    restore_thread_context(cx, &thread);
+    let mut thread = schedule_next_thread();
+    restore_thread_context(cx, &mut thread);
+    current_thread.replace(thread);
+}
+
+/// Blocks the current thread until the channel receives a message or is closed. The syscall is
+/// restarted when the thread wakes up, so its arguments must still be in `cx`.
+pub(crate) fn wait_for_channel_in_current_thread(cx: &mut ExceptionContext, channel_id: u64) {
+    const SVC_INSTRUCTION_SIZE: u64 = 4;
+
+    let mut current_thread = CURRENT_THREAD.lock();
+
+    let mut thread = current_thread
+        .take()
+        .expect("There is no current thread calling wait_for_channel!");
+    assert!(!thread.is_idle_thread);
+
+    save_thread_context(&mut thread, cx);
+    thread.elr -= SVC_INSTRUCTION_SIZE;
+
+    thread.block_reason = Some(BlockReason::ChannelReceive(channel_id));
+    BLOCKED_THREADS.lock().push(thread);
+
+    let mut thread = schedule_next_thread();
+    restore_thread_context(cx, &mut thread);
     current_thread.replace(thread);
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::drivers::interfaces::timer::MockTimer;
+
+    /// Runs a scheduling decision over the queue and moves the selected thread to the back, as if
+    /// it was preempted afterwards. Returns the priority of the selected thread.
+    fn schedule(queue: &mut Vec<SchedulingState>) -> Priority {
+        let index = select_next(queue.iter()).unwrap();
+        age_passed_over(queue.iter_mut(), index);
+        let state = queue.remove(index);
+        let priority = state.priority;
+        queue.push(state);
+        priority
+    }
+
+    #[test]
+    fn higher_priority_runs_first() {
+        assert_eq!(select_next(Vec::new().iter()), None);
+
+        let queue = [
+            SchedulingState::new(DEFAULT_PRIORITY),
+            SchedulingState::new(MIN_PRIORITY),
+            SchedulingState::new(12),
+            SchedulingState::new(12),
+        ];
+        assert_eq!(select_next(queue.iter()), Some(2));
+    }
+
+    #[test]
+    fn round_robin_within_priority() {
+        let mut queue = vec![
+            SchedulingState::new(12),
+            SchedulingState::new(DEFAULT_PRIORITY),
+            SchedulingState::new(12),
+        ];
+        queue[0].passed_over = 1;
+
+        // Both high priority threads alternate
+        let index = select_next(queue.iter()).unwrap();
+        assert_eq!(index, 0);
+        age_passed_over(queue.iter_mut(), index);
+        assert_eq!(queue[0].passed_over, 0);
+        assert_eq!(queue[1].passed_over, 1);
+        assert_eq!(queue[2].passed_over, 1);
+
+        let state = queue.remove(index);
+        queue.push(state);
+        assert_eq!(select_next(queue.iter()), Some(1));
+    }
+
+    #[test]
+    fn aging_prevents_starvation() {
+        let mut queue = vec![
+            SchedulingState::new(MIN_PRIORITY),
+            SchedulingState::new(MAX_PRIORITY),
+        ];
+
+        let decisions = (MAX_PRIORITY as u32 + 1) * AGING_PERIOD;
+        let low_priority_runs = (0..decisions)
+            .filter(|_| schedule(&mut queue) == MIN_PRIORITY)
+            .count();
+        assert_eq!(low_priority_runs, 1);
+
+        // The effective priority is capped
+        let mut state = SchedulingState::new(MAX_PRIORITY);
+        state.passed_over = 100;
+        assert_eq!(state.effective_priority(), MAX_PRIORITY as u32);
+    }
+
+    #[test]
+    fn sleepers_wake_in_deadline_order() {
+        let timer = MockTimer::new(MockTimer::DEFAULT_RESOLUTION_HZ);
+        let deadline_after = |ms| {
+            timer
+                .resolution()
+                .duration_to_ticks(Duration::from_millis(ms))
+        };
+
+        // Sleepers are (id, deadline), inserted out of order
+        let mut sleeping = IntrusiveList::new();
+        for (id, ms) in [(1, 30), (2, 10), (3, 20), (4, 10), (5, 50)] {
+            let sleeper =
+                OwnedMutPtr::new_from_box(Box::new(IntrusiveItem::new((id, deadline_after(ms)))));
+            sleeping.insert_sorted_by_key(sleeper, |(_, deadline)| *deadline);
+        }
+
+        let mut wake = |ms| -> Vec<u32> {
+            timer.set_ticks(0);
+            timer.advance(Duration::from_millis(ms));
+            let mut ids = vec![];
+            pop_expired(&mut sleeping, timer.ticks(), |(_, deadline)| *deadline).release(
+                |sleeper| {
+                    ids.push(sleeper.0);
+                    let _ = unsafe { sleeper.into_box() };
+                },
+            );
+            ids
+        };
+
+        assert!(wake(5).is_empty());
+        assert_eq!(wake(10), vec![2, 4]);
+        assert_eq!(wake(29), vec![3]);
+        assert_eq!(wake(60), vec![1, 5]);
+        assert!(wake(100).is_empty());
+    }
+
+    #[test]
+    fn cpu_time_accounting() {
+        let timer = MockTimer::new(MockTimer::DEFAULT_RESOLUTION_HZ);
+        let resolution = timer.resolution();
+        let ms = |ticks| resolution.ticks_to_duration(ticks).as_millis();
+
+        let mut thread_a = CpuTime::default();
+        let mut thread_b = CpuTime::default();
+        let mut idle = CpuTime::default();
+
+        // A runs for 3ms, then B for 2ms, then the CPU is idle for 5ms and A runs again
+        thread_a.switch_in(timer.ticks());
+        timer.advance(Duration::from_millis(3));
+        thread_a.switch_out(timer.ticks());
+        thread_b.switch_in(timer.ticks());
+        timer.advance(Duration::from_millis(2));
+        thread_b.switch_out(timer.ticks());
+        idle.switch_in(timer.ticks());
+        timer.advance(Duration::from_millis(5));
+        idle.switch_out(timer.ticks());
+        thread_a.switch_in(timer.ticks());
+        timer.advance(Duration::from_millis(4));
+
+        // The running thread includes its current time slice
+        assert_eq!(ms(thread_a.total(timer.ticks())), 7);
+        assert_eq!(ms(thread_b.total(timer.ticks())), 2);
+        assert_eq!(ms(idle.total(timer.ticks())), 5);
+
+        thread_a.switch_out(timer.ticks());
+        timer.advance(Duration::from_millis(10));
+        assert_eq!(ms(thread_a.total(timer.ticks())), 7);
+
+        // Switching out a thread that was never switched in accounts nothing
+        thread_b.switch_out(timer.ticks());
+        assert_eq!(ms(thread_b.total(timer.ticks())), 2);
+    }
+
+    #[test]
+    fn thread_name_truncation() {
+        assert_eq!(truncated_name("HID"), "HID");
+        assert_eq!(truncated_name(""), "");
+
+        let exact = "a".repeat(MAX_THREAD_NAME_LENGTH);
+        assert_eq!(truncated_name(&exact), exact.as_str());
+
+        let long = "b".repeat(MAX_THREAD_NAME_LENGTH + 8);
+        assert_eq!(truncated_name(&long), &long[..MAX_THREAD_NAME_LENGTH]);
+
+        // Multi-byte characters are never split
+        let long = alloc::format!("{}é", "c".repeat(MAX_THREAD_NAME_LENGTH - 1));
+        assert_eq!(truncated_name(&long), &long[..MAX_THREAD_NAME_LENGTH - 1]);
+    }
+}
@@ -3,6 +3,14 @@ use object::{
 };
 use rustc_demangle::demangle;
 
+/// Selects whether symbol names are demangled when the symbol file is generated or left for the
+/// kernel to demangle when it prints them. The latter keeps the symbol file smaller.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SymbolNames {
+    Demangled,
+    Mangled,
+}
+
 struct Symbol {
     address: u64,
     size: u64,
@@ -13,6 +21,7 @@ struct Symbol {
 pub fn symbols_from_elf_file(
     elf: &ElfFile<elf::FileHeader64<endian::LittleEndian>>,
     symbol_file: &mut impl std::io::Write,
+    symbol_names: SymbolNames,
 ) -> anyhow::Result<()> {
     let mut symbols = vec![];
     let mut string_table: Vec<u8> = vec![];
@@ -23,7 +32,10 @@ pub fn symbols_from_elf_file(
             .filter(|symbol| symbol.kind() == SymbolKind::Text)
             .for_each(|symbol| {
                 if let Ok(name) = symbol.name() {
-                    let name = demangle(name).to_string();
+                    let name = match symbol_names {
+                        SymbolNames::Demangled => format!("{:#}", demangle(name)),
+                        SymbolNames::Mangled => name.to_string(),
+                    };
 
                     let name_offset = string_table.len() as u32;
                     let name_length = name.bytes().len() as u32;
@@ -45,11 +57,23 @@ pub fn symbols_from_elf_file(
     }
 
     const MAGIC_BYTES: [u8; 4] = *b"Smbl";
-    const SYMBOL_TABLE_OFFSET: u32 = 0x14;
+    const SYMBOL_TABLE_OFFSET: u32 = 0x20;
     const SYMBOL_ENTRY_SIZE: u32 = 0x18;
+    const FLAG_MANGLED_NAMES: u32 = 1 << 0;
+
+    let flags = match symbol_names {
+        SymbolNames::Demangled => 0,
+        SymbolNames::Mangled => FLAG_MANGLED_NAMES,
+    };
+
+    // Ranges where functions were inlined into their callers. The kernel reports them as part of
+    // the backtrace, but they are not extracted from the debug info yet.
+    let inlined: Vec<Symbol> = vec![];
 
     let num_symbols = symbols.len() as u32;
-    let string_table_offset = SYMBOL_TABLE_OFFSET + num_symbols * SYMBOL_ENTRY_SIZE;
+    let num_inlined = inlined.len() as u32;
+    let inline_table_offset = SYMBOL_TABLE_OFFSET + num_symbols * SYMBOL_ENTRY_SIZE;
+    let string_table_offset = inline_table_offset + num_inlined * SYMBOL_ENTRY_SIZE;
     let filesize = string_table_offset + string_table.len() as u32;
 
     symbol_file.write_all(&MAGIC_BYTES)?;
@@ -57,8 +81,11 @@ pub fn symbols_from_elf_file(
     symbol_file.write_all(&u32::to_le_bytes(symbols.len() as u32))?;
     symbol_file.write_all(&u32::to_le_bytes(SYMBOL_TABLE_OFFSET))?;
     symbol_file.write_all(&u32::to_le_bytes(string_table_offset))?;
+    symbol_file.write_all(&u32::to_le_bytes(flags))?;
+    symbol_file.write_all(&u32::to_le_bytes(num_inlined))?;
+    symbol_file.write_all(&u32::to_le_bytes(inline_table_offset))?;
 
-    for symbol in symbols {
+    for symbol in symbols.iter().chain(inlined.iter()) {
         symbol_file.write_all(&u32::to_le_bytes(symbol.name_offset))?;
         symbol_file.write_all(&u32::to_le_bytes(symbol.name_length))?;
         symbol_file.write_all(&u64::to_le_bytes(symbol.address))?;
@@ -7,6 +7,10 @@ use structopt::StructOpt;
 struct Options {
     elf_file: std::path::PathBuf,
     symbol_file: std::path::PathBuf,
+
+    /// Keeps symbol names mangled. The kernel demangles them when printing backtraces
+    #[structopt(long)]
+    mangled: bool,
 }
 
 fn main() -> anyhow::Result<()> {
@@ -16,5 +20,10 @@ fn main() -> anyhow::Result<()> {
     let elf_file = ElfFile::parse(&elf_file[..])?;
     let mut symbol_file = fs::File::create(options.symbol_file)?;
 
-    stripper::symbols_from_elf_file(&elf_file, &mut symbol_file)
+    let symbol_names = if options.mangled {
+        stripper::SymbolNames::Mangled
+    } else {
+        stripper::SymbolNames::Demangled
+    };
+    stripper::symbols_from_elf_file(&elf_file, &mut symbol_file, symbol_names)
 }
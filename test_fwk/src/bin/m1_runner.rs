@@ -15,6 +15,9 @@ use xshell::{cmd, rm_rf};
 struct Opts {
     fw_elf: std::path::PathBuf,
 
+    /// Only run tests whose name contains this string
+    filter: Option<String>,
+
     #[structopt(long, short)]
     show_display: bool,
 
@@ -108,7 +111,7 @@ fn build_macho_executable_with_payload(elf: &Path, macho_exec: &Path) -> anyhow:
     })?;
 
     // Append symbols to file
-    stripper::symbols_from_elf_file(&elf_file, &mut macho_exec)?;
+    stripper::symbols_from_elf_file(&elf_file, &mut macho_exec, stripper::SymbolNames::Demangled)?;
 
     // Flush the mach-o file
     macho_exec.flush()?;
@@ -174,11 +177,19 @@ fn main() -> Result<(), Box<dyn Error>> {
         additional_args.push("-S".to_string());
     }
 
+    // Arguments of the semihosting command line. The coverage file has to go first.
+    let mut semihosting_args: Vec<String> = vec![];
     if opts.profile {
+        semihosting_args.push(format!("arg={}", coverage_file.to_string_lossy()));
+    }
+
+    if let Some(filter) = &opts.filter {
+        semihosting_args.push(format!("arg=--filter={}", filter));
+    }
+
+    if !semihosting_args.is_empty() {
         additional_args.push("-semihosting-config".to_string());
-        let mut semihosting_arg = String::from("arg=");
-        semihosting_arg.push_str(&coverage_file.to_string_lossy());
-        additional_args.push(semihosting_arg);
+        additional_args.push(semihosting_args.join(","));
     }
 
     qemu_cmd.args(additional_args.iter()).run()?;
@@ -1,7 +1,8 @@
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 
 use arm_semihosting::{print, println};
 use core::{
+    fmt,
     ops::Fn,
     sync::atomic::{AtomicBool, Ordering},
 };
@@ -13,22 +14,50 @@ use core::panic::PanicInfo;
 #[cfg(feature = "coverage")]
 use minicov as _;
 
-#[derive(Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Status {
     Fail,
     Success,
 }
 
+/// Prefix of the command line argument that selects the tests to run
+const FILTER_ARG: &str = "--filter=";
+
+/// Arguments given to the test executable through the semihosting command line
+#[derive(Debug, Default, PartialEq)]
+struct CmdLine<'a> {
+    /// File where coverage data is saved
+    coverage_file: Option<&'a str>,
+    /// Only tests with a name containing this are run
+    filter: Option<&'a str>,
+}
+
+fn parse_cmd_line(cmdline: &str) -> CmdLine<'_> {
+    let mut args = CmdLine::default();
+    for arg in cmdline.split_whitespace() {
+        if let Some(filter) = arg.strip_prefix(FILTER_ARG) {
+            args.filter = Some(filter);
+        } else if args.coverage_file.is_none() {
+            args.coverage_file = Some(arg);
+        }
+    }
+    args
+}
+
+fn matches_filter(name: &str, filter: Option<&str>) -> bool {
+    filter.map(|filter| name.contains(filter)).unwrap_or(true)
+}
+
 fn exit_and_collect_coverage(status: Status) -> ! {
     #[cfg(feature = "coverage")]
     {
         // Get the command line and use the name of the executable for the coverage file
         let cmdline = arm_semihosting::get_cmd_line().unwrap();
-        if !cmdline.is_empty() {
-            println!("Saving coverage as: {}", cmdline);
+        if let Some(coverage_file) = parse_cmd_line(&cmdline).coverage_file {
+            println!("Saving coverage as: {}", coverage_file);
             let coverage = minicov::capture_coverage();
             let mut file = match arm_semihosting::io::create(
-                &cmdline,
+                coverage_file,
                 arm_semihosting::io::AccessType::Binary,
             ) {
                 Ok(f) => f,
@@ -48,18 +77,82 @@ fn exit_and_collect_coverage(status: Status) -> ! {
     arm_semihosting::exit(exit_code);
 }
 
-pub fn runner(tests: &[&dyn Testable]) {
+/// Returns the tests selected by the filter in the order they have to run, or the number of
+/// selected tests expected to panic if there is more than one. Without unwinding a panic ends the
+/// executable, so only one test may panic and it has to run last.
+fn run_order<'a>(
+    tests: &'a [&'a dyn Testable],
+    filter: Option<&'a str>,
+) -> Result<impl Iterator<Item = &'a dyn Testable> + 'a, usize> {
+    let selected = move || {
+        tests
+            .iter()
+            .copied()
+            .filter(move |test| matches_filter(test.name(), filter))
+    };
+
+    let num_should_panic = selected().filter(|test| test.should_panic()).count();
+    if num_should_panic > 1 {
+        return Err(num_should_panic);
+    }
+
+    Ok(selected()
+        .filter(|test| !test.should_panic())
+        .chain(selected().filter(|test| test.should_panic())))
+}
+
+/// Runs the tests selected by the filter of the command line and returns how many of them ran.
+fn run_tests(tests: &[&dyn Testable]) -> usize {
     println!("{}", "Starting test execution".fg(cyan_blue()));
-    tests.iter().for_each(|test| test.run());
+
+    let cmdline = arm_semihosting::get_cmd_line().unwrap();
+    let filter = parse_cmd_line(&cmdline).filter;
+
+    let selected = tests
+        .iter()
+        .filter(|test| matches_filter(test.name(), filter))
+        .count();
+    if selected != tests.len() {
+        println!("{} filtered out", tests.len() - selected);
+    }
+
+    match run_order(tests, filter) {
+        Ok(order) => order.for_each(|test| test.run()),
+        Err(num_should_panic) => {
+            println!(
+                "{} {} tests are expected to panic, but only one can run per executable",
+                "Invalid test suite:".fg(red()),
+                num_should_panic
+            );
+            finish_with_status(Status::Fail);
+        }
+    }
+    selected
+}
+
+pub fn runner(tests: &[&dyn Testable]) {
+    run_tests(tests);
     finish_with_status(Status::Success);
 }
 
 pub fn runner_should_panic(tests: &[&dyn Testable]) {
-    println!("{}", "Starting test execution".fg(cyan_blue()));
-    tests.iter().for_each(|test| test.run());
+    // If the filter did not select any test, nothing was expected to panic
+    if run_tests(tests) == 0 {
+        finish_with_status(Status::Success);
+    }
     finish_with_status(Status::Fail);
 }
 
+/// Result of a test that ended either by panicking or by returning. It passes if it panicked only
+/// when it was expected to.
+fn panic_outcome(expecting: bool, panicked: bool) -> Status {
+    if expecting == panicked {
+        Status::Success
+    } else {
+        Status::Fail
+    }
+}
+
 pub fn panic_handler(panic_info: &PanicInfo) -> ! {
     static ALREADY_PANICKED: AtomicBool = AtomicBool::new(false);
     if ALREADY_PANICKED.load(Ordering::Relaxed) {
@@ -68,8 +161,14 @@ pub fn panic_handler(panic_info: &PanicInfo) -> ! {
     }
     ALREADY_PANICKED.store(true, Ordering::Relaxed);
 
-    println!("{} {:?}", "Panicked at:".fg(red()), panic_info);
-    finish_with_status(Status::Fail);
+    let status = panic_outcome(EXPECTING_PANIC.load(Ordering::Relaxed), true);
+    if status == Status::Success {
+        println!("{} {:?}", "Expected panic at:".fg(green_cyan()), panic_info);
+        println!("{}", "ok".fg(green_cyan()));
+    } else {
+        println!("{} {:?}", "Panicked at:".fg(red()), panic_info);
+    }
+    finish_with_status(status);
 }
 
 pub fn panic_handler_should_panic(panic_info: &PanicInfo) -> ! {
@@ -93,22 +192,357 @@ pub fn finish_with_status(status: Status) -> ! {
     exit_and_collect_coverage(status);
 }
 
+/// Tests taking longer than this, in centiseconds, are flagged as slow.
+pub const SLOW_TEST_THRESHOLD_CS: u64 = 100;
+
+/// Centiseconds since the emulator started, as reported by SYS_CLOCK. Returns `None` when the host
+/// cannot provide it.
+#[cfg(target_arch = "aarch64")]
+fn clock() -> Option<u64> {
+    const SYS_CLOCK: u64 = 0x10;
+
+    let result: i64;
+    unsafe {
+        core::arch::asm!(
+            "hlt #0xf000",
+            inout("x0") SYS_CLOCK => result,
+            in("x1") 0u64,
+            options(nostack)
+        )
+    };
+    u64::try_from(result).ok()
+}
+
+#[cfg(not(target_arch = "aarch64"))]
+fn clock() -> Option<u64> {
+    None
+}
+
+/// Result line of a test that passed, with the time it took when the clock is available.
+struct TestOk {
+    elapsed_cs: Option<u64>,
+}
+
+impl TestOk {
+    fn new(start_cs: Option<u64>, end_cs: Option<u64>) -> Self {
+        let elapsed_cs = match (start_cs, end_cs) {
+            (Some(start), Some(end)) => end.checked_sub(start),
+            _ => None,
+        };
+        Self { elapsed_cs }
+    }
+
+    fn is_slow(&self) -> bool {
+        self.elapsed_cs
+            .map(|elapsed| elapsed > SLOW_TEST_THRESHOLD_CS)
+            .unwrap_or(false)
+    }
+}
+
+impl fmt::Display for TestOk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ok")?;
+        if let Some(elapsed) = self.elapsed_cs {
+            write!(f, " ({}.{:02}s", elapsed / 100, elapsed % 100)?;
+            if self.is_slow() {
+                write!(f, ", slow")?;
+            }
+            write!(f, ")")?;
+        }
+        Ok(())
+    }
+}
+
+/// Set while a test expected to panic is running, so that `panic_handler` reports it as passed.
+static EXPECTING_PANIC: AtomicBool = AtomicBool::new(false);
+
 pub trait Testable {
+    fn name(&self) -> &'static str;
     fn run(&self);
+
+    fn should_panic(&self) -> bool {
+        false
+    }
+}
+
+/// Prepares and restores the global state a test depends on, so that tests do not depend on the
+/// order they run in.
+pub trait Fixture {
+    /// Runs before the test
+    fn setup(&self) {}
+
+    /// Runs after the test, if it did not panic
+    fn teardown(&self) {}
+}
+
+/// Fixture of the tests that do not need one
+pub struct NoFixture;
+
+impl Fixture for NoFixture {}
+
+fn run_in_fixture(fixture: &dyn Fixture, test: &dyn Fn()) {
+    fixture.setup();
+    test();
+    fixture.teardown();
+}
+
+fn run_test(name: &str, fixture: &dyn Fixture, test: &dyn Fn()) {
+    print!(
+        "{} {} ... ",
+        "Running test:".fg(cyan_blue()),
+        name.fg(cyan_blue())
+    );
+    let start = clock();
+    run_in_fixture(fixture, test);
+    let result = TestOk::new(start, clock());
+    if result.is_slow() {
+        println!("{}", result.fg(red()));
+    } else {
+        println!("{}", result.fg(green_cyan()));
+    }
 }
 
 impl<T> Testable for T
 where
     T: Fn(),
 {
+    fn name(&self) -> &'static str {
+        core::any::type_name::<Self>()
+    }
+
+    fn run(&self) {
+        run_test(self.name(), &NoFixture, self);
+    }
+}
+
+/// A test that runs with the given fixture.
+///
+/// ```ignore
+/// #[test_case]
+/// static TEST_MAP: WithFixture<ResetState, fn()> = WithFixture::new("test_map", ResetState, map);
+/// ```
+pub struct WithFixture<F, T> {
+    name: &'static str,
+    fixture: F,
+    test: T,
+}
+
+impl<F, T> WithFixture<F, T> {
+    pub const fn new(name: &'static str, fixture: F, test: T) -> Self {
+        Self {
+            name,
+            fixture,
+            test,
+        }
+    }
+}
+
+impl<F, T> Testable for WithFixture<F, T>
+where
+    F: Fixture,
+    T: Fn(),
+{
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn run(&self) {
+        run_test(self.name, &self.fixture, &self.test);
+    }
+}
+
+/// A test that passes only if it panics. Suites using it must set `test_fwk::panic_handler` as
+/// the panic handler. Since a panic ends the executable, it runs after all other tests and a suite
+/// can only contain one of them.
+///
+/// ```ignore
+/// #[test_case]
+/// static TEST_DOUBLE_MAP: ShouldPanic<fn()> = ShouldPanic::new("test_double_map", double_map);
+/// ```
+pub struct ShouldPanic<T> {
+    name: &'static str,
+    test: T,
+}
+
+impl<T> ShouldPanic<T> {
+    pub const fn new(name: &'static str, test: T) -> Self {
+        Self { name, test }
+    }
+}
+
+impl<T> Testable for ShouldPanic<T>
+where
+    T: Fn(),
+{
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
     fn run(&self) {
-        let type_name = core::any::type_name::<Self>();
         print!(
             "{} {} ... ",
             "Running test:".fg(cyan_blue()),
-            type_name.fg(cyan_blue())
+            self.name().fg(cyan_blue())
         );
-        self();
-        println!("{}", "ok".fg(green_cyan()));
+
+        EXPECTING_PANIC.store(true, Ordering::Relaxed);
+        (self.test)();
+        EXPECTING_PANIC.store(false, Ordering::Relaxed);
+
+        let status = panic_outcome(true, false);
+        println!("{}", "FAILED, the test did not panic".fg(red()));
+        finish_with_status(status);
+    }
+
+    fn should_panic(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn cmd_line_arguments() {
+        assert_eq!(parse_cmd_line(""), CmdLine::default());
+        assert_eq!(
+            parse_cmd_line("target/fw.profraw"),
+            CmdLine {
+                coverage_file: Some("target/fw.profraw"),
+                filter: None,
+            }
+        );
+        assert_eq!(
+            parse_cmd_line("--filter=mmu"),
+            CmdLine {
+                coverage_file: None,
+                filter: Some("mmu"),
+            }
+        );
+        assert_eq!(
+            parse_cmd_line("target/fw.profraw --filter=flat_map"),
+            CmdLine {
+                coverage_file: Some("target/fw.profraw"),
+                filter: Some("flat_map"),
+            }
+        );
+    }
+
+    #[test]
+    fn test_filter() {
+        let name = "mmu_tests::test_map_two_pages";
+        assert!(matches_filter(name, None));
+        assert!(matches_filter(name, Some("")));
+        assert!(matches_filter(name, Some("mmu")));
+        assert!(matches_filter(name, Some("test_map_two_pages")));
+        assert!(!matches_filter(name, Some("flat_map")));
+        assert!(!matches_filter(name, Some("MMU")));
+    }
+
+    fn first_test() {}
+    fn second_test() {}
+    fn panicking_test() {
+        panic!("expected");
+    }
+
+    fn names<'a>(order: impl Iterator<Item = &'a dyn Testable>) -> Vec<&'static str> {
+        order.map(|test| test.name()).collect()
+    }
+
+    #[test]
+    fn should_panic_tests_run_last() {
+        let should_panic = ShouldPanic::new("panicking_test", panicking_test);
+        let tests: [&dyn Testable; 3] = [&first_test, &should_panic, &second_test];
+
+        assert!(!tests[0].should_panic());
+        assert!(tests[1].should_panic());
+
+        let order = names(run_order(&tests, None).ok().unwrap());
+        assert_eq!(order.len(), 3);
+        assert!(order[0].ends_with("first_test"));
+        assert!(order[1].ends_with("second_test"));
+        assert_eq!(order[2], "panicking_test");
+
+        // The filter applies to tests expected to panic as well
+        let order = names(run_order(&tests, Some("second")).ok().unwrap());
+        assert_eq!(order.len(), 1);
+        assert!(order[0].ends_with("second_test"));
+    }
+
+    #[test]
+    fn only_one_should_panic_test() {
+        let should_panic = ShouldPanic::new("panicking_test", panicking_test);
+        let other_should_panic = ShouldPanic::new("other_panicking_test", panicking_test);
+        let tests: [&dyn Testable; 3] = [&should_panic, &first_test, &other_should_panic];
+
+        assert_eq!(run_order(&tests, None).err(), Some(2));
+
+        let order = names(run_order(&tests, Some("other")).ok().unwrap());
+        assert_eq!(order, vec!["other_panicking_test"]);
+    }
+
+    #[test]
+    fn panic_outcomes() {
+        // An expected panic passes and a test expected to panic that returns fails
+        assert_eq!(panic_outcome(true, true), Status::Success);
+        assert_eq!(panic_outcome(true, false), Status::Fail);
+
+        // Tests that are not expected to panic fail only if they do
+        assert_eq!(panic_outcome(false, true), Status::Fail);
+        assert_eq!(panic_outcome(false, false), Status::Success);
+    }
+
+    #[test]
+    fn fixture_resets_state() {
+        use core::sync::atomic::AtomicUsize;
+
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+        struct ResetCounter;
+
+        impl Fixture for ResetCounter {
+            fn setup(&self) {
+                COUNTER.store(0, Ordering::Relaxed);
+            }
+
+            fn teardown(&self) {
+                COUNTER.store(0, Ordering::Relaxed);
+            }
+        }
+
+        let increment = || {
+            assert_eq!(COUNTER.fetch_add(1, Ordering::Relaxed), 0);
+        };
+
+        COUNTER.store(5, Ordering::Relaxed);
+        run_in_fixture(&ResetCounter, &increment);
+        run_in_fixture(&ResetCounter, &increment);
+        assert_eq!(COUNTER.load(Ordering::Relaxed), 0);
+
+        // Without a fixture the state leaks between tests
+        run_in_fixture(&NoFixture, &|| {
+            COUNTER.fetch_add(1, Ordering::Relaxed);
+        });
+        assert_eq!(COUNTER.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn test_ok_formatting() {
+        assert_eq!(format!("{}", TestOk::new(Some(10), Some(22))), "ok (0.12s)");
+        assert_eq!(format!("{}", TestOk::new(Some(0), Some(100))), "ok (1.00s)");
+        assert_eq!(
+            format!("{}", TestOk::new(Some(5), Some(312))),
+            "ok (3.07s, slow)"
+        );
+    }
+
+    #[test]
+    fn test_ok_without_clock() {
+        assert_eq!(format!("{}", TestOk::new(None, Some(22))), "ok");
+        assert_eq!(format!("{}", TestOk::new(Some(10), None)), "ok");
+        // The clock should never go back, but the line is still printed if it does
+        assert_eq!(format!("{}", TestOk::new(Some(22), Some(10))), "ok");
+        assert!(!TestOk::new(None, None).is_slow());
     }
 }
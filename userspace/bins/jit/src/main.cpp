@@ -0,0 +1,57 @@
+#include <libcxx/types.h>
+#include <libcxx/syscalls.h>
+
+using libcxx::u32;
+using libcxx::usize;
+
+namespace {
+    constexpr usize PAGE_SIZE = 16384;
+
+    // mov w0, #42
+    // ret
+    constexpr u32 CODE[] = {0x52800540, 0xd65f03c0};
+}
+
+int main() {
+  namespace syscalls = libcxx::syscalls;
+
+  auto *const page = static_cast<volatile u32 *>(
+          syscalls::mmap(PAGE_SIZE, syscalls::PROT_READ | syscalls::PROT_WRITE));
+  if (page == nullptr) {
+    return 1;
+  }
+
+  for (usize i = 0; i < sizeof(CODE) / sizeof(CODE[0]); i++) {
+    page[i] = CODE[i];
+  }
+
+  void *const addr = const_cast<u32 *>(page);
+  if (syscalls::mprotect(addr, PAGE_SIZE, syscalls::PROT_READ | syscalls::PROT_EXEC) != 0) {
+    return 2;
+  }
+
+  using Function = int (*)();
+  const auto function = reinterpret_cast<Function>(addr);
+  if (function() != 42) {
+    return 3;
+  }
+
+  // Write-only memory cannot be mapped
+  if (syscalls::mprotect(addr, PAGE_SIZE, syscalls::PROT_WRITE) != syscalls::EINVAL) {
+    return 4;
+  }
+
+  // The page after the mapping is not mapped
+  auto *const next_page = static_cast<u32 *>(addr) + PAGE_SIZE / sizeof(u32);
+  if (syscalls::mprotect(next_page, PAGE_SIZE, syscalls::PROT_READ) != syscalls::EFAULT) {
+    return 5;
+  }
+
+  // Sizes that overflow are rejected
+  if (syscalls::mprotect(addr, ~usize{0}, syscalls::PROT_READ | syscalls::PROT_EXEC) !=
+      syscalls::EINVAL) {
+    return 6;
+  }
+
+  return syscalls::munmap(addr, PAGE_SIZE) ? 0 : 7;
+}
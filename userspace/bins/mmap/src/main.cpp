@@ -0,0 +1,73 @@
+#include <libcxx/types.h>
+#include <libcxx/syscalls.h>
+
+using libcxx::u64;
+using libcxx::usize;
+
+namespace {
+    // Spans multiple 16KB pages and doesn't end on a page boundary
+    constexpr usize MAPPING_SIZE = 3 * 16384 + 100;
+    constexpr usize NUM_WORDS = MAPPING_SIZE / sizeof(u64);
+}
+
+int main() {
+  namespace syscalls = libcxx::syscalls;
+
+  auto *const words = static_cast<volatile u64 *>(
+          syscalls::mmap(MAPPING_SIZE, syscalls::PROT_READ | syscalls::PROT_WRITE));
+  if (words == nullptr) {
+    return 1;
+  }
+
+  // Fresh mappings are zero-filled
+  for (usize i = 0; i < NUM_WORDS; i++) {
+    if (words[i] != 0) {
+      return 2;
+    }
+  }
+
+  for (usize i = 0; i < NUM_WORDS; i++) {
+    words[i] = i * 0x0101010101010101;
+  }
+
+  for (usize i = 0; i < NUM_WORDS; i++) {
+    if (words[i] != i * 0x0101010101010101) {
+      return 3;
+    }
+  }
+
+  if (!syscalls::munmap(const_cast<u64 *>(words), MAPPING_SIZE)) {
+    return 4;
+  }
+
+  // The mapping is gone, so unmapping it again must fail
+  if (syscalls::munmap(const_cast<u64 *>(words), MAPPING_SIZE)) {
+    return 5;
+  }
+
+  // The kernel must not read the unmapped memory on our behalf
+  u64 tx;
+  u64 rx;
+  if (!syscalls::channel_create(&tx, &rx)) {
+    return 6;
+  }
+  if (syscalls::channel_send(tx, const_cast<u64 *>(words), sizeof(u64)) !=
+      syscalls::CHANNEL_ERROR_FAULT) {
+    return 7;
+  }
+
+  // Channels created by others cannot be used, even if their endpoints are guessed
+  const u64 other_tx = tx + 2;
+  if (syscalls::channel_send(other_tx, &tx, sizeof(tx)) !=
+      syscalls::CHANNEL_ERROR_INVALID_ENDPOINT) {
+    return 8;
+  }
+  if (syscalls::channel_close(other_tx)) {
+    return 9;
+  }
+
+  syscalls::channel_close(tx);
+  syscalls::channel_close(rx);
+
+  return 0;
+}
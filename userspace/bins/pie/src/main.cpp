@@ -0,0 +1,14 @@
+namespace {
+    int value = 42;
+}
+
+// Initialized with the link address of `value`. It only points to `value` if the relocations of the
+// executable were applied for the address it was loaded at.
+int *volatile pointer = &value;
+
+int main() {
+  if (pointer != &value) {
+    return 1;
+  }
+  return *pointer == 42 ? 0 : 2;
+}
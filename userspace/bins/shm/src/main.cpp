@@ -0,0 +1,81 @@
+#include <libcxx/types.h>
+#include <libcxx/syscalls.h>
+
+using libcxx::u64;
+using libcxx::usize;
+
+// Shares a region between two instances of this binary. The writer creates the region and fills
+// it, then waits until the reader has checked the contents before exiting, since the region is
+// freed when the last process unmaps it.
+namespace {
+    namespace syscalls = libcxx::syscalls;
+
+    constexpr const char *REGION_NAME = "shm-test";
+    constexpr usize REGION_SIZE = 2 * 16384;
+
+    struct Region {
+        volatile u64 reader_done;
+        volatile u64 data[(REGION_SIZE / sizeof(u64)) - 1];
+    };
+
+    constexpr usize NUM_WORDS = sizeof(Region::data) / sizeof(u64);
+    constexpr u64 MAX_RETRIES = 100;
+
+    int writer() {
+      auto *const region = static_cast<Region *>(
+              syscalls::shm_create(REGION_NAME, REGION_SIZE, syscalls::PROT_READ | syscalls::PROT_WRITE));
+      if (region == nullptr) {
+        return 1;
+      }
+
+      for (usize i = 0; i < NUM_WORDS; i++) {
+        region->data[i] = ~i;
+      }
+
+      while (region->reader_done == 0) {
+        syscalls::sleep(10'000);
+      }
+
+      return syscalls::munmap(region, REGION_SIZE) ? 0 : 2;
+    }
+
+    int reader() {
+      Region *region = nullptr;
+      for (u64 retry = 0; retry < MAX_RETRIES && region == nullptr; retry++) {
+        region = static_cast<Region *>(
+                syscalls::shm_map(REGION_NAME, syscalls::PROT_READ | syscalls::PROT_WRITE));
+        if (region == nullptr) {
+          syscalls::sleep(10'000);
+        }
+      }
+
+      if (region == nullptr) {
+        return 3;
+      }
+
+      int result = 0;
+      for (usize i = 0; i < NUM_WORDS; i++) {
+        if (region->data[i] != ~i) {
+          result = 4;
+          break;
+        }
+      }
+
+      region->reader_done = 1;
+      if (!syscalls::munmap(region, REGION_SIZE)) {
+        return 5;
+      }
+      return result;
+    }
+}
+
+int main(int argc, char *argv[]) {
+  if (argc < 2) {
+    return 6;
+  }
+
+  if (argv[1][0] == 'w') {
+    return writer();
+  }
+  return reader();
+}
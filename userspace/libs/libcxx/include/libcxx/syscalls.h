@@ -13,6 +13,76 @@ namespace libcxx::syscalls {
      * @brief Sleeps for the given number of nanoseconds
      */
     void sleep(u64 time_us);
+
+    constexpr u64 PROT_READ = 1 << 0;
+    constexpr u64 PROT_WRITE = 1 << 1;
+    constexpr u64 PROT_EXEC = 1 << 2;
+
+    /**
+     * @brief Maps zero-filled memory with the given protection flags. Returns nullptr on failure
+     */
+    void *mmap(usize length, u64 prot);
+
+    /**
+     * @brief Unmaps memory returned by mmap. The whole mapping must be unmapped at once
+     */
+    bool munmap(void *addr, usize length);
+
+    constexpr u64 EFAULT = 14;
+    constexpr u64 EINVAL = 22;
+
+    /**
+     * @brief Changes the protection flags of pages of a mapping. Returns 0 on success, EFAULT if
+     * the pages are not within a single mapping or EINVAL if the arguments are invalid
+     */
+    u64 mprotect(void *addr, usize length, u64 prot);
+
+    /**
+     * @brief Writes the memory map of the process to the buffer, one mapping per line. Returns the
+     * length of the whole map, which is larger than length if the map did not fit, or 0 on failure
+     */
+    usize memory_map(char *buffer, usize length);
+
+    /**
+     * @brief Creates a named shared memory region and maps it. Returns nullptr on failure.
+     * The region is freed once every process unmaps it
+     */
+    void *shm_create(const char *name, usize length, u64 prot);
+
+    /**
+     * @brief Maps an existing shared memory region by name. Returns nullptr on failure.
+     * Use munmap with the size of the region to unmap it
+     */
+    void *shm_map(const char *name, u64 prot);
+
+    constexpr u64 CHANNEL_ERROR_INVALID_ENDPOINT = 0xFFFF;
+    constexpr u64 CHANNEL_ERROR_AGAIN = 0xFFFE;
+    constexpr u64 CHANNEL_ERROR_CLOSED = 0xFFFD;
+    constexpr u64 CHANNEL_ERROR_INVALID_SIZE = 0xFFFC;
+    constexpr u64 CHANNEL_ERROR_BUFFER_TOO_SMALL = 0xFFFB;
+    constexpr u64 CHANNEL_ERROR_FAULT = 0xFFFA;
+
+    /**
+     * @brief Creates a message channel and returns the IDs of its transmit and receive endpoints
+     */
+    bool channel_create(u64 *tx, u64 *rx);
+
+    /**
+     * @brief Queues a message in the channel. Returns 0 on success or one of the CHANNEL_ERROR
+     * values. CHANNEL_ERROR_AGAIN is returned if the channel is full
+     */
+    u64 channel_send(u64 tx, const void *data, usize length);
+
+    /**
+     * @brief Blocks until a message is available and returns its length or one of the
+     * CHANNEL_ERROR values
+     */
+    u64 channel_recv(u64 rx, void *buffer, usize length);
+
+    /**
+     * @brief Closes an endpoint of a channel
+     */
+    bool channel_close(u64 endpoint);
 }
 
 #endif  // LIBCXX_SYSCALLS_H_
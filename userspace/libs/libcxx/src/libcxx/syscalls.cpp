@@ -17,4 +17,112 @@ namespace libcxx::syscalls {
       "mov x0, %0\n"
       "svc 2" : : "r" (time_us) : "x0");
     }
+
+    void *mmap(const usize length, const u64 prot) {
+      void *addr;
+      asm volatile(
+      "mov x0, %1\n"
+      "mov x1, %2\n"
+      "svc 12\n"
+      "mov %0, x0" : "=r" (addr) : "r" (length), "r" (prot) : "x0", "x1", "memory");
+      return addr;
+    }
+
+    bool munmap(void *addr, const usize length) {
+      u64 result;
+      asm volatile(
+      "mov x0, %1\n"
+      "mov x1, %2\n"
+      "svc 13\n"
+      "mov %0, x0" : "=r" (result) : "r" (addr), "r" (length) : "x0", "x1", "memory");
+      return result == 0;
+    }
+
+    u64 mprotect(void *addr, const usize length, const u64 prot) {
+      u64 result;
+      asm volatile(
+      "mov x0, %1\n"
+      "mov x1, %2\n"
+      "mov x2, %3\n"
+      "svc 20\n"
+      "mov %0, x0" : "=r" (result) : "r" (addr), "r" (length), "r" (prot) : "x0", "x1", "x2", "memory");
+      return result;
+    }
+
+    usize memory_map(char *buffer, const usize length) {
+      usize result;
+      asm volatile(
+      "mov x0, %1\n"
+      "mov x1, %2\n"
+      "svc 21\n"
+      "mov %0, x0" : "=r" (result) : "r" (buffer), "r" (length) : "x0", "x1", "memory");
+      return result;
+    }
+
+    void *shm_create(const char *name, const usize length, const u64 prot) {
+      const usize name_length = strlen(name);
+      void *addr;
+      asm volatile(
+      "mov x0, %1\n"
+      "mov x1, %2\n"
+      "mov x2, %3\n"
+      "mov x3, %4\n"
+      "svc 14\n"
+      "mov %0, x0" : "=r" (addr) : "r" (name), "r" (name_length), "r" (length), "r" (prot)
+      : "x0", "x1", "x2", "x3", "memory");
+      return addr;
+    }
+
+    void *shm_map(const char *name, const u64 prot) {
+      const usize name_length = strlen(name);
+      void *addr;
+      asm volatile(
+      "mov x0, %1\n"
+      "mov x1, %2\n"
+      "mov x2, %3\n"
+      "svc 15\n"
+      "mov %0, x0" : "=r" (addr) : "r" (name), "r" (name_length), "r" (prot) : "x0", "x1", "x2", "memory");
+      return addr;
+    }
+
+    bool channel_create(u64 *tx, u64 *rx) {
+      u64 result;
+      asm volatile(
+      "mov x0, %1\n"
+      "mov x1, %2\n"
+      "svc 16\n"
+      "mov %0, x0" : "=r" (result) : "r" (tx), "r" (rx) : "x0", "x1", "memory");
+      return result == 0;
+    }
+
+    u64 channel_send(const u64 tx, const void *data, const usize length) {
+      u64 result;
+      asm volatile(
+      "mov x0, %1\n"
+      "mov x1, %2\n"
+      "mov x2, %3\n"
+      "svc 17\n"
+      "mov %0, x0" : "=r" (result) : "r" (tx), "r" (data), "r" (length) : "x0", "x1", "x2", "memory");
+      return result;
+    }
+
+    u64 channel_recv(const u64 rx, void *buffer, const usize length) {
+      u64 result;
+      asm volatile(
+      "mov x0, %1\n"
+      "mov x1, %2\n"
+      "mov x2, %3\n"
+      "svc 18\n"
+      "mov %0, x0" : "=r" (result) : "r" (rx), "r" (buffer), "r" (length) : "x0", "x1", "x2", "memory");
+      return result;
+    }
+
+    bool channel_close(const u64 endpoint) {
+      u64 result;
+      asm volatile(
+      "mov x0, %1\n"
+      "svc 19\n"
+      "mov %0, x0" : "=r" (result) : "r" (endpoint) : "x0", "memory");
+      return result == 0;
+    }
 }
\ No newline at end of file